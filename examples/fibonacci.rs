@@ -1,38 +1,40 @@
-#[macro_use]
 extern crate web_assembler as wasm;
 
 use wasm::builder::*;
 use wasm::*;
+use std::error;
 use std::fs::File;
 use std::io::Write;
 use std::env;
 
-fn main() {
-    let out_file = env::args().nth(1).expect("argument missing: output file");
+fn main() -> Result<(), Box<dyn error::Error>> {
+    let out_file = env::args().nth(1).ok_or("argument missing: output file")?;
 
     let mut md = ModuleBuilder::new();
-    // function to create must be the 0th function of the module...
-    let fib = FunctionIndex(0).into();
-    let f = FunctionBuilder::new(funtype!((i32) -> i32))
+    // declare first so that the body can call itself
+    let fib = md.declare_function(funtype!((i32) -> i32));
+    md.name_function(fib, "fib");
+    let (_, body) = FunctionBuilder::new(funtype!((i32) -> i32))
         .code(|cb, params| {
             let n = params[0];
             cb.get_local(n)
                 .constant(1i32)
                 .i32_sub()
-                .call(fib)
+                .call(fib.into())
                 .get_local(n)
                 .constant(2i32)
                 .i32_sub()
-                .call(fib)
+                .call(fib.into())
                 .i32_add()
                 .return_()
         })
-        .build();
-    md.new_function(f);
+        .try_build()?;
+    md.try_define_function(fib, body)?;
 
-    let module = md.build();
+    let module = md.try_build()?;
     let mut code = Vec::new();
-    module.dump(&mut code);
-    let mut out = File::create(out_file).unwrap();
-    out.write(&code).unwrap();
+    module.try_dump(&mut code)?;
+    let mut out = File::create(out_file)?;
+    out.write_all(&code)?;
+    Ok(())
 }
@@ -1,10 +1,16 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::mem;
 use std::ops::{Range, RangeFrom};
+use std::sync::atomic::{AtomicU64, Ordering};
 
+use error::Error;
 use module::*;
 use types::*;
 use ops;
 use ops::Op;
-pub struct ModuleBuilder(Module);
+/// The module being built, and the functions declared with
+/// `declare_function` that have no body yet.
+pub struct ModuleBuilder(Module, BTreeSet<FunctionIndex>);
 
 macro_rules! gen_add {
     ($name: tt ($param: tt, $ty: ty) -> $ret: tt, $field: tt) => {
@@ -43,19 +49,23 @@ macro_rules! gen_add {
 impl ModuleBuilder {
     pub fn new() -> Self {
         ModuleBuilder(Module {
-                          unknown: None,
+                          customs: None,
                           types: None,
                           imports: None,
                           functions: None,
                           tables: None,
                           memories: None,
+                          tags: None,
                           globals: None,
                           exports: None,
                           start: None,
                           elements: None,
+                          data_count: None,
                           codes: None,
                           data: None,
-                      })
+                          names: None,
+                      },
+                      BTreeSet::new())
     }
 
     pub fn function_index_of(&self, i: ImportIndex) -> Result<FunctionSpaceIndex, ImportIndex> {
@@ -77,30 +87,79 @@ impl ModuleBuilder {
         for f in self.0.codes.iter_mut().flat_map(|f| f.iter_mut()) {
             f.resolve_functions(nimports)
         }
+        // resolve `ref.func` in globals
+        for g in self.0.globals.iter_mut().flat_map(|g| g.iter_mut()) {
+            g.init.resolve_functions(nimports)
+        }
         // resolve tables
         for e in self.0.elements.iter_mut().flat_map(|e| e.iter_mut()) {
-            for index in e.elems.iter_mut() {
-                use InnerFunctionSpaceIndex::*;
-                match index.0 {
-                    Function(ref mut f) => {
-                        f.0 += nimports;
-                    }
-                    _ => (),
-                }
-            }
+            e.elems.resolve_functions(nimports)
         }
+        // resolve names
+        if let Some(ref mut names) = self.0.names {
+            names.functions = mem::take(&mut names.functions)
+                .into_iter()
+                .map(|(mut index, name)| {
+                         resolve_function(&mut index, nimports);
+                         (index, name)
+                     })
+                .collect();
+            names.locals = mem::take(&mut names.locals)
+                .into_iter()
+                .map(|(mut index, locals)| {
+                         resolve_function(&mut index, nimports);
+                         (index, locals)
+                     })
+                .collect();
+        }
+    }
+
+    /// # Panics
+    /// if a function declared with `declare_function` was never defined.
+    pub fn build(self) -> Module {
+        self.try_build().unwrap_or_else(|e| panic!("{}", e))
     }
 
-    pub fn build(mut self) -> Module {
+    pub fn try_build(mut self) -> Result<Module, Error> {
+        if let Some(&index) = self.1.iter().next() {
+            return Err(Error::UndefinedFunction(index));
+        }
         self.resolve_functions();
+        if self.0.names.as_ref().is_some_and(|n| n.is_empty()) {
+            self.0.names = None;
+        }
         let nimports = self.nimports();
-        self.0.start.as_mut().map(|i| i.0 += nimports);
-        self.0
+        if let Some(ref mut i) = self.0.start {
+            i.0 += nimports;
+        }
+        if self.0.needs_data_count() {
+            self.0.data_count = Some(self.0.data.as_ref().map_or(0, |d| d.len() as u32));
+        }
+        Ok(self.0)
     }
 
 
-    gen_add!(add_type(ty, FuncType) -> TypeIndex,
-             types);
+    /// Adds a function type, or returns the index of an identical one added
+    /// before.
+    pub fn add_type(&mut self, ty: FuncType) -> TypeIndex {
+        let group = RecGroup::from(ty);
+        let mut index = 0;
+        for g in self.0.types.iter().flat_map(|t| t.iter()) {
+            if *g == group {
+                return TypeIndex::new(index);
+            }
+            index += g.types.len() as u32;
+        }
+        self.add_rec_group(group)
+    }
+
+    /// Adds a recursion group and returns the index of its first type, the
+    /// others following it. A single `SubType` or `FuncType` makes a group too.
+    pub fn add_rec_group<G: Into<RecGroup>>(&mut self, group: G) -> TypeIndex {
+        let index = self.0.sub_types().count() as u32;
+        self.0.types.get_or_insert_with(Vec::new).push(group.into());
+        TypeIndex::new(index)
+    }
     gen_add!(add_import(import, ImportEntry) -> ImportIndex,
              imports);
     gen_add!(prv, add_function(func, Function) -> FunctionIndex,
@@ -109,6 +168,8 @@ impl ModuleBuilder {
              tables);
     gen_add!(add_memory(memory, MemoryType) -> MemoryIndex,
              memories);
+    gen_add!(add_tag(tag, TagType) -> TagIndex,
+             tags);
     gen_add!(add_global(global, GlobalVariable) -> GlobalIndex,
              globals);
     gen_add!(add_export(export, ExportEntry) -> ExportIndex,
@@ -122,51 +183,189 @@ impl ModuleBuilder {
              codes);
     gen_add!(add_data(data, DataSegment) -> DataIndex,
              data);
+    gen_add!(add_custom_section(section, CustomSection) -> CustomSectionIndex,
+             customs);
+
+    pub fn new_function(&mut self, f: (FuncType, FunctionBody)) -> FunctionIndex {
+        self.try_new_function(f).unwrap_or_else(|e| panic!("{}", e))
+    }
 
-    pub fn new_function(&mut self, (t, body): (FuncType, FunctionBody)) -> FunctionIndex {
+    pub fn try_new_function(&mut self, (t, body): (FuncType, FunctionBody)) -> Result<FunctionIndex, Error> {
+        // before the type is added, so that nothing changes on error
+        self.check_function_code()?;
         let tidx = self.add_type(t);
-        let fidx = self.add_function(Function(tidx));
-        let cidx = self.add_code(body);
-        assert_eq!(*cidx, *fidx);
-        fidx
+        self.add_function_body(Function(tidx), body)
+    }
+
+    /// Like `new_function`, also recording the names from `FunctionBuilder::build_with_names`.
+    pub fn new_function_with_names(&mut self, f: (FuncType, FunctionBody, FunctionNames)) -> FunctionIndex {
+        self.try_new_function_with_names(f).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    pub fn try_new_function_with_names(&mut self,
+                                       (t, body, names): (FuncType, FunctionBody, FunctionNames))
+                                       -> Result<FunctionIndex, Error> {
+        let fidx = self.try_new_function((t, body))?;
+        if let Some(name) = names.name {
+            self.name_function(fidx, name);
+        }
+        if !names.locals.is_empty() {
+            // already renumbered by `FunctionBuilder::try_build_with_names`
+            self.names().locals.insert(fidx.into(), names.locals);
+        }
+        Ok(fidx)
+    }
+
+    fn check_function_code(&self) -> Result<(), Error> {
+        let functions = self.0.functions.as_ref().map_or(0, |f| f.len());
+        let codes = self.0.codes.as_ref().map_or(0, |c| c.len());
+        if functions != codes {
+            return Err(Error::FunctionCodeMismatch {
+                           functions: functions,
+                           codes: codes,
+                       });
+        }
+        Ok(())
+    }
+
+    fn add_function_body(&mut self, f: Function, body: FunctionBody) -> Result<FunctionIndex, Error> {
+        self.check_function_code()?;
+        let fidx = self.add_function(f);
+        self.add_code(body);
+        Ok(fidx)
+    }
+
+    /// Reserves a function of type `t` whose body is given later with
+    /// `define_function`, so that it can be called before it is built.
+    pub fn declare_function(&mut self, t: FuncType) -> FunctionIndex {
+        self.try_declare_function(t).unwrap_or_else(|e| panic!("{}", e))
     }
 
-    pub fn new_data(&mut self, idx: MemoryIndex, offset: Code, data: Vec<u8>) -> DataIndex {
+    pub fn try_declare_function(&mut self, t: FuncType) -> Result<FunctionIndex, Error> {
+        let body = FunctionBody {
+            locals: Vec::new(),
+            code: Code(Vec::new()),
+        };
+        let fidx = self.try_new_function((t, body))?;
+        self.1.insert(fidx);
+        Ok(fidx)
+    }
+
+    /// # Panics
+    /// if `index` was not declared with `declare_function` or is already
+    /// defined.
+    pub fn define_function(&mut self, index: FunctionIndex, body: FunctionBody) {
+        self.try_define_function(index, body).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    pub fn try_define_function(&mut self, index: FunctionIndex, body: FunctionBody) -> Result<(), Error> {
+        if !self.1.remove(&index) {
+            return Err(Error::NotDeclared(index));
+        }
+        if let Some(ref mut codes) = self.0.codes {
+            codes[*index as usize] = body;
+        }
+        Ok(())
+    }
+
+    fn names(&mut self) -> &mut NameSection {
+        self.0.names.get_or_insert_with(NameSection::default)
+    }
+
+    /// Sets the module name emitted in the `name` section.
+    pub fn name<S: Into<String>>(&mut self, name: S) {
+        self.names().module = Some(name.into());
+    }
+
+    /// Sets the debug name of a function. Imported functions can be named
+    /// with the index `function_index_of` returns. Locals are named with
+    /// `FunctionBuilder::name_local`.
+    pub fn name_function<I, S>(&mut self, index: I, name: S)
+        where I: Into<FunctionSpaceIndex>,
+              S: Into<String>
+    {
+        self.names().functions.insert(index.into(), name.into());
+    }
+
+    /// The `offset` is a `Code` ending in `end`, or an `InitExpr` from `InitExprBuilder`.
+    pub fn new_data<I: Into<InitExpr>>(&mut self, idx: MemoryIndex, offset: I, data: Vec<u8>) -> DataIndex {
+        let seg = DataSegment {
+            mode: DataMode::Active {
+                index: idx,
+                offset: offset.into(),
+            },
+            data: data,
+        };
+        self.add_data(seg)
+    }
+
+    /// Adds a data segment that is only copied into memory by `memory.init`.
+    pub fn new_passive_data(&mut self, data: Vec<u8>) -> DataIndex {
         let seg = DataSegment {
-            index: idx,
-            offset: InitExpr(offset),
+            mode: DataMode::Passive,
             data: data,
         };
         self.add_data(seg)
     }
 
-    pub fn new_global(&mut self, ty: GlobalType, init: Code) -> GlobalIndex {
+    /// Adds a memory the atomic instructions can share between threads. Shared memories
+    /// always have a maximum.
+    pub fn new_shared_memory(&mut self, range: Range<u32>) -> MemoryIndex {
+        let memory = MemoryType {
+            limits: ResizableLimits::new(range.start.into()).max(range.end.into()),
+            shared: true,
+            memory64: false,
+        };
+        self.add_memory(memory)
+    }
+
+    /// Adds an exception tag whose exceptions carry values of the parameter types of `ty`.
+    pub fn new_tag(&mut self, ty: FuncType) -> TagIndex {
+        let ty = self.add_type(ty);
+        self.add_tag(TagType { ty: ty })
+    }
+
+    /// The `init` is a `Code` ending in `end`, or an `InitExpr` from `InitExprBuilder`.
+    pub fn new_global<I: Into<InitExpr>>(&mut self, ty: GlobalType, init: I) -> GlobalIndex {
         self.add_global(GlobalVariable {
                             ty: ty,
-                            init: InitExpr(init),
+                            init: init.into(),
                         })
     }
 }
 
+fn resolve_function(index: &mut FunctionSpaceIndex, nimports: u32) {
+    if let InnerFunctionSpaceIndex::Function(ref mut f) = index.0 {
+        f.0 += nimports;
+    }
+}
+
+impl Default for ModuleBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A table of `element`, an `ElemType` or a nullable `RefType`.
 pub trait NewTable<T> {
-    fn new_table(&mut self, element: ElemType, range: T) -> TableIndex;
+    fn new_table<E: Into<RefType>>(&mut self, element: E, range: T) -> TableIndex;
 }
 
 impl NewTable<Range<u32>> for ModuleBuilder {
-    fn new_table(&mut self, element: ElemType, range: Range<u32>) -> TableIndex {
+    fn new_table<E: Into<RefType>>(&mut self, element: E, range: Range<u32>) -> TableIndex {
         let table = TableType {
-            element: element,
-            limits: ResizableLimits::new(range.start).max(range.end),
+            element: element.into(),
+            limits: ResizableLimits::new(range.start.into()).max(range.end.into()),
         };
         self.add_table(table)
     }
 }
 
 impl NewTable<RangeFrom<u32>> for ModuleBuilder {
-    fn new_table(&mut self, element: ElemType, range: RangeFrom<u32>) -> TableIndex {
+    fn new_table<E: Into<RefType>>(&mut self, element: E, range: RangeFrom<u32>) -> TableIndex {
         let table = TableType {
-            element: element,
-            limits: ResizableLimits::new(range.start),
+            element: element.into(),
+            limits: ResizableLimits::new(range.start.into()),
         };
         self.add_table(table)
     }
@@ -178,14 +377,49 @@ pub trait NewMemory<T> {
 
 impl NewMemory<Range<u32>> for ModuleBuilder {
     fn new_memory(&mut self, range: Range<u32>) -> MemoryIndex {
-        let memory = MemoryType { limits: ResizableLimits::new(range.start).max(range.end) };
+        let memory = MemoryType {
+            limits: ResizableLimits::new(range.start.into()).max(range.end.into()),
+            shared: false,
+            memory64: false,
+        };
         self.add_memory(memory)
     }
 }
 
 impl NewMemory<RangeFrom<u32>> for ModuleBuilder {
     fn new_memory(&mut self, range: RangeFrom<u32>) -> MemoryIndex {
-        let memory = MemoryType { limits: ResizableLimits::new(range.start) };
+        let memory = MemoryType {
+            limits: ResizableLimits::new(range.start.into()),
+            shared: false,
+            memory64: false,
+        };
+        self.add_memory(memory)
+    }
+}
+
+/// Memories addressed with `i64`, whose sizes in pages are `u64`.
+pub trait NewMemory64<T> {
+    fn new_memory64(&mut self, range: T) -> MemoryIndex;
+}
+
+impl NewMemory64<Range<u64>> for ModuleBuilder {
+    fn new_memory64(&mut self, range: Range<u64>) -> MemoryIndex {
+        let memory = MemoryType {
+            limits: ResizableLimits::new(range.start).max(range.end),
+            shared: false,
+            memory64: true,
+        };
+        self.add_memory(memory)
+    }
+}
+
+impl NewMemory64<RangeFrom<u64>> for ModuleBuilder {
+    fn new_memory64(&mut self, range: RangeFrom<u64>) -> MemoryIndex {
+        let memory = MemoryType {
+            limits: ResizableLimits::new(range.start),
+            shared: false,
+            memory64: true,
+        };
         self.add_memory(memory)
     }
 }
@@ -213,6 +447,7 @@ gen_export!(FunctionIndex, Function);
 gen_export!(TableIndex, Table);
 gen_export!(MemoryIndex, Memory);
 gen_export!(GlobalIndex, Global);
+gen_export!(TagIndex, Tag);
 
 
 pub trait Import<Ty> {
@@ -243,31 +478,58 @@ gen_import!(TypeIndex, Function);
 gen_import!(TableType, Table);
 gen_import!(MemoryType, Memory);
 gen_import!(GlobalType, Global);
+gen_import!(TagType, Tag);
 
 
 pub trait NewFunction<T> {
-    fn new_function(&mut self, t: T, body: FunctionBody) -> FunctionIndex;
+    fn try_new_function(&mut self, t: T, body: FunctionBody) -> Result<FunctionIndex, Error>;
+
+    fn new_function(&mut self, t: T, body: FunctionBody) -> FunctionIndex {
+        self.try_new_function(t, body).unwrap_or_else(|e| panic!("{}", e))
+    }
 }
 
 impl NewFunction<TypeIndex> for ModuleBuilder {
-    fn new_function(&mut self, t: TypeIndex, body: FunctionBody) -> FunctionIndex {
-        let fidx = self.add_function(Function(t));
-        let cidx = self.add_code(body);
-        assert_eq!(*cidx, *fidx);
-        fidx
+    fn try_new_function(&mut self, t: TypeIndex, body: FunctionBody) -> Result<FunctionIndex, Error> {
+        self.add_function_body(Function(t), body)
     }
 }
 
 impl NewFunction<FuncType> for ModuleBuilder {
-    fn new_function(&mut self, t: FuncType, body: FunctionBody) -> FunctionIndex {
-        self.new_function((t, body))
+    fn try_new_function(&mut self, t: FuncType, body: FunctionBody) -> Result<FunctionIndex, Error> {
+        self.try_new_function((t, body))
     }
 }
 
 
 
+/// A branch target. `CodeBuilder::block`, `loop_`, `if_` and `try_` hand out
+/// labels for the blocks they open, and a plain `u32` is taken as a relative depth.
+/// A label handed out by one builder never matches a block of another.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Label(InnerLabel);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum InnerLabel {
+    Depth(u32),
+    Block(u64),
+}
+
+// shared by all builders so that labels are unique across them
+static NEXT_LABEL: AtomicU64 = AtomicU64::new(0);
+
+impl From<u32> for Label {
+    fn from(depth: u32) -> Self {
+        Label(InnerLabel::Depth(depth))
+    }
+}
+
 pub struct CodeBuilder {
     code: Vec<Op>,
+    // labels of the open blocks, innermost last
+    blocks: Vec<u64>,
+    // the first branch to a label whose block is not open
+    unscoped: Option<usize>,
 }
 
 macro_rules! gen_builder {
@@ -302,40 +564,128 @@ macro_rules! gen_builder {
     };
 }
 
+/// The memory is memory 0 if it is `None`.
 macro_rules! gen_memory_builder {
     ($variant: tt, $fname: ident, $align: expr) => {
-        pub fn $fname(mut self, offset: u32) -> Self {
+        pub fn $fname<M: Into<Option<MemoryIndex>>>(mut self, offset: u64, memory: M) -> Self {
             let imm = ops::MemoryImmediate{
                 flags: $align - 3,
-                offset: offset
+                offset: offset,
+                memory: memory.into().unwrap_or(MemoryIndex::new(0)),
             };
             self.code.push($variant{imm: imm});
             self
         }
     };
 
+    ($variant: tt, $fname: ident, $align: expr, lane) => {
+        pub fn $fname<M: Into<Option<MemoryIndex>>>(mut self, offset: u64, lane: u8, memory: M) -> Self {
+            let imm = ops::MemoryImmediate{
+                flags: $align - 3,
+                offset: offset,
+                memory: memory.into().unwrap_or(MemoryIndex::new(0)),
+            };
+            self.code.push($variant{imm: imm, lane: lane});
+            self
+        }
+    };
 }
 use Op::*;
 
 impl CodeBuilder {
     pub fn new() -> Self {
-        CodeBuilder { code: Vec::new() }
+        CodeBuilder {
+            code: Vec::new(),
+            blocks: Vec::new(),
+            unscoped: None,
+        }
     }
 
+    /// # Panics
+    /// if a branch targets a label outside of its block.
     pub fn build(self) -> Code {
-        Code(self.code)
+        self.try_build().unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    pub fn try_build(self) -> Result<Code, Error> {
+        match self.unscoped {
+            Some(at) => Err(Error::UnscopedLabel { at: at }),
+            None => Ok(Code(self.code)),
+        }
+    }
+
+    fn open(mut self, op: Op) -> (Self, Label) {
+        let label = NEXT_LABEL.fetch_add(1, Ordering::Relaxed);
+        self.blocks.push(label);
+        self.code.push(op);
+        (self, Label(InnerLabel::Block(label)))
+    }
+
+    fn depth<L: Into<Label>>(&mut self, label: L) -> u32 {
+        match label.into().0 {
+            InnerLabel::Depth(depth) => depth,
+            InnerLabel::Block(label) => {
+                match self.blocks.iter().rev().position(|&b| b == label) {
+                    Some(depth) => depth as u32,
+                    None => {
+                        self.unscoped.get_or_insert(self.code.len());
+                        0
+                    }
+                }
+            }
+        }
     }
 
     gen_builder!(Unreachable, unreachable);
     gen_builder!(Nop, nop);
-    gen_builder!(Block { sig: BlockType }, block);
-    gen_builder!(Loop { sig: BlockType }, loop_);
-    gen_builder!(If { sig: BlockType }, if_);
+    pub fn block(self, sig: BlockType) -> (Self, Label) {
+        self.open(Block { sig: sig })
+    }
+    pub fn loop_(self, sig: BlockType) -> (Self, Label) {
+        self.open(Loop { sig: sig })
+    }
+    pub fn if_(self, sig: BlockType) -> (Self, Label) {
+        self.open(If { sig: sig })
+    }
     gen_builder!(Else, else_);
-    gen_builder!(End, end);
-    gen_builder!(Br { depth: u32 }, br);
-    gen_builder!(BrIf { depth: u32 }, br_if);
-    pub fn br_table(mut self, table: Vec<u32>, default: u32) -> Self {
+    /// Opens a block whose exceptions the `catch` and `catch_all` clauses following it
+    /// handle. The label is the one `rethrow` takes in those clauses.
+    pub fn try_(self, sig: BlockType) -> (Self, Label) {
+        self.open(Try { sig: sig })
+    }
+    gen_builder!(Catch[tag: TagIndex], catch);
+    gen_builder!(CatchAll, catch_all);
+    /// Ends the innermost `try` block, rethrowing its exceptions in the block of `label`.
+    pub fn delegate<L: Into<Label>>(mut self, label: L) -> Self {
+        self.blocks.pop();
+        let depth = self.depth(label);
+        self.code.push(Delegate { depth: depth });
+        self
+    }
+    gen_builder!(Throw[tag: TagIndex], throw);
+    pub fn rethrow<L: Into<Label>>(mut self, label: L) -> Self {
+        let depth = self.depth(label);
+        self.code.push(Rethrow { depth: depth });
+        self
+    }
+    pub fn end(mut self) -> Self {
+        self.blocks.pop();
+        self.code.push(End);
+        self
+    }
+    pub fn br<L: Into<Label>>(mut self, label: L) -> Self {
+        let depth = self.depth(label);
+        self.code.push(Br { depth: depth });
+        self
+    }
+    pub fn br_if<L: Into<Label>>(mut self, label: L) -> Self {
+        let depth = self.depth(label);
+        self.code.push(BrIf { depth: depth });
+        self
+    }
+    pub fn br_table<L: Into<Label>>(mut self, table: Vec<L>, default: L) -> Self {
+        let table = table.into_iter().map(|l| self.depth(l)).collect();
+        let default = self.depth(default);
         self.code
             .push(BrTable(ops::BrTarget {
                               table: table,
@@ -348,18 +698,29 @@ impl CodeBuilder {
     gen_builder!(Call { index: FunctionSpaceIndex }, call);
     gen_builder!(CallIndirect {
                      index: TypeIndex,
-                     reserved: bool,
+                     table: TableIndex,
                  },
                  call_indirect);
+    gen_builder!(ReturnCall { index: FunctionSpaceIndex }, return_call);
+    gen_builder!(ReturnCallIndirect {
+                     index: TypeIndex,
+                     table: TableIndex,
+                 },
+                 return_call_indirect);
+    gen_builder!(CallRef { index: TypeIndex }, call_ref);
+    gen_builder!(ReturnCallRef { index: TypeIndex }, return_call_ref);
 
     gen_builder!(Drop, drop);
     gen_builder!(Select, select);
+    gen_builder!(TypedSelect[ty: ValueType], typed_select);
 
     gen_builder!(GetLocal[idx: LocalIndex], get_local);
     gen_builder!(SetLocal[idx: LocalIndex], set_local);
     gen_builder!(TeeLocal[idx: LocalIndex], tee_local);
     gen_builder!(GetGlobal[idx: GlobalIndex], get_global);
     gen_builder!(SetGlobal[idx: GlobalIndex], set_global);
+    gen_builder!(TableGet[idx: TableIndex], table_get);
+    gen_builder!(TableSet[idx: TableIndex], table_set);
 
     // TODO: generate with-flag API too.
     gen_memory_builder!(I32Load, i32_load, 5);
@@ -385,8 +746,38 @@ impl CodeBuilder {
     gen_memory_builder!(I64Store8, i64_store8, 3);
     gen_memory_builder!(I64Store16, i64_store16, 4);
     gen_memory_builder!(I64Store32, i64_store32, 5);
-    gen_builder!(CurrentMemory { reserved: bool }, current_memory);
-    gen_builder!(GrowMemory { reserved: bool }, grow_memory);
+    gen_builder!(CurrentMemory { memory: MemoryIndex }, current_memory);
+    gen_builder!(GrowMemory { memory: MemoryIndex }, grow_memory);
+    gen_builder!(MemoryInit { segment: DataIndex, memory: MemoryIndex }, memory_init);
+    gen_builder!(DataDrop [segment: DataIndex], data_drop);
+    gen_builder!(MemoryCopy { dst: MemoryIndex, src: MemoryIndex }, memory_copy);
+    gen_builder!(MemoryFill { memory: MemoryIndex }, memory_fill);
+    gen_builder!(TableInit { segment: ElementIndex, table: TableIndex }, table_init);
+    gen_builder!(ElemDrop [segment: ElementIndex], elem_drop);
+    gen_builder!(TableCopy { dst: TableIndex, src: TableIndex }, table_copy);
+    gen_builder!(TableGrow[idx: TableIndex], table_grow);
+    gen_builder!(TableSize[idx: TableIndex], table_size);
+    gen_builder!(TableFill[idx: TableIndex], table_fill);
+
+    /// The null reference of `ty`, an `ElemType` or a `HeapType`.
+    pub fn ref_null<H: Into<HeapType>>(mut self, ty: H) -> Self {
+        self.code.push(RefNull(ty.into()));
+        self
+    }
+    gen_builder!(RefIsNull, ref_is_null);
+    gen_builder!(RefFunc[index: FunctionSpaceIndex], ref_func);
+    gen_builder!(RefEq, ref_eq);
+    gen_builder!(RefAsNonNull, ref_as_non_null);
+    pub fn br_on_null<L: Into<Label>>(mut self, label: L) -> Self {
+        let depth = self.depth(label);
+        self.code.push(BrOnNull { depth: depth });
+        self
+    }
+    pub fn br_on_non_null<L: Into<Label>>(mut self, label: L) -> Self {
+        let depth = self.depth(label);
+        self.code.push(BrOnNonNull { depth: depth });
+        self
+    }
 
 
     pub fn constant<C>(mut self, c: C) -> Self
@@ -522,12 +913,451 @@ impl CodeBuilder {
     gen_builder!(I64ReinterpretF64, i64_reinterpret_f64);
     gen_builder!(F32ReinterpretI32, f32_reinterpret_i32);
     gen_builder!(F64ReinterpretI64, f64_reinterpret_i64);
+
+    gen_builder!(I32Extend8S, i32_extend8_s);
+    gen_builder!(I32Extend16S, i32_extend16_s);
+    gen_builder!(I64Extend8S, i64_extend8_s);
+    gen_builder!(I64Extend16S, i64_extend16_s);
+    gen_builder!(I64Extend32S, i64_extend32_s);
+
+    gen_builder!(I32TruncSatSF32, i32_trunc_sat_s_f32);
+    gen_builder!(I32TruncSatUF32, i32_trunc_sat_u_f32);
+    gen_builder!(I32TruncSatSF64, i32_trunc_sat_s_f64);
+    gen_builder!(I32TruncSatUF64, i32_trunc_sat_u_f64);
+    gen_builder!(I64TruncSatSF32, i64_trunc_sat_s_f32);
+    gen_builder!(I64TruncSatUF32, i64_trunc_sat_u_f32);
+    gen_builder!(I64TruncSatSF64, i64_trunc_sat_s_f64);
+    gen_builder!(I64TruncSatUF64, i64_trunc_sat_u_f64);
+
+    gen_memory_builder!(V128Load, v128_load, 7);
+    gen_memory_builder!(V128Load8x8S, v128_load8x8_s, 6);
+    gen_memory_builder!(V128Load8x8U, v128_load8x8_u, 6);
+    gen_memory_builder!(V128Load16x4S, v128_load16x4_s, 6);
+    gen_memory_builder!(V128Load16x4U, v128_load16x4_u, 6);
+    gen_memory_builder!(V128Load32x2S, v128_load32x2_s, 6);
+    gen_memory_builder!(V128Load32x2U, v128_load32x2_u, 6);
+    gen_memory_builder!(V128Load8Splat, v128_load8_splat, 3);
+    gen_memory_builder!(V128Load16Splat, v128_load16_splat, 4);
+    gen_memory_builder!(V128Load32Splat, v128_load32_splat, 5);
+    gen_memory_builder!(V128Load64Splat, v128_load64_splat, 6);
+    gen_memory_builder!(V128Store, v128_store, 7);
+    gen_builder!(V128Const[value: u128], v128_const);
+    gen_builder!(I8x16Shuffle[lanes: [u8; 16]], i8x16_shuffle);
+    gen_builder!(I8x16Swizzle, i8x16_swizzle);
+    gen_builder!(I8x16Splat, i8x16_splat);
+    gen_builder!(I16x8Splat, i16x8_splat);
+    gen_builder!(I32x4Splat, i32x4_splat);
+    gen_builder!(I64x2Splat, i64x2_splat);
+    gen_builder!(F32x4Splat, f32x4_splat);
+    gen_builder!(F64x2Splat, f64x2_splat);
+    gen_builder!(I8x16ExtractLaneS[lane: u8], i8x16_extract_lane_s);
+    gen_builder!(I8x16ExtractLaneU[lane: u8], i8x16_extract_lane_u);
+    gen_builder!(I8x16ReplaceLane[lane: u8], i8x16_replace_lane);
+    gen_builder!(I16x8ExtractLaneS[lane: u8], i16x8_extract_lane_s);
+    gen_builder!(I16x8ExtractLaneU[lane: u8], i16x8_extract_lane_u);
+    gen_builder!(I16x8ReplaceLane[lane: u8], i16x8_replace_lane);
+    gen_builder!(I32x4ExtractLane[lane: u8], i32x4_extract_lane);
+    gen_builder!(I32x4ReplaceLane[lane: u8], i32x4_replace_lane);
+    gen_builder!(I64x2ExtractLane[lane: u8], i64x2_extract_lane);
+    gen_builder!(I64x2ReplaceLane[lane: u8], i64x2_replace_lane);
+    gen_builder!(F32x4ExtractLane[lane: u8], f32x4_extract_lane);
+    gen_builder!(F32x4ReplaceLane[lane: u8], f32x4_replace_lane);
+    gen_builder!(F64x2ExtractLane[lane: u8], f64x2_extract_lane);
+    gen_builder!(F64x2ReplaceLane[lane: u8], f64x2_replace_lane);
+    gen_builder!(I8x16Eq, i8x16_eq);
+    gen_builder!(I8x16Ne, i8x16_ne);
+    gen_builder!(I8x16LtS, i8x16_lt_s);
+    gen_builder!(I8x16LtU, i8x16_lt_u);
+    gen_builder!(I8x16GtS, i8x16_gt_s);
+    gen_builder!(I8x16GtU, i8x16_gt_u);
+    gen_builder!(I8x16LeS, i8x16_le_s);
+    gen_builder!(I8x16LeU, i8x16_le_u);
+    gen_builder!(I8x16GeS, i8x16_ge_s);
+    gen_builder!(I8x16GeU, i8x16_ge_u);
+    gen_builder!(I16x8Eq, i16x8_eq);
+    gen_builder!(I16x8Ne, i16x8_ne);
+    gen_builder!(I16x8LtS, i16x8_lt_s);
+    gen_builder!(I16x8LtU, i16x8_lt_u);
+    gen_builder!(I16x8GtS, i16x8_gt_s);
+    gen_builder!(I16x8GtU, i16x8_gt_u);
+    gen_builder!(I16x8LeS, i16x8_le_s);
+    gen_builder!(I16x8LeU, i16x8_le_u);
+    gen_builder!(I16x8GeS, i16x8_ge_s);
+    gen_builder!(I16x8GeU, i16x8_ge_u);
+    gen_builder!(I32x4Eq, i32x4_eq);
+    gen_builder!(I32x4Ne, i32x4_ne);
+    gen_builder!(I32x4LtS, i32x4_lt_s);
+    gen_builder!(I32x4LtU, i32x4_lt_u);
+    gen_builder!(I32x4GtS, i32x4_gt_s);
+    gen_builder!(I32x4GtU, i32x4_gt_u);
+    gen_builder!(I32x4LeS, i32x4_le_s);
+    gen_builder!(I32x4LeU, i32x4_le_u);
+    gen_builder!(I32x4GeS, i32x4_ge_s);
+    gen_builder!(I32x4GeU, i32x4_ge_u);
+    gen_builder!(F32x4Eq, f32x4_eq);
+    gen_builder!(F32x4Ne, f32x4_ne);
+    gen_builder!(F32x4Lt, f32x4_lt);
+    gen_builder!(F32x4Gt, f32x4_gt);
+    gen_builder!(F32x4Le, f32x4_le);
+    gen_builder!(F32x4Ge, f32x4_ge);
+    gen_builder!(F64x2Eq, f64x2_eq);
+    gen_builder!(F64x2Ne, f64x2_ne);
+    gen_builder!(F64x2Lt, f64x2_lt);
+    gen_builder!(F64x2Gt, f64x2_gt);
+    gen_builder!(F64x2Le, f64x2_le);
+    gen_builder!(F64x2Ge, f64x2_ge);
+    gen_builder!(V128Not, v128_not);
+    gen_builder!(V128And, v128_and);
+    gen_builder!(V128Andnot, v128_andnot);
+    gen_builder!(V128Or, v128_or);
+    gen_builder!(V128Xor, v128_xor);
+    gen_builder!(V128Bitselect, v128_bitselect);
+    gen_builder!(V128AnyTrue, v128_any_true);
+    gen_memory_builder!(V128Load8Lane, v128_load8_lane, 3, lane);
+    gen_memory_builder!(V128Load16Lane, v128_load16_lane, 4, lane);
+    gen_memory_builder!(V128Load32Lane, v128_load32_lane, 5, lane);
+    gen_memory_builder!(V128Load64Lane, v128_load64_lane, 6, lane);
+    gen_memory_builder!(V128Store8Lane, v128_store8_lane, 3, lane);
+    gen_memory_builder!(V128Store16Lane, v128_store16_lane, 4, lane);
+    gen_memory_builder!(V128Store32Lane, v128_store32_lane, 5, lane);
+    gen_memory_builder!(V128Store64Lane, v128_store64_lane, 6, lane);
+    gen_memory_builder!(V128Load32Zero, v128_load32_zero, 5);
+    gen_memory_builder!(V128Load64Zero, v128_load64_zero, 6);
+    gen_builder!(F32x4DemoteF64x2Zero, f32x4_demote_f64x2_zero);
+    gen_builder!(F64x2PromoteLowF32x4, f64x2_promote_low_f32x4);
+    gen_builder!(I8x16Abs, i8x16_abs);
+    gen_builder!(I8x16Neg, i8x16_neg);
+    gen_builder!(I8x16Popcnt, i8x16_popcnt);
+    gen_builder!(I8x16AllTrue, i8x16_all_true);
+    gen_builder!(I8x16Bitmask, i8x16_bitmask);
+    gen_builder!(I8x16NarrowI16x8S, i8x16_narrow_i16x8_s);
+    gen_builder!(I8x16NarrowI16x8U, i8x16_narrow_i16x8_u);
+    gen_builder!(F32x4Ceil, f32x4_ceil);
+    gen_builder!(F32x4Floor, f32x4_floor);
+    gen_builder!(F32x4Trunc, f32x4_trunc);
+    gen_builder!(F32x4Nearest, f32x4_nearest);
+    gen_builder!(I8x16Shl, i8x16_shl);
+    gen_builder!(I8x16ShrS, i8x16_shr_s);
+    gen_builder!(I8x16ShrU, i8x16_shr_u);
+    gen_builder!(I8x16Add, i8x16_add);
+    gen_builder!(I8x16AddSatS, i8x16_add_sat_s);
+    gen_builder!(I8x16AddSatU, i8x16_add_sat_u);
+    gen_builder!(I8x16Sub, i8x16_sub);
+    gen_builder!(I8x16SubSatS, i8x16_sub_sat_s);
+    gen_builder!(I8x16SubSatU, i8x16_sub_sat_u);
+    gen_builder!(F64x2Ceil, f64x2_ceil);
+    gen_builder!(F64x2Floor, f64x2_floor);
+    gen_builder!(I8x16MinS, i8x16_min_s);
+    gen_builder!(I8x16MinU, i8x16_min_u);
+    gen_builder!(I8x16MaxS, i8x16_max_s);
+    gen_builder!(I8x16MaxU, i8x16_max_u);
+    gen_builder!(F64x2Trunc, f64x2_trunc);
+    gen_builder!(I8x16AvgrU, i8x16_avgr_u);
+    gen_builder!(I16x8ExtaddPairwiseI8x16S, i16x8_extadd_pairwise_i8x16_s);
+    gen_builder!(I16x8ExtaddPairwiseI8x16U, i16x8_extadd_pairwise_i8x16_u);
+    gen_builder!(I32x4ExtaddPairwiseI16x8S, i32x4_extadd_pairwise_i16x8_s);
+    gen_builder!(I32x4ExtaddPairwiseI16x8U, i32x4_extadd_pairwise_i16x8_u);
+    gen_builder!(I16x8Abs, i16x8_abs);
+    gen_builder!(I16x8Neg, i16x8_neg);
+    gen_builder!(I16x8Q15mulrSatS, i16x8_q15mulr_sat_s);
+    gen_builder!(I16x8AllTrue, i16x8_all_true);
+    gen_builder!(I16x8Bitmask, i16x8_bitmask);
+    gen_builder!(I16x8NarrowI32x4S, i16x8_narrow_i32x4_s);
+    gen_builder!(I16x8NarrowI32x4U, i16x8_narrow_i32x4_u);
+    gen_builder!(I16x8ExtendLowI8x16S, i16x8_extend_low_i8x16_s);
+    gen_builder!(I16x8ExtendHighI8x16S, i16x8_extend_high_i8x16_s);
+    gen_builder!(I16x8ExtendLowI8x16U, i16x8_extend_low_i8x16_u);
+    gen_builder!(I16x8ExtendHighI8x16U, i16x8_extend_high_i8x16_u);
+    gen_builder!(I16x8Shl, i16x8_shl);
+    gen_builder!(I16x8ShrS, i16x8_shr_s);
+    gen_builder!(I16x8ShrU, i16x8_shr_u);
+    gen_builder!(I16x8Add, i16x8_add);
+    gen_builder!(I16x8AddSatS, i16x8_add_sat_s);
+    gen_builder!(I16x8AddSatU, i16x8_add_sat_u);
+    gen_builder!(I16x8Sub, i16x8_sub);
+    gen_builder!(I16x8SubSatS, i16x8_sub_sat_s);
+    gen_builder!(I16x8SubSatU, i16x8_sub_sat_u);
+    gen_builder!(F64x2Nearest, f64x2_nearest);
+    gen_builder!(I16x8Mul, i16x8_mul);
+    gen_builder!(I16x8MinS, i16x8_min_s);
+    gen_builder!(I16x8MinU, i16x8_min_u);
+    gen_builder!(I16x8MaxS, i16x8_max_s);
+    gen_builder!(I16x8MaxU, i16x8_max_u);
+    gen_builder!(I16x8AvgrU, i16x8_avgr_u);
+    gen_builder!(I16x8ExtmulLowI8x16S, i16x8_extmul_low_i8x16_s);
+    gen_builder!(I16x8ExtmulHighI8x16S, i16x8_extmul_high_i8x16_s);
+    gen_builder!(I16x8ExtmulLowI8x16U, i16x8_extmul_low_i8x16_u);
+    gen_builder!(I16x8ExtmulHighI8x16U, i16x8_extmul_high_i8x16_u);
+    gen_builder!(I32x4Abs, i32x4_abs);
+    gen_builder!(I32x4Neg, i32x4_neg);
+    gen_builder!(I32x4AllTrue, i32x4_all_true);
+    gen_builder!(I32x4Bitmask, i32x4_bitmask);
+    gen_builder!(I32x4ExtendLowI16x8S, i32x4_extend_low_i16x8_s);
+    gen_builder!(I32x4ExtendHighI16x8S, i32x4_extend_high_i16x8_s);
+    gen_builder!(I32x4ExtendLowI16x8U, i32x4_extend_low_i16x8_u);
+    gen_builder!(I32x4ExtendHighI16x8U, i32x4_extend_high_i16x8_u);
+    gen_builder!(I32x4Shl, i32x4_shl);
+    gen_builder!(I32x4ShrS, i32x4_shr_s);
+    gen_builder!(I32x4ShrU, i32x4_shr_u);
+    gen_builder!(I32x4Add, i32x4_add);
+    gen_builder!(I32x4Sub, i32x4_sub);
+    gen_builder!(I32x4Mul, i32x4_mul);
+    gen_builder!(I32x4MinS, i32x4_min_s);
+    gen_builder!(I32x4MinU, i32x4_min_u);
+    gen_builder!(I32x4MaxS, i32x4_max_s);
+    gen_builder!(I32x4MaxU, i32x4_max_u);
+    gen_builder!(I32x4DotI16x8S, i32x4_dot_i16x8_s);
+    gen_builder!(I32x4ExtmulLowI16x8S, i32x4_extmul_low_i16x8_s);
+    gen_builder!(I32x4ExtmulHighI16x8S, i32x4_extmul_high_i16x8_s);
+    gen_builder!(I32x4ExtmulLowI16x8U, i32x4_extmul_low_i16x8_u);
+    gen_builder!(I32x4ExtmulHighI16x8U, i32x4_extmul_high_i16x8_u);
+    gen_builder!(I64x2Abs, i64x2_abs);
+    gen_builder!(I64x2Neg, i64x2_neg);
+    gen_builder!(I64x2AllTrue, i64x2_all_true);
+    gen_builder!(I64x2Bitmask, i64x2_bitmask);
+    gen_builder!(I64x2ExtendLowI32x4S, i64x2_extend_low_i32x4_s);
+    gen_builder!(I64x2ExtendHighI32x4S, i64x2_extend_high_i32x4_s);
+    gen_builder!(I64x2ExtendLowI32x4U, i64x2_extend_low_i32x4_u);
+    gen_builder!(I64x2ExtendHighI32x4U, i64x2_extend_high_i32x4_u);
+    gen_builder!(I64x2Shl, i64x2_shl);
+    gen_builder!(I64x2ShrS, i64x2_shr_s);
+    gen_builder!(I64x2ShrU, i64x2_shr_u);
+    gen_builder!(I64x2Add, i64x2_add);
+    gen_builder!(I64x2Sub, i64x2_sub);
+    gen_builder!(I64x2Mul, i64x2_mul);
+    gen_builder!(I64x2Eq, i64x2_eq);
+    gen_builder!(I64x2Ne, i64x2_ne);
+    gen_builder!(I64x2LtS, i64x2_lt_s);
+    gen_builder!(I64x2GtS, i64x2_gt_s);
+    gen_builder!(I64x2LeS, i64x2_le_s);
+    gen_builder!(I64x2GeS, i64x2_ge_s);
+    gen_builder!(I64x2ExtmulLowI32x4S, i64x2_extmul_low_i32x4_s);
+    gen_builder!(I64x2ExtmulHighI32x4S, i64x2_extmul_high_i32x4_s);
+    gen_builder!(I64x2ExtmulLowI32x4U, i64x2_extmul_low_i32x4_u);
+    gen_builder!(I64x2ExtmulHighI32x4U, i64x2_extmul_high_i32x4_u);
+    gen_builder!(F32x4Abs, f32x4_abs);
+    gen_builder!(F32x4Neg, f32x4_neg);
+    gen_builder!(F32x4Sqrt, f32x4_sqrt);
+    gen_builder!(F32x4Add, f32x4_add);
+    gen_builder!(F32x4Sub, f32x4_sub);
+    gen_builder!(F32x4Mul, f32x4_mul);
+    gen_builder!(F32x4Div, f32x4_div);
+    gen_builder!(F32x4Min, f32x4_min);
+    gen_builder!(F32x4Max, f32x4_max);
+    gen_builder!(F32x4Pmin, f32x4_pmin);
+    gen_builder!(F32x4Pmax, f32x4_pmax);
+    gen_builder!(F64x2Abs, f64x2_abs);
+    gen_builder!(F64x2Neg, f64x2_neg);
+    gen_builder!(F64x2Sqrt, f64x2_sqrt);
+    gen_builder!(F64x2Add, f64x2_add);
+    gen_builder!(F64x2Sub, f64x2_sub);
+    gen_builder!(F64x2Mul, f64x2_mul);
+    gen_builder!(F64x2Div, f64x2_div);
+    gen_builder!(F64x2Min, f64x2_min);
+    gen_builder!(F64x2Max, f64x2_max);
+    gen_builder!(F64x2Pmin, f64x2_pmin);
+    gen_builder!(F64x2Pmax, f64x2_pmax);
+    gen_builder!(I32x4TruncSatF32x4S, i32x4_trunc_sat_f32x4_s);
+    gen_builder!(I32x4TruncSatF32x4U, i32x4_trunc_sat_f32x4_u);
+    gen_builder!(F32x4ConvertI32x4S, f32x4_convert_i32x4_s);
+    gen_builder!(F32x4ConvertI32x4U, f32x4_convert_i32x4_u);
+    gen_builder!(I32x4TruncSatF64x2SZero, i32x4_trunc_sat_f64x2_s_zero);
+    gen_builder!(I32x4TruncSatF64x2UZero, i32x4_trunc_sat_f64x2_u_zero);
+    gen_builder!(F64x2ConvertLowI32x4S, f64x2_convert_low_i32x4_s);
+    gen_builder!(F64x2ConvertLowI32x4U, f64x2_convert_low_i32x4_u);
+    gen_memory_builder!(MemoryAtomicNotify, memory_atomic_notify, 5);
+    gen_memory_builder!(MemoryAtomicWait32, memory_atomic_wait32, 5);
+    gen_memory_builder!(MemoryAtomicWait64, memory_atomic_wait64, 6);
+    /// The reserved byte is always written as 0.
+    pub fn atomic_fence(mut self) -> Self {
+        self.code.push(AtomicFence { reserved: false });
+        self
+    }
+    gen_memory_builder!(I32AtomicLoad, i32_atomic_load, 5);
+    gen_memory_builder!(I64AtomicLoad, i64_atomic_load, 6);
+    gen_memory_builder!(I32AtomicLoad8U, i32_atomic_load8_u, 3);
+    gen_memory_builder!(I32AtomicLoad16U, i32_atomic_load16_u, 4);
+    gen_memory_builder!(I64AtomicLoad8U, i64_atomic_load8_u, 3);
+    gen_memory_builder!(I64AtomicLoad16U, i64_atomic_load16_u, 4);
+    gen_memory_builder!(I64AtomicLoad32U, i64_atomic_load32_u, 5);
+    gen_memory_builder!(I32AtomicStore, i32_atomic_store, 5);
+    gen_memory_builder!(I64AtomicStore, i64_atomic_store, 6);
+    gen_memory_builder!(I32AtomicStore8, i32_atomic_store8, 3);
+    gen_memory_builder!(I32AtomicStore16, i32_atomic_store16, 4);
+    gen_memory_builder!(I64AtomicStore8, i64_atomic_store8, 3);
+    gen_memory_builder!(I64AtomicStore16, i64_atomic_store16, 4);
+    gen_memory_builder!(I64AtomicStore32, i64_atomic_store32, 5);
+    gen_memory_builder!(I32AtomicRmwAdd, i32_atomic_rmw_add, 5);
+    gen_memory_builder!(I64AtomicRmwAdd, i64_atomic_rmw_add, 6);
+    gen_memory_builder!(I32AtomicRmw8AddU, i32_atomic_rmw8_add_u, 3);
+    gen_memory_builder!(I32AtomicRmw16AddU, i32_atomic_rmw16_add_u, 4);
+    gen_memory_builder!(I64AtomicRmw8AddU, i64_atomic_rmw8_add_u, 3);
+    gen_memory_builder!(I64AtomicRmw16AddU, i64_atomic_rmw16_add_u, 4);
+    gen_memory_builder!(I64AtomicRmw32AddU, i64_atomic_rmw32_add_u, 5);
+    gen_memory_builder!(I32AtomicRmwSub, i32_atomic_rmw_sub, 5);
+    gen_memory_builder!(I64AtomicRmwSub, i64_atomic_rmw_sub, 6);
+    gen_memory_builder!(I32AtomicRmw8SubU, i32_atomic_rmw8_sub_u, 3);
+    gen_memory_builder!(I32AtomicRmw16SubU, i32_atomic_rmw16_sub_u, 4);
+    gen_memory_builder!(I64AtomicRmw8SubU, i64_atomic_rmw8_sub_u, 3);
+    gen_memory_builder!(I64AtomicRmw16SubU, i64_atomic_rmw16_sub_u, 4);
+    gen_memory_builder!(I64AtomicRmw32SubU, i64_atomic_rmw32_sub_u, 5);
+    gen_memory_builder!(I32AtomicRmwAnd, i32_atomic_rmw_and, 5);
+    gen_memory_builder!(I64AtomicRmwAnd, i64_atomic_rmw_and, 6);
+    gen_memory_builder!(I32AtomicRmw8AndU, i32_atomic_rmw8_and_u, 3);
+    gen_memory_builder!(I32AtomicRmw16AndU, i32_atomic_rmw16_and_u, 4);
+    gen_memory_builder!(I64AtomicRmw8AndU, i64_atomic_rmw8_and_u, 3);
+    gen_memory_builder!(I64AtomicRmw16AndU, i64_atomic_rmw16_and_u, 4);
+    gen_memory_builder!(I64AtomicRmw32AndU, i64_atomic_rmw32_and_u, 5);
+    gen_memory_builder!(I32AtomicRmwOr, i32_atomic_rmw_or, 5);
+    gen_memory_builder!(I64AtomicRmwOr, i64_atomic_rmw_or, 6);
+    gen_memory_builder!(I32AtomicRmw8OrU, i32_atomic_rmw8_or_u, 3);
+    gen_memory_builder!(I32AtomicRmw16OrU, i32_atomic_rmw16_or_u, 4);
+    gen_memory_builder!(I64AtomicRmw8OrU, i64_atomic_rmw8_or_u, 3);
+    gen_memory_builder!(I64AtomicRmw16OrU, i64_atomic_rmw16_or_u, 4);
+    gen_memory_builder!(I64AtomicRmw32OrU, i64_atomic_rmw32_or_u, 5);
+    gen_memory_builder!(I32AtomicRmwXor, i32_atomic_rmw_xor, 5);
+    gen_memory_builder!(I64AtomicRmwXor, i64_atomic_rmw_xor, 6);
+    gen_memory_builder!(I32AtomicRmw8XorU, i32_atomic_rmw8_xor_u, 3);
+    gen_memory_builder!(I32AtomicRmw16XorU, i32_atomic_rmw16_xor_u, 4);
+    gen_memory_builder!(I64AtomicRmw8XorU, i64_atomic_rmw8_xor_u, 3);
+    gen_memory_builder!(I64AtomicRmw16XorU, i64_atomic_rmw16_xor_u, 4);
+    gen_memory_builder!(I64AtomicRmw32XorU, i64_atomic_rmw32_xor_u, 5);
+    gen_memory_builder!(I32AtomicRmwXchg, i32_atomic_rmw_xchg, 5);
+    gen_memory_builder!(I64AtomicRmwXchg, i64_atomic_rmw_xchg, 6);
+    gen_memory_builder!(I32AtomicRmw8XchgU, i32_atomic_rmw8_xchg_u, 3);
+    gen_memory_builder!(I32AtomicRmw16XchgU, i32_atomic_rmw16_xchg_u, 4);
+    gen_memory_builder!(I64AtomicRmw8XchgU, i64_atomic_rmw8_xchg_u, 3);
+    gen_memory_builder!(I64AtomicRmw16XchgU, i64_atomic_rmw16_xchg_u, 4);
+    gen_memory_builder!(I64AtomicRmw32XchgU, i64_atomic_rmw32_xchg_u, 5);
+    gen_memory_builder!(I32AtomicRmwCmpxchg, i32_atomic_rmw_cmpxchg, 5);
+    gen_memory_builder!(I64AtomicRmwCmpxchg, i64_atomic_rmw_cmpxchg, 6);
+    gen_memory_builder!(I32AtomicRmw8CmpxchgU, i32_atomic_rmw8_cmpxchg_u, 3);
+    gen_memory_builder!(I32AtomicRmw16CmpxchgU, i32_atomic_rmw16_cmpxchg_u, 4);
+    gen_memory_builder!(I64AtomicRmw8CmpxchgU, i64_atomic_rmw8_cmpxchg_u, 3);
+    gen_memory_builder!(I64AtomicRmw16CmpxchgU, i64_atomic_rmw16_cmpxchg_u, 4);
+    gen_memory_builder!(I64AtomicRmw32CmpxchgU, i64_atomic_rmw32_cmpxchg_u, 5);
+
+    gen_builder!(StructNew { index: TypeIndex }, struct_new);
+    gen_builder!(StructNewDefault { index: TypeIndex }, struct_new_default);
+    gen_builder!(StructGet { index: TypeIndex, field: u32 }, struct_get);
+    gen_builder!(StructGetS { index: TypeIndex, field: u32 }, struct_get_s);
+    gen_builder!(StructGetU { index: TypeIndex, field: u32 }, struct_get_u);
+    gen_builder!(StructSet { index: TypeIndex, field: u32 }, struct_set);
+    gen_builder!(ArrayNew { index: TypeIndex }, array_new);
+    gen_builder!(ArrayNewDefault { index: TypeIndex }, array_new_default);
+    gen_builder!(ArrayNewFixed { index: TypeIndex, len: u32 }, array_new_fixed);
+    gen_builder!(ArrayNewData { index: TypeIndex, segment: DataIndex }, array_new_data);
+    gen_builder!(ArrayNewElem { index: TypeIndex, segment: ElementIndex }, array_new_elem);
+    gen_builder!(ArrayGet { index: TypeIndex }, array_get);
+    gen_builder!(ArrayGetS { index: TypeIndex }, array_get_s);
+    gen_builder!(ArrayGetU { index: TypeIndex }, array_get_u);
+    gen_builder!(ArraySet { index: TypeIndex }, array_set);
+    gen_builder!(ArrayLen, array_len);
+    gen_builder!(ArrayFill { index: TypeIndex }, array_fill);
+    gen_builder!(ArrayCopy { dst: TypeIndex, src: TypeIndex }, array_copy);
+    gen_builder!(ArrayInitData { index: TypeIndex, segment: DataIndex }, array_init_data);
+    gen_builder!(ArrayInitElem { index: TypeIndex, segment: ElementIndex }, array_init_elem);
+    gen_builder!(RefTest[ty: RefType], ref_test);
+    gen_builder!(RefCast[ty: RefType], ref_cast);
+    /// Branches to `label` with the reference if it is of type `to`. `from` is its type on the stack.
+    pub fn br_on_cast<L: Into<Label>>(mut self, label: L, from: RefType, to: RefType) -> Self {
+        let depth = self.depth(label);
+        self.code.push(BrOnCast {
+                           depth: depth,
+                           from: from,
+                           to: to,
+                       });
+        self
+    }
+    pub fn br_on_cast_fail<L: Into<Label>>(mut self, label: L, from: RefType, to: RefType) -> Self {
+        let depth = self.depth(label);
+        self.code.push(BrOnCastFail {
+                           depth: depth,
+                           from: from,
+                           to: to,
+                       });
+        self
+    }
+    gen_builder!(AnyConvertExtern, any_convert_extern);
+    gen_builder!(ExternConvertAny, extern_convert_any);
+    gen_builder!(RefI31, ref_i31);
+    gen_builder!(I31GetS, i31_get_s);
+    gen_builder!(I31GetU, i31_get_u);
+}
+
+impl Default for CodeBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Builds the constant expression of a global or a segment offset, offering only the
+/// instructions allowed there.
+pub struct InitExprBuilder {
+    code: Vec<Op>,
+}
+
+impl InitExprBuilder {
+    pub fn new() -> Self {
+        InitExprBuilder { code: Vec::new() }
+    }
+
+    /// Closes the expression with `end`.
+    pub fn build(mut self) -> InitExpr {
+        self.code.push(End);
+        InitExpr(Code(self.code))
+    }
+
+    pub fn constant<C>(mut self, c: C) -> Self
+        where Op: From<C>
+    {
+        self.code.push(Op::from(c));
+        self
+    }
+
+    // only immutable globals, imported or defined before, may be read
+    gen_builder!(GetGlobal[idx: GlobalIndex], get_global);
+    /// The null reference of `ty`, an `ElemType` or a `HeapType`.
+    pub fn ref_null<H: Into<HeapType>>(mut self, ty: H) -> Self {
+        self.code.push(RefNull(ty.into()));
+        self
+    }
+    gen_builder!(RefFunc[index: FunctionSpaceIndex], ref_func);
+    // structs and arrays allocated once, at instantiation
+    gen_builder!(StructNew { index: TypeIndex }, struct_new);
+    gen_builder!(StructNewDefault { index: TypeIndex }, struct_new_default);
+    gen_builder!(ArrayNew { index: TypeIndex }, array_new);
+    gen_builder!(ArrayNewDefault { index: TypeIndex }, array_new_default);
+    gen_builder!(ArrayNewFixed { index: TypeIndex, len: u32 }, array_new_fixed);
+    gen_builder!(AnyConvertExtern, any_convert_extern);
+    gen_builder!(ExternConvertAny, extern_convert_any);
+    gen_builder!(RefI31, ref_i31);
+    gen_builder!(I32Add, i32_add);
+    gen_builder!(I32Sub, i32_sub);
+    gen_builder!(I32Mul, i32_mul);
+    gen_builder!(I64Add, i64_add);
+    gen_builder!(I64Sub, i64_sub);
+    gen_builder!(I64Mul, i64_mul);
+}
+
+impl Default for InitExprBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The debug names given to a `FunctionBuilder`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FunctionNames {
+    pub name: Option<String>,
+    pub locals: BTreeMap<LocalIndex, String>,
 }
 
 pub struct FunctionBuilder {
     ty: FuncType,
     args: Vec<LocalIndex>,
     locals: Vec<ValueType>,
+    names: FunctionNames,
     cb: CodeBuilder,
 }
 
@@ -537,26 +1367,98 @@ impl FunctionBuilder {
         let args = (0..ty.params.len())
             .map(|i| LocalIndex::new(i as u32))
             .collect();
-        let fb = FunctionBuilder {
+        FunctionBuilder {
             ty: ty,
             args: args,
             locals: Vec::new(),
+            names: FunctionNames::default(),
             cb: CodeBuilder::new(),
-        };
-        fb
+        }
     }
 
+    /// # Panics
+    /// if a branch targets a label outside of its block.
     pub fn build(self) -> (FuncType, FunctionBody) {
-        // TODO: compact local entry
-        let locals = self.locals
+        self.try_build().unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Groups the locals by type, in the order each type first appears, and
+    /// renumbers the local instructions to match.
+    pub fn try_build(self) -> Result<(FuncType, FunctionBody), Error> {
+        let (ty, body, _) = self.try_build_with_names()?;
+        Ok((ty, body))
+    }
+
+    /// # Panics
+    /// if a branch targets a label outside of its block.
+    pub fn build_with_names(self) -> (FuncType, FunctionBody, FunctionNames) {
+        self.try_build_with_names().unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Like `try_build`, also returning the names given to the function and
+    /// its locals, renumbered as the locals are.
+    pub fn try_build_with_names(self) -> Result<(FuncType, FunctionBody, FunctionNames), Error> {
+        let mut locals: Vec<LocalEntry> = Vec::new();
+        for ty in self.locals.iter() {
+            match locals.iter_mut().find(|e| e.ty == *ty) {
+                Some(entry) => entry.count += 1,
+                None => locals.push(LocalEntry { count: 1, ty: *ty }),
+            }
+        }
+
+        let nparams = self.ty.params.len() as u32;
+        // the next free index of each entry
+        let mut next = Vec::with_capacity(locals.len());
+        let mut start = nparams;
+        for entry in locals.iter() {
+            next.push(start);
+            start += entry.count;
+        }
+        let remap = self.locals
+            .iter()
+            .map(|ty| {
+                     let i = locals.iter().position(|e| e.ty == *ty).unwrap();
+                     next[i] += 1;
+                     next[i] - 1
+                 })
+            .collect::<Vec<_>>();
+        // parameters and out of range indices are left as they are
+        let renumber = |idx: LocalIndex| {
+            idx.checked_sub(nparams)
+                .and_then(|i| remap.get(i as usize))
+                .map_or(idx, |&i| LocalIndex::new(i))
+        };
+
+        let mut code = self.cb.try_build()?;
+        for op in code.0.iter_mut() {
+            match *op {
+                GetLocal(ref mut idx) |
+                SetLocal(ref mut idx) |
+                TeeLocal(ref mut idx) => *idx = renumber(*idx),
+                _ => (),
+            }
+        }
+        let mut names = self.names;
+        names.locals = names.locals
             .into_iter()
-            .map(|l| LocalEntry { count: 1, ty: l })
+            .map(|(idx, name)| (renumber(idx), name))
             .collect();
+
         let body = FunctionBody {
             locals: locals,
-            code: self.cb.build(),
+            code: code,
         };
-        (self.ty, body)
+        Ok((self.ty, body, names))
+    }
+
+    pub fn name<S: Into<String>>(mut self, name: S) -> Self {
+        self.names.name = Some(name.into());
+        self
+    }
+
+    /// Names a parameter or a local returned by `new_local`.
+    pub fn name_local<S: Into<String>>(&mut self, idx: LocalIndex, name: S) {
+        self.names.locals.insert(idx, name.into());
     }
 
     pub fn new_local(&mut self, ty: ValueType) -> LocalIndex {
@@ -601,16 +1503,335 @@ macro_rules! ty_vec {
 
 #[macro_export]
 macro_rules! funtype {
+    (($($params: tt)*) -> ($($results: tt)*)) => {
+        FuncType {
+            params: ty_vec!($($params)*),
+            results: ty_vec!($($results)*),
+        }
+    };
     (($($params: tt)*) -> $ret: tt) => {
         FuncType {
             params: ty_vec!($($params)*),
-            ret: Some(ty!($ret))
+            results: vec!(ty!($ret)),
         }
     };
     (($($params: tt)*)) => {
         FuncType {
             params: ty_vec!($($params)*),
-            ret: None,
+            results: Vec::new(),
         }
     };
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use Dump;
+
+    fn ops(code: Code) -> String {
+        format!("{:?}", code.0)
+    }
+
+    fn empty_body() -> FunctionBody {
+        FunctionBody {
+            locals: Vec::new(),
+            code: Code(Vec::new()),
+        }
+    }
+
+    #[test]
+    fn function_names() {
+        let mut fb = FunctionBuilder::new(funtype!((i32))).name("f");
+        let x = fb.new_local(ValueType::I64);
+        fb.name_local(x, "x");
+        let (_, _, names) = fb.build_with_names();
+        assert_eq!(names.name, Some("f".to_string()));
+        assert_eq!(names.locals.into_iter().collect::<Vec<_>>(),
+                   [(LocalIndex::new(1), "x".to_string())]);
+
+        // `build` leaves the names out
+        let mut mb = ModuleBuilder::new();
+        mb.new_function(FunctionBuilder::new(funtype!(())).name("f").build());
+        assert!(mb.build().names.is_none());
+
+        // a function named before an import is added still gets its name
+        let mut mb = ModuleBuilder::new();
+        let f = mb.new_function_with_names(FunctionBuilder::new(funtype!(())).name("f").build_with_names());
+        let ty = mb.add_type(funtype!(()));
+        mb.import("env", "g", ty);
+        let names = mb.build().names.unwrap();
+        assert_eq!(names.functions.into_iter().collect::<Vec<_>>(),
+                   [(FunctionIndex::new(1).into(), "f".to_string())]);
+        assert_eq!(*f, 0);
+    }
+
+    #[test]
+    fn atomic_fence() {
+        let mut mb = ModuleBuilder::new();
+        let body = FunctionBody {
+            locals: Vec::new(),
+            code: CodeBuilder::new().atomic_fence().build(),
+        };
+        NewFunction::new_function(&mut mb, funtype!(()), body);
+        let mut buf = Vec::new();
+        mb.build().dump(&mut buf);
+        // the body: no locals, `atomic.fence` with a zero reserved byte and `end`
+        assert!(buf.ends_with(&[0x00, 0xfe, 0x03, 0x00, 0x0b]));
+    }
+
+    #[test]
+    fn init_expr_builder() {
+        let expr = InitExprBuilder::new()
+            .get_global(GlobalIndex::new(0))
+            .constant(2i64)
+            .i64_sub()
+            .constant(3i64)
+            .i64_mul()
+            .build();
+        assert_eq!(format!("{:?}", expr),
+                   format!("{:?}",
+                           InitExpr(Code(vec![GetGlobal(GlobalIndex::new(0)),
+                                              I64Const(2),
+                                              I64Sub,
+                                              I64Const(3),
+                                              I64Mul,
+                                              End]))));
+
+        // `new_global` and `new_data` take a built expression or a `Code`
+        let mut mb = ModuleBuilder::new();
+        let ty = GlobalType {
+            content: ValueType::I64,
+            mutable: false,
+        };
+        mb.new_global(ty.clone(), InitExprBuilder::new().constant(1i64).build());
+        mb.new_global(ty, Code(vec![I64Const(1), End]));
+        let module = mb.build();
+        let globals = module.globals.unwrap();
+        assert_eq!(format!("{:?}", globals[0].init), format!("{:?}", globals[1].init));
+    }
+
+    #[test]
+    fn nested_labels() {
+        let (code, outer) = CodeBuilder::new().block(BlockType::Empty);
+        let (code, inner) = code.loop_(BlockType::Empty);
+        let (code, _) = code.br_if(outer).br(inner).if_(BlockType::Empty);
+        let code = code.br_table(vec![inner, outer], 0.into()).end().end().br(outer).end();
+        let expected = CodeBuilder::new()
+            .block(BlockType::Empty)
+            .0
+            .loop_(BlockType::Empty)
+            .0
+            .br_if(1)
+            .br(0)
+            .if_(BlockType::Empty)
+            .0
+            .br_table(vec![1, 2], 0)
+            .end()
+            .end()
+            .br(0)
+            .end();
+        assert_eq!(ops(code.build()), ops(expected.build()));
+    }
+
+    #[test]
+    #[should_panic(expected = "the branch at instruction 3 targets a label outside of its block")]
+    fn unscoped_label() {
+        let (code, label) = CodeBuilder::new().nop().block(BlockType::Empty);
+        code.end().br(label).build();
+    }
+
+    #[test]
+    #[should_panic(expected = "the branch at instruction 1 targets a label outside of its block")]
+    fn foreign_label() {
+        // both builders open their first block, which must not make the labels interchangeable
+        let (_, foreign) = CodeBuilder::new().block(BlockType::Empty);
+        let (code, _) = CodeBuilder::new().block(BlockType::Empty);
+        code.br(foreign).end().build();
+    }
+
+    #[test]
+    fn declare_then_define() {
+        let mut mb = ModuleBuilder::new();
+        let even = mb.declare_function(funtype!((i32) -> i32));
+        let odd = mb.new_function(FunctionBuilder::new(funtype!((i32) -> i32))
+            .code(|cb, args| {
+                cb.get_local(args[0])
+                    .constant(1i32)
+                    .i32_sub()
+                    .call(even.into())
+            })
+            .build());
+        let (_, body) = FunctionBuilder::new(funtype!((i32) -> i32))
+            .code(|cb, args| {
+                cb.get_local(args[0])
+                    .i32_eqz()
+                    .if_(BlockType::Value(ValueType::I32))
+                    .0
+                    .constant(1i32)
+                    .else_()
+                    .get_local(args[0])
+                    .constant(1i32)
+                    .i32_sub()
+                    .call(odd.into())
+                    .end()
+            })
+            .build();
+        mb.define_function(even, body);
+        assert_eq!((*even, *odd), (0, 1));
+        // the declared body is empty, which would not validate
+        mb.build().validate().unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = "function 0 was declared but never defined")]
+    fn undefined_function() {
+        let mut mb = ModuleBuilder::new();
+        mb.declare_function(funtype!(()));
+        mb.build();
+    }
+
+    #[test]
+    #[should_panic(expected = "function 0 is not waiting for a body")]
+    fn defined_twice() {
+        let mut mb = ModuleBuilder::new();
+        let f = mb.declare_function(funtype!(()));
+        let (_, body) = FunctionBuilder::new(funtype!(())).build();
+        mb.define_function(f, body.clone());
+        mb.define_function(f, body);
+    }
+
+    #[test]
+    fn interned_types() {
+        let mut mb = ModuleBuilder::new();
+        let unary = mb.add_type(funtype!((i32) -> i32));
+        let log = mb.add_type(funtype!((i32)));
+        assert_eq!(mb.add_type(funtype!((i32) -> i32)), unary);
+        mb.import("env", "log", log);
+        let same = mb.add_type(funtype!((i32)));
+        mb.import("env", "twice", same);
+        let f = mb.new_function(FunctionBuilder::new(funtype!((i32) -> i32)).build());
+        NewFunction::new_function(&mut mb, log, FunctionBuilder::new(funtype!((i32))).build().1);
+        let module = mb.build();
+        assert_eq!(module.types.as_ref().unwrap().len(), 2);
+        let imported: Vec<_> = module.imports
+            .unwrap()
+            .iter()
+            .map(|i| match i.kind {
+                     ImportKind::Function(t) => t,
+                     _ => unreachable!(),
+                 })
+            .collect();
+        assert_eq!(imported, [log, log]);
+        let functions: Vec<_> = module.functions.unwrap().iter().map(|f| f.0).collect();
+        assert_eq!(functions, [unary, log]);
+        assert_eq!(*f, 0);
+    }
+
+    #[test]
+    fn grouped_locals() {
+        let mut fb = FunctionBuilder::new(funtype!((i64)));
+        let locals = fb.new_locals(vec![ValueType::I32, ValueType::F64, ValueType::I32]);
+        fb.name_local(LocalIndex::new(0), "p");
+        for (&local, name) in locals.iter().zip(["a", "b", "c"].iter()) {
+            fb.name_local(local, *name);
+        }
+        let (_, body, names) = fb.code(|cb, _| {
+                cb.get_local(locals[2])
+                    .set_local(locals[0])
+                    .get_local(locals[1])
+                    .tee_local(locals[1])
+                    .drop()
+            })
+            .build_with_names();
+        // the two i32 locals are grouped before the f64 one
+        assert_eq!(format!("{:?}", body.locals),
+                   format!("{:?}",
+                           [LocalEntry {
+                                count: 2,
+                                ty: ValueType::I32,
+                            },
+                            LocalEntry {
+                                count: 1,
+                                ty: ValueType::F64,
+                            }]));
+        let expected = CodeBuilder::new()
+            .get_local(LocalIndex::new(2))
+            .set_local(LocalIndex::new(1))
+            .get_local(LocalIndex::new(3))
+            .tee_local(LocalIndex::new(3))
+            .drop()
+            .build();
+        assert_eq!(ops(body.code), ops(expected));
+        let names: Vec<_> = names.locals.iter().map(|(i, name)| (**i, name.as_str())).collect();
+        assert_eq!(names, [(0, "p"), (1, "a"), (2, "c"), (3, "b")]);
+    }
+
+    #[test]
+    fn try_variants() {
+        let (code, label) = CodeBuilder::new().nop().block(BlockType::Empty);
+        let code = code.end().br(label);
+        assert_eq!(code.try_build().unwrap_err(), Error::UnscopedLabel { at: 3 });
+        let fb = FunctionBuilder::new(funtype!(())).code(|cb, _| cb.br(label));
+        assert_eq!(fb.try_build_with_names().unwrap_err(), Error::UnscopedLabel { at: 0 });
+
+        let mut mb = ModuleBuilder::new();
+        let f = mb.try_declare_function(funtype!(())).unwrap();
+        assert_eq!(mb.try_define_function(FunctionIndex::new(1), empty_body()),
+                   Err(Error::NotDeclared(FunctionIndex::new(1))));
+        let mut unfinished = ModuleBuilder::new();
+        unfinished.declare_function(funtype!(()));
+        assert_eq!(unfinished.try_build().err(), Some(Error::UndefinedFunction(f)));
+        mb.try_define_function(f, empty_body()).unwrap();
+        assert_eq!(mb.try_define_function(f, empty_body()), Err(Error::NotDeclared(f)));
+        assert!(mb.try_build().is_ok());
+    }
+
+    #[test]
+    fn function_code_mismatch() {
+        let mut mb = ModuleBuilder::new();
+        NewFunction::new_function(&mut mb, funtype!(()), empty_body());
+        mb.0.codes.as_mut().unwrap().push(empty_body());
+        let expected = Error::FunctionCodeMismatch {
+            functions: 1,
+            codes: 2,
+        };
+        assert_eq!(NewFunction::try_new_function(&mut mb, funtype!((i32)), empty_body()), Err(expected.clone()));
+        assert_eq!(NewFunction::try_new_function(&mut mb, TypeIndex::new(0), empty_body()), Err(expected.clone()));
+        // neither the type nor the function was added
+        assert_eq!(mb.0.types.as_ref().map(|t| t.len()), Some(1));
+        assert_eq!(mb.0.functions.as_ref().map(|f| f.len()), Some(1));
+        let module = mb.build();
+        let mut buf = Vec::new();
+        assert_eq!(module.try_dump(&mut buf), Err(expected));
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn try_dump() {
+        let mut mb = ModuleBuilder::new();
+        mb.new_memory(1..);
+        let data = mb.new_passive_data(vec![1, 2, 3]);
+        let body = FunctionBody {
+            locals: Vec::new(),
+            code: Code(vec![Op::DataDrop(data)]),
+        };
+        NewFunction::new_function(&mut mb, funtype!(()), body);
+        let mut module = mb.build();
+        let mut buf = Vec::new();
+        assert_eq!(module.try_dump(&mut buf), Ok(buf.len()));
+        let mut written = Vec::new();
+        module.dump(&mut written);
+        assert_eq!(buf, written);
+
+        module.data_count = Some(2);
+        let mut buf = Vec::new();
+        assert_eq!(module.try_dump(&mut buf),
+                   Err(Error::DataCountMismatch {
+                           data_count: 2,
+                           data: 1,
+                       }));
+        module.data_count = None;
+        assert_eq!(module.try_dump(&mut buf), Err(Error::MissingDataCount));
+        assert!(buf.is_empty());
+    }
+}
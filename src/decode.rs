@@ -0,0 +1,891 @@
+use std::error;
+use std::fmt;
+
+use util::*;
+use Parse;
+
+/// An error raised while parsing a binary module, with the byte offset it occurred at.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecodeError {
+    pub offset: usize,
+    pub kind: DecodeErrorKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecodeErrorKind {
+    UnexpectedEof,
+    BadMagic,
+    UnsupportedVersion(u32),
+    InvalidLeb128,
+    InvalidUtf8,
+    InvalidValueType,
+    InvalidElemType,
+    /// a heap type that is neither abstract nor a type index
+    InvalidHeapType,
+    /// a type of the type section that is not a function, struct or array type
+    InvalidFuncTypeForm,
+    InvalidExternalKind(u8),
+    /// a tag attribute other than 0, which marks exceptions
+    InvalidTagAttribute(u8),
+    UnknownOpcode(u8),
+    /// a typed `select` lists other than one type
+    InvalidSelectArity(u32),
+    /// an opcode following a prefix byte such as `0xfc`
+    UnknownPrefixedOpcode(u8, u32),
+    UnknownSection(u8),
+    /// the flags of an element or a data segment select an unknown form
+    UnsupportedSegmentFlags(u32),
+    SectionOutOfOrder(u8),
+    /// a section or a function body did not end at its declared size
+    SizeMismatch,
+    /// a function body or an init expr is not terminated by `end`
+    MissingEnd,
+    FunctionCodeMismatch { functions: usize, codes: usize },
+}
+
+impl DecodeError {
+    pub fn new(offset: usize, kind: DecodeErrorKind) -> Self {
+        DecodeError {
+            offset: offset,
+            kind: kind,
+        }
+    }
+}
+
+impl fmt::Display for DecodeErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::DecodeErrorKind::*;
+        match *self {
+            UnexpectedEof => write!(f, "unexpected end of input"),
+            BadMagic => write!(f, "bad magic number"),
+            UnsupportedVersion(v) => write!(f, "unsupported version {}", v),
+            InvalidLeb128 => write!(f, "invalid LEB128 integer"),
+            InvalidUtf8 => write!(f, "invalid UTF-8 string"),
+            InvalidValueType => write!(f, "invalid value type"),
+            InvalidElemType => write!(f, "invalid element type"),
+            InvalidHeapType => write!(f, "invalid heap type"),
+            InvalidFuncTypeForm => write!(f, "invalid type form"),
+            InvalidExternalKind(k) => write!(f, "invalid external kind 0x{:02x}", k),
+            InvalidTagAttribute(a) => write!(f, "invalid tag attribute 0x{:02x}", a),
+            UnknownOpcode(op) => write!(f, "unknown opcode 0x{:02x}", op),
+            InvalidSelectArity(n) => write!(f, "a typed `select` needs one type, not {}", n),
+            UnknownPrefixedOpcode(prefix, op) => write!(f, "unknown opcode 0x{:02x} {}", prefix, op),
+            UnknownSection(id) => write!(f, "unknown section id {}", id),
+            UnsupportedSegmentFlags(flags) => write!(f, "unsupported segment flags {}", flags),
+            SectionOutOfOrder(id) => write!(f, "section id {} is out of order or duplicated", id),
+            SizeMismatch => write!(f, "contents do not match the declared size"),
+            MissingEnd => write!(f, "expression is not terminated by `end`"),
+            FunctionCodeMismatch { functions, codes } => {
+                write!(f,
+                       "{} functions are declared but {} bodies are given",
+                       functions,
+                       codes)
+            }
+        }
+    }
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at offset 0x{:x}", self.kind, self.offset)
+    }
+}
+
+impl error::Error for DecodeError {}
+
+pub fn parse_vec<T: Parse>(buf: &[u8], pos: &mut usize) -> Result<Vec<T>, DecodeError> {
+    let len = read_varuint32(buf, pos)?;
+    // don't trust `len` for the allocation, it may be garbage
+    let mut v = Vec::new();
+    for _ in 0..len {
+        v.push(T::parse(buf, pos)?);
+    }
+    Ok(v)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use builder::*;
+    use module::{CustomSection, ElemMode, ElemSegment, Elements, FunctionBody, Placement, SectionId};
+    use ops::{MemoryImmediate, Op};
+    use types::*;
+    use validate::ValidationErrorKind;
+    use {Dump, Module, Parse};
+    use {funtype, ty, ty_vec};
+
+    fn dump(module: &Module) -> Vec<u8> {
+        let mut buf = Vec::new();
+        module.dump(&mut buf);
+        buf
+    }
+
+    fn parse(bytes: &[u8]) -> Result<Module, DecodeError> {
+        Module::parse(bytes, &mut 0)
+    }
+
+    /// Dumping a decoded module gives back the bytes it was decoded from.
+    fn assert_round_trip(bytes: &[u8]) {
+        let module = parse(bytes).unwrap();
+        assert_eq!(dump(&module), bytes);
+    }
+
+    /// The header and a section with the given id and contents.
+    fn section(id: u8, contents: &[u8]) -> Vec<u8> {
+        let mut buf = b"\0asm\x01\0\0\0".to_vec();
+        buf.push(id);
+        buf.push(contents.len() as u8);
+        buf.extend_from_slice(contents);
+        buf
+    }
+
+    /// A module importing `env.log` and calling it from an exported start function.
+    fn built_module() -> Module {
+        let mut mb = ModuleBuilder::new();
+        let log_ty = mb.add_type(funtype!((i32)));
+        let log = mb.import("env", "log", log_ty);
+        let log = mb.function_index_of(log).unwrap();
+        mb.new_memory(1..2);
+        mb.new_data(MemoryIndex::new(0), CodeBuilder::new().constant(8i32).end().build(), b"hello".to_vec());
+        let counter = mb.new_global(GlobalType {
+                                        content: ValueType::I64,
+                                        mutable: true,
+                                    },
+                                    CodeBuilder::new().constant(-1i64).end().build());
+        let f = mb.new_function(FunctionBuilder::new(funtype!(()))
+            .code(|cb, _| {
+                cb.block(BlockType::Value(ValueType::I32))
+                    .0
+                    .constant(1234567i32)
+                    .call(log)
+                    .get_global(counter)
+                    .constant(1i64)
+                    .i64_add()
+                    .set_global(counter)
+                    .constant(64i32)
+                    .constant(0i32)
+                    .br_if(0)
+                    .drop()
+                    .constant(-65i32)
+                    .end()
+                    .drop()
+            })
+            .build());
+        mb.export("f", f);
+        mb.start(f);
+        mb.build()
+    }
+
+    #[test]
+    fn empty_module() {
+        assert_round_trip(b"\0asm\x01\0\0\0");
+    }
+
+    #[test]
+    fn round_trip() {
+        assert_round_trip(&dump(&built_module()));
+    }
+
+    #[test]
+    fn imports_are_classified() {
+        let decoded = parse(&dump(&built_module())).unwrap();
+        let code = &decoded.codes.as_ref().unwrap()[0].code.0;
+        let callees: Vec<_> = code.iter()
+            .filter_map(|op| match *op {
+                            Op::Call { ref index } => Some(index.0),
+                            _ => None,
+                        })
+            .collect();
+        assert_eq!(callees, [InnerFunctionSpaceIndex::Import(ImportedFunctionIndex(0))]);
+    }
+
+    #[test]
+    fn custom_sections() {
+        let placements = [Placement::First,
+                          Placement::After(SectionId::Type),
+                          Placement::After(SectionId::Table),
+                          Placement::After(SectionId::Code),
+                          Placement::After(SectionId::Data)];
+        let mut mb = ModuleBuilder::new();
+        mb.new_function(FunctionBuilder::new(funtype!(())).build());
+        for (i, &placement) in placements.iter().enumerate() {
+            mb.add_custom_section(CustomSection {
+                                      name: format!("c{}", i),
+                                      placement: placement,
+                                      payload: vec![i as u8; i],
+                                  });
+        }
+        let module = mb.build();
+        module.validate().unwrap();
+        let bytes = dump(&module);
+        // each custom section is emitted where it is placed, ahead of the section that follows
+        assert_eq!(section_ids(&bytes), [0, 1, 0, 3, 0, 10, 0, 0]);
+        assert_round_trip(&bytes);
+
+        // left out sections are not known to the decoder, which places the
+        // section after the last one it has seen
+        let decoded = parse(&bytes).unwrap();
+        decoded.validate().unwrap();
+        let customs: Vec<_> = decoded.customs
+            .unwrap()
+            .into_iter()
+            .map(|c| (c.name, c.placement, c.payload.len()))
+            .collect();
+        assert_eq!(customs,
+                   [("c0".to_string(), Placement::First, 0),
+                    ("c1".to_string(), Placement::After(SectionId::Type), 1),
+                    ("c2".to_string(), Placement::After(SectionId::Function), 2),
+                    ("c3".to_string(), Placement::After(SectionId::Code), 3),
+                    ("c4".to_string(), Placement::After(SectionId::Code), 4)]);
+    }
+
+    fn named_module() -> Module {
+        let mut mb = ModuleBuilder::new();
+        let log_ty = mb.add_type(funtype!((i32)));
+        let log = mb.import("env", "log", log_ty);
+        let log = mb.function_index_of(log).unwrap();
+        mb.name("named");
+        mb.name_function(log, "log");
+        let mut fb = FunctionBuilder::new(funtype!((i32))).name("f");
+        let x = fb.new_local(ValueType::I64);
+        fb.name_local(LocalIndex::new(0), "p");
+        fb.name_local(x, "x");
+        mb.new_function_with_names(fb.build_with_names());
+        mb.build()
+    }
+
+    #[test]
+    fn name_section() {
+        let module = named_module();
+        let bytes = dump(&module);
+        let decoded = parse(&bytes).unwrap();
+        assert_eq!(decoded.names, module.names);
+        assert!(decoded.customs.is_none());
+        assert_eq!(dump(&decoded), bytes);
+
+        let names = decoded.names.unwrap();
+        assert_eq!(names.module, Some("named".to_string()));
+        let f = FunctionSpaceIndex::from(FunctionIndex::new(1));
+        assert_eq!(names.functions.values().collect::<Vec<_>>(), ["log", "f"]);
+        assert_eq!(names.functions.keys().next(),
+                   Some(&FunctionSpaceIndex(InnerFunctionSpaceIndex::Import(ImportedFunctionIndex(0)))));
+        assert_eq!(names.locals[&f].values().collect::<Vec<_>>(), ["p", "x"]);
+    }
+
+    #[test]
+    fn undecoded_name_section() {
+        let mut module = named_module();
+        let names = module.names.take().unwrap();
+        let mut section = Vec::new();
+        names.dump(&mut section);
+        // a name section that is not the last one would be moved by `names`
+        let mut bytes = dump(&module);
+        bytes.extend_from_slice(&section);
+        bytes.extend_from_slice(&[0x00, 0x02, 0x01, b'c']);
+        let decoded = parse(&bytes).unwrap();
+        assert!(decoded.names.is_none());
+        assert_eq!(decoded.customs.as_ref().unwrap().len(), 2);
+        assert_eq!(dump(&decoded), bytes);
+        // neither are subsections that `names` has no place for
+        let mut bytes = dump(&module);
+        let payload = b"\x04name\x07\x01\x00";
+        bytes.push(0x00);
+        bytes.push(payload.len() as u8);
+        bytes.extend_from_slice(payload);
+        let decoded = parse(&bytes).unwrap();
+        assert!(decoded.names.is_none());
+        assert_eq!(dump(&decoded), bytes);
+    }
+
+    #[test]
+    fn names_replace_name_section() {
+        let mut module = named_module();
+        module.customs = Some(vec![CustomSection {
+                                       name: "name".to_string(),
+                                       placement: Placement::First,
+                                       payload: Vec::new(),
+                                   }]);
+        let bytes = dump(&module);
+        module.customs = None;
+        assert_eq!(bytes, dump(&module));
+    }
+
+    /// The ids of the sections in a module, in order.
+    fn section_ids(bytes: &[u8]) -> Vec<u8> {
+        let mut pos = 8;
+        let mut ids = Vec::new();
+        while pos < bytes.len() {
+            ids.push(bytes[pos]);
+            pos += 1;
+            let size = read_varuint32(bytes, &mut pos).unwrap() as usize;
+            pos += size;
+        }
+        ids
+    }
+
+    #[test]
+    fn multi_value() {
+        let mut mb = ModuleBuilder::new();
+        let ty = mb.add_type(funtype!((i32) -> (i32, i64)));
+        let body = FunctionBody {
+            locals: Vec::new(),
+            code: Code(vec![Op::GetLocal(LocalIndex::new(0)),
+                            Op::Block { sig: BlockType::Type(ty) },
+                            Op::I64Const(-1),
+                            Op::End]),
+        };
+        NewFunction::new_function(&mut mb, ty, body);
+        let bytes = dump(&mb.build());
+        assert_round_trip(&bytes);
+        let module = parse(&bytes).unwrap();
+        module.validate().unwrap();
+        assert_eq!(module.func_type(0).unwrap().results, [ValueType::I32, ValueType::I64]);
+        match module.codes.as_ref().unwrap()[0].code.0[1] {
+            Op::Block { sig: BlockType::Type(index) } => assert_eq!(*index, 0),
+            ref op => panic!("expected a block, got {:?}", op),
+        }
+    }
+
+    /// `mb` with a function of type `ty`.
+    fn function_module(mut mb: ModuleBuilder, ty: FuncType, code: Vec<Op>) -> Module {
+        let body = FunctionBody {
+            locals: Vec::new(),
+            code: Code(code),
+        };
+        mb.new_function((ty, body));
+        mb.build()
+    }
+
+    fn with_memory() -> ModuleBuilder {
+        let mut mb = ModuleBuilder::new();
+        mb.new_memory(1..);
+        mb
+    }
+
+    /// Checks that a function with `code` in `mb` survives a round trip,
+    /// validates, and is encoded with `bytes`.
+    fn assert_encoding_in(mb: ModuleBuilder, ty: FuncType, code: Vec<Op>, bytes: &[u8]) {
+        let module = function_module(mb, ty, code);
+        let code = &module.codes.as_ref().unwrap()[0].code.0;
+        let buf = dump(&module);
+        assert!(buf.windows(bytes.len()).any(|w| w == bytes), "{:?}", code);
+        assert_round_trip(&buf);
+        let decoded = parse(&buf).unwrap();
+        decoded.validate().unwrap_or_else(|e| panic!("{:?}: {}", code, e));
+        assert_eq!(format!("{:?}", decoded.codes.unwrap()[0].code.0), format!("{:?}", code));
+    }
+
+    fn assert_encoding(ty: FuncType, code: Vec<Op>, bytes: &[u8]) {
+        assert_encoding_in(with_memory(), ty, code, bytes)
+    }
+
+    #[test]
+    fn saturating_truncations() {
+        let cases = [(Op::I32TruncSatSF32, funtype!((f32) -> i32), 0),
+                     (Op::I32TruncSatUF32, funtype!((f32) -> i32), 1),
+                     (Op::I32TruncSatSF64, funtype!((f64) -> i32), 2),
+                     (Op::I32TruncSatUF64, funtype!((f64) -> i32), 3),
+                     (Op::I64TruncSatSF32, funtype!((f32) -> i64), 4),
+                     (Op::I64TruncSatUF32, funtype!((f32) -> i64), 5),
+                     (Op::I64TruncSatSF64, funtype!((f64) -> i64), 6),
+                     (Op::I64TruncSatUF64, funtype!((f64) -> i64), 7)];
+        for &(ref op, ref ty, code) in cases.iter() {
+            assert_encoding(ty.clone(), vec![Op::GetLocal(LocalIndex::new(0)), op.clone()], &[0x20, 0x00, 0xfc, code]);
+        }
+        assert_encoding(funtype!((i32) -> i32),
+                        vec![Op::GetLocal(LocalIndex::new(0)), Op::I32Extend8S, Op::I32Extend16S],
+                        &[0xc0, 0xc1]);
+        assert_encoding(funtype!((i64) -> i64),
+                        vec![Op::GetLocal(LocalIndex::new(0)), Op::I64Extend8S, Op::I64Extend16S, Op::I64Extend32S],
+                        &[0xc2, 0xc3, 0xc4]);
+
+        let mut bytes = dump(&function_module(with_memory(),
+                                              funtype!((f32) -> i32),
+                                              vec![Op::GetLocal(LocalIndex::new(0)), Op::I32TruncSatSF32]));
+        let at = bytes.windows(2).position(|w| w == [0xfc, 0x00]).unwrap();
+        bytes[at + 1] = 0x20;
+        assert_eq!(parse(&bytes).unwrap_err(),
+                   DecodeError::new(at, DecodeErrorKind::UnknownPrefixedOpcode(0xfc, 0x20)));
+    }
+
+    #[test]
+    fn bulk_memory() {
+        let mb = || {
+            let mut mb = with_memory();
+            mb.new_table(ElemType::AnyFunc, 1..);
+            mb.new_passive_data(vec![1, 2, 3]);
+            mb.add_element(ElemSegment {
+                               mode: ElemMode::Passive,
+                               elems: Elements::Functions(vec![FunctionIndex::new(0).into()]),
+                           });
+            mb
+        };
+        let data = DataIndex::new(0);
+        let zeros = || vec![Op::I32Const(0), Op::I32Const(0), Op::I32Const(0)];
+        let element = ElementIndex::new(0);
+        let table = TableIndex::new(0);
+        let cases = [(Op::MemoryInit {
+                          segment: data,
+                          memory: MemoryIndex::new(0),
+                      },
+                      vec![0xfc, 8, 0, 0]),
+                     (Op::MemoryCopy {
+                          dst: MemoryIndex::new(0),
+                          src: MemoryIndex::new(0),
+                      },
+                      vec![0xfc, 10, 0, 0]),
+                     (Op::MemoryFill { memory: MemoryIndex::new(0) }, vec![0xfc, 11, 0]),
+                     (Op::TableInit {
+                          segment: element,
+                          table: table,
+                      },
+                      vec![0xfc, 12, 0, 0]),
+                     (Op::TableCopy {
+                          dst: table,
+                          src: table,
+                      },
+                      vec![0xfc, 14, 0, 0])];
+        for &(ref op, ref bytes) in cases.iter() {
+            let mut code = zeros();
+            code.push(op.clone());
+            assert_encoding_in(mb(), funtype!(()), code, bytes);
+        }
+        assert_encoding_in(mb(), funtype!(()), vec![Op::DataDrop(data)], &[0xfc, 9, 0]);
+        assert_encoding_in(mb(), funtype!(()), vec![Op::ElemDrop(element)], &[0xfc, 13, 0]);
+
+        // the data count section goes between the elements and the code
+        let bytes = dump(&function_module(mb(), funtype!(()), vec![Op::DataDrop(data)]));
+        let module = parse(&bytes).unwrap();
+        assert_eq!(module.data_count, Some(1));
+        assert_eq!(format!("{:?}", module.elements.unwrap()[0].mode), "Passive");
+        assert!(bytes.windows(3).any(|w| w == [0x0c, 1, 1]));
+    }
+
+    #[test]
+    fn reference_types() {
+        // a function table, an `externref` table and a second function table
+        // that a declarative segment and an expression segment fill in
+        let mb = || {
+            let mut mb = with_memory();
+            mb.new_table(ElemType::AnyFunc, 1..);
+            mb.new_table(ElemType::ExternRef, 1..);
+            mb.new_table(ElemType::AnyFunc, 2..);
+            mb.add_element(ElemSegment {
+                               mode: ElemMode::Declarative,
+                               elems: Elements::Functions(vec![FunctionIndex::new(0).into()]),
+                           });
+            let constant = |op| InitExpr(Code(vec![op, Op::End]));
+            mb.add_element(ElemSegment {
+                               mode: ElemMode::Active {
+                                   index: TableIndex::new(2),
+                                   offset: constant(Op::I32Const(0)),
+                               },
+                               elems: Elements::Expressions(ElemType::AnyFunc,
+                                                            vec![constant(Op::RefFunc(FunctionIndex::new(0).into())),
+                                                                 constant(Op::RefNull(HeapType::Func))]),
+                           });
+            mb
+        };
+        let x = || Op::GetLocal(LocalIndex::new(0));
+        let externs = TableIndex::new(1);
+        let cases = [(vec![Op::RefNull(HeapType::Func), Op::RefIsNull, Op::Drop], vec![0xd0, 0x70, 0xd1]),
+                     (vec![Op::RefFunc(FunctionIndex::new(0).into()), Op::Drop], vec![0xd2, 0]),
+                     (vec![Op::I32Const(0), Op::TableGet(externs), Op::Drop], vec![0x25, 1]),
+                     (vec![Op::I32Const(0), x(), Op::TableSet(externs)], vec![0x26, 1]),
+                     (vec![x(), Op::I32Const(1), Op::TableGrow(externs), Op::Drop], vec![0xfc, 15, 1]),
+                     (vec![Op::TableSize(externs), Op::Drop], vec![0xfc, 16, 1]),
+                     (vec![Op::I32Const(0), x(), Op::I32Const(1), Op::TableFill(externs)], vec![0xfc, 17, 1]),
+                     (vec![x(), x(), Op::I32Const(1), Op::TypedSelect(ValueType::ExternRef), Op::Drop],
+                      vec![0x1c, 1, 0x6f]),
+                     (vec![x(),
+                           Op::I32Const(0),
+                           Op::CallIndirect {
+                               index: TypeIndex::new(0),
+                               table: TableIndex::new(2),
+                           }],
+                      vec![0x11, 0, 2])];
+        let ty = FuncType {
+            params: vec![ValueType::ExternRef],
+            results: Vec::new(),
+        };
+        for &(ref code, ref bytes) in cases.iter() {
+            assert_encoding_in(mb(), ty.clone(), code.clone(), bytes);
+        }
+    }
+
+    #[test]
+    fn simd() {
+        let imm = || {
+            MemoryImmediate {
+                flags: 4,
+                offset: 0,
+                memory: MemoryIndex::new(0),
+            }
+        };
+        let mut reversed = [0; 16];
+        for (i, lane) in reversed.iter_mut().enumerate() {
+            *lane = 31 - i as u8;
+        }
+        let cases = [(vec![Op::V128Const(0x0f0e), Op::I8x16ExtractLaneS(1)],
+                      vec![0xfd, 0x0c, 0x0e, 0x0f, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xfd, 0x15, 1]),
+                     (vec![Op::I32Const(0), Op::V128Load { imm: imm() }, Op::I32x4ExtractLane(3)],
+                      vec![0xfd, 0x00, 4, 0, 0xfd, 0x1b, 3]),
+                     // opcodes past 0x7f take two bytes
+                     (vec![Op::I32Const(1), Op::I32x4Splat, Op::I32Const(2), Op::I32x4Splat, Op::I32x4Add, Op::I32x4ExtractLane(0)],
+                      vec![0xfd, 0x11, 0xfd, 0xae, 0x01]),
+                     (vec![Op::V128Const(0), Op::V128Const(1), Op::I8x16Shuffle(reversed), Op::I16x8ExtractLaneU(0)],
+                      [&[0xfd, 0x0d][..], &reversed[..], &[0xfd, 0x19, 0][..]].concat())];
+        for &(ref code, ref bytes) in cases.iter() {
+            assert_encoding(funtype!(() -> i32), code.clone(), bytes);
+        }
+        assert_encoding(funtype!(()),
+                        vec![Op::I32Const(0), Op::V128Const(0), Op::V128Store { imm: imm() }],
+                        &[0xfd, 0x0b, 4, 0]);
+        let ty = FuncType {
+            params: Vec::new(),
+            results: vec![ValueType::V128],
+        };
+        // the type section has `v128` as 0x7b
+        assert_encoding(ty, vec![Op::V128Const(7)], &[0x60, 0, 1, 0x7b]);
+    }
+
+    #[test]
+    fn threads() {
+        let mb = || {
+            let mut mb = ModuleBuilder::new();
+            mb.new_shared_memory(1..2);
+            mb
+        };
+        let imm = |flags| {
+            MemoryImmediate {
+                flags: flags,
+                offset: 0,
+                memory: MemoryIndex::new(0),
+            }
+        };
+        let cases = [(vec![Op::I32Const(0), Op::I32AtomicLoad { imm: imm(2) }], vec![0xfe, 0x10, 2, 0]),
+                     (vec![Op::I32Const(0), Op::I32Const(1), Op::I32AtomicRmwAdd { imm: imm(2) }], vec![0xfe, 0x1e, 2, 0]),
+                     (vec![Op::I32Const(0), Op::I64Const(1), Op::I64Const(2), Op::I64AtomicRmw8CmpxchgU { imm: imm(0) }, Op::I32wrapI64],
+                      vec![0xfe, 0x4c, 0, 0]),
+                     (vec![Op::I32Const(0), Op::I32Const(1), Op::MemoryAtomicNotify { imm: imm(2) }], vec![0xfe, 0x00, 2, 0]),
+                     (vec![Op::I32Const(0), Op::I32Const(0), Op::I64Const(-1), Op::MemoryAtomicWait32 { imm: imm(2) }],
+                      vec![0xfe, 0x01, 2, 0]),
+                     (vec![Op::AtomicFence { reserved: false }, Op::I32Const(0)], vec![0xfe, 0x03, 0])];
+        for &(ref code, ref bytes) in cases.iter() {
+            assert_encoding_in(mb(), funtype!(() -> i32), code.clone(), bytes);
+        }
+
+        // a shared memory has the limits flags 3
+        let bytes = dump(&mb().build());
+        assert!(bytes.ends_with(&[0x05, 4, 1, 0x03, 1, 2]));
+        let module = parse(&bytes).unwrap();
+        assert!(module.memories.unwrap()[0].shared);
+
+        let bytes = dump(&function_module(mb(), funtype!(()), vec![Op::AtomicFence { reserved: true }]));
+        let module = parse(&bytes).unwrap();
+        assert_eq!(module.validate().map_err(|e| e.kind), Err(ValidationErrorKind::NonZeroReserved));
+    }
+
+    #[test]
+    fn tail_calls() {
+        // an imported function of type 0, and a table to call through
+        let mb = || {
+            let mut mb = with_memory();
+            let ty = mb.add_type(funtype!((i32) -> i32));
+            mb.import("env", "f", ty);
+            mb.new_table(ElemType::AnyFunc, 1..);
+            mb
+        };
+        let x = || Op::GetLocal(LocalIndex::new(0));
+        let import = FunctionSpaceIndex(InnerFunctionSpaceIndex::Import(ImportedFunctionIndex(0)));
+        let cases = [(vec![x(), Op::ReturnCall { index: import }], vec![0x20, 0, 0x12, 0]),
+                     // the defined function comes after the import
+                     (vec![x(), Op::ReturnCall { index: FunctionIndex::new(0).into() }], vec![0x20, 0, 0x12, 1]),
+                     (vec![x(),
+                           x(),
+                           Op::ReturnCallIndirect {
+                               index: TypeIndex::new(0),
+                               table: TableIndex::new(0),
+                           }],
+                      vec![0x13, 0, 0])];
+        for &(ref code, ref bytes) in cases.iter() {
+            assert_encoding_in(mb(), funtype!((i32) -> i32), code.clone(), bytes);
+        }
+    }
+
+    #[test]
+    fn exceptions() {
+        let mb = || {
+            let mut mb = ModuleBuilder::new();
+            mb.new_tag(funtype!((i32)));
+            mb
+        };
+        let tag = TagIndex::new(0);
+        let cases = [(vec![Op::Try { sig: BlockType::Value(ValueType::I32) },
+                           Op::GetLocal(LocalIndex::new(0)),
+                           Op::Throw(tag),
+                           Op::Catch(tag),
+                           Op::CatchAll,
+                           Op::I32Const(0),
+                           Op::End],
+                      vec![0x06, 0x7f, 0x20, 0, 0x08, 0, 0x07, 0, 0x19, 0x41, 0, 0x0b]),
+                     (vec![Op::Try { sig: BlockType::Empty },
+                           Op::Try { sig: BlockType::Empty },
+                           Op::Delegate { depth: 0 },
+                           Op::CatchAll,
+                           Op::Rethrow { depth: 0 },
+                           Op::End,
+                           Op::GetLocal(LocalIndex::new(0))],
+                      vec![0x06, 0x40, 0x06, 0x40, 0x18, 0, 0x19, 0x09, 0, 0x0b])];
+        for &(ref code, ref bytes) in cases.iter() {
+            assert_encoding_in(mb(), funtype!((i32) -> i32), code.clone(), bytes);
+        }
+
+        // the tag section comes after the memories, with an attribute before the type
+        let bytes = dump(&mb().build());
+        assert!(bytes.ends_with(&[0x0d, 3, 1, 0, 0]));
+        let module = parse(&bytes).unwrap();
+        assert_eq!(module.tags.unwrap()[0].ty, TypeIndex::new(0));
+    }
+
+    #[test]
+    fn multi_memory() {
+        let mb = || {
+            let mut mb = with_memory();
+            mb.new_memory(1..);
+            mb
+        };
+        let one = MemoryIndex::new(1);
+        let code = |cb: CodeBuilder| cb.build().0;
+        // bit 6 of the flags announces a memory index after them
+        let cases = [(code(CodeBuilder::new().constant(0i32).i32_load(4, one)), vec![0x28, 0x42, 1, 4]),
+                     (code(CodeBuilder::new().constant(0i32).i32_load(4, None)), vec![0x28, 0x02, 4]),
+                     (code(CodeBuilder::new().constant(0i32).constant(0i64).i64_store8(0, one).constant(0i32)),
+                      vec![0x3c, 0x40, 1, 0]),
+                     (code(CodeBuilder::new().constant(0i32).i32_atomic_load(0, one)), vec![0xfe, 0x10, 0x42, 1, 0]),
+                     (vec![Op::CurrentMemory { memory: one }], vec![0x3f, 1]),
+                     (vec![Op::I32Const(1), Op::GrowMemory { memory: one }], vec![0x40, 1]),
+                     (vec![Op::I32Const(0),
+                           Op::I32Const(0),
+                           Op::I32Const(0),
+                           Op::MemoryCopy {
+                               dst: one,
+                               src: MemoryIndex::new(0),
+                           },
+                           Op::I32Const(0)],
+                      vec![0xfc, 10, 1, 0]),
+                     (vec![Op::I32Const(0), Op::I32Const(0), Op::I32Const(0), Op::MemoryFill { memory: one }, Op::I32Const(0)],
+                      vec![0xfc, 11, 1])];
+        for &(ref code, ref bytes) in cases.iter() {
+            assert_encoding_in(mb(), funtype!(() -> i32), code.clone(), bytes);
+        }
+    }
+
+    #[test]
+    fn memory64() {
+        let mb = || {
+            let mut mb = ModuleBuilder::new();
+            mb.new_memory64(1..);
+            mb
+        };
+        let imm = MemoryImmediate {
+            flags: 2,
+            offset: 1 << 32,
+            memory: MemoryIndex::new(0),
+        };
+        assert_encoding_in(mb(),
+                           funtype!(() -> i32),
+                           vec![Op::I64Const(0), Op::I32Load { imm: imm }],
+                           &[0x28, 2, 0x80, 0x80, 0x80, 0x80, 0x10]);
+        assert_encoding_in(mb(), funtype!(() -> i64), vec![Op::CurrentMemory { memory: MemoryIndex::new(0) }], &[0x3f, 0]);
+
+        // the limits flags have bit 2 set
+        let bytes = dump(&mb().build());
+        assert!(bytes.ends_with(&[0x05, 3, 1, 0x04, 1]));
+        assert!(parse(&bytes).unwrap().memories.unwrap()[0].memory64);
+    }
+
+    #[test]
+    fn extended_const() {
+        let mut mb = ModuleBuilder::new();
+        let imported = GlobalType {
+            content: ValueType::I32,
+            mutable: false,
+        };
+        mb.import("env", "base", imported.clone());
+        mb.new_memory(1..);
+        let base = GlobalIndex::new(0);
+        mb.new_global(imported,
+                      InitExprBuilder::new().get_global(base).constant(8i32).i32_mul().build());
+        mb.new_data(MemoryIndex::new(0),
+                    InitExprBuilder::new().get_global(base).constant(16i32).i32_add().build(),
+                    b"data".to_vec());
+        let bytes = dump(&mb.build());
+        assert!(bytes.windows(6).any(|w| w == [0x23, 0, 0x41, 8, 0x6c, 0x0b]));
+        assert!(bytes.windows(6).any(|w| w == [0x23, 0, 0x41, 16, 0x6a, 0x0b]));
+        assert_round_trip(&bytes);
+        assert_eq!(parse(&bytes).unwrap().validate(), Ok(()));
+    }
+
+    #[test]
+    fn gc_module() {
+        let module = Module::from_wat(r#"
+            (module
+              (rec
+                (type $node (sub (struct (field $value (mut i64)) (field $next (ref null $node)))))
+                (type $leaf (sub final $node (struct (field $value (mut i64)) (field $next (ref null $node)) (field i16)))))
+              (type $bytes (array (mut i8)))
+              (type $refs (array (mut (ref null any))))
+              (type $funcs (array funcref))
+              (type $unary (func (param (ref null $node)) (result i64)))
+              (global $seed (ref $bytes) (array.new_fixed $bytes 3 (i32.const 1) (i32.const 2) (i32.const 3)))
+              (global $null (ref null $node) (ref.null $node))
+              (global $empty (ref null $node) (global.get $null))
+              (global $small i31ref (ref.i31 (i32.const -1)))
+              (global $host anyref (any.convert_extern (ref.null extern)))
+              (table $nodes 1 (ref null $node))
+              (data "data")
+              (elem $fs func $value)
+              (elem declare func $value)
+              (func $value (type $unary)
+                (struct.get $node $value (ref.as_non_null (local.get 0))))
+              (func (param $n (ref null $node)) (param $a (ref null $refs)) (result i64)
+                (local $b (ref null $bytes))
+                (local.set $b (array.new_data $bytes 0 (i32.const 0) (i32.const 4)))
+                (array.copy $bytes $bytes (local.get $b) (i32.const 0) (global.get $seed) (i32.const 0) (i32.const 3))
+                (array.fill $refs (local.get $a) (i32.const 0) (local.get $n) (array.len (local.get $b)))
+                (array.init_data $bytes 0 (local.get $b) (i32.const 0) (i32.const 0) (i32.const 1))
+                (drop (array.new_elem $funcs $fs (i32.const 0) (i32.const 0)))
+                (drop (ref.test (ref $leaf) (local.get $n)))
+                (drop (ref.eq (local.get $n) (local.get $a)))
+                (drop (block $cast (result (ref $leaf))
+                  (br_on_cast $cast (ref null $node) (ref $leaf) (local.get $n))
+                  (drop)
+                  (unreachable)))
+                (drop (block $other (result anyref)
+                  (drop (br_on_cast_fail $other anyref (ref $leaf) (array.get $refs (local.get $a) (i32.const 0))))
+                  (ref.null any)))
+                (drop (block $some (result (ref $node))
+                  (br_on_non_null $some (local.get $n))
+                  (unreachable)))
+                (struct.set $leaf $value (ref.cast (ref $leaf) (local.get $n)) (i64.const 1))
+                (drop (struct.get_u $leaf 2 (struct.new_default $leaf)))
+                (table.set $nodes (i32.const 0) (table.get $nodes (i32.const 0)))
+                (drop (i31.get_s (global.get $small)))
+                (drop (extern.convert_any (local.get $a)))
+                (return_call_ref $unary (local.get $n) (ref.func $value))))"#)
+            .unwrap();
+        assert_eq!(module.validate(), Ok(()));
+        assert_round_trip(&dump(&module));
+    }
+
+    #[test]
+    fn i31_and_extern_conversions() {
+        let x = || Op::GetLocal(LocalIndex::new(0));
+        let cases = [(vec![x(), Op::RefI31, Op::I31GetS], vec![0x20, 0, 0xfb, 0x1c, 0xfb, 0x1d]),
+                     (vec![x(), Op::RefI31, Op::I31GetU], vec![0xfb, 0x1c, 0xfb, 0x1e]),
+                     (vec![x(), Op::RefI31, Op::ExternConvertAny, Op::AnyConvertExtern,
+                           Op::RefTest(RefType::non_null(HeapType::I31))],
+                      vec![0xfb, 0x1b, 0xfb, 0x1a, 0xfb, 0x14, 0x6c])];
+        for &(ref code, ref bytes) in cases.iter() {
+            assert_encoding(funtype!((i32) -> i32), code.clone(), bytes);
+        }
+
+        // tables hold any nullable reference
+        let mut mb = ModuleBuilder::new();
+        mb.new_table(RefType::nullable(HeapType::I31), 1..);
+        let bytes = dump(&mb.build());
+        assert!(bytes.windows(6).any(|w| w == [0x04, 4, 1, 0x6c, 0x00, 1]));
+        assert_round_trip(&bytes);
+        assert_eq!(parse(&bytes).unwrap().validate(), Ok(()));
+        let mut mb = ModuleBuilder::new();
+        mb.new_table(RefType::non_null(HeapType::I31), 1..);
+        let i31 = ValueType::Ref(RefType::non_null(HeapType::I31));
+        assert_eq!(mb.build().validate().map_err(|e| e.kind),
+                   Err(ValidationErrorKind::NonDefaultableType(i31)));
+    }
+
+    #[test]
+    fn bad_header() {
+        assert_eq!(parse(b"\0wasm\x01\0\0").unwrap_err(), DecodeError::new(0, DecodeErrorKind::BadMagic));
+        assert_eq!(parse(b"\0asm\x02\0\0\0").unwrap_err(),
+                   DecodeError::new(4, DecodeErrorKind::UnsupportedVersion(2)));
+        assert_eq!(parse(b"\0as").unwrap_err().kind, DecodeErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn truncated() {
+        let bytes = dump(&built_module());
+        for len in 9..bytes.len() {
+            // cutting between sections leaves fewer sections, or a function without its body
+            match parse(&bytes[..len]) {
+                Ok(module) => assert_eq!(dump(&module), &bytes[..len]),
+                Err(DecodeError { kind: DecodeErrorKind::UnexpectedEof, offset }) => assert!(offset <= len),
+                Err(DecodeError { kind: DecodeErrorKind::FunctionCodeMismatch { .. }, .. }) => {}
+                r => panic!("{} bytes: {:?}", len, r),
+            }
+        }
+    }
+
+    #[test]
+    fn leb_overflow() {
+        // a type section whose vector length takes more than five bytes
+        let bytes = section(0x01, &[0x80, 0x80, 0x80, 0x80, 0x80, 0x00]);
+        assert_eq!(parse(&bytes).unwrap_err(), DecodeError::new(10, DecodeErrorKind::InvalidLeb128));
+    }
+
+    #[test]
+    fn unknown_section() {
+        assert_eq!(parse(&section(0x2a, &[])).unwrap_err(),
+                   DecodeError::new(8, DecodeErrorKind::UnknownSection(0x2a)));
+    }
+
+    #[test]
+    fn section_size_mismatch() {
+        // one function type taking nothing, declared one byte longer than it is
+        let bytes = section(0x01, &[0x01, 0x60, 0x00, 0x00, 0x00]);
+        assert_eq!(parse(&bytes).unwrap_err(), DecodeError::new(14, DecodeErrorKind::SizeMismatch));
+        // and one byte shorter
+        let bytes = section(0x01, &[0x01, 0x60, 0x00]);
+        assert_eq!(parse(&bytes).unwrap_err().kind, DecodeErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn sections_out_of_order() {
+        let mut bytes = section(0x03, &[0x00]);
+        bytes.extend_from_slice(&[0x01, 0x01, 0x00]);
+        assert_eq!(parse(&bytes).unwrap_err(), DecodeError::new(11, DecodeErrorKind::SectionOutOfOrder(1)));
+    }
+
+    #[test]
+    fn function_without_body() {
+        let mut bytes = section(0x01, &[0x01, 0x60, 0x00, 0x00]);
+        bytes.extend_from_slice(&[0x03, 0x02, 0x01, 0x00]);
+        assert_eq!(parse(&bytes).unwrap_err().kind,
+                   DecodeErrorKind::FunctionCodeMismatch {
+                       functions: 1,
+                       codes: 0,
+                   });
+    }
+
+    #[test]
+    fn invalid_contents() {
+        assert_eq!(parse(&section(0x01, &[0x01, 0x40])).unwrap_err(),
+                   DecodeError::new(11, DecodeErrorKind::InvalidFuncTypeForm));
+        assert_eq!(parse(&section(0x01, &[0x01, 0x60, 0x01, 0x00, 0x00])).unwrap_err(),
+                   DecodeError::new(13, DecodeErrorKind::InvalidValueType));
+        // an import named by the invalid UTF-8 byte 0xff
+        assert_eq!(parse(&section(0x02, &[0x01, 0x01, 0xff, 0x00, 0x00, 0x00])).unwrap_err(),
+                   DecodeError::new(12, DecodeErrorKind::InvalidUtf8));
+        let mut bytes = section(0x01, &[0x01, 0x60, 0x00, 0x00]);
+        bytes.extend_from_slice(&[0x03, 0x02, 0x01, 0x00]);
+        // a body with the unassigned opcode 0x27
+        bytes.extend_from_slice(&[0x0a, 0x05, 0x01, 0x03, 0x00, 0x27, 0x0b]);
+        assert_eq!(parse(&bytes).unwrap_err(), DecodeError::new(23, DecodeErrorKind::UnknownOpcode(0x27)));
+        // and a `nop` without `end`
+        let len = bytes.len();
+        bytes[len - 2] = 0x01;
+        bytes[len - 1] = 0x01;
+        assert_eq!(parse(&bytes).unwrap_err().kind, DecodeErrorKind::MissingEnd);
+    }
+}
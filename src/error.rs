@@ -0,0 +1,45 @@
+use std::error;
+use std::fmt;
+
+use types::*;
+
+/// A misuse of the builders, or a module that cannot be encoded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// the branch at `at` in the code targets a label whose block is not open
+    UnscopedLabel { at: usize },
+    /// a function from `declare_function` never got a body
+    UndefinedFunction(FunctionIndex),
+    /// `define_function` on a function that is not waiting for a body
+    NotDeclared(FunctionIndex),
+    FunctionCodeMismatch { functions: usize, codes: usize },
+    /// the code refers to data segments by index but the module has no `data_count`
+    MissingDataCount,
+    /// `data_count` differs from the number of data segments
+    DataCountMismatch { data_count: u32, data: usize },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::Error::*;
+        match *self {
+            UnscopedLabel { at } => {
+                write!(f, "the branch at instruction {} targets a label outside of its block", at)
+            }
+            UndefinedFunction(index) => write!(f, "function {} was declared but never defined", *index),
+            NotDeclared(index) => write!(f, "function {} is not waiting for a body", *index),
+            FunctionCodeMismatch { functions, codes } => {
+                write!(f,
+                       "{} functions are declared but {} bodies are given",
+                       functions,
+                       codes)
+            }
+            MissingDataCount => write!(f, "the code uses data segments but the module has no data count"),
+            DataCountMismatch { data_count, data } => {
+                write!(f, "the data count is {} but {} data segments are given", data_count, data)
+            }
+        }
+    }
+}
+
+impl error::Error for Error {}
@@ -0,0 +1,3655 @@
+use std::any::Any;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::error;
+use std::fmt;
+use std::mem;
+use std::rc::{Rc, Weak};
+
+use module::*;
+use ops::{MemoryImmediate, Op};
+use types::*;
+use validate::{Subtyping, ValidationError};
+
+/// The size of a memory page in bytes.
+pub const PAGE_SIZE: usize = 0x10000;
+const MAX_PAGES: u32 = 0x10000;
+/// The size past which tables do not grow, the limit of the JS API.
+const MAX_TABLE_SIZE: u32 = 10_000_000;
+/// The number of nested calls after which execution traps with `Trap::StackExhausted`.
+const MAX_FRAMES: usize = 0x10000;
+/// The length past which arrays are not allocated, like tables.
+const MAX_ARRAY_LEN: u32 = MAX_TABLE_SIZE;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    I32(i32),
+    I64(i64),
+    F32(f32),
+    F64(f64),
+    V128(u128),
+    /// `None` is the null reference
+    FuncRef(Option<Func>),
+    ExternRef(Option<ExternRef>),
+    /// a reference of the `any` hierarchy, or its null
+    AnyRef(Option<AnyRef>),
+}
+
+impl Value {
+    pub fn ty(&self) -> ValueType {
+        match *self {
+            Value::I32(_) => ValueType::I32,
+            Value::I64(_) => ValueType::I64,
+            Value::F32(_) => ValueType::F32,
+            Value::F64(_) => ValueType::F64,
+            Value::V128(_) => ValueType::V128,
+            Value::FuncRef(_) => ValueType::FuncRef,
+            Value::ExternRef(_) => ValueType::ExternRef,
+            Value::AnyRef(_) => ValueType::Ref(RefType::nullable(HeapType::Any)),
+        }
+    }
+
+    /// The zero of `ty`, or null for references, which locals start with.
+    /// The null of a defined type is the one of `any`, whatever its kind.
+    pub fn default(ty: ValueType) -> Self {
+        match ty {
+            ValueType::I32 => Value::I32(0),
+            ValueType::I64 => Value::I64(0),
+            ValueType::F32 => Value::F32(0.0),
+            ValueType::F64 => Value::F64(0.0),
+            ValueType::V128 => Value::V128(0),
+            ValueType::FuncRef => Value::FuncRef(None),
+            ValueType::ExternRef => Value::ExternRef(None),
+            ValueType::Ref(ty) => {
+                match ty.heap {
+                    HeapType::Func | HeapType::NoFunc => Value::FuncRef(None),
+                    HeapType::Extern | HeapType::NoExtern => Value::ExternRef(None),
+                    _ => Value::AnyRef(None),
+                }
+            }
+        }
+    }
+
+    pub fn is_null(&self) -> bool {
+        matches!(*self, Value::FuncRef(None) | Value::ExternRef(None) | Value::AnyRef(None))
+    }
+}
+
+impl From<Func> for Value {
+    fn from(f: Func) -> Self {
+        Value::FuncRef(Some(f))
+    }
+}
+
+impl From<ExternRef> for Value {
+    fn from(r: ExternRef) -> Self {
+        Value::ExternRef(Some(r))
+    }
+}
+
+/// A host value handed to a module as an `externref`. The module can only
+/// pass it around, the host gets at it again with `downcast_ref`.
+#[derive(Clone)]
+pub struct ExternRef(Rc<dyn Any>);
+
+impl ExternRef {
+    pub fn new<T: Any>(value: T) -> Self {
+        ExternRef(Rc::new(value))
+    }
+
+    pub fn downcast_ref<T: Any>(&self) -> Option<&T> {
+        self.0.downcast_ref()
+    }
+}
+
+/// References are equal if they refer to the same value.
+impl PartialEq for ExternRef {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl fmt::Debug for ExternRef {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "ExternRef({:p})", Rc::as_ptr(&self.0))
+    }
+}
+
+/// What an `anyref` refers to.
+#[derive(Debug, Clone, PartialEq)]
+pub enum AnyRef {
+    Object(Object),
+    /// the 31 bits of an `i31ref`, zero-extended
+    I31(u32),
+    /// a host reference converted with `any.convert_extern`
+    Extern(ExternRef),
+}
+
+/// An `anyref` converted with `extern.convert_any`, which converts back to it.
+struct Externalized(AnyRef);
+
+/// The `anyref` that `any.convert_extern` makes of `value`, an `externref`.
+fn internalize(value: Value) -> Value {
+    match value {
+        Value::ExternRef(None) => Value::AnyRef(None),
+        Value::ExternRef(Some(r)) => {
+            let any = match r.downcast_ref::<Externalized>() {
+                Some(&Externalized(ref any)) => any.clone(),
+                None => AnyRef::Extern(r),
+            };
+            Value::AnyRef(Some(any))
+        }
+        _ => unreachable!("validated operand"),
+    }
+}
+
+/// The `externref` that `extern.convert_any` makes of `value`, an `anyref`.
+fn externalize(value: Value) -> Value {
+    match value {
+        Value::AnyRef(None) => Value::ExternRef(None),
+        Value::AnyRef(Some(AnyRef::Extern(r))) => Value::ExternRef(Some(r)),
+        Value::AnyRef(Some(any)) => Value::ExternRef(Some(ExternRef::new(Externalized(any)))),
+        _ => unreachable!("validated operand"),
+    }
+}
+
+/// A struct or an array allocated by an instance. Objects are equal if they are the same object.
+#[derive(Clone)]
+pub struct Object(Rc<ObjectInner>);
+
+struct ObjectInner {
+    /// among the types of the instance that allocated it
+    ty: TypeIndex,
+    types: Rc<Types>,
+    /// packed fields hold the zero-extended bits as an `i32`
+    fields: RefCell<Vec<Value>>,
+}
+
+impl Object {
+    /// The index of its type in the module that allocated it.
+    pub fn ty(&self) -> u32 {
+        *self.0.ty
+    }
+
+    pub fn is_array(&self) -> bool {
+        matches!(self.0.types.sub_types[*self.0.ty as usize].composite, CompositeType::Array(_))
+    }
+
+    /// The number of fields of a struct or elements of an array.
+    pub fn len(&self) -> u32 {
+        self.0.fields.borrow().len() as u32
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.fields.borrow().is_empty()
+    }
+
+    /// A field or an element, `None` if `index` is out of bounds. Packed ones are zero-extended.
+    pub fn get(&self, index: u32) -> Option<Value> {
+        self.0.fields.borrow().get(index as usize).cloned()
+    }
+}
+
+impl Object {
+    fn alloc(types: &Rc<Types>, ty: TypeIndex, fields: Vec<Value>) -> Value {
+        let object = Object(Rc::new(ObjectInner {
+                                        ty: ty,
+                                        types: types.clone(),
+                                        fields: RefCell::new(fields),
+                                    }));
+        Value::AnyRef(Some(AnyRef::Object(object)))
+    }
+}
+
+impl PartialEq for Object {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl fmt::Debug for Object {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Object({}, {:p})", *self.0.ty, Rc::as_ptr(&self.0))
+    }
+}
+
+impl From<i32> for Value {
+    fn from(v: i32) -> Self {
+        Value::I32(v)
+    }
+}
+
+impl From<i64> for Value {
+    fn from(v: i64) -> Self {
+        Value::I64(v)
+    }
+}
+
+impl From<f32> for Value {
+    fn from(v: f32) -> Self {
+        Value::F32(v)
+    }
+}
+
+impl From<f64> for Value {
+    fn from(v: f64) -> Self {
+        Value::F64(v)
+    }
+}
+
+impl From<u128> for Value {
+    fn from(v: u128) -> Self {
+        Value::V128(v)
+    }
+}
+
+/// A runtime error aborting execution, as defined by the spec.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Trap {
+    Unreachable,
+    MemoryOutOfBounds,
+    /// `call_indirect` with an index past the end of the table
+    UndefinedElement,
+    /// `table.init` or `table.copy` past the end of a table or a segment
+    TableOutOfBounds,
+    /// `call_indirect` with an index of an empty table slot
+    UninitializedElement,
+    IndirectCallTypeMismatch,
+    IntegerDivideByZero,
+    IntegerOverflow,
+    InvalidConversionToInteger,
+    StackExhausted,
+    /// a host function returned values that do not match its result types
+    HostResultMismatch,
+    /// an atomic access to an address that is not a multiple of its size
+    UnalignedAtomic,
+    /// `memory.atomic.wait` on a memory that is not shared
+    ExpectedSharedMemory,
+    /// `memory.atomic.wait` without a timeout, which no other thread could end
+    Deadlock,
+    /// an exception no handler caught. Host functions may throw one too.
+    Exception(Exception),
+    /// a struct, array or function reference instruction on null, or `ref.as_non_null` of it
+    NullReference,
+    /// `ref.cast` of a reference of another type
+    CastFailure,
+    /// an array access past the end of the array
+    ArrayOutOfBounds,
+    /// an array longer than the interpreter allocates
+    AllocationTooLarge,
+    /// raised by a host function
+    Host(String),
+}
+
+impl fmt::Display for Trap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::Trap::*;
+        match *self {
+            Unreachable => write!(f, "unreachable"),
+            MemoryOutOfBounds => write!(f, "out of bounds memory access"),
+            UndefinedElement => write!(f, "undefined element"),
+            TableOutOfBounds => write!(f, "out of bounds table access"),
+            UninitializedElement => write!(f, "uninitialized element"),
+            IndirectCallTypeMismatch => write!(f, "indirect call type mismatch"),
+            IntegerDivideByZero => write!(f, "integer divide by zero"),
+            IntegerOverflow => write!(f, "integer overflow"),
+            InvalidConversionToInteger => write!(f, "invalid conversion to integer"),
+            StackExhausted => write!(f, "call stack exhausted"),
+            HostResultMismatch => write!(f, "host function returned values of the wrong types"),
+            UnalignedAtomic => write!(f, "unaligned atomic"),
+            ExpectedSharedMemory => write!(f, "expected shared memory"),
+            Deadlock => write!(f, "wait would block forever"),
+            Exception(_) => write!(f, "uncaught exception"),
+            NullReference => write!(f, "null reference"),
+            CastFailure => write!(f, "cast failure"),
+            ArrayOutOfBounds => write!(f, "out of bounds array access"),
+            AllocationTooLarge => write!(f, "allocation too large"),
+            Host(ref msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+impl error::Error for Trap {}
+
+/// An error raised while instantiating a module or invoking one of its exports.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    Invalid(ValidationError),
+    UnknownImport { module: String, field: String },
+    /// the provided import does not have the kind or type the module expects
+    IncompatibleImport { module: String, field: String },
+    /// an element or a data segment does not fit into its table or memory
+    SegmentDoesNotFit,
+    /// a 64-bit memory is larger initially than the 4 GiB the interpreter allocates at most
+    MemoryTooLarge,
+    UnknownExport(String),
+    /// the export is not a function, or the arguments do not match its parameters
+    ArgumentMismatch,
+    Trap(Trap),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::Error::*;
+        match *self {
+            Invalid(ref e) => write!(f, "invalid module: {}", e),
+            UnknownImport { ref module, ref field } => write!(f, "unknown import \"{}\" \"{}\"", module, field),
+            IncompatibleImport { ref module, ref field } => {
+                write!(f, "incompatible import \"{}\" \"{}\"", module, field)
+            }
+            SegmentDoesNotFit => write!(f, "segment does not fit"),
+            MemoryTooLarge => write!(f, "memory is too large"),
+            UnknownExport(ref name) => write!(f, "unknown export \"{}\"", name),
+            ArgumentMismatch => write!(f, "arguments do not match the function type"),
+            Trap(ref t) => write!(f, "trap: {}", t),
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+impl From<Trap> for Error {
+    fn from(t: Trap) -> Self {
+        Error::Trap(t)
+    }
+}
+
+/// A linear memory.
+#[derive(Debug, Clone)]
+pub struct Memory {
+    data: Vec<u8>,
+    maximum: Option<u32>,
+    shared: bool,
+    memory64: bool,
+}
+
+pub type MemoryRef = Rc<RefCell<Memory>>;
+
+impl Memory {
+    pub fn new(initial: u32, maximum: Option<u32>) -> Self {
+        Memory {
+            data: vec![0; initial as usize * PAGE_SIZE],
+            maximum: maximum,
+            shared: false,
+            memory64: false,
+        }
+    }
+
+    /// A memory of the shared memory type, for modules that import one.
+    pub fn new_shared(initial: u32, maximum: u32) -> Self {
+        Memory { shared: true, ..Memory::new(initial, Some(maximum)) }
+    }
+
+    /// A memory addressed with `i64`. It still holds at most 4 GiB here.
+    pub fn new_64(initial: u32, maximum: Option<u32>) -> Self {
+        Memory { memory64: true, ..Memory::new(initial, maximum) }
+    }
+
+    pub fn is_shared(&self) -> bool {
+        self.shared
+    }
+
+    pub fn is_64(&self) -> bool {
+        self.memory64
+    }
+
+    /// The current size in pages.
+    pub fn pages(&self) -> u32 {
+        (self.data.len() / PAGE_SIZE) as u32
+    }
+
+    pub fn maximum(&self) -> Option<u32> {
+        self.maximum
+    }
+
+    /// Grows the memory by `delta` pages and returns the previous size, or `None` if the maximum would be exceeded.
+    pub fn grow(&mut self, delta: u32) -> Option<u32> {
+        let pages = self.pages();
+        let limit = self.maximum.map_or(MAX_PAGES, |m| m.min(MAX_PAGES));
+        match pages.checked_add(delta) {
+            Some(new) if new <= limit => {
+                self.data.resize(new as usize * PAGE_SIZE, 0);
+                Some(pages)
+            }
+            _ => None,
+        }
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn data_mut(&mut self) -> &mut [u8] {
+        &mut self.data
+    }
+
+    pub fn read(&self, addr: u32, buf: &mut [u8]) -> Result<(), Trap> {
+        let range = self.range(addr as u64, buf.len())?;
+        buf.copy_from_slice(&self.data[range]);
+        Ok(())
+    }
+
+    pub fn write(&mut self, addr: u32, buf: &[u8]) -> Result<(), Trap> {
+        let range = self.range(addr as u64, buf.len())?;
+        self.data[range].copy_from_slice(buf);
+        Ok(())
+    }
+
+    fn range(&self, addr: u64, len: usize) -> Result<::std::ops::Range<usize>, Trap> {
+        let end = addr.saturating_add(len as u64);
+        if end > self.data.len() as u64 {
+            return Err(Trap::MemoryOutOfBounds);
+        }
+        Ok(addr as usize..end as usize)
+    }
+
+    fn load(&self, addr: u64, len: usize) -> Result<u64, Trap> {
+        let range = self.range(addr, len)?;
+        Ok(self.data[range].iter().rev().fold(0, |acc, &b| acc << 8 | b as u64))
+    }
+
+    fn store(&mut self, addr: u64, len: usize, value: u64) -> Result<(), Trap> {
+        self.store_vector(addr, len, value as u128)
+    }
+
+    /// Like `load`, for up to the 16 bytes of a `v128`.
+    fn load_vector(&self, addr: u64, len: usize) -> Result<u128, Trap> {
+        let range = self.range(addr, len)?;
+        Ok(self.data[range].iter().rev().fold(0, |acc, &b| acc << 8 | b as u128))
+    }
+
+    fn store_vector(&mut self, addr: u64, len: usize, value: u128) -> Result<(), Trap> {
+        let range = self.range(addr, len)?;
+        for (i, b) in self.data[range].iter_mut().enumerate() {
+            *b = (value >> (i * 8)) as u8;
+        }
+        Ok(())
+    }
+}
+
+/// A table of references.
+#[derive(Clone)]
+pub struct Table {
+    element: RefType,
+    elements: Vec<Value>,
+    maximum: Option<u32>,
+}
+
+pub type TableRef = Rc<RefCell<Table>>;
+
+impl Table {
+    /// A table of `initial` null references of `element`, an `ElemType` or a nullable `RefType`.
+    /// Like with `Value::default`, the null of a defined type is the one of `any`.
+    pub fn new<E: Into<RefType>>(element: E, initial: u32, maximum: Option<u32>) -> Self {
+        let element = element.into();
+        Table {
+            element: element,
+            elements: vec![Value::default(element.into()); initial as usize],
+            maximum: maximum,
+        }
+    }
+
+    pub fn element(&self) -> RefType {
+        self.element
+    }
+
+    pub fn len(&self) -> u32 {
+        self.elements.len() as u32
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.elements.is_empty()
+    }
+
+    pub fn maximum(&self) -> Option<u32> {
+        self.maximum
+    }
+
+    /// `None` if `index` is out of bounds.
+    pub fn get(&self, index: u32) -> Option<Value> {
+        self.elements.get(index as usize).cloned()
+    }
+
+    /// It is not checked that `value` is a reference of the element type.
+    pub fn set(&mut self, index: u32, value: Value) -> Result<(), Trap> {
+        match self.elements.get_mut(index as usize) {
+            Some(slot) => {
+                *slot = value;
+                Ok(())
+            }
+            None => Err(Trap::TableOutOfBounds),
+        }
+    }
+
+    /// Grows the table by `delta` elements set to `init` and returns the
+    /// previous size, or `None` if the maximum would be exceeded.
+    pub fn grow(&mut self, delta: u32, init: Value) -> Option<u32> {
+        let len = self.len();
+        let limit = self.maximum.map_or(MAX_TABLE_SIZE, |m| m.min(MAX_TABLE_SIZE));
+        match len.checked_add(delta) {
+            Some(new) if new <= limit => {
+                self.elements.resize(new as usize, init);
+                Some(len)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// What a host function gets to see of the instance calling it.
+pub struct Caller<'a> {
+    instance: Option<&'a InstanceInner>,
+}
+
+impl<'a> Caller<'a> {
+    /// The first memory of the calling instance, if it has one.
+    pub fn memory(&self) -> Option<MemoryRef> {
+        self.instance.and_then(|i| i.memories.first().cloned())
+    }
+}
+
+type HostFn = dyn Fn(&Caller, &[Value]) -> Result<Vec<Value>, Trap>;
+
+/// A function, either provided by the host or defined by an instance.
+#[derive(Clone)]
+pub struct Func(Rc<FuncInner>);
+
+/// Functions are equal if they are the same function.
+impl PartialEq for Func {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+enum FuncInner {
+    Host { ty: FuncType, call: Box<HostFn> },
+    Wasm {
+        ty: FuncType,
+        instance: Weak<InstanceInner>,
+        /// among the functions the module defines
+        index: usize,
+    },
+}
+
+impl Func {
+    /// A host function of type `ty`. Returning values of other types traps with `Trap::HostResultMismatch`.
+    pub fn host<F>(ty: FuncType, call: F) -> Self
+        where F: Fn(&Caller, &[Value]) -> Result<Vec<Value>, Trap> + 'static
+    {
+        Func(Rc::new(FuncInner::Host {
+                         ty: ty,
+                         call: Box::new(call),
+                     }))
+    }
+
+    pub fn ty(&self) -> &FuncType {
+        match *self.0 {
+            FuncInner::Host { ref ty, .. } | FuncInner::Wasm { ref ty, .. } => ty,
+        }
+    }
+
+    /// Calls the function, checking the arguments against its type.
+    pub fn call(&self, args: &[Value]) -> Result<Vec<Value>, Error> {
+        let types = match *self.0 {
+            FuncInner::Wasm { ref instance, .. } => instance.upgrade().map(|i| i.types.clone()),
+            FuncInner::Host { .. } => None,
+        };
+        let types = types.unwrap_or_default();
+        if args.len() != self.ty().params.len() ||
+           args.iter().zip(self.ty().params.iter()).any(|(a, ty)| !has_type(a, *ty, &types)) {
+            return Err(Error::ArgumentMismatch);
+        }
+        let mut machine = Machine::default();
+        machine.stack.extend_from_slice(args);
+        machine.call(self, None)?;
+        Ok(machine.stack)
+    }
+
+    /// The index of its type if the instance with `types` defines it.
+    fn type_index(&self, types: &Rc<Types>) -> Option<TypeIndex> {
+        match *self.0 {
+            FuncInner::Host { .. } => None,
+            FuncInner::Wasm { ref instance, index, .. } => {
+                let instance = instance.upgrade()?;
+                if !Rc::ptr_eq(&instance.types, types) {
+                    return None;
+                }
+                Some(instance.module.functions.as_ref()?[index].0)
+            }
+        }
+    }
+}
+
+impl fmt::Debug for Func {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self.0 {
+            FuncInner::Host { ref ty, .. } => write!(f, "Func::Host({:?})", ty),
+            FuncInner::Wasm { ref ty, index, .. } => write!(f, "Func::Wasm({}, {:?})", index, ty),
+        }
+    }
+}
+
+/// An exception tag. Tags are equal if they are the same tag, not if their types are.
+#[derive(Clone)]
+pub struct Tag(Rc<FuncType>);
+
+impl Tag {
+    pub fn new(ty: FuncType) -> Self {
+        Tag(Rc::new(ty))
+    }
+
+    pub fn ty(&self) -> &FuncType {
+        &self.0
+    }
+}
+
+impl PartialEq for Tag {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl fmt::Debug for Tag {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Tag({:?})", self.0)
+    }
+}
+
+/// A thrown exception, the values of the parameters of its tag's type.
+/// Exceptions are equal if they are the same throw.
+#[derive(Clone)]
+pub struct Exception(Rc<(Tag, Vec<Value>)>);
+
+impl Exception {
+    /// It is not checked that `values` have the types of the tag's parameters.
+    pub fn new(tag: Tag, values: Vec<Value>) -> Self {
+        Exception(Rc::new((tag, values)))
+    }
+
+    pub fn tag(&self) -> &Tag {
+        &(self.0).0
+    }
+
+    pub fn values(&self) -> &[Value] {
+        &(self.0).1
+    }
+}
+
+impl PartialEq for Exception {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for Exception {}
+
+impl fmt::Debug for Exception {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Exception({:?}, {:?})", self.tag(), self.values())
+    }
+}
+
+pub enum Extern {
+    Func(Func),
+    /// an immutable global
+    Global(Value),
+    Memory(MemoryRef),
+    Table(TableRef),
+    Tag(Tag),
+}
+
+/// The values imports are resolved to, by module and field name.
+#[derive(Default)]
+pub struct Imports {
+    entries: HashMap<(String, String), Extern>,
+}
+
+impl Imports {
+    pub fn new() -> Self {
+        Imports::default()
+    }
+
+    pub fn add(mut self, module: &str, field: &str, value: Extern) -> Self {
+        self.entries.insert((module.to_string(), field.to_string()), value);
+        self
+    }
+
+    pub fn function<F>(self, module: &str, field: &str, ty: FuncType, call: F) -> Self
+        where F: Fn(&Caller, &[Value]) -> Result<Vec<Value>, Trap> + 'static
+    {
+        self.add(module, field, Extern::Func(Func::host(ty, call)))
+    }
+
+    pub fn global(self, module: &str, field: &str, value: Value) -> Self {
+        self.add(module, field, Extern::Global(value))
+    }
+
+    pub fn memory(self, module: &str, field: &str, memory: MemoryRef) -> Self {
+        self.add(module, field, Extern::Memory(memory))
+    }
+
+    pub fn table(self, module: &str, field: &str, table: TableRef) -> Self {
+        self.add(module, field, Extern::Table(table))
+    }
+
+    pub fn tag(self, module: &str, field: &str, tag: Tag) -> Self {
+        self.add(module, field, Extern::Tag(tag))
+    }
+}
+
+/// An instantiated module.
+pub struct Instance(Rc<InstanceInner>);
+
+/// The types of a module, shared by its instance and the objects the instance allocates.
+#[derive(Default)]
+struct Types {
+    sub_types: Vec<SubType>,
+    subtyping: Subtyping,
+}
+
+impl Types {
+    fn new(module: &Module) -> Self {
+        Types {
+            sub_types: module.sub_types().cloned().collect(),
+            subtyping: Subtyping::new(module.types.as_ref().map_or(&[][..], |t| &t[..])),
+        }
+    }
+
+    fn func_type(&self, index: TypeIndex) -> &FuncType {
+        self.sub_types[*index as usize].func_type().expect("validated function type")
+    }
+
+    fn struct_type(&self, index: TypeIndex) -> &[FieldType] {
+        match self.sub_types[*index as usize].composite {
+            CompositeType::Struct(ref fields) => fields,
+            _ => unreachable!("validated struct type"),
+        }
+    }
+
+    fn array_type(&self, index: TypeIndex) -> FieldType {
+        match self.sub_types[*index as usize].composite {
+            CompositeType::Array(field) => field,
+            _ => unreachable!("validated array type"),
+        }
+    }
+
+    /// The null reference of the hierarchy of `heap`.
+    fn null(&self, heap: HeapType) -> Value {
+        match self.subtyping.top(heap) {
+            Some(HeapType::Func) => Value::FuncRef(None),
+            Some(HeapType::Extern) => Value::ExternRef(None),
+            _ => Value::AnyRef(None),
+        }
+    }
+
+    /// Like `Value::default`, with the null of a defined type of its kind.
+    fn default(&self, ty: ValueType) -> Value {
+        match ty.ref_type() {
+            Some(ty) => self.null(ty.heap),
+            None => Value::default(ty),
+        }
+    }
+}
+
+/// Whether `value` is of type `ty`, a type of the instance with `types`. Functions
+/// of other instances match a defined type if they have its signature, objects of
+/// other instances only match abstract types.
+fn has_type(value: &Value, ty: ValueType, types: &Rc<Types>) -> bool {
+    let expected = match ty.ref_type() {
+        Some(ty) => ty,
+        None => return value.ty() == ty,
+    };
+    let found = match *value {
+        Value::FuncRef(None) => RefType::nullable(HeapType::NoFunc),
+        Value::ExternRef(None) => RefType::nullable(HeapType::NoExtern),
+        Value::AnyRef(None) => RefType::nullable(HeapType::None),
+        Value::ExternRef(Some(_)) => RefType::non_null(HeapType::Extern),
+        Value::FuncRef(Some(ref f)) => {
+            match (f.type_index(types), expected.heap) {
+                (Some(index), _) => RefType::non_null(HeapType::Type(index)),
+                (None, HeapType::Type(index)) => {
+                    return types.sub_types.get(*index as usize).and_then(|t| t.func_type()) == Some(f.ty());
+                }
+                (None, _) => RefType::non_null(HeapType::Func),
+            }
+        }
+        Value::AnyRef(Some(AnyRef::I31(_))) => RefType::non_null(HeapType::I31),
+        Value::AnyRef(Some(AnyRef::Extern(_))) => RefType::non_null(HeapType::Any),
+        Value::AnyRef(Some(AnyRef::Object(ref object))) => {
+            if Rc::ptr_eq(&object.0.types, types) {
+                RefType::non_null(HeapType::Type(object.0.ty))
+            } else if object.is_array() {
+                RefType::non_null(HeapType::Array)
+            } else {
+                RefType::non_null(HeapType::Struct)
+            }
+        }
+        _ => return false,
+    };
+    types.subtyping.is_ref_subtype(found, expected)
+}
+
+struct InstanceInner {
+    module: Module,
+    types: Rc<Types>,
+    /// the matching `End` of every `Block`, `Loop`, `If` and `Else`, per defined function
+    ends: Vec<Vec<usize>>,
+    /// the matching `Else` of every `If` that has one, per defined function
+    elses: Vec<HashMap<usize, usize>>,
+    /// the `Catch` and `CatchAll` of every `Try` that has any, per defined function
+    handlers: Vec<HashMap<usize, Vec<usize>>>,
+    funcs: Vec<Func>,
+    nimported_funcs: usize,
+    tables: Vec<TableRef>,
+    memories: Vec<MemoryRef>,
+    globals: Vec<Rc<RefCell<Value>>>,
+    tags: Vec<Tag>,
+    /// segments dropped by `elem.drop` and `data.drop`, and all but the
+    /// passive ones once the instance is initialized
+    dropped_elements: Vec<Cell<bool>>,
+    dropped_data: Vec<Cell<bool>>,
+}
+
+impl Instance {
+    /// Validates and instantiates `module`: resolves its imports, allocates
+    /// memories, tables and globals, initializes the segments and runs the
+    /// start function.
+    pub fn new(module: &Module, imports: &Imports) -> Result<Instance, Error> {
+        module.validate().map_err(Error::Invalid)?;
+
+        let types = Rc::new(Types::new(module));
+        let mut funcs = Vec::new();
+        let mut tables = Vec::new();
+        let mut memories = Vec::new();
+        let mut globals = Vec::new();
+        let mut tags = Vec::new();
+        for import in module.imports.iter().flat_map(|i| i.iter()) {
+            let incompatible = || {
+                Error::IncompatibleImport {
+                    module: import.module.clone(),
+                    field: import.field.clone(),
+                }
+            };
+            let provided = imports.entries
+                .get(&(import.module.clone(), import.field.clone()))
+                .ok_or_else(|| {
+                                Error::UnknownImport {
+                                    module: import.module.clone(),
+                                    field: import.field.clone(),
+                                }
+                            })?;
+            match (&import.kind, provided) {
+                (&ImportKind::Function(ref ty), &Extern::Func(ref f)) => {
+                    if f.ty() != types.func_type(*ty) {
+                        return Err(incompatible());
+                    }
+                    funcs.push(f.clone());
+                }
+                (&ImportKind::Global(ref ty), &Extern::Global(ref value)) => {
+                    if ty.mutable || !has_type(value, ty.content, &types) {
+                        return Err(incompatible());
+                    }
+                    globals.push(Rc::new(RefCell::new(value.clone())));
+                }
+                (&ImportKind::Memory(ref ty), &Extern::Memory(ref memory)) => {
+                    {
+                        let m = memory.borrow();
+                        if m.is_shared() != ty.shared || m.is_64() != ty.memory64 ||
+                           !limits_match(&ty.limits, m.pages(), m.maximum()) {
+                            return Err(incompatible());
+                        }
+                    }
+                    memories.push(memory.clone());
+                }
+                (&ImportKind::Table(ref ty), &Extern::Table(ref table)) => {
+                    {
+                        let t = table.borrow();
+                        if t.element() != ty.element || !limits_match(&ty.limits, t.len(), t.maximum()) {
+                            return Err(incompatible());
+                        }
+                    }
+                    tables.push(table.clone());
+                }
+                (&ImportKind::Tag(ref ty), &Extern::Tag(ref tag)) => {
+                    if tag.ty() != types.func_type(ty.ty) {
+                        return Err(incompatible());
+                    }
+                    tags.push(tag.clone());
+                }
+                _ => return Err(incompatible()),
+            }
+        }
+
+        for table in module.tables.iter().flat_map(|t| t.iter()) {
+            // validated to fit a u32
+            let table = Table {
+                element: table.element,
+                elements: vec![types.null(table.element.heap); table.limits.initial as usize],
+                maximum: table.limits.maximum.map(|m| m as u32),
+            };
+            tables.push(Rc::new(RefCell::new(table)));
+        }
+        for memory in module.memories.iter().flat_map(|m| m.iter()) {
+            // only a 64-bit memory can exceed the pages the interpreter grows memories to
+            if memory.limits.initial > MAX_PAGES as u64 {
+                return Err(Error::MemoryTooLarge);
+            }
+            let maximum = memory.limits.maximum.map(|m| m.min(MAX_PAGES as u64) as u32);
+            let mut m = Memory::new(memory.limits.initial as u32, maximum);
+            m.shared = memory.shared;
+            m.memory64 = memory.memory64;
+            memories.push(Rc::new(RefCell::new(m)));
+        }
+        for tag in module.tags.iter().flat_map(|t| t.iter()) {
+            tags.push(Tag::new(types.func_type(tag.ty).clone()));
+        }
+        let codes = module.codes.as_ref().map_or(&[][..], |c| &c[..]);
+        let (mut ends, mut elses, mut handlers) = (Vec::new(), Vec::new(), Vec::new());
+        for c in codes {
+            let blocks = match_blocks(&c.code.0);
+            ends.push(blocks.ends);
+            elses.push(blocks.elses);
+            handlers.push(blocks.handlers);
+        }
+        let nimported_funcs = funcs.len();
+        let mut trap = None;
+        let inner = Rc::new_cyclic(|weak| {
+            for (index, f) in module.functions.iter().flat_map(|f| f.iter()).enumerate() {
+                funcs.push(Func(Rc::new(FuncInner::Wasm {
+                                            ty: types.func_type(f.0).clone(),
+                                            instance: weak.clone(),
+                                            index: index,
+                                        })));
+            }
+            // globals come after the functions, which `ref.func` refers to
+            for global in module.globals.iter().flat_map(|g| g.iter()) {
+                match instance_const(&global.init, &types, &globals, &funcs) {
+                    Ok(value) => globals.push(Rc::new(RefCell::new(value))),
+                    Err(t) => {
+                        trap = Some(t);
+                        break;
+                    }
+                }
+            }
+            InstanceInner {
+                module: module.clone(),
+                types: types,
+                ends: ends,
+                elses: elses,
+                handlers: handlers,
+                nimported_funcs: nimported_funcs,
+                funcs: funcs,
+                tables: tables,
+                memories: memories,
+                globals: globals,
+                tags: tags,
+                dropped_elements: module.elements.iter().flat_map(|e| e.iter()).map(|_| Cell::new(false)).collect(),
+                dropped_data: module.data.iter().flat_map(|d| d.iter()).map(|_| Cell::new(false)).collect(),
+            }
+        });
+        if let Some(trap) = trap {
+            return Err(Error::Trap(trap));
+        }
+
+        // segments are copied in order, those before one that does not fit stay written
+        for (i, e) in module.elements.iter().flat_map(|e| e.iter()).enumerate() {
+            if let ElemMode::Active { ref index, ref offset } = e.mode {
+                let start = const_offset(offset, &inner.types, &inner.globals, &inner.funcs)? as u32;
+                inner.table_init(**index as usize, i, start, 0, e.elems.len() as u32)
+                    .map_err(|_| Error::SegmentDoesNotFit)?;
+            }
+            if let ElemMode::Active { .. } | ElemMode::Declarative = e.mode {
+                inner.dropped_elements[i].set(true);
+            }
+        }
+        for (i, d) in module.data.iter().flat_map(|d| d.iter()).enumerate() {
+            if let DataMode::Active { ref index, ref offset } = d.mode {
+                let start = const_offset(offset, &inner.types, &inner.globals, &inner.funcs)?;
+                inner.memory_init(**index as usize, i, start, 0, d.data.len() as u32)
+                    .map_err(|_| Error::SegmentDoesNotFit)?;
+                inner.dropped_data[i].set(true);
+            }
+        }
+
+        let instance = Instance(inner);
+        if let Some(start) = module.start {
+            let func = instance.0.funcs[start.0 as usize].clone();
+            let mut machine = Machine::default();
+            machine.call(&func, Some(&*instance.0))?;
+        }
+        Ok(instance)
+    }
+
+    fn export(&self, name: &str) -> Option<&ExportKind> {
+        self.0
+            .module
+            .exports
+            .iter()
+            .flat_map(|e| e.iter())
+            .find(|e| e.field == name)
+            .map(|e| &e.kind)
+    }
+
+    /// The exported function `name`.
+    pub fn func(&self, name: &str) -> Option<Func> {
+        match self.export(name) {
+            Some(&ExportKind::Function(ref f)) => self.0.funcs.get(f.0 as usize).cloned(),
+            _ => None,
+        }
+    }
+
+    /// The current value of the exported global `name`.
+    pub fn global(&self, name: &str) -> Option<Value> {
+        match self.export(name) {
+            Some(&ExportKind::Global(ref g)) => self.0.globals.get(**g as usize).map(|g| g.borrow().clone()),
+            _ => None,
+        }
+    }
+
+    pub fn memory(&self, name: &str) -> Option<MemoryRef> {
+        match self.export(name) {
+            Some(&ExportKind::Memory(ref m)) => self.0.memories.get(**m as usize).cloned(),
+            _ => None,
+        }
+    }
+
+    pub fn table(&self, name: &str) -> Option<TableRef> {
+        match self.export(name) {
+            Some(&ExportKind::Table(ref t)) => self.0.tables.get(**t as usize).cloned(),
+            _ => None,
+        }
+    }
+
+    pub fn tag(&self, name: &str) -> Option<Tag> {
+        match self.export(name) {
+            Some(&ExportKind::Tag(ref t)) => self.0.tags.get(**t as usize).cloned(),
+            _ => None,
+        }
+    }
+
+    /// Calls the exported function `name`.
+    pub fn invoke(&self, name: &str, args: &[Value]) -> Result<Vec<Value>, Error> {
+        match self.export(name) {
+            None => Err(Error::UnknownExport(name.to_string())),
+            Some(_) => self.func(name).ok_or(Error::ArgumentMismatch)?.call(args),
+        }
+    }
+}
+
+fn limits_match(declared: &ResizableLimits, size: u32, maximum: Option<u32>) -> bool {
+    size as u64 >= declared.initial &&
+    match (declared.maximum, maximum) {
+        (None, _) => true,
+        (Some(d), Some(m)) => m as u64 <= d,
+        (Some(_), None) => false,
+    }
+}
+
+fn function_space_index(index: &FunctionSpaceIndex) -> usize {
+    match index.0 {
+        InnerFunctionSpaceIndex::Import(ref i) => i.0 as usize,
+        InnerFunctionSpaceIndex::Function(ref f) => f.0 as usize,
+    }
+}
+
+/// The function at `i` in `table`, which `call_indirect` checks against the type `index`.
+fn indirect_callee(instance: &InstanceInner, index: u32, table: u32, i: u32) -> Result<Func, Trap> {
+    let callee = match instance.tables[table as usize].borrow().get(i) {
+        None => return Err(Trap::UndefinedElement),
+        Some(Value::FuncRef(Some(f))) => f,
+        Some(_) => return Err(Trap::UninitializedElement),
+    };
+    let ty = ValueType::Ref(RefType::non_null(HeapType::Type(TypeIndex::new(index))));
+    if !has_type(&Value::FuncRef(Some(callee.clone())), ty, &instance.types) {
+        return Err(Trap::IndirectCallTypeMismatch);
+    }
+    Ok(callee)
+}
+
+/// Folds the constant expression `expr`, where `globals` are the values of the globals it
+/// may read, the imported ones and those defined before the global it initializes. `None`
+/// if it is not a valid constant expression, reads a global past `globals`, takes a function
+/// reference or the null of a defined type, or allocates an object, which only an instance
+/// can do.
+pub fn eval_const(expr: &InitExpr, globals: &[Value]) -> Option<Value> {
+    fold_const(expr, None, |g| globals.get(g as usize).cloned(), |_| None)
+}
+
+fn fold_const<G, F>(expr: &InitExpr, types: Option<&Rc<Types>>, global: G, func: F) -> Option<Value>
+    where G: Fn(u32) -> Option<Value>,
+          F: Fn(&FunctionSpaceIndex) -> Option<Value>
+{
+    let mut stack = Vec::new();
+    for op in (expr.0).0.iter() {
+        let value = match *op {
+            Op::End => break,
+            Op::I32Const(v) => Value::I32(v),
+            Op::I64Const(v) => Value::I64(v),
+            Op::F32Const(v) => Value::F32(v),
+            Op::F64Const(v) => Value::F64(v),
+            Op::V128Const(v) => Value::V128(v),
+            Op::GetGlobal(ref g) => global(**g)?,
+            Op::RefNull(HeapType::Type(_)) if types.is_none() => return None,
+            Op::RefNull(heap) => types.map_or_else(|| Value::default(ValueType::Ref(RefType::nullable(heap))), |t| t.null(heap)),
+            Op::RefFunc(ref f) => func(f)?,
+            Op::StructNew { index } => {
+                let types = types?;
+                let fields = types.struct_type(index);
+                let values = stack.split_off(stack.len().checked_sub(fields.len())?);
+                Object::alloc(types, index, fields.iter().zip(values).map(|(f, v)| pack(f.storage, v)).collect())
+            }
+            Op::StructNewDefault { index } => {
+                let types = types?;
+                let values = types.struct_type(index).iter().map(|f| types.default(f.storage.unpacked())).collect();
+                Object::alloc(types, index, values)
+            }
+            Op::ArrayNew { index } | Op::ArrayNewDefault { index } => {
+                let types = types?;
+                let len = match stack.pop()? {
+                    Value::I32(len) => array_len(len).ok()?,
+                    _ => return None,
+                };
+                let storage = types.array_type(index).storage;
+                let value = match *op {
+                    Op::ArrayNew { .. } => pack(storage, stack.pop()?),
+                    _ => types.default(storage.unpacked()),
+                };
+                Object::alloc(types, index, vec![value; len])
+            }
+            Op::ArrayNewFixed { index, len } => {
+                let types = types?;
+                let storage = types.array_type(index).storage;
+                let values = stack.split_off(stack.len().checked_sub(len as usize)?);
+                Object::alloc(types, index, values.into_iter().map(|v| pack(storage, v)).collect())
+            }
+            Op::AnyConvertExtern => {
+                match stack.pop()? {
+                    v @ Value::ExternRef(_) => internalize(v),
+                    _ => return None,
+                }
+            }
+            Op::ExternConvertAny => {
+                match stack.pop()? {
+                    v @ Value::AnyRef(_) => externalize(v),
+                    _ => return None,
+                }
+            }
+            Op::RefI31 => {
+                match stack.pop()? {
+                    Value::I32(v) => Value::AnyRef(Some(AnyRef::I31(v as u32 & 0x7fff_ffff))),
+                    _ => return None,
+                }
+            }
+            Op::I32Add | Op::I32Sub | Op::I32Mul | Op::I64Add | Op::I64Sub | Op::I64Mul => {
+                let b = stack.pop()?;
+                let a = stack.pop()?;
+                match (op, a, b) {
+                    (&Op::I32Add, Value::I32(a), Value::I32(b)) => Value::I32(a.wrapping_add(b)),
+                    (&Op::I32Sub, Value::I32(a), Value::I32(b)) => Value::I32(a.wrapping_sub(b)),
+                    (&Op::I32Mul, Value::I32(a), Value::I32(b)) => Value::I32(a.wrapping_mul(b)),
+                    (&Op::I64Add, Value::I64(a), Value::I64(b)) => Value::I64(a.wrapping_add(b)),
+                    (&Op::I64Sub, Value::I64(a), Value::I64(b)) => Value::I64(a.wrapping_sub(b)),
+                    (&Op::I64Mul, Value::I64(a), Value::I64(b)) => Value::I64(a.wrapping_mul(b)),
+                    _ => return None,
+                }
+            }
+            _ => return None,
+        };
+        stack.push(value);
+    }
+    match stack.pop() {
+        Some(value) if stack.is_empty() => Some(value),
+        _ => None,
+    }
+}
+
+/// A validated constant expression of the instance whose types, globals and functions
+/// are given. Only the allocation of an array can fail.
+fn instance_const(expr: &InitExpr,
+                  types: &Rc<Types>,
+                  globals: &[Rc<RefCell<Value>>],
+                  funcs: &[Func])
+                  -> Result<Value, Trap> {
+    fold_const(expr,
+               Some(types),
+               |g| Some(globals[g as usize].borrow().clone()),
+               |f| Some(Value::FuncRef(Some(funcs[function_space_index(f)].clone()))))
+        .ok_or(Trap::AllocationTooLarge)
+}
+
+/// The offset of an active segment, an `i64` for a 64-bit memory.
+fn const_offset(expr: &InitExpr,
+                types: &Rc<Types>,
+                globals: &[Rc<RefCell<Value>>],
+                funcs: &[Func])
+                -> Result<u64, Trap> {
+    match instance_const(expr, types, globals, funcs)? {
+        Value::I32(v) => Ok(v as u32 as u64),
+        Value::I64(v) => Ok(v as u64),
+        _ => unreachable!("validated offset"),
+    }
+}
+
+/// The length of an array to allocate.
+fn array_len(len: i32) -> Result<usize, Trap> {
+    if len as u32 > MAX_ARRAY_LEN {
+        return Err(Trap::AllocationTooLarge);
+    }
+    Ok(len as u32 as usize)
+}
+
+/// The elements `[i, i + n)` of an array of `len` elements.
+fn array_range(len: usize, i: u32, n: u32) -> Result<::std::ops::Range<usize>, Trap> {
+    if i as u64 + n as u64 > len as u64 {
+        return Err(Trap::ArrayOutOfBounds);
+    }
+    Ok(i as usize..i as usize + n as usize)
+}
+
+/// A value written to a field of type `storage`, wrapped to the width of a packed field.
+fn pack(storage: StorageType, value: Value) -> Value {
+    match (storage, value) {
+        (StorageType::I8, Value::I32(v)) => Value::I32(v & 0xff),
+        (StorageType::I16, Value::I32(v)) => Value::I32(v & 0xffff),
+        (_, value) => value,
+    }
+}
+
+/// A value read from a field of type `storage`, sign-extended from a packed field if `signed`.
+fn unpack(storage: StorageType, value: Value, signed: bool) -> Value {
+    match (storage, value) {
+        (StorageType::I8, Value::I32(v)) if signed => Value::I32(v as i8 as i32),
+        (StorageType::I16, Value::I32(v)) if signed => Value::I32(v as i16 as i32),
+        (_, value) => value,
+    }
+}
+
+impl InstanceInner {
+    /// Copies `n` references from element segment `segment`, starting at
+    /// `src`, into table `table` at `dst`. A dropped segment is empty.
+    fn table_init(&self, table: usize, segment: usize, dst: u32, src: u32, n: u32) -> Result<(), Trap> {
+        let values = self.elements(segment, src, n)?;
+        let mut table = self.tables[table].borrow_mut();
+        if dst as u64 + n as u64 > table.len() as u64 {
+            return Err(Trap::TableOutOfBounds);
+        }
+        table.elements[dst as usize..dst as usize + n as usize].clone_from_slice(&values);
+        Ok(())
+    }
+
+    /// The `n` references of element segment `segment` from `src`. A dropped segment is empty.
+    fn elements(&self, segment: usize, src: u32, n: u32) -> Result<Vec<Value>, Trap> {
+        let elems = &self.module.elements.as_ref().unwrap()[segment].elems;
+        let len = if self.dropped_elements[segment].get() { 0 } else { elems.len() };
+        if src as u64 + n as u64 > len as u64 {
+            return Err(Trap::TableOutOfBounds);
+        }
+        (src as usize..src as usize + n as usize)
+            .map(|i| match *elems {
+                     Elements::Functions(ref fs) => Ok(Value::FuncRef(Some(self.funcs[function_space_index(&fs[i])].clone()))),
+                     Elements::Expressions(_, ref es) => instance_const(&es[i], &self.types, &self.globals, &self.funcs),
+                 })
+            .collect()
+    }
+
+    /// The `n` elements of an array of type `index` stored in data segment `segment` from `src`.
+    fn array_data(&self, index: TypeIndex, segment: usize, src: u32, n: u32) -> Result<Vec<Value>, Trap> {
+        let storage = self.types.array_type(index).storage;
+        let size = match storage.unpacked() {
+            _ if storage == StorageType::I8 => 1,
+            _ if storage == StorageType::I16 => 2,
+            ValueType::I32 | ValueType::F32 => 4,
+            ValueType::I64 | ValueType::F64 => 8,
+            ValueType::V128 => 16,
+            _ => unreachable!("validated numeric array"),
+        };
+        let data = &self.module.data.as_ref().unwrap()[segment].data;
+        let len = if self.dropped_data[segment].get() { 0 } else { data.len() };
+        let end = src as u64 + n as u64 * size as u64;
+        if end > len as u64 {
+            return Err(Trap::MemoryOutOfBounds);
+        }
+        let values = data[src as usize..end as usize].chunks(size).map(|bytes| {
+            let bits = bytes.iter().rev().fold(0, |acc, &b| acc << 8 | b as u128);
+            match storage.unpacked() {
+                ValueType::I64 => Value::I64(bits as i64),
+                ValueType::F32 => Value::F32(f32::from_bits(bits as u32)),
+                ValueType::F64 => Value::F64(f64::from_bits(bits as u64)),
+                ValueType::V128 => Value::V128(bits),
+                _ => Value::I32(bits as i32),
+            }
+        });
+        Ok(values.collect())
+    }
+
+    /// Copies `n` bytes from data segment `segment`, starting at `src`, into
+    /// memory `memory` at `dst`. A dropped segment is empty.
+    fn memory_init(&self, memory: usize, segment: usize, dst: u64, src: u32, n: u32) -> Result<(), Trap> {
+        let data = &self.module.data.as_ref().unwrap()[segment].data;
+        let len = if self.dropped_data[segment].get() { 0 } else { data.len() };
+        if src as u64 + n as u64 > len as u64 {
+            return Err(Trap::MemoryOutOfBounds);
+        }
+        let src = &data[src as usize..src as usize + n as usize];
+        let mut memory = self.memories[memory].borrow_mut();
+        let dst = memory.range(dst, src.len())?;
+        memory.data[dst].copy_from_slice(src);
+        Ok(())
+    }
+}
+
+/// The structured instructions of a function body, paired up. The `End` of a
+/// `Try` is also the one of its handlers, and a `Delegate` ends its `Try`.
+struct Blocks {
+    ends: Vec<usize>,
+    elses: HashMap<usize, usize>,
+    handlers: HashMap<usize, Vec<usize>>,
+}
+
+fn match_blocks(code: &[Op]) -> Blocks {
+    let mut ends = vec![0; code.len()];
+    let mut elses = HashMap::new();
+    let mut handlers: HashMap<usize, Vec<usize>> = HashMap::new();
+    let mut open = Vec::new();
+    for (i, op) in code.iter().enumerate() {
+        match *op {
+            Op::Block { .. } | Op::Loop { .. } | Op::If { .. } | Op::Try { .. } => open.push(i),
+            Op::Else => {
+                if let Some(&start) = open.last() {
+                    elses.insert(start, i);
+                }
+                open.push(i);
+            }
+            Op::Catch(_) | Op::CatchAll => {
+                if let Some(&start) = open.last() {
+                    handlers.entry(start).or_default().push(i);
+                }
+            }
+            Op::End | Op::Delegate { .. } => {
+                if let Some(start) = open.pop() {
+                    ends[start] = i;
+                    if let Op::Else = code[start] {
+                        let if_ = open.pop().unwrap();
+                        ends[if_] = i;
+                    }
+                    for &handler in handlers.get(&start).iter().flat_map(|h| h.iter()) {
+                        ends[handler] = i;
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    Blocks {
+        ends: ends,
+        elses: elses,
+        handlers: handlers,
+    }
+}
+
+struct Label {
+    arity: usize,
+    /// the height of the value stack when the block was entered
+    height: usize,
+    /// where a branch to the label continues
+    target: usize,
+    is_loop: bool,
+    /// the `Try` while its body runs, whose handlers catch exceptions thrown in it
+    try_: Option<usize>,
+    /// the exception a `catch` or `catch_all` handler runs for, which `rethrow` throws again
+    caught: Option<Exception>,
+}
+
+struct Frame {
+    instance: Rc<InstanceInner>,
+    /// among the functions the module defines
+    func: usize,
+    pc: usize,
+    locals: Vec<Value>,
+    labels: Vec<Label>,
+    arity: usize,
+    height: usize,
+}
+
+#[derive(Default)]
+struct Machine {
+    stack: Vec<Value>,
+    frames: Vec<Frame>,
+}
+
+macro_rules! pop {
+    ($m: ident, $ty: ident) => {
+        match $m.stack.pop() {
+            Some(Value::$ty(v)) => v,
+            _ => unreachable!("validated operand"),
+        }
+    };
+}
+
+/// An address or a length into a memory, the `i64` of a 64-bit memory or a zero-extended `i32`.
+macro_rules! pop_address {
+    ($m: ident) => {
+        match $m.stack.pop() {
+            Some(Value::I32(v)) => v as u32 as u64,
+            Some(Value::I64(v)) => v as u64,
+            _ => unreachable!("validated operand"),
+        }
+    };
+}
+
+macro_rules! unop {
+    ($m: ident, $ty: ident -> $res: ident, |$a: ident| $e: expr) => {{
+        let $a = pop!($m, $ty);
+        $m.stack.push(Value::$res($e));
+    }};
+    ($m: ident, $ty: ident, |$a: ident| $e: expr) => {
+        unop!($m, $ty -> $ty, |$a| $e)
+    };
+}
+
+macro_rules! binop {
+    ($m: ident, $ty: ident -> $res: ident, |$a: ident, $b: ident| $e: expr) => {{
+        let $b = pop!($m, $ty);
+        let $a = pop!($m, $ty);
+        $m.stack.push(Value::$res($e));
+    }};
+    ($m: ident, $ty: ident, |$a: ident, $b: ident| $e: expr) => {
+        binop!($m, $ty -> $ty, |$a, $b| $e)
+    };
+}
+
+macro_rules! load {
+    ($m: ident, $memory: expr, $imm: expr, $len: expr, $res: ident, |$v: ident| $e: expr) => {{
+        let addr = pop_address!($m);
+        let $v = $memory($imm).borrow().load(effective_address(addr, $imm), $len)?;
+        $m.stack.push(Value::$res($e));
+    }};
+}
+
+macro_rules! store {
+    ($m: ident, $memory: expr, $imm: expr, $len: expr, $ty: ident, |$v: ident| $e: expr) => {{
+        let $v = pop!($m, $ty);
+        let addr = pop_address!($m);
+        $memory($imm).borrow_mut().store(effective_address(addr, $imm), $len, $e)?;
+    }};
+}
+
+macro_rules! atomic_load {
+    ($m: ident, $memory: expr, $imm: expr, $len: expr, $res: ident, |$v: ident| $e: expr) => {{
+        let addr = pop_address!($m);
+        let memory = $memory($imm).borrow();
+        let $v = memory.load(atomic_address(&memory, addr, $imm, $len)?, $len)?;
+        $m.stack.push(Value::$res($e));
+    }};
+}
+
+macro_rules! atomic_store {
+    ($m: ident, $memory: expr, $imm: expr, $len: expr, $ty: ident, |$v: ident| $e: expr) => {{
+        let $v = pop!($m, $ty);
+        let addr = pop_address!($m);
+        let mut memory = $memory($imm).borrow_mut();
+        let addr = atomic_address(&memory, addr, $imm, $len)?;
+        memory.store(addr, $len, $e)?;
+    }};
+}
+
+/// Stores `$e` computed from the `$old` bits and the operand `$v`, pushing `$old`.
+macro_rules! atomic_rmw {
+    ($m: ident, $memory: expr, $imm: expr, $len: expr, $ty: ident, $t: ty, |$old: ident, $v: ident| $e: expr) => {{
+        let $v = pop!($m, $ty) as u64;
+        let addr = pop_address!($m);
+        let mut memory = $memory($imm).borrow_mut();
+        let addr = atomic_address(&memory, addr, $imm, $len)?;
+        let $old = memory.load(addr, $len)?;
+        memory.store(addr, $len, $e)?;
+        $m.stack.push(Value::$ty($old as $t));
+    }};
+}
+
+macro_rules! atomic_cmpxchg {
+    ($m: ident, $memory: expr, $imm: expr, $len: expr, $ty: ident, $t: ty) => {{
+        let replacement = pop!($m, $ty) as u64;
+        let expected = pop!($m, $ty) as u64 & atomic_mask($len);
+        let addr = pop_address!($m);
+        let mut memory = $memory($imm).borrow_mut();
+        let addr = atomic_address(&memory, addr, $imm, $len)?;
+        let old = memory.load(addr, $len)?;
+        if old == expected {
+            memory.store(addr, $len, replacement)?;
+        }
+        $m.stack.push(Value::$ty(old as $t));
+    }};
+}
+
+/// No other thread shares the memory, so a wait on a matching value can only time out.
+macro_rules! atomic_wait {
+    ($m: ident, $memory: expr, $imm: expr, $len: expr, $ty: ident) => {{
+        let timeout = pop!($m, I64);
+        let expected = pop!($m, $ty) as u64 & atomic_mask($len);
+        let addr = pop_address!($m);
+        let memory = $memory($imm).borrow();
+        let addr = atomic_address(&memory, addr, $imm, $len)?;
+        if !memory.shared {
+            return Err(Trap::ExpectedSharedMemory);
+        }
+        let result = if memory.load(addr, $len)? != expected {
+            1
+        } else if timeout >= 0 {
+            2
+        } else {
+            return Err(Trap::Deadlock);
+        };
+        $m.stack.push(Value::I32(result));
+    }};
+}
+
+macro_rules! float_min {
+    ($a: expr, $b: expr) => {{
+        let (a, b) = ($a, $b);
+        if a.is_nan() || b.is_nan() {
+            a + b
+        } else if a == b {
+            // -0 is smaller than +0
+            if a.is_sign_negative() { a } else { b }
+        } else {
+            a.min(b)
+        }
+    }};
+}
+
+macro_rules! float_max {
+    ($a: expr, $b: expr) => {{
+        let (a, b) = ($a, $b);
+        if a.is_nan() || b.is_nan() {
+            a + b
+        } else if a == b {
+            if a.is_sign_negative() { b } else { a }
+        } else {
+            a.max(b)
+        }
+    }};
+}
+
+macro_rules! vector_load {
+    ($m: ident, $memory: expr, $imm: expr, $len: expr, |$v: ident| $e: expr) => {{
+        let addr = pop_address!($m);
+        let $v = $memory($imm).borrow().load_vector(effective_address(addr, $imm), $len)?;
+        $m.stack.push(Value::V128($e));
+    }};
+}
+
+macro_rules! load_lane {
+    ($m: ident, $memory: expr, $imm: expr, $lane: expr, $t: ty) => {{
+        let v = pop!($m, V128);
+        let addr = pop_address!($m);
+        let x = $memory($imm).borrow().load_vector(effective_address(addr, $imm), mem::size_of::<$t>())?;
+        $m.stack.push(Value::V128(replace_lane(v, $lane, x as $t)));
+    }};
+}
+
+macro_rules! store_lane {
+    ($m: ident, $memory: expr, $imm: expr, $lane: expr, $t: ty) => {{
+        let v = pop!($m, V128);
+        let addr = pop_address!($m);
+        let x = <$t as Lane>::extract(v, $lane as usize).bits();
+        $memory($imm).borrow_mut().store_vector(effective_address(addr, $imm), mem::size_of::<$t>(), x)?;
+    }};
+}
+
+/// A lane of a `v128` as the vector instructions see it, lane 0 in the lowest bits.
+trait Lane: Copy {
+    const COUNT: usize;
+    fn extract(v: u128, i: usize) -> Self;
+    /// the bits of the lane, zero-extended
+    fn bits(self) -> u128;
+}
+
+macro_rules! impl_lane {
+    ($t: ty, $bits: ty, $count: expr) => {
+        impl Lane for $t {
+            const COUNT: usize = $count;
+            fn extract(v: u128, i: usize) -> Self {
+                (v >> (i * 128 / $count)) as $bits as $t
+            }
+            fn bits(self) -> u128 {
+                self as $bits as u128
+            }
+        }
+    };
+    (float $t: ty, $bits: ty, $count: expr) => {
+        impl Lane for $t {
+            const COUNT: usize = $count;
+            fn extract(v: u128, i: usize) -> Self {
+                <$t>::from_bits((v >> (i * 128 / $count)) as $bits)
+            }
+            fn bits(self) -> u128 {
+                self.to_bits() as u128
+            }
+        }
+    };
+}
+
+impl_lane!(i8, u8, 16);
+impl_lane!(u8, u8, 16);
+impl_lane!(i16, u16, 8);
+impl_lane!(u16, u16, 8);
+impl_lane!(i32, u32, 4);
+impl_lane!(u32, u32, 4);
+impl_lane!(i64, u64, 2);
+impl_lane!(u64, u64, 2);
+impl_lane!(float f32, u32, 4);
+impl_lane!(float f64, u64, 2);
+
+fn lanes<T: Lane>(v: u128) -> impl Iterator<Item = T> {
+    (0..T::COUNT).map(move |i| T::extract(v, i))
+}
+
+/// Packs lanes into a `v128`, the lanes not given being zero.
+fn from_lanes<T: Lane, I: IntoIterator<Item = T>>(lanes: I) -> u128 {
+    lanes.into_iter().enumerate().fold(0, |v, (i, x)| v | x.bits() << (i * 128 / T::COUNT))
+}
+
+fn splat<T: Lane>(x: T) -> u128 {
+    from_lanes((0..T::COUNT).map(|_| x))
+}
+
+fn replace_lane<T: Lane>(v: u128, i: u8, x: T) -> u128 {
+    let width = 128 / T::COUNT;
+    let shift = i as usize * width;
+    let mask = (!0 >> (128 - width)) << shift;
+    v & !mask | x.bits() << shift
+}
+
+/// Extends the lanes from `skip` on to twice their width.
+fn widen<A: Lane, R: Lane + From<A>>(v: u128, skip: usize) -> u128 {
+    from_lanes(lanes::<A>(v).skip(skip).take(R::COUNT).map(R::from))
+}
+
+/// All ones in a lane for true.
+macro_rules! mask {
+    ($e: expr, $t: ty) => {
+        -($e as $t)
+    };
+}
+
+/// The number of values a block takes and leaves.
+fn block_arity(types: &Types, sig: &BlockType) -> (usize, usize) {
+    match *sig {
+        BlockType::Empty => (0, 0),
+        BlockType::Value(_) => (0, 1),
+        BlockType::Type(index) => {
+            let ty = types.func_type(index);
+            (ty.params.len(), ty.results.len())
+        }
+    }
+}
+
+/// An address past the end of the address space is out of bounds of any memory.
+fn effective_address(addr: u64, imm: &MemoryImmediate) -> u64 {
+    addr.saturating_add(imm.offset)
+}
+
+/// The effective address of an atomic access of `len` bytes, which must be in bounds and aligned.
+fn atomic_address(memory: &Memory, addr: u64, imm: &MemoryImmediate, len: usize) -> Result<u64, Trap> {
+    let addr = effective_address(addr, imm);
+    memory.range(addr, len)?;
+    if !addr.is_multiple_of(len as u64) {
+        return Err(Trap::UnalignedAtomic);
+    }
+    Ok(addr)
+}
+
+fn atomic_mask(len: usize) -> u64 {
+    u64::MAX >> (64 - 8 * len)
+}
+
+/// Truncates `x` towards zero, trapping unless the result lies in `[min, max)`.
+fn trunc(x: f64, min: f64, max: f64) -> Result<f64, Trap> {
+    if x.is_nan() {
+        return Err(Trap::InvalidConversionToInteger);
+    }
+    let t = x.trunc();
+    if t < min || t >= max {
+        return Err(Trap::IntegerOverflow);
+    }
+    Ok(t)
+}
+
+const SIGN_32: u32 = 1 << 31;
+const SIGN_64: u64 = 1 << 63;
+
+impl Machine {
+    /// Calls `func` with its arguments on the stack, leaving the result there.
+    fn call(&mut self, func: &Func, caller: Option<&InstanceInner>) -> Result<(), Trap> {
+        match *func.0 {
+            FuncInner::Host { ref ty, ref call } => {
+                let args = self.stack.split_off(self.stack.len() - ty.params.len());
+                let result = call(&Caller { instance: caller }, &args)?;
+                if result.len() != ty.results.len() ||
+                   result.iter().zip(ty.results.iter()).any(|(v, ty)| v.ty() != *ty) {
+                    return Err(Trap::HostResultMismatch);
+                }
+                self.stack.extend(result);
+                Ok(())
+            }
+            FuncInner::Wasm { ref instance, index, .. } => {
+                // the instance is gone if only a table shared with the host still refers to the function
+                let instance = instance.upgrade().ok_or(Trap::UninitializedElement)?;
+                let base = self.frames.len();
+                self.enter(instance, index)?;
+                self.run(base)
+            }
+        }
+    }
+
+    fn enter(&mut self, instance: Rc<InstanceInner>, func: usize) -> Result<(), Trap> {
+        if self.frames.len() == MAX_FRAMES {
+            return Err(Trap::StackExhausted);
+        }
+        let (arity, locals) = {
+            let ty = instance.funcs[instance.nimported_funcs + func].ty();
+            let mut locals = self.stack.split_off(self.stack.len() - ty.params.len());
+            for entry in instance.module.codes.as_ref().unwrap()[func].locals.iter() {
+                locals.extend((0..entry.count).map(|_| instance.types.default(entry.ty)));
+            }
+            (ty.results.len(), locals)
+        };
+        let height = self.stack.len();
+        self.frames.push(Frame {
+                             instance: instance,
+                             func: func,
+                             pc: 0,
+                             locals: locals,
+                             labels: Vec::new(),
+                             arity: arity,
+                             height: height,
+                         });
+        Ok(())
+    }
+
+    /// Pops the top frame, leaving its results on the stack.
+    fn leave(&mut self) {
+        let frame = self.frames.pop().unwrap();
+        let results = self.stack.split_off(self.stack.len() - frame.arity);
+        self.stack.truncate(frame.height);
+        self.stack.extend(results);
+    }
+
+    /// Branches to the label `depth` levels up and returns where to continue,
+    /// or `None` if the label is the one of the function body.
+    fn branch(&mut self, depth: u32) -> Option<usize> {
+        let frame = self.frames.last_mut().unwrap();
+        if depth as usize == frame.labels.len() {
+            return None;
+        }
+        let index = frame.labels.len() - 1 - depth as usize;
+        let (arity, height, target, is_loop) = {
+            let label = &frame.labels[index];
+            (label.arity, label.height, label.target, label.is_loop)
+        };
+        // a loop label stays, since the branch re-enters the loop
+        frame.labels.truncate(if is_loop { index + 1 } else { index });
+        let results = self.stack.split_off(self.stack.len() - arity);
+        self.stack.truncate(height);
+        self.stack.extend(results);
+        Some(target)
+    }
+
+    /// Runs until the frame at `base` returns, passing thrown exceptions to their handlers.
+    fn run(&mut self, base: usize) -> Result<(), Trap> {
+        loop {
+            match self.execute(base) {
+                Err(Trap::Exception(ref e)) if self.catch(e, base) => {}
+                result => return result,
+            }
+        }
+    }
+
+    /// Unwinds to the innermost handler of `exception` in the frames above
+    /// `base` and continues there. Returns false, with those frames popped,
+    /// if none catches it.
+    fn catch(&mut self, exception: &Exception, base: usize) -> bool {
+        while self.frames.len() > base {
+            {
+                let frame = self.frames.last_mut().unwrap();
+                let instance = frame.instance.clone();
+                let code = &instance.module.codes.as_ref().unwrap()[frame.func].code.0;
+                let mut i = frame.labels.len();
+                while i > 0 {
+                    i -= 1;
+                    let try_ = match frame.labels[i].try_ {
+                        Some(try_) => try_,
+                        None => continue,
+                    };
+                    for &handler in instance.handlers[frame.func].get(&try_).iter().flat_map(|h| h.iter()) {
+                        let values = match code[handler] {
+                            Op::Catch(ref tag) if instance.tags[**tag as usize] == *exception.tag() => {
+                                exception.values().to_vec()
+                            }
+                            Op::CatchAll => Vec::new(),
+                            _ => continue,
+                        };
+                        frame.labels.truncate(i + 1);
+                        let label = &mut frame.labels[i];
+                        label.try_ = None;
+                        label.caught = Some(exception.clone());
+                        self.stack.truncate(label.height);
+                        self.stack.extend(values);
+                        frame.pc = handler + 1;
+                        return true;
+                    }
+                    // the search goes on inside the label `depth` levels outside of the
+                    // `try`, or in the caller if that is the function body
+                    if let Op::Delegate { depth } = code[instance.ends[frame.func][try_]] {
+                        if depth as usize >= i {
+                            break;
+                        }
+                        i -= depth as usize;
+                    }
+                }
+            }
+            let frame = self.frames.pop().unwrap();
+            self.stack.truncate(frame.height);
+        }
+        false
+    }
+
+    /// Runs until the frame at `base` returns or an instruction traps.
+    fn execute(&mut self, base: usize) -> Result<(), Trap> {
+        use ops::Op::*;
+        'frames: while self.frames.len() > base {
+            let instance = self.frames.last().unwrap().instance.clone();
+            let func = self.frames.last().unwrap().func;
+            let code = &instance.module.codes.as_ref().unwrap()[func].code.0;
+            let ends = &instance.ends[func];
+            let memory = |imm: &MemoryImmediate| &instance.memories[*imm.memory as usize];
+            loop {
+                let pc = self.frames.last().unwrap().pc;
+                if pc == code.len() {
+                    self.leave();
+                    continue 'frames;
+                }
+                self.frames.last_mut().unwrap().pc = pc + 1;
+                let mut next = None;
+                let mut branch = None;
+                match code[pc] {
+                    Unreachable => return Err(Trap::Unreachable),
+                    Nop => {}
+                    Block { ref sig } => {
+                        let (params, results) = block_arity(&instance.types, sig);
+                        let label = Label {
+                            arity: results,
+                            height: self.stack.len() - params,
+                            target: ends[pc] + 1,
+                            is_loop: false,
+                            try_: None,
+                            caught: None,
+                        };
+                        self.frames.last_mut().unwrap().labels.push(label);
+                    }
+                    Loop { ref sig } => {
+                        let (params, _) = block_arity(&instance.types, sig);
+                        let label = Label {
+                            arity: params,
+                            height: self.stack.len() - params,
+                            target: pc + 1,
+                            is_loop: true,
+                            try_: None,
+                            caught: None,
+                        };
+                        self.frames.last_mut().unwrap().labels.push(label);
+                    }
+                    If { ref sig } => {
+                        let cond = pop!(self, I32);
+                        let (params, results) = block_arity(&instance.types, sig);
+                        let label = Label {
+                            arity: results,
+                            height: self.stack.len() - params,
+                            target: ends[pc] + 1,
+                            is_loop: false,
+                            try_: None,
+                            caught: None,
+                        };
+                        self.frames.last_mut().unwrap().labels.push(label);
+                        if cond == 0 {
+                            next = Some(match instance.elses[func].get(&pc) {
+                                            Some(&else_) => else_ + 1,
+                                            // the `End` pops the label
+                                            None => ends[pc],
+                                        });
+                        }
+                    }
+                    // the end of the `then` branch
+                    Else => next = Some(ends[pc]),
+                    End => {
+                        self.frames.last_mut().unwrap().labels.pop();
+                    }
+                    Try { ref sig } => {
+                        let (params, results) = block_arity(&instance.types, sig);
+                        let label = Label {
+                            arity: results,
+                            height: self.stack.len() - params,
+                            target: ends[pc] + 1,
+                            is_loop: false,
+                            try_: Some(pc),
+                            caught: None,
+                        };
+                        self.frames.last_mut().unwrap().labels.push(label);
+                    }
+                    // the end of the `try` body or of a handler, the `End` pops the label
+                    Catch(_) | CatchAll => next = Some(ends[pc]),
+                    Delegate { .. } => {
+                        self.frames.last_mut().unwrap().labels.pop();
+                    }
+                    Throw(ref tag) => {
+                        let tag = instance.tags[**tag as usize].clone();
+                        let values = self.stack.split_off(self.stack.len() - tag.ty().params.len());
+                        return Err(Trap::Exception(Exception::new(tag, values)));
+                    }
+                    Rethrow { depth } => {
+                        let labels = &self.frames.last().unwrap().labels;
+                        let caught = labels[labels.len() - 1 - depth as usize].caught.clone();
+                        return Err(Trap::Exception(caught.expect("validated rethrow")));
+                    }
+                    Br { depth } => branch = Some(depth),
+                    BrIf { depth } => {
+                        if pop!(self, I32) != 0 {
+                            branch = Some(depth);
+                        }
+                    }
+                    BrTable(ref target) => {
+                        let i = pop!(self, I32) as u32 as usize;
+                        branch = Some(*target.table.get(i).unwrap_or(&target.default_target));
+                    }
+                    Return => {
+                        self.leave();
+                        continue 'frames;
+                    }
+                    Call { ref index } => {
+                        let callee = instance.funcs[function_space_index(index)].clone();
+                        if self.call_func(&callee, &instance)? {
+                            continue 'frames;
+                        }
+                    }
+                    CallIndirect { ref index, ref table } => {
+                        let i = pop!(self, I32) as u32;
+                        let callee = indirect_callee(&instance, **index, **table, i)?;
+                        if self.call_func(&callee, &instance)? {
+                            continue 'frames;
+                        }
+                    }
+                    ReturnCall { ref index } => {
+                        let callee = instance.funcs[function_space_index(index)].clone();
+                        self.return_call(&callee, &instance)?;
+                        continue 'frames;
+                    }
+                    ReturnCallIndirect { ref index, ref table } => {
+                        let i = pop!(self, I32) as u32;
+                        let callee = indirect_callee(&instance, **index, **table, i)?;
+                        self.return_call(&callee, &instance)?;
+                        continue 'frames;
+                    }
+                    CallRef { .. } => {
+                        let callee = self.pop_func()?;
+                        if self.call_func(&callee, &instance)? {
+                            continue 'frames;
+                        }
+                    }
+                    ReturnCallRef { .. } => {
+                        let callee = self.pop_func()?;
+                        self.return_call(&callee, &instance)?;
+                        continue 'frames;
+                    }
+                    BrOnNull { depth } => {
+                        if self.stack.last().unwrap().is_null() {
+                            self.stack.pop();
+                            branch = Some(depth);
+                        }
+                    }
+                    BrOnNonNull { depth } => {
+                        if self.stack.last().unwrap().is_null() {
+                            self.stack.pop();
+                        } else {
+                            branch = Some(depth);
+                        }
+                    }
+                    BrOnCast { depth, to, .. } => {
+                        if has_type(self.stack.last().unwrap(), ValueType::Ref(to), &instance.types) {
+                            branch = Some(depth);
+                        }
+                    }
+                    BrOnCastFail { depth, to, .. } => {
+                        if !has_type(self.stack.last().unwrap(), ValueType::Ref(to), &instance.types) {
+                            branch = Some(depth);
+                        }
+                    }
+                    Drop => {
+                        self.stack.pop();
+                    }
+                    Select | TypedSelect(_) => {
+                        let cond = pop!(self, I32);
+                        let b = self.stack.pop().unwrap();
+                        let a = self.stack.pop().unwrap();
+                        self.stack.push(if cond != 0 { a } else { b });
+                    }
+                    GetLocal(ref i) => {
+                        let v = self.frames.last().unwrap().locals[**i as usize].clone();
+                        self.stack.push(v);
+                    }
+                    SetLocal(ref i) => {
+                        let v = self.stack.pop().unwrap();
+                        self.frames.last_mut().unwrap().locals[**i as usize] = v;
+                    }
+                    TeeLocal(ref i) => {
+                        let v = self.stack.last().unwrap().clone();
+                        self.frames.last_mut().unwrap().locals[**i as usize] = v;
+                    }
+                    GetGlobal(ref i) => self.stack.push(instance.globals[**i as usize].borrow().clone()),
+                    SetGlobal(ref i) => {
+                        let v = self.stack.pop().unwrap();
+                        *instance.globals[**i as usize].borrow_mut() = v;
+                    }
+                    TableGet(ref t) => {
+                        let i = pop!(self, I32) as u32;
+                        let v = instance.tables[**t as usize].borrow().get(i).ok_or(Trap::TableOutOfBounds)?;
+                        self.stack.push(v);
+                    }
+                    TableSet(ref t) => {
+                        let v = self.stack.pop().unwrap();
+                        let i = pop!(self, I32) as u32;
+                        instance.tables[**t as usize].borrow_mut().set(i, v)?;
+                    }
+                    I32Load { ref imm } => load!(self, memory, imm, 4, I32, |v| v as i32),
+                    I64Load { ref imm } => load!(self, memory, imm, 8, I64, |v| v as i64),
+                    F32Load { ref imm } => load!(self, memory, imm, 4, F32, |v| f32::from_bits(v as u32)),
+                    F64Load { ref imm } => load!(self, memory, imm, 8, F64, |v| f64::from_bits(v)),
+                    I32Load8S { ref imm } => load!(self, memory, imm, 1, I32, |v| v as i8 as i32),
+                    I32Load8U { ref imm } => load!(self, memory, imm, 1, I32, |v| v as i32),
+                    I32Load16S { ref imm } => load!(self, memory, imm, 2, I32, |v| v as i16 as i32),
+                    I32Load16U { ref imm } => load!(self, memory, imm, 2, I32, |v| v as i32),
+                    I64Load8S { ref imm } => load!(self, memory, imm, 1, I64, |v| v as i8 as i64),
+                    I64Load8U { ref imm } => load!(self, memory, imm, 1, I64, |v| v as i64),
+                    I64Load16S { ref imm } => load!(self, memory, imm, 2, I64, |v| v as i16 as i64),
+                    I64Load16U { ref imm } => load!(self, memory, imm, 2, I64, |v| v as i64),
+                    I64load32S { ref imm } => load!(self, memory, imm, 4, I64, |v| v as i32 as i64),
+                    I64load32U { ref imm } => load!(self, memory, imm, 4, I64, |v| v as i64),
+                    I32Store { ref imm } => store!(self, memory, imm, 4, I32, |v| v as u32 as u64),
+                    I64Store { ref imm } => store!(self, memory, imm, 8, I64, |v| v as u64),
+                    F32Store { ref imm } => store!(self, memory, imm, 4, F32, |v| v.to_bits() as u64),
+                    F64Store { ref imm } => store!(self, memory, imm, 8, F64, |v| v.to_bits()),
+                    I32Store8 { ref imm } => store!(self, memory, imm, 1, I32, |v| v as u64),
+                    I32Store16 { ref imm } => store!(self, memory, imm, 2, I32, |v| v as u64),
+                    I64Store8 { ref imm } => store!(self, memory, imm, 1, I64, |v| v as u64),
+                    I64Store16 { ref imm } => store!(self, memory, imm, 2, I64, |v| v as u64),
+                    I64Store32 { ref imm } => store!(self, memory, imm, 4, I64, |v| v as u64),
+                    CurrentMemory { ref memory } => {
+                        let memory = instance.memories[**memory as usize].borrow();
+                        let pages = memory.pages();
+                        let pages = if memory.memory64 { Value::I64(pages as i64) } else { Value::I32(pages as i32) };
+                        self.stack.push(pages);
+                    }
+                    GrowMemory { ref memory } => {
+                        let delta = pop_address!(self);
+                        let mut memory = instance.memories[**memory as usize].borrow_mut();
+                        let old = if delta > MAX_PAGES as u64 { None } else { memory.grow(delta as u32) };
+                        let old = old.map_or(-1, |p| p as i64);
+                        self.stack.push(if memory.memory64 { Value::I64(old) } else { Value::I32(old as i32) });
+                    }
+                    MemoryInit { ref segment, ref memory } => {
+                        let n = pop!(self, I32) as u32;
+                        let src = pop!(self, I32) as u32;
+                        let dst = pop_address!(self);
+                        instance.memory_init(**memory as usize, **segment as usize, dst, src, n)?;
+                    }
+                    DataDrop(ref segment) => instance.dropped_data[**segment as usize].set(true),
+                    MemoryCopy { dst: ref d, src: ref s } => {
+                        let n = pop_address!(self) as usize;
+                        let src = pop_address!(self);
+                        let dst = pop_address!(self);
+                        let (d, s) = (&instance.memories[**d as usize], &instance.memories[**s as usize]);
+                        // the same memory may be imported twice
+                        if Rc::ptr_eq(d, s) {
+                            let mut memory = d.borrow_mut();
+                            let src = memory.range(src, n)?;
+                            let dst = memory.range(dst, n)?;
+                            memory.data.copy_within(src, dst.start);
+                        } else {
+                            let (mut d, s) = (d.borrow_mut(), s.borrow());
+                            let src = s.range(src, n)?;
+                            let dst = d.range(dst, n)?;
+                            d.data[dst].copy_from_slice(&s.data[src]);
+                        }
+                    }
+                    MemoryFill { ref memory } => {
+                        let n = pop_address!(self) as usize;
+                        let value = pop!(self, I32);
+                        let dst = pop_address!(self);
+                        let mut memory = instance.memories[**memory as usize].borrow_mut();
+                        let dst = memory.range(dst, n)?;
+                        memory.data[dst].fill(value as u8);
+                    }
+                    TableInit { ref segment, ref table } => {
+                        let n = pop!(self, I32) as u32;
+                        let src = pop!(self, I32) as u32;
+                        let dst = pop!(self, I32) as u32;
+                        instance.table_init(**table as usize, **segment as usize, dst, src, n)?;
+                    }
+                    ElemDrop(ref segment) => instance.dropped_elements[**segment as usize].set(true),
+                    TableCopy { ref dst, ref src } => {
+                        let n = pop!(self, I32) as u32 as usize;
+                        let s = pop!(self, I32) as u32 as usize;
+                        let d = pop!(self, I32) as u32 as usize;
+                        // both ranges are checked before anything is written
+                        let funcs = {
+                            let table = instance.tables[**src as usize].borrow();
+                            if s as u64 + n as u64 > table.elements.len() as u64 {
+                                return Err(Trap::TableOutOfBounds);
+                            }
+                            table.elements[s..s + n].to_vec()
+                        };
+                        let mut table = instance.tables[**dst as usize].borrow_mut();
+                        if d as u64 + n as u64 > table.elements.len() as u64 {
+                            return Err(Trap::TableOutOfBounds);
+                        }
+                        table.elements[d..d + n].clone_from_slice(&funcs);
+                    }
+                    TableGrow(ref t) => {
+                        let delta = pop!(self, I32) as u32;
+                        let init = self.stack.pop().unwrap();
+                        let old = instance.tables[**t as usize].borrow_mut().grow(delta, init).map_or(-1, |l| l as i32);
+                        self.stack.push(Value::I32(old));
+                    }
+                    TableSize(ref t) => {
+                        let len = instance.tables[**t as usize].borrow().len();
+                        self.stack.push(Value::I32(len as i32));
+                    }
+                    TableFill(ref t) => {
+                        let n = pop!(self, I32) as u32 as usize;
+                        let v = self.stack.pop().unwrap();
+                        let i = pop!(self, I32) as u32 as usize;
+                        let mut table = instance.tables[**t as usize].borrow_mut();
+                        if i as u64 + n as u64 > table.elements.len() as u64 {
+                            return Err(Trap::TableOutOfBounds);
+                        }
+                        for slot in table.elements[i..i + n].iter_mut() {
+                            *slot = v.clone();
+                        }
+                    }
+                    RefNull(heap) => self.stack.push(instance.types.null(heap)),
+                    RefIsNull => {
+                        let v = self.stack.pop().unwrap();
+                        self.stack.push(Value::I32(v.is_null() as i32));
+                    }
+                    RefFunc(ref f) => {
+                        let func = instance.funcs[function_space_index(f)].clone();
+                        self.stack.push(Value::FuncRef(Some(func)));
+                    }
+                    I32Const(v) => self.stack.push(Value::I32(v)),
+                    I64Const(v) => self.stack.push(Value::I64(v)),
+                    F32Const(v) => self.stack.push(Value::F32(v)),
+                    F64Const(v) => self.stack.push(Value::F64(v)),
+
+                    I32Eqz => unop!(self, I32, |a| (a == 0) as i32),
+                    I32Eq => binop!(self, I32, |a, b| (a == b) as i32),
+                    I32NE => binop!(self, I32, |a, b| (a != b) as i32),
+                    I32LtS => binop!(self, I32, |a, b| (a < b) as i32),
+                    I32LtU => binop!(self, I32, |a, b| ((a as u32) < b as u32) as i32),
+                    I32GtS => binop!(self, I32, |a, b| (a > b) as i32),
+                    I32GtU => binop!(self, I32, |a, b| (a as u32 > b as u32) as i32),
+                    I32LeS => binop!(self, I32, |a, b| (a <= b) as i32),
+                    I32LeU => binop!(self, I32, |a, b| (a as u32 <= b as u32) as i32),
+                    I32GeS => binop!(self, I32, |a, b| (a >= b) as i32),
+                    I32GeU => binop!(self, I32, |a, b| (a as u32 >= b as u32) as i32),
+                    I64Eqz => unop!(self, I64 -> I32, |a| (a == 0) as i32),
+                    I64Eq => binop!(self, I64 -> I32, |a, b| (a == b) as i32),
+                    I64Ne => binop!(self, I64 -> I32, |a, b| (a != b) as i32),
+                    I64LtS => binop!(self, I64 -> I32, |a, b| (a < b) as i32),
+                    I64LtU => binop!(self, I64 -> I32, |a, b| ((a as u64) < b as u64) as i32),
+                    I64GtS => binop!(self, I64 -> I32, |a, b| (a > b) as i32),
+                    I64GtU => binop!(self, I64 -> I32, |a, b| (a as u64 > b as u64) as i32),
+                    I64LeS => binop!(self, I64 -> I32, |a, b| (a <= b) as i32),
+                    I64LeU => binop!(self, I64 -> I32, |a, b| (a as u64 <= b as u64) as i32),
+                    I64GeS => binop!(self, I64 -> I32, |a, b| (a >= b) as i32),
+                    I64GeU => binop!(self, I64 -> I32, |a, b| (a as u64 >= b as u64) as i32),
+                    F32Eq => binop!(self, F32 -> I32, |a, b| (a == b) as i32),
+                    F32Ne => binop!(self, F32 -> I32, |a, b| (a != b) as i32),
+                    F32Lt => binop!(self, F32 -> I32, |a, b| (a < b) as i32),
+                    F32Gt => binop!(self, F32 -> I32, |a, b| (a > b) as i32),
+                    F32Le => binop!(self, F32 -> I32, |a, b| (a <= b) as i32),
+                    F32Ge => binop!(self, F32 -> I32, |a, b| (a >= b) as i32),
+                    F64Eq => binop!(self, F64 -> I32, |a, b| (a == b) as i32),
+                    F64Ne => binop!(self, F64 -> I32, |a, b| (a != b) as i32),
+                    F64Lt => binop!(self, F64 -> I32, |a, b| (a < b) as i32),
+                    F64Gt => binop!(self, F64 -> I32, |a, b| (a > b) as i32),
+                    F64Le => binop!(self, F64 -> I32, |a, b| (a <= b) as i32),
+                    F64Ge => binop!(self, F64 -> I32, |a, b| (a >= b) as i32),
+
+                    I32Clz => unop!(self, I32, |a| a.leading_zeros() as i32),
+                    I32Ctz => unop!(self, I32, |a| a.trailing_zeros() as i32),
+                    I32Popcnt => unop!(self, I32, |a| a.count_ones() as i32),
+                    I32Add => binop!(self, I32, |a, b| a.wrapping_add(b)),
+                    I32Sub => binop!(self, I32, |a, b| a.wrapping_sub(b)),
+                    I32Mul => binop!(self, I32, |a, b| a.wrapping_mul(b)),
+                    I32DivS => {
+                        binop!(self, I32, |a, b| {
+                            if b == 0 {
+                                return Err(Trap::IntegerDivideByZero);
+                            }
+                            a.checked_div(b).ok_or(Trap::IntegerOverflow)?
+                        })
+                    }
+                    I32DivU => {
+                        binop!(self, I32, |a, b| {
+                            (a as u32).checked_div(b as u32).ok_or(Trap::IntegerDivideByZero)? as i32
+                        })
+                    }
+                    I32RemS => {
+                        binop!(self, I32, |a, b| {
+                            if b == 0 {
+                                return Err(Trap::IntegerDivideByZero);
+                            }
+                            a.wrapping_rem(b)
+                        })
+                    }
+                    I32RemU => {
+                        binop!(self, I32, |a, b| {
+                            (a as u32).checked_rem(b as u32).ok_or(Trap::IntegerDivideByZero)? as i32
+                        })
+                    }
+                    I32And => binop!(self, I32, |a, b| a & b),
+                    I32Or => binop!(self, I32, |a, b| a | b),
+                    I32Xor => binop!(self, I32, |a, b| a ^ b),
+                    I32Shl => binop!(self, I32, |a, b| a.wrapping_shl(b as u32)),
+                    I32ShrS => binop!(self, I32, |a, b| a.wrapping_shr(b as u32)),
+                    I32ShrU => binop!(self, I32, |a, b| (a as u32).wrapping_shr(b as u32) as i32),
+                    I32Rotl => binop!(self, I32, |a, b| a.rotate_left(b as u32 % 32)),
+                    I32Rotr => binop!(self, I32, |a, b| a.rotate_right(b as u32 % 32)),
+                    I64Clz => unop!(self, I64, |a| a.leading_zeros() as i64),
+                    I64Ctz => unop!(self, I64, |a| a.trailing_zeros() as i64),
+                    I64Popcnt => unop!(self, I64, |a| a.count_ones() as i64),
+                    I64Add => binop!(self, I64, |a, b| a.wrapping_add(b)),
+                    I64Sub => binop!(self, I64, |a, b| a.wrapping_sub(b)),
+                    I64Mul => binop!(self, I64, |a, b| a.wrapping_mul(b)),
+                    I64DivS => {
+                        binop!(self, I64, |a, b| {
+                            if b == 0 {
+                                return Err(Trap::IntegerDivideByZero);
+                            }
+                            a.checked_div(b).ok_or(Trap::IntegerOverflow)?
+                        })
+                    }
+                    I64DivU => {
+                        binop!(self, I64, |a, b| {
+                            (a as u64).checked_div(b as u64).ok_or(Trap::IntegerDivideByZero)? as i64
+                        })
+                    }
+                    I64RemS => {
+                        binop!(self, I64, |a, b| {
+                            if b == 0 {
+                                return Err(Trap::IntegerDivideByZero);
+                            }
+                            a.wrapping_rem(b)
+                        })
+                    }
+                    I64RemU => {
+                        binop!(self, I64, |a, b| {
+                            (a as u64).checked_rem(b as u64).ok_or(Trap::IntegerDivideByZero)? as i64
+                        })
+                    }
+                    I64And => binop!(self, I64, |a, b| a & b),
+                    I64Or => binop!(self, I64, |a, b| a | b),
+                    I64Xor => binop!(self, I64, |a, b| a ^ b),
+                    I64Shl => binop!(self, I64, |a, b| a.wrapping_shl(b as u32)),
+                    I64ShrS => binop!(self, I64, |a, b| a.wrapping_shr(b as u32)),
+                    I64ShrU => binop!(self, I64, |a, b| (a as u64).wrapping_shr(b as u32) as i64),
+                    I64Rotl => binop!(self, I64, |a, b| a.rotate_left((b % 64) as u32)),
+                    I64Rotr => binop!(self, I64, |a, b| a.rotate_right((b % 64) as u32)),
+
+                    F32Abs => unop!(self, F32, |a| f32::from_bits(a.to_bits() & !SIGN_32)),
+                    F32Neg => unop!(self, F32, |a| f32::from_bits(a.to_bits() ^ SIGN_32)),
+                    F32Ceil => unop!(self, F32, |a| a.ceil()),
+                    F32Floor => unop!(self, F32, |a| a.floor()),
+                    F32Trunc => unop!(self, F32, |a| a.trunc()),
+                    F32Nearest => unop!(self, F32, |a| a.round_ties_even()),
+                    F32Sqrt => unop!(self, F32, |a| a.sqrt()),
+                    F32Add => binop!(self, F32, |a, b| a + b),
+                    F32Sub => binop!(self, F32, |a, b| a - b),
+                    F32Mul => binop!(self, F32, |a, b| a * b),
+                    F32Div => binop!(self, F32, |a, b| a / b),
+                    F32Min => binop!(self, F32, |a, b| float_min!(a, b)),
+                    F32Max => binop!(self, F32, |a, b| float_max!(a, b)),
+                    F32Copysign => binop!(self, F32, |a, b| a.copysign(b)),
+                    F64Abs => unop!(self, F64, |a| f64::from_bits(a.to_bits() & !SIGN_64)),
+                    F64Neg => unop!(self, F64, |a| f64::from_bits(a.to_bits() ^ SIGN_64)),
+                    F64Ceil => unop!(self, F64, |a| a.ceil()),
+                    F64Floor => unop!(self, F64, |a| a.floor()),
+                    F64Trunc => unop!(self, F64, |a| a.trunc()),
+                    F64Nearest => unop!(self, F64, |a| a.round_ties_even()),
+                    F64Sqrt => unop!(self, F64, |a| a.sqrt()),
+                    F64Add => binop!(self, F64, |a, b| a + b),
+                    F64Sub => binop!(self, F64, |a, b| a - b),
+                    F64Mul => binop!(self, F64, |a, b| a * b),
+                    F64Div => binop!(self, F64, |a, b| a / b),
+                    F64Min => binop!(self, F64, |a, b| float_min!(a, b)),
+                    F64Max => binop!(self, F64, |a, b| float_max!(a, b)),
+                    F64Copysign => binop!(self, F64, |a, b| a.copysign(b)),
+
+                    I32wrapI64 => unop!(self, I64 -> I32, |a| a as i32),
+                    I32TruncSF32 => unop!(self, F32 -> I32, |a| trunc(a as f64, -2147483648.0, 2147483648.0)? as i32),
+                    I32TruncUF32 => unop!(self, F32 -> I32, |a| trunc(a as f64, 0.0, 4294967296.0)? as u32 as i32),
+                    I32TruncSF64 => unop!(self, F64 -> I32, |a| trunc(a, -2147483648.0, 2147483648.0)? as i32),
+                    I32TruncUF64 => unop!(self, F64 -> I32, |a| trunc(a, 0.0, 4294967296.0)? as u32 as i32),
+                    I64ExtendSI32 => unop!(self, I32 -> I64, |a| a as i64),
+                    I64ExtendUI32 => unop!(self, I32 -> I64, |a| a as u32 as i64),
+                    I64TruncSF32 => {
+                        unop!(self, F32 -> I64, |a| trunc(a as f64, -9223372036854775808.0, 9223372036854775808.0)? as i64)
+                    }
+                    I64TruncUF32 => {
+                        unop!(self, F32 -> I64, |a| trunc(a as f64, 0.0, 18446744073709551616.0)? as u64 as i64)
+                    }
+                    I64TruncSF64 => {
+                        unop!(self, F64 -> I64, |a| trunc(a, -9223372036854775808.0, 9223372036854775808.0)? as i64)
+                    }
+                    I64TruncUF64 => {
+                        unop!(self, F64 -> I64, |a| trunc(a, 0.0, 18446744073709551616.0)? as u64 as i64)
+                    }
+                    F32ConvertSI32 => unop!(self, I32 -> F32, |a| a as f32),
+                    F32ConvertUI32 => unop!(self, I32 -> F32, |a| a as u32 as f32),
+                    F32ConvertSI64 => unop!(self, I64 -> F32, |a| a as f32),
+                    F32ConvertUI64 => unop!(self, I64 -> F32, |a| a as u64 as f32),
+                    F32DemoteF64 => unop!(self, F64 -> F32, |a| a as f32),
+                    F64ConvertSI32 => unop!(self, I32 -> F64, |a| a as f64),
+                    F64ConvertUI32 => unop!(self, I32 -> F64, |a| a as u32 as f64),
+                    F64ConvertSI64 => unop!(self, I64 -> F64, |a| a as f64),
+                    F64ConvertUI64 => unop!(self, I64 -> F64, |a| a as u64 as f64),
+                    F64PromoteF32 => unop!(self, F32 -> F64, |a| a as f64),
+                    I32ReinterpretF32 => unop!(self, F32 -> I32, |a| a.to_bits() as i32),
+                    I64ReinterpretF64 => unop!(self, F64 -> I64, |a| a.to_bits() as i64),
+                    F32ReinterpretI32 => unop!(self, I32 -> F32, |a| f32::from_bits(a as u32)),
+                    F64ReinterpretI64 => unop!(self, I64 -> F64, |a| f64::from_bits(a as u64)),
+                    I32Extend8S => unop!(self, I32, |a| a as i8 as i32),
+                    I32Extend16S => unop!(self, I32, |a| a as i16 as i32),
+                    I64Extend8S => unop!(self, I64, |a| a as i8 as i64),
+                    I64Extend16S => unop!(self, I64, |a| a as i16 as i64),
+                    I64Extend32S => unop!(self, I64, |a| a as i32 as i64),
+                    // `as` saturates and maps NaN to 0, just like `trunc_sat`
+                    I32TruncSatSF32 => unop!(self, F32 -> I32, |a| a as i32),
+                    I32TruncSatUF32 => unop!(self, F32 -> I32, |a| a as u32 as i32),
+                    I32TruncSatSF64 => unop!(self, F64 -> I32, |a| a as i32),
+                    I32TruncSatUF64 => unop!(self, F64 -> I32, |a| a as u32 as i32),
+                    I64TruncSatSF32 => unop!(self, F32 -> I64, |a| a as i64),
+                    I64TruncSatUF32 => unop!(self, F32 -> I64, |a| a as u64 as i64),
+                    I64TruncSatSF64 => unop!(self, F64 -> I64, |a| a as i64),
+                    I64TruncSatUF64 => unop!(self, F64 -> I64, |a| a as u64 as i64),
+                    V128Load { ref imm } => vector_load!(self, memory, imm, 16, |v| v),
+                    V128Load8x8S { ref imm } => vector_load!(self, memory, imm, 8, |v| widen::<i8, i16>(v, 0)),
+                    V128Load8x8U { ref imm } => vector_load!(self, memory, imm, 8, |v| widen::<u8, u16>(v, 0)),
+                    V128Load16x4S { ref imm } => vector_load!(self, memory, imm, 8, |v| widen::<i16, i32>(v, 0)),
+                    V128Load16x4U { ref imm } => vector_load!(self, memory, imm, 8, |v| widen::<u16, u32>(v, 0)),
+                    V128Load32x2S { ref imm } => vector_load!(self, memory, imm, 8, |v| widen::<i32, i64>(v, 0)),
+                    V128Load32x2U { ref imm } => vector_load!(self, memory, imm, 8, |v| widen::<u32, u64>(v, 0)),
+                    V128Load8Splat { ref imm } => vector_load!(self, memory, imm, 1, |v| splat(v as u8)),
+                    V128Load16Splat { ref imm } => vector_load!(self, memory, imm, 2, |v| splat(v as u16)),
+                    V128Load32Splat { ref imm } => vector_load!(self, memory, imm, 4, |v| splat(v as u32)),
+                    V128Load64Splat { ref imm } => vector_load!(self, memory, imm, 8, |v| splat(v as u64)),
+                    V128Load32Zero { ref imm } => vector_load!(self, memory, imm, 4, |v| v),
+                    V128Load64Zero { ref imm } => vector_load!(self, memory, imm, 8, |v| v),
+                    V128Store { ref imm } => {
+                        let v = pop!(self, V128);
+                        let addr = pop_address!(self);
+                        memory(imm).borrow_mut().store_vector(effective_address(addr, imm), 16, v)?;
+                    }
+                    V128Load8Lane { ref imm, lane } => load_lane!(self, memory, imm, lane, u8),
+                    V128Load16Lane { ref imm, lane } => load_lane!(self, memory, imm, lane, u16),
+                    V128Load32Lane { ref imm, lane } => load_lane!(self, memory, imm, lane, u32),
+                    V128Load64Lane { ref imm, lane } => load_lane!(self, memory, imm, lane, u64),
+                    V128Store8Lane { ref imm, lane } => store_lane!(self, memory, imm, lane, u8),
+                    V128Store16Lane { ref imm, lane } => store_lane!(self, memory, imm, lane, u16),
+                    V128Store32Lane { ref imm, lane } => store_lane!(self, memory, imm, lane, u32),
+                    V128Store64Lane { ref imm, lane } => store_lane!(self, memory, imm, lane, u64),
+                    MemoryAtomicNotify { ref imm } => {
+                        pop!(self, I32);
+                        let addr = pop_address!(self);
+                        atomic_address(&memory(imm).borrow(), addr, imm, 4)?;
+                        // there are no waiters to wake
+                        self.stack.push(Value::I32(0));
+                    }
+                    MemoryAtomicWait32 { ref imm } => atomic_wait!(self, memory, imm, 4, I32),
+                    MemoryAtomicWait64 { ref imm } => atomic_wait!(self, memory, imm, 8, I64),
+                    AtomicFence { .. } => {}
+                    I32AtomicLoad { ref imm } => atomic_load!(self, memory, imm, 4, I32, |v| v as i32),
+                    I64AtomicLoad { ref imm } => atomic_load!(self, memory, imm, 8, I64, |v| v as i64),
+                    I32AtomicLoad8U { ref imm } => atomic_load!(self, memory, imm, 1, I32, |v| v as i32),
+                    I32AtomicLoad16U { ref imm } => atomic_load!(self, memory, imm, 2, I32, |v| v as i32),
+                    I64AtomicLoad8U { ref imm } => atomic_load!(self, memory, imm, 1, I64, |v| v as i64),
+                    I64AtomicLoad16U { ref imm } => atomic_load!(self, memory, imm, 2, I64, |v| v as i64),
+                    I64AtomicLoad32U { ref imm } => atomic_load!(self, memory, imm, 4, I64, |v| v as i64),
+                    I32AtomicStore { ref imm } => atomic_store!(self, memory, imm, 4, I32, |v| v as u64),
+                    I64AtomicStore { ref imm } => atomic_store!(self, memory, imm, 8, I64, |v| v as u64),
+                    I32AtomicStore8 { ref imm } => atomic_store!(self, memory, imm, 1, I32, |v| v as u64),
+                    I32AtomicStore16 { ref imm } => atomic_store!(self, memory, imm, 2, I32, |v| v as u64),
+                    I64AtomicStore8 { ref imm } => atomic_store!(self, memory, imm, 1, I64, |v| v as u64),
+                    I64AtomicStore16 { ref imm } => atomic_store!(self, memory, imm, 2, I64, |v| v as u64),
+                    I64AtomicStore32 { ref imm } => atomic_store!(self, memory, imm, 4, I64, |v| v as u64),
+                    I32AtomicRmwAdd { ref imm } => {
+                        atomic_rmw!(self, memory, imm, 4, I32, i32, |old, v| old.wrapping_add(v))
+                    }
+                    I64AtomicRmwAdd { ref imm } => {
+                        atomic_rmw!(self, memory, imm, 8, I64, i64, |old, v| old.wrapping_add(v))
+                    }
+                    I32AtomicRmw8AddU { ref imm } => {
+                        atomic_rmw!(self, memory, imm, 1, I32, i32, |old, v| old.wrapping_add(v))
+                    }
+                    I32AtomicRmw16AddU { ref imm } => {
+                        atomic_rmw!(self, memory, imm, 2, I32, i32, |old, v| old.wrapping_add(v))
+                    }
+                    I64AtomicRmw8AddU { ref imm } => {
+                        atomic_rmw!(self, memory, imm, 1, I64, i64, |old, v| old.wrapping_add(v))
+                    }
+                    I64AtomicRmw16AddU { ref imm } => {
+                        atomic_rmw!(self, memory, imm, 2, I64, i64, |old, v| old.wrapping_add(v))
+                    }
+                    I64AtomicRmw32AddU { ref imm } => {
+                        atomic_rmw!(self, memory, imm, 4, I64, i64, |old, v| old.wrapping_add(v))
+                    }
+                    I32AtomicRmwSub { ref imm } => {
+                        atomic_rmw!(self, memory, imm, 4, I32, i32, |old, v| old.wrapping_sub(v))
+                    }
+                    I64AtomicRmwSub { ref imm } => {
+                        atomic_rmw!(self, memory, imm, 8, I64, i64, |old, v| old.wrapping_sub(v))
+                    }
+                    I32AtomicRmw8SubU { ref imm } => {
+                        atomic_rmw!(self, memory, imm, 1, I32, i32, |old, v| old.wrapping_sub(v))
+                    }
+                    I32AtomicRmw16SubU { ref imm } => {
+                        atomic_rmw!(self, memory, imm, 2, I32, i32, |old, v| old.wrapping_sub(v))
+                    }
+                    I64AtomicRmw8SubU { ref imm } => {
+                        atomic_rmw!(self, memory, imm, 1, I64, i64, |old, v| old.wrapping_sub(v))
+                    }
+                    I64AtomicRmw16SubU { ref imm } => {
+                        atomic_rmw!(self, memory, imm, 2, I64, i64, |old, v| old.wrapping_sub(v))
+                    }
+                    I64AtomicRmw32SubU { ref imm } => {
+                        atomic_rmw!(self, memory, imm, 4, I64, i64, |old, v| old.wrapping_sub(v))
+                    }
+                    I32AtomicRmwAnd { ref imm } => {
+                        atomic_rmw!(self, memory, imm, 4, I32, i32, |old, v| old & v)
+                    }
+                    I64AtomicRmwAnd { ref imm } => {
+                        atomic_rmw!(self, memory, imm, 8, I64, i64, |old, v| old & v)
+                    }
+                    I32AtomicRmw8AndU { ref imm } => {
+                        atomic_rmw!(self, memory, imm, 1, I32, i32, |old, v| old & v)
+                    }
+                    I32AtomicRmw16AndU { ref imm } => {
+                        atomic_rmw!(self, memory, imm, 2, I32, i32, |old, v| old & v)
+                    }
+                    I64AtomicRmw8AndU { ref imm } => {
+                        atomic_rmw!(self, memory, imm, 1, I64, i64, |old, v| old & v)
+                    }
+                    I64AtomicRmw16AndU { ref imm } => {
+                        atomic_rmw!(self, memory, imm, 2, I64, i64, |old, v| old & v)
+                    }
+                    I64AtomicRmw32AndU { ref imm } => {
+                        atomic_rmw!(self, memory, imm, 4, I64, i64, |old, v| old & v)
+                    }
+                    I32AtomicRmwOr { ref imm } => {
+                        atomic_rmw!(self, memory, imm, 4, I32, i32, |old, v| old | v)
+                    }
+                    I64AtomicRmwOr { ref imm } => {
+                        atomic_rmw!(self, memory, imm, 8, I64, i64, |old, v| old | v)
+                    }
+                    I32AtomicRmw8OrU { ref imm } => {
+                        atomic_rmw!(self, memory, imm, 1, I32, i32, |old, v| old | v)
+                    }
+                    I32AtomicRmw16OrU { ref imm } => {
+                        atomic_rmw!(self, memory, imm, 2, I32, i32, |old, v| old | v)
+                    }
+                    I64AtomicRmw8OrU { ref imm } => {
+                        atomic_rmw!(self, memory, imm, 1, I64, i64, |old, v| old | v)
+                    }
+                    I64AtomicRmw16OrU { ref imm } => {
+                        atomic_rmw!(self, memory, imm, 2, I64, i64, |old, v| old | v)
+                    }
+                    I64AtomicRmw32OrU { ref imm } => {
+                        atomic_rmw!(self, memory, imm, 4, I64, i64, |old, v| old | v)
+                    }
+                    I32AtomicRmwXor { ref imm } => {
+                        atomic_rmw!(self, memory, imm, 4, I32, i32, |old, v| old ^ v)
+                    }
+                    I64AtomicRmwXor { ref imm } => {
+                        atomic_rmw!(self, memory, imm, 8, I64, i64, |old, v| old ^ v)
+                    }
+                    I32AtomicRmw8XorU { ref imm } => {
+                        atomic_rmw!(self, memory, imm, 1, I32, i32, |old, v| old ^ v)
+                    }
+                    I32AtomicRmw16XorU { ref imm } => {
+                        atomic_rmw!(self, memory, imm, 2, I32, i32, |old, v| old ^ v)
+                    }
+                    I64AtomicRmw8XorU { ref imm } => {
+                        atomic_rmw!(self, memory, imm, 1, I64, i64, |old, v| old ^ v)
+                    }
+                    I64AtomicRmw16XorU { ref imm } => {
+                        atomic_rmw!(self, memory, imm, 2, I64, i64, |old, v| old ^ v)
+                    }
+                    I64AtomicRmw32XorU { ref imm } => {
+                        atomic_rmw!(self, memory, imm, 4, I64, i64, |old, v| old ^ v)
+                    }
+                    I32AtomicRmwXchg { ref imm } => {
+                        atomic_rmw!(self, memory, imm, 4, I32, i32, |old, v| v)
+                    }
+                    I64AtomicRmwXchg { ref imm } => {
+                        atomic_rmw!(self, memory, imm, 8, I64, i64, |old, v| v)
+                    }
+                    I32AtomicRmw8XchgU { ref imm } => {
+                        atomic_rmw!(self, memory, imm, 1, I32, i32, |old, v| v)
+                    }
+                    I32AtomicRmw16XchgU { ref imm } => {
+                        atomic_rmw!(self, memory, imm, 2, I32, i32, |old, v| v)
+                    }
+                    I64AtomicRmw8XchgU { ref imm } => {
+                        atomic_rmw!(self, memory, imm, 1, I64, i64, |old, v| v)
+                    }
+                    I64AtomicRmw16XchgU { ref imm } => {
+                        atomic_rmw!(self, memory, imm, 2, I64, i64, |old, v| v)
+                    }
+                    I64AtomicRmw32XchgU { ref imm } => {
+                        atomic_rmw!(self, memory, imm, 4, I64, i64, |old, v| v)
+                    }
+                    I32AtomicRmwCmpxchg { ref imm } => atomic_cmpxchg!(self, memory, imm, 4, I32, i32),
+                    I64AtomicRmwCmpxchg { ref imm } => atomic_cmpxchg!(self, memory, imm, 8, I64, i64),
+                    I32AtomicRmw8CmpxchgU { ref imm } => atomic_cmpxchg!(self, memory, imm, 1, I32, i32),
+                    I32AtomicRmw16CmpxchgU { ref imm } => atomic_cmpxchg!(self, memory, imm, 2, I32, i32),
+                    I64AtomicRmw8CmpxchgU { ref imm } => atomic_cmpxchg!(self, memory, imm, 1, I64, i64),
+                    I64AtomicRmw16CmpxchgU { ref imm } => atomic_cmpxchg!(self, memory, imm, 2, I64, i64),
+                    I64AtomicRmw32CmpxchgU { ref imm } => atomic_cmpxchg!(self, memory, imm, 4, I64, i64),
+                    RefEq | RefAsNonNull | StructNew { .. } | StructNewDefault { .. } | StructGet { .. } |
+                    StructGetS { .. } | StructGetU { .. } | StructSet { .. } | ArrayNew { .. } |
+                    ArrayNewDefault { .. } | ArrayNewFixed { .. } | ArrayNewData { .. } | ArrayNewElem { .. } |
+                    ArrayGet { .. } | ArrayGetS { .. } | ArrayGetU { .. } | ArraySet { .. } | ArrayLen |
+                    ArrayFill { .. } | ArrayCopy { .. } | ArrayInitData { .. } | ArrayInitElem { .. } |
+                    RefTest(_) | RefCast(_) | AnyConvertExtern | ExternConvertAny | RefI31 | I31GetS |
+                    I31GetU => self.gc(&code[pc], &instance)?,
+                    ref op => self.vector(op),
+                }
+                if let Some(depth) = branch {
+                    match self.branch(depth) {
+                        Some(target) => next = Some(target),
+                        None => {
+                            self.leave();
+                            continue 'frames;
+                        }
+                    }
+                }
+                if let Some(pc) = next {
+                    self.frames.last_mut().unwrap().pc = pc;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn unop_vector<A: Lane, R: Lane, F: Fn(A) -> R>(&mut self, f: F) {
+        let a = pop!(self, V128);
+        self.stack.push(Value::V128(from_lanes(lanes(a).map(f))));
+    }
+
+    fn binop_vector<A: Lane, R: Lane, F: Fn(A, A) -> R>(&mut self, f: F) {
+        let b = pop!(self, V128);
+        let a = pop!(self, V128);
+        self.stack.push(Value::V128(from_lanes(lanes(a).zip(lanes(b)).map(|(a, b)| f(a, b)))));
+    }
+
+    /// Shifts take the amount modulo the lane width, as `wrapping_shl` does.
+    fn shift_vector<A: Lane, F: Fn(A, u32) -> A>(&mut self, f: F) {
+        let n = pop!(self, I32) as u32;
+        let a = pop!(self, V128);
+        self.stack.push(Value::V128(from_lanes(lanes(a).map(|a| f(a, n)))));
+    }
+
+    /// Narrows the lanes of two vectors into one, saturating.
+    fn narrow_vector<A: Lane, R: Lane, F: Fn(A) -> R>(&mut self, f: F) {
+        let b = pop!(self, V128);
+        let a = pop!(self, V128);
+        self.stack.push(Value::V128(from_lanes(lanes(a).chain(lanes(b)).map(f))));
+    }
+
+    /// Multiplies the low or the high halves of two vectors into lanes of twice the width.
+    fn extmul_vector<A: Lane, R: Lane + From<A>, F: Fn(R, R) -> R>(&mut self, skip: usize, f: F) {
+        let b = pop!(self, V128);
+        let a = pop!(self, V128);
+        let half = |v| lanes::<A>(v).skip(skip).take(R::COUNT).map(R::from);
+        self.stack.push(Value::V128(from_lanes(half(a).zip(half(b)).map(|(a, b)| f(a, b)))));
+    }
+
+    /// Adds adjacent pairs of lanes into lanes of twice the width.
+    fn extadd_vector<A: Lane, R: Lane + From<A> + ::std::ops::Add<Output = R>>(&mut self) {
+        let a = pop!(self, V128);
+        let lanes: Vec<R> = lanes::<A>(a).map(R::from).collect();
+        self.stack.push(Value::V128(from_lanes(lanes.chunks(2).map(|p| p[0] + p[1]))));
+    }
+
+    fn widen_vector<A: Lane, R: Lane + From<A>>(&mut self, skip: usize) {
+        let a = pop!(self, V128);
+        self.stack.push(Value::V128(widen::<A, R>(a, skip)));
+    }
+
+    fn all_true<A: Lane + PartialEq + Default>(&mut self) {
+        let a = pop!(self, V128);
+        self.stack.push(Value::I32(lanes::<A>(a).all(|a| a != A::default()) as i32));
+    }
+
+    fn bitmask<A: Lane + PartialOrd + Default>(&mut self) {
+        let a = pop!(self, V128);
+        let mask = lanes::<A>(a).enumerate().fold(0, |m, (i, a)| m | ((a < A::default()) as i32) << i);
+        self.stack.push(Value::I32(mask));
+    }
+
+    /// Runs a vector instruction that does not access memory.
+    fn vector(&mut self, op: &Op) {
+        use ops::Op::*;
+        match *op {
+            V128Const(v) => self.stack.push(Value::V128(v)),
+            I8x16Shuffle(ref indices) => {
+                let b = pop!(self, V128);
+                let a = pop!(self, V128);
+                let bytes: Vec<u8> = lanes(a).chain(lanes(b)).collect();
+                self.stack.push(Value::V128(from_lanes(indices.iter().map(|&i| bytes[i as usize]))));
+            }
+            I8x16Swizzle => {
+                let s = pop!(self, V128);
+                let a = pop!(self, V128);
+                let bytes: Vec<u8> = lanes(a).collect();
+                let swizzled = lanes::<u8>(s).map(|i| *bytes.get(i as usize).unwrap_or(&0));
+                self.stack.push(Value::V128(from_lanes(swizzled)));
+            }
+            I8x16Splat => unop!(self, I32 -> V128, |a| splat(a as u8)),
+            I16x8Splat => unop!(self, I32 -> V128, |a| splat(a as u16)),
+            I32x4Splat => unop!(self, I32 -> V128, |a| splat(a)),
+            I64x2Splat => unop!(self, I64 -> V128, |a| splat(a)),
+            F32x4Splat => unop!(self, F32 -> V128, |a| splat(a)),
+            F64x2Splat => unop!(self, F64 -> V128, |a| splat(a)),
+            I8x16ExtractLaneS(i) => unop!(self, V128 -> I32, |a| i8::extract(a, i as usize) as i32),
+            I8x16ExtractLaneU(i) => unop!(self, V128 -> I32, |a| u8::extract(a, i as usize) as i32),
+            I16x8ExtractLaneS(i) => unop!(self, V128 -> I32, |a| i16::extract(a, i as usize) as i32),
+            I16x8ExtractLaneU(i) => unop!(self, V128 -> I32, |a| u16::extract(a, i as usize) as i32),
+            I32x4ExtractLane(i) => unop!(self, V128 -> I32, |a| i32::extract(a, i as usize)),
+            I64x2ExtractLane(i) => unop!(self, V128 -> I64, |a| i64::extract(a, i as usize)),
+            F32x4ExtractLane(i) => unop!(self, V128 -> F32, |a| f32::extract(a, i as usize)),
+            F64x2ExtractLane(i) => unop!(self, V128 -> F64, |a| f64::extract(a, i as usize)),
+            I8x16ReplaceLane(i) => {
+                let x = pop!(self, I32);
+                unop!(self, V128, |a| replace_lane(a, i, x as u8))
+            }
+            I16x8ReplaceLane(i) => {
+                let x = pop!(self, I32);
+                unop!(self, V128, |a| replace_lane(a, i, x as u16))
+            }
+            I32x4ReplaceLane(i) => {
+                let x = pop!(self, I32);
+                unop!(self, V128, |a| replace_lane(a, i, x))
+            }
+            I64x2ReplaceLane(i) => {
+                let x = pop!(self, I64);
+                unop!(self, V128, |a| replace_lane(a, i, x))
+            }
+            F32x4ReplaceLane(i) => {
+                let x = pop!(self, F32);
+                unop!(self, V128, |a| replace_lane(a, i, x))
+            }
+            F64x2ReplaceLane(i) => {
+                let x = pop!(self, F64);
+                unop!(self, V128, |a| replace_lane(a, i, x))
+            }
+
+            I8x16Eq => self.binop_vector(|a: i8, b: i8| mask!(a == b, i8)),
+            I8x16Ne => self.binop_vector(|a: i8, b: i8| mask!(a != b, i8)),
+            I8x16LtS => self.binop_vector(|a: i8, b: i8| mask!(a < b, i8)),
+            I8x16LtU => self.binop_vector(|a: u8, b: u8| mask!(a < b, i8)),
+            I8x16GtS => self.binop_vector(|a: i8, b: i8| mask!(a > b, i8)),
+            I8x16GtU => self.binop_vector(|a: u8, b: u8| mask!(a > b, i8)),
+            I8x16LeS => self.binop_vector(|a: i8, b: i8| mask!(a <= b, i8)),
+            I8x16LeU => self.binop_vector(|a: u8, b: u8| mask!(a <= b, i8)),
+            I8x16GeS => self.binop_vector(|a: i8, b: i8| mask!(a >= b, i8)),
+            I8x16GeU => self.binop_vector(|a: u8, b: u8| mask!(a >= b, i8)),
+            I16x8Eq => self.binop_vector(|a: i16, b: i16| mask!(a == b, i16)),
+            I16x8Ne => self.binop_vector(|a: i16, b: i16| mask!(a != b, i16)),
+            I16x8LtS => self.binop_vector(|a: i16, b: i16| mask!(a < b, i16)),
+            I16x8LtU => self.binop_vector(|a: u16, b: u16| mask!(a < b, i16)),
+            I16x8GtS => self.binop_vector(|a: i16, b: i16| mask!(a > b, i16)),
+            I16x8GtU => self.binop_vector(|a: u16, b: u16| mask!(a > b, i16)),
+            I16x8LeS => self.binop_vector(|a: i16, b: i16| mask!(a <= b, i16)),
+            I16x8LeU => self.binop_vector(|a: u16, b: u16| mask!(a <= b, i16)),
+            I16x8GeS => self.binop_vector(|a: i16, b: i16| mask!(a >= b, i16)),
+            I16x8GeU => self.binop_vector(|a: u16, b: u16| mask!(a >= b, i16)),
+            I32x4Eq => self.binop_vector(|a: i32, b: i32| mask!(a == b, i32)),
+            I32x4Ne => self.binop_vector(|a: i32, b: i32| mask!(a != b, i32)),
+            I32x4LtS => self.binop_vector(|a: i32, b: i32| mask!(a < b, i32)),
+            I32x4LtU => self.binop_vector(|a: u32, b: u32| mask!(a < b, i32)),
+            I32x4GtS => self.binop_vector(|a: i32, b: i32| mask!(a > b, i32)),
+            I32x4GtU => self.binop_vector(|a: u32, b: u32| mask!(a > b, i32)),
+            I32x4LeS => self.binop_vector(|a: i32, b: i32| mask!(a <= b, i32)),
+            I32x4LeU => self.binop_vector(|a: u32, b: u32| mask!(a <= b, i32)),
+            I32x4GeS => self.binop_vector(|a: i32, b: i32| mask!(a >= b, i32)),
+            I32x4GeU => self.binop_vector(|a: u32, b: u32| mask!(a >= b, i32)),
+            I64x2Eq => self.binop_vector(|a: i64, b: i64| mask!(a == b, i64)),
+            I64x2Ne => self.binop_vector(|a: i64, b: i64| mask!(a != b, i64)),
+            I64x2LtS => self.binop_vector(|a: i64, b: i64| mask!(a < b, i64)),
+            I64x2GtS => self.binop_vector(|a: i64, b: i64| mask!(a > b, i64)),
+            I64x2LeS => self.binop_vector(|a: i64, b: i64| mask!(a <= b, i64)),
+            I64x2GeS => self.binop_vector(|a: i64, b: i64| mask!(a >= b, i64)),
+            F32x4Eq => self.binop_vector(|a: f32, b: f32| mask!(a == b, i32)),
+            F32x4Ne => self.binop_vector(|a: f32, b: f32| mask!(a != b, i32)),
+            F32x4Lt => self.binop_vector(|a: f32, b: f32| mask!(a < b, i32)),
+            F32x4Gt => self.binop_vector(|a: f32, b: f32| mask!(a > b, i32)),
+            F32x4Le => self.binop_vector(|a: f32, b: f32| mask!(a <= b, i32)),
+            F32x4Ge => self.binop_vector(|a: f32, b: f32| mask!(a >= b, i32)),
+            F64x2Eq => self.binop_vector(|a: f64, b: f64| mask!(a == b, i64)),
+            F64x2Ne => self.binop_vector(|a: f64, b: f64| mask!(a != b, i64)),
+            F64x2Lt => self.binop_vector(|a: f64, b: f64| mask!(a < b, i64)),
+            F64x2Gt => self.binop_vector(|a: f64, b: f64| mask!(a > b, i64)),
+            F64x2Le => self.binop_vector(|a: f64, b: f64| mask!(a <= b, i64)),
+            F64x2Ge => self.binop_vector(|a: f64, b: f64| mask!(a >= b, i64)),
+
+            V128Not => unop!(self, V128, |a| !a),
+            V128And => binop!(self, V128, |a, b| a & b),
+            V128Andnot => binop!(self, V128, |a, b| a & !b),
+            V128Or => binop!(self, V128, |a, b| a | b),
+            V128Xor => binop!(self, V128, |a, b| a ^ b),
+            V128Bitselect => {
+                let c = pop!(self, V128);
+                binop!(self, V128, |a, b| a & c | b & !c)
+            }
+            V128AnyTrue => unop!(self, V128 -> I32, |a| (a != 0) as i32),
+
+            I8x16Abs => self.unop_vector(|a: i8| a.wrapping_abs()),
+            I8x16Neg => self.unop_vector(|a: i8| a.wrapping_neg()),
+            I8x16Popcnt => self.unop_vector(|a: u8| a.count_ones() as u8),
+            I8x16AllTrue => self.all_true::<i8>(),
+            I8x16Bitmask => self.bitmask::<i8>(),
+            I8x16NarrowI16x8S => self.narrow_vector(|a: i16| a.clamp(i8::MIN as i16, i8::MAX as i16) as i8),
+            I8x16NarrowI16x8U => self.narrow_vector(|a: i16| a.clamp(0, u8::MAX as i16) as u8),
+            I8x16Shl => self.shift_vector(|a: i8, n| a.wrapping_shl(n)),
+            I8x16ShrS => self.shift_vector(|a: i8, n| a.wrapping_shr(n)),
+            I8x16ShrU => self.shift_vector(|a: u8, n| a.wrapping_shr(n)),
+            I8x16Add => self.binop_vector(|a: i8, b: i8| a.wrapping_add(b)),
+            I8x16AddSatS => self.binop_vector(|a: i8, b: i8| a.saturating_add(b)),
+            I8x16AddSatU => self.binop_vector(|a: u8, b: u8| a.saturating_add(b)),
+            I8x16Sub => self.binop_vector(|a: i8, b: i8| a.wrapping_sub(b)),
+            I8x16SubSatS => self.binop_vector(|a: i8, b: i8| a.saturating_sub(b)),
+            I8x16SubSatU => self.binop_vector(|a: u8, b: u8| a.saturating_sub(b)),
+            I8x16MinS => self.binop_vector(|a: i8, b: i8| a.min(b)),
+            I8x16MinU => self.binop_vector(|a: u8, b: u8| a.min(b)),
+            I8x16MaxS => self.binop_vector(|a: i8, b: i8| a.max(b)),
+            I8x16MaxU => self.binop_vector(|a: u8, b: u8| a.max(b)),
+            I8x16AvgrU => self.binop_vector(|a: u8, b: u8| (a as u16 + b as u16).div_ceil(2) as u8),
+
+            I16x8ExtaddPairwiseI8x16S => self.extadd_vector::<i8, i16>(),
+            I16x8ExtaddPairwiseI8x16U => self.extadd_vector::<u8, u16>(),
+            I32x4ExtaddPairwiseI16x8S => self.extadd_vector::<i16, i32>(),
+            I32x4ExtaddPairwiseI16x8U => self.extadd_vector::<u16, u32>(),
+            I16x8Abs => self.unop_vector(|a: i16| a.wrapping_abs()),
+            I16x8Neg => self.unop_vector(|a: i16| a.wrapping_neg()),
+            I16x8Q15mulrSatS => {
+                self.binop_vector(|a: i16, b: i16| {
+                    ((a as i32 * b as i32 + 0x4000) >> 15).clamp(i16::MIN as i32, i16::MAX as i32) as i16
+                })
+            }
+            I16x8AllTrue => self.all_true::<i16>(),
+            I16x8Bitmask => self.bitmask::<i16>(),
+            I16x8NarrowI32x4S => self.narrow_vector(|a: i32| a.clamp(i16::MIN as i32, i16::MAX as i32) as i16),
+            I16x8NarrowI32x4U => self.narrow_vector(|a: i32| a.clamp(0, u16::MAX as i32) as u16),
+            I16x8ExtendLowI8x16S => self.widen_vector::<i8, i16>(0),
+            I16x8ExtendHighI8x16S => self.widen_vector::<i8, i16>(8),
+            I16x8ExtendLowI8x16U => self.widen_vector::<u8, u16>(0),
+            I16x8ExtendHighI8x16U => self.widen_vector::<u8, u16>(8),
+            I16x8Shl => self.shift_vector(|a: i16, n| a.wrapping_shl(n)),
+            I16x8ShrS => self.shift_vector(|a: i16, n| a.wrapping_shr(n)),
+            I16x8ShrU => self.shift_vector(|a: u16, n| a.wrapping_shr(n)),
+            I16x8Add => self.binop_vector(|a: i16, b: i16| a.wrapping_add(b)),
+            I16x8AddSatS => self.binop_vector(|a: i16, b: i16| a.saturating_add(b)),
+            I16x8AddSatU => self.binop_vector(|a: u16, b: u16| a.saturating_add(b)),
+            I16x8Sub => self.binop_vector(|a: i16, b: i16| a.wrapping_sub(b)),
+            I16x8SubSatS => self.binop_vector(|a: i16, b: i16| a.saturating_sub(b)),
+            I16x8SubSatU => self.binop_vector(|a: u16, b: u16| a.saturating_sub(b)),
+            I16x8Mul => self.binop_vector(|a: i16, b: i16| a.wrapping_mul(b)),
+            I16x8MinS => self.binop_vector(|a: i16, b: i16| a.min(b)),
+            I16x8MinU => self.binop_vector(|a: u16, b: u16| a.min(b)),
+            I16x8MaxS => self.binop_vector(|a: i16, b: i16| a.max(b)),
+            I16x8MaxU => self.binop_vector(|a: u16, b: u16| a.max(b)),
+            I16x8AvgrU => self.binop_vector(|a: u16, b: u16| (a as u32 + b as u32).div_ceil(2) as u16),
+            I16x8ExtmulLowI8x16S => self.extmul_vector::<i8, i16, _>(0, |a, b| a * b),
+            I16x8ExtmulHighI8x16S => self.extmul_vector::<i8, i16, _>(8, |a, b| a * b),
+            I16x8ExtmulLowI8x16U => self.extmul_vector::<u8, u16, _>(0, |a, b| a * b),
+            I16x8ExtmulHighI8x16U => self.extmul_vector::<u8, u16, _>(8, |a, b| a * b),
+
+            I32x4Abs => self.unop_vector(|a: i32| a.wrapping_abs()),
+            I32x4Neg => self.unop_vector(|a: i32| a.wrapping_neg()),
+            I32x4AllTrue => self.all_true::<i32>(),
+            I32x4Bitmask => self.bitmask::<i32>(),
+            I32x4ExtendLowI16x8S => self.widen_vector::<i16, i32>(0),
+            I32x4ExtendHighI16x8S => self.widen_vector::<i16, i32>(4),
+            I32x4ExtendLowI16x8U => self.widen_vector::<u16, u32>(0),
+            I32x4ExtendHighI16x8U => self.widen_vector::<u16, u32>(4),
+            I32x4Shl => self.shift_vector(|a: i32, n| a.wrapping_shl(n)),
+            I32x4ShrS => self.shift_vector(|a: i32, n| a.wrapping_shr(n)),
+            I32x4ShrU => self.shift_vector(|a: u32, n| a.wrapping_shr(n)),
+            I32x4Add => self.binop_vector(|a: i32, b: i32| a.wrapping_add(b)),
+            I32x4Sub => self.binop_vector(|a: i32, b: i32| a.wrapping_sub(b)),
+            I32x4Mul => self.binop_vector(|a: i32, b: i32| a.wrapping_mul(b)),
+            I32x4MinS => self.binop_vector(|a: i32, b: i32| a.min(b)),
+            I32x4MinU => self.binop_vector(|a: u32, b: u32| a.min(b)),
+            I32x4MaxS => self.binop_vector(|a: i32, b: i32| a.max(b)),
+            I32x4MaxU => self.binop_vector(|a: u32, b: u32| a.max(b)),
+            I32x4DotI16x8S => {
+                let b = pop!(self, V128);
+                let a = pop!(self, V128);
+                let products: Vec<i32> = lanes::<i16>(a).zip(lanes::<i16>(b)).map(|(a, b)| a as i32 * b as i32).collect();
+                // only -0x8000 * -0x8000 twice overflows
+                self.stack.push(Value::V128(from_lanes(products.chunks(2).map(|p| p[0].wrapping_add(p[1])))));
+            }
+            I32x4ExtmulLowI16x8S => self.extmul_vector::<i16, i32, _>(0, |a, b| a * b),
+            I32x4ExtmulHighI16x8S => self.extmul_vector::<i16, i32, _>(4, |a, b| a * b),
+            I32x4ExtmulLowI16x8U => self.extmul_vector::<u16, u32, _>(0, |a, b| a * b),
+            I32x4ExtmulHighI16x8U => self.extmul_vector::<u16, u32, _>(4, |a, b| a * b),
+
+            I64x2Abs => self.unop_vector(|a: i64| a.wrapping_abs()),
+            I64x2Neg => self.unop_vector(|a: i64| a.wrapping_neg()),
+            I64x2AllTrue => self.all_true::<i64>(),
+            I64x2Bitmask => self.bitmask::<i64>(),
+            I64x2ExtendLowI32x4S => self.widen_vector::<i32, i64>(0),
+            I64x2ExtendHighI32x4S => self.widen_vector::<i32, i64>(2),
+            I64x2ExtendLowI32x4U => self.widen_vector::<u32, u64>(0),
+            I64x2ExtendHighI32x4U => self.widen_vector::<u32, u64>(2),
+            I64x2Shl => self.shift_vector(|a: i64, n| a.wrapping_shl(n)),
+            I64x2ShrS => self.shift_vector(|a: i64, n| a.wrapping_shr(n)),
+            I64x2ShrU => self.shift_vector(|a: u64, n| a.wrapping_shr(n)),
+            I64x2Add => self.binop_vector(|a: i64, b: i64| a.wrapping_add(b)),
+            I64x2Sub => self.binop_vector(|a: i64, b: i64| a.wrapping_sub(b)),
+            I64x2Mul => self.binop_vector(|a: i64, b: i64| a.wrapping_mul(b)),
+            I64x2ExtmulLowI32x4S => self.extmul_vector::<i32, i64, _>(0, |a, b| a * b),
+            I64x2ExtmulHighI32x4S => self.extmul_vector::<i32, i64, _>(2, |a, b| a * b),
+            I64x2ExtmulLowI32x4U => self.extmul_vector::<u32, u64, _>(0, |a, b| a * b),
+            I64x2ExtmulHighI32x4U => self.extmul_vector::<u32, u64, _>(2, |a, b| a * b),
+
+            F32x4Abs => self.unop_vector(|a: u32| a & !SIGN_32),
+            F32x4Neg => self.unop_vector(|a: u32| a ^ SIGN_32),
+            F32x4Sqrt => self.unop_vector(|a: f32| a.sqrt()),
+            F32x4Ceil => self.unop_vector(|a: f32| a.ceil()),
+            F32x4Floor => self.unop_vector(|a: f32| a.floor()),
+            F32x4Trunc => self.unop_vector(|a: f32| a.trunc()),
+            F32x4Nearest => self.unop_vector(|a: f32| a.round_ties_even()),
+            F32x4Add => self.binop_vector(|a: f32, b: f32| a + b),
+            F32x4Sub => self.binop_vector(|a: f32, b: f32| a - b),
+            F32x4Mul => self.binop_vector(|a: f32, b: f32| a * b),
+            F32x4Div => self.binop_vector(|a: f32, b: f32| a / b),
+            F32x4Min => self.binop_vector(|a: f32, b: f32| float_min!(a, b)),
+            F32x4Max => self.binop_vector(|a: f32, b: f32| float_max!(a, b)),
+            F32x4Pmin => self.binop_vector(|a: f32, b: f32| if b < a { b } else { a }),
+            F32x4Pmax => self.binop_vector(|a: f32, b: f32| if a < b { b } else { a }),
+            F64x2Abs => self.unop_vector(|a: u64| a & !SIGN_64),
+            F64x2Neg => self.unop_vector(|a: u64| a ^ SIGN_64),
+            F64x2Sqrt => self.unop_vector(|a: f64| a.sqrt()),
+            F64x2Ceil => self.unop_vector(|a: f64| a.ceil()),
+            F64x2Floor => self.unop_vector(|a: f64| a.floor()),
+            F64x2Trunc => self.unop_vector(|a: f64| a.trunc()),
+            F64x2Nearest => self.unop_vector(|a: f64| a.round_ties_even()),
+            F64x2Add => self.binop_vector(|a: f64, b: f64| a + b),
+            F64x2Sub => self.binop_vector(|a: f64, b: f64| a - b),
+            F64x2Mul => self.binop_vector(|a: f64, b: f64| a * b),
+            F64x2Div => self.binop_vector(|a: f64, b: f64| a / b),
+            F64x2Min => self.binop_vector(|a: f64, b: f64| float_min!(a, b)),
+            F64x2Max => self.binop_vector(|a: f64, b: f64| float_max!(a, b)),
+            F64x2Pmin => self.binop_vector(|a: f64, b: f64| if b < a { b } else { a }),
+            F64x2Pmax => self.binop_vector(|a: f64, b: f64| if a < b { b } else { a }),
+
+            // `as` saturates and maps NaN to 0, the lanes not converted are zero
+            I32x4TruncSatF32x4S => self.unop_vector(|a: f32| a as i32),
+            I32x4TruncSatF32x4U => self.unop_vector(|a: f32| a as u32),
+            F32x4ConvertI32x4S => self.unop_vector(|a: i32| a as f32),
+            F32x4ConvertI32x4U => self.unop_vector(|a: u32| a as f32),
+            I32x4TruncSatF64x2SZero => unop!(self, V128, |a| from_lanes(lanes::<f64>(a).map(|a| a as i32))),
+            I32x4TruncSatF64x2UZero => unop!(self, V128, |a| from_lanes(lanes::<f64>(a).map(|a| a as u32))),
+            F64x2ConvertLowI32x4S => unop!(self, V128, |a| from_lanes(lanes::<i32>(a).take(2).map(|a| a as f64))),
+            F64x2ConvertLowI32x4U => unop!(self, V128, |a| from_lanes(lanes::<u32>(a).take(2).map(|a| a as f64))),
+            F32x4DemoteF64x2Zero => unop!(self, V128, |a| from_lanes(lanes::<f64>(a).map(|a| a as f32))),
+            F64x2PromoteLowF32x4 => unop!(self, V128, |a| from_lanes(lanes::<f32>(a).take(2).map(|a| a as f64))),
+            _ => unreachable!("not a vector instruction: {:?}", op),
+        }
+    }
+
+    fn pop_func(&mut self) -> Result<Func, Trap> {
+        match self.stack.pop() {
+            Some(Value::FuncRef(Some(f))) => Ok(f),
+            Some(Value::FuncRef(None)) => Err(Trap::NullReference),
+            _ => unreachable!("validated operand"),
+        }
+    }
+
+    fn pop_object(&mut self) -> Result<Object, Trap> {
+        match self.stack.pop() {
+            Some(Value::AnyRef(Some(AnyRef::Object(o)))) => Ok(o),
+            Some(Value::AnyRef(None)) => Err(Trap::NullReference),
+            _ => unreachable!("validated operand"),
+        }
+    }
+
+    /// Runs an instruction on references, structs or arrays that does not branch.
+    fn gc(&mut self, op: &Op, instance: &InstanceInner) -> Result<(), Trap> {
+        use ops::Op::*;
+        let types = &instance.types;
+        match *op {
+            RefEq => {
+                let b = self.stack.pop().unwrap();
+                let a = self.stack.pop().unwrap();
+                self.stack.push(Value::I32((a == b) as i32));
+            }
+            RefAsNonNull => {
+                if self.stack.last().unwrap().is_null() {
+                    return Err(Trap::NullReference);
+                }
+            }
+            StructNew { index } => {
+                let fields = types.struct_type(index);
+                let values = self.stack.split_off(self.stack.len() - fields.len());
+                let values = fields.iter().zip(values).map(|(f, v)| pack(f.storage, v)).collect();
+                self.stack.push(Object::alloc(types, index, values));
+            }
+            StructNewDefault { index } => {
+                let values = types.struct_type(index).iter().map(|f| types.default(f.storage.unpacked())).collect();
+                self.stack.push(Object::alloc(types, index, values));
+            }
+            StructGet { index, field } | StructGetS { index, field } | StructGetU { index, field } => {
+                let object = self.pop_object()?;
+                let value = object.0.fields.borrow()[field as usize].clone();
+                let storage = types.struct_type(index)[field as usize].storage;
+                self.stack.push(unpack(storage, value, matches!(*op, StructGetS { .. })));
+            }
+            StructSet { index, field } => {
+                let value = pack(types.struct_type(index)[field as usize].storage, self.stack.pop().unwrap());
+                let object = self.pop_object()?;
+                object.0.fields.borrow_mut()[field as usize] = value;
+            }
+            ArrayNew { index } => {
+                let len = array_len(pop!(self, I32))?;
+                let value = pack(types.array_type(index).storage, self.stack.pop().unwrap());
+                self.stack.push(Object::alloc(types, index, vec![value; len]));
+            }
+            ArrayNewDefault { index } => {
+                let len = array_len(pop!(self, I32))?;
+                let value = types.default(types.array_type(index).storage.unpacked());
+                self.stack.push(Object::alloc(types, index, vec![value; len]));
+            }
+            ArrayNewFixed { index, len } => {
+                let storage = types.array_type(index).storage;
+                let values = self.stack.split_off(self.stack.len() - len as usize);
+                self.stack.push(Object::alloc(types, index, values.into_iter().map(|v| pack(storage, v)).collect()));
+            }
+            ArrayNewData { index, segment } => {
+                let n = pop!(self, I32) as u32;
+                let src = pop!(self, I32) as u32;
+                let values = instance.array_data(index, *segment as usize, src, n)?;
+                self.stack.push(Object::alloc(types, index, values));
+            }
+            ArrayNewElem { index, segment } => {
+                let n = pop!(self, I32) as u32;
+                let src = pop!(self, I32) as u32;
+                let values = instance.elements(*segment as usize, src, n)?;
+                self.stack.push(Object::alloc(types, index, values));
+            }
+            ArrayGet { index } | ArrayGetS { index } | ArrayGetU { index } => {
+                let i = pop!(self, I32) as u32;
+                let value = self.pop_object()?.get(i).ok_or(Trap::ArrayOutOfBounds)?;
+                let storage = types.array_type(index).storage;
+                self.stack.push(unpack(storage, value, matches!(*op, ArrayGetS { .. })));
+            }
+            ArraySet { index } => {
+                let value = pack(types.array_type(index).storage, self.stack.pop().unwrap());
+                let i = pop!(self, I32) as u32;
+                let object = self.pop_object()?;
+                let mut fields = object.0.fields.borrow_mut();
+                *fields.get_mut(i as usize).ok_or(Trap::ArrayOutOfBounds)? = value;
+            }
+            ArrayLen => {
+                let len = self.pop_object()?.len();
+                self.stack.push(Value::I32(len as i32));
+            }
+            ArrayFill { index } => {
+                let n = pop!(self, I32) as u32;
+                let value = pack(types.array_type(index).storage, self.stack.pop().unwrap());
+                let i = pop!(self, I32) as u32;
+                let object = self.pop_object()?;
+                let mut fields = object.0.fields.borrow_mut();
+                let range = array_range(fields.len(), i, n)?;
+                for slot in fields[range].iter_mut() {
+                    *slot = value.clone();
+                }
+            }
+            ArrayCopy { .. } => {
+                let n = pop!(self, I32) as u32;
+                let s = pop!(self, I32) as u32;
+                let src = self.pop_object()?;
+                let d = pop!(self, I32) as u32;
+                let dst = self.pop_object()?;
+                // both ranges are checked before anything is written, the arrays may be the same
+                let values = {
+                    let fields = src.0.fields.borrow();
+                    fields[array_range(fields.len(), s, n)?].to_vec()
+                };
+                let mut fields = dst.0.fields.borrow_mut();
+                let range = array_range(fields.len(), d, n)?;
+                fields[range].clone_from_slice(&values);
+            }
+            ArrayInitData { index, segment } => {
+                let n = pop!(self, I32) as u32;
+                let s = pop!(self, I32) as u32;
+                let d = pop!(self, I32) as u32;
+                let object = self.pop_object()?;
+                let mut fields = object.0.fields.borrow_mut();
+                let range = array_range(fields.len(), d, n)?;
+                fields[range].clone_from_slice(&instance.array_data(index, *segment as usize, s, n)?);
+            }
+            ArrayInitElem { segment, .. } => {
+                let n = pop!(self, I32) as u32;
+                let s = pop!(self, I32) as u32;
+                let d = pop!(self, I32) as u32;
+                let object = self.pop_object()?;
+                let mut fields = object.0.fields.borrow_mut();
+                let range = array_range(fields.len(), d, n)?;
+                fields[range].clone_from_slice(&instance.elements(*segment as usize, s, n)?);
+            }
+            RefTest(ty) => {
+                let v = self.stack.pop().unwrap();
+                self.stack.push(Value::I32(has_type(&v, ValueType::Ref(ty), types) as i32));
+            }
+            RefCast(ty) => {
+                if !has_type(self.stack.last().unwrap(), ValueType::Ref(ty), types) {
+                    return Err(Trap::CastFailure);
+                }
+            }
+            AnyConvertExtern => {
+                let v = self.stack.pop().unwrap();
+                self.stack.push(internalize(v));
+            }
+            ExternConvertAny => {
+                let v = self.stack.pop().unwrap();
+                self.stack.push(externalize(v));
+            }
+            RefI31 => {
+                let v = pop!(self, I32);
+                self.stack.push(Value::AnyRef(Some(AnyRef::I31(v as u32 & 0x7fff_ffff))));
+            }
+            I31GetS | I31GetU => {
+                let bits = match self.stack.pop() {
+                    Some(Value::AnyRef(Some(AnyRef::I31(bits)))) => bits,
+                    Some(Value::AnyRef(None)) => return Err(Trap::NullReference),
+                    _ => unreachable!("validated operand"),
+                };
+                let v = if let I31GetS = *op { (bits << 1) as i32 >> 1 } else { bits as i32 };
+                self.stack.push(Value::I32(v));
+            }
+            _ => unreachable!("not a GC instruction: {:?}", op),
+        }
+        Ok(())
+    }
+
+    /// Replaces the current frame by a call to `callee`, so that tail calls do not grow the frames.
+    fn return_call(&mut self, callee: &Func, instance: &Rc<InstanceInner>) -> Result<(), Trap> {
+        let args = self.stack.split_off(self.stack.len() - callee.ty().params.len());
+        let frame = self.frames.pop().unwrap();
+        self.stack.truncate(frame.height);
+        self.stack.extend(args);
+        self.call_func(callee, instance)?;
+        Ok(())
+    }
+
+    /// Calls `callee` from a function of `instance`. Returns whether a frame was entered.
+    fn call_func(&mut self, callee: &Func, instance: &Rc<InstanceInner>) -> Result<bool, Trap> {
+        match *callee.0 {
+            FuncInner::Host { .. } => {
+                self.call(callee, Some(&**instance))?;
+                Ok(false)
+            }
+            FuncInner::Wasm { ref instance, index, .. } => {
+                let instance = instance.upgrade().ok_or(Trap::UninitializedElement)?;
+                self.enter(instance, index)?;
+                Ok(true)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use builder::InitExprBuilder;
+    use funtype;
+    use {ty, ty_vec};
+
+    fn instance(src: &str) -> Instance {
+        instantiate(src, &Imports::new()).unwrap()
+    }
+
+    fn instantiate(src: &str, imports: &Imports) -> Result<Instance, Error> {
+        Instance::new(&Module::from_wat(src).unwrap(), imports)
+    }
+
+    /// The trap raised by calling the export `run` without arguments.
+    fn trap(src: &str) -> Trap {
+        match instance(src).invoke("run", &[]) {
+            Err(Error::Trap(trap)) => trap,
+            other => panic!("expected a trap, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn invoke() {
+        let instance = instance(r#"
+            (module
+              (func $fac (export "fac") (param i64) (result i64)
+                (if (result i64) (i64.eqz (local.get 0))
+                  (then (i64.const 1))
+                  (else (i64.mul (local.get 0) (call $fac (i64.sub (local.get 0) (i64.const 1)))))))
+              (func (export "swap") (param i32 f32) (result f32 i32)
+                (local.get 1) (local.get 0))
+              (func (export "pick") (param i32 f32) (result f32)
+                (block $zero (result f32)
+                  (drop (br_if $zero (f32.const 0) (i32.eqz (local.get 0))))
+                  (local.get 1)))
+              (global (export "g") i32 (i32.const 7)))"#);
+        assert_eq!(instance.invoke("fac", &[Value::I64(20)]), Ok(vec![Value::I64(2432902008176640000)]));
+        assert_eq!(instance.invoke("swap", &[Value::I32(1), Value::F32(2.5)]),
+                   Ok(vec![Value::F32(2.5), Value::I32(1)]));
+        assert_eq!(instance.invoke("pick", &[Value::I32(1), Value::F32(2.5)]), Ok(vec![Value::F32(2.5)]));
+        assert_eq!(instance.invoke("pick", &[Value::I32(0), Value::F32(2.5)]), Ok(vec![Value::F32(0.0)]));
+        assert_eq!(instance.global("g"), Some(Value::I32(7)));
+        assert_eq!(instance.invoke("missing", &[]), Err(Error::UnknownExport("missing".into())));
+        assert_eq!(instance.invoke("g", &[]), Err(Error::ArgumentMismatch));
+        assert_eq!(instance.invoke("fac", &[Value::I32(1)]), Err(Error::ArgumentMismatch));
+        assert_eq!(instance.invoke("fac", &[]), Err(Error::ArgumentMismatch));
+    }
+
+    #[test]
+    fn tail_calls() {
+        let instance = instance(r#"
+            (module
+              (type $count (func (param i64 i64) (result i64)))
+              (table funcref (elem $sum))
+              (func $sum (export "sum") (param i64 i64) (result i64)
+                (if (result i64) (i64.eqz (local.get 0))
+                  (then (local.get 1))
+                  (else (return_call $sum (i64.sub (local.get 0) (i64.const 1)) (i64.add (local.get 1) (local.get 0))))))
+              (func (export "indirect") (param i64 i64) (result i64)
+                (if (result i64) (i64.eqz (local.get 0))
+                  (then (local.get 1))
+                  (else (return_call_indirect (type $count) (local.get 0) (local.get 1) (i32.const 0))))))"#);
+        // deeper than the call stack allows for ordinary calls
+        assert_eq!(instance.invoke("sum", &[Value::I64(100_000), Value::I64(0)]),
+                   Ok(vec![Value::I64(5_000_050_000)]));
+        assert_eq!(instance.invoke("indirect", &[Value::I64(10), Value::I64(0)]), Ok(vec![Value::I64(55)]));
+    }
+
+    #[test]
+    fn numeric_traps() {
+        assert_eq!(trap(r#"(module (func (export "run") unreachable))"#), Trap::Unreachable);
+        assert_eq!(trap(r#"(module (func (export "run") (drop (i32.div_s (i32.const 1) (i32.const 0)))))"#),
+                   Trap::IntegerDivideByZero);
+        assert_eq!(trap(r#"(module (func (export "run") (drop (i32.div_s (i32.const 0x80000000) (i32.const -1)))))"#),
+                   Trap::IntegerOverflow);
+        assert_eq!(trap(r#"(module (func (export "run") (drop (i32.trunc_f32_s (f32.const nan)))))"#),
+                   Trap::InvalidConversionToInteger);
+        assert_eq!(trap(r#"(module (func (export "run") (drop (i64.trunc_f64_u (f64.const -1)))))"#),
+                   Trap::IntegerOverflow);
+        assert_eq!(trap(r#"(module (func $f (export "run") (call $f)))"#), Trap::StackExhausted);
+    }
+
+    #[test]
+    fn saturating_conversions() {
+        let instance = instance(r#"
+            (module
+              (func (export "i32") (param f32) (result i32) (i32.trunc_sat_f32_s (local.get 0)))
+              (func (export "u64") (param f64) (result i64) (i64.trunc_sat_f64_u (local.get 0)))
+              (func (export "extend") (param i32) (result i32) (i32.extend8_s (local.get 0)))
+              (func (export "extend32") (param i64) (result i64) (i64.extend32_s (local.get 0))))"#);
+        let cases = [("i32", Value::F32(f32::NAN), Value::I32(0)),
+                     ("i32", Value::F32(-3e9), Value::I32(i32::MIN)),
+                     ("i32", Value::F32(3e9), Value::I32(i32::MAX)),
+                     ("i32", Value::F32(-2.9), Value::I32(-2)),
+                     ("u64", Value::F64(-1.0), Value::I64(0)),
+                     ("u64", Value::F64(f64::INFINITY), Value::I64(-1)),
+                     ("extend", Value::I32(0x180), Value::I32(-128)),
+                     ("extend32", Value::I64(0x1_8000_0000), Value::I64(-0x8000_0000))];
+        for &(name, ref arg, ref result) in cases.iter() {
+            assert_eq!(instance.invoke(name, std::slice::from_ref(arg)), Ok(vec![result.clone()]), "{} {:?}", name, arg);
+        }
+    }
+
+    #[test]
+    fn atomics() {
+        let instance = instance(r#"
+            (module
+              (memory $shared 1 1 shared)
+              (func (export "add") (param i32 i32) (result i32) (i32.atomic.rmw.add (local.get 0) (local.get 1)))
+              (func (export "load") (param i32) (result i32) (i32.atomic.load (local.get 0)))
+              (func (export "cmpxchg") (param i32 i32) (result i32)
+                (i32.atomic.rmw.cmpxchg (i32.const 0) (local.get 0) (local.get 1)))
+              (func (export "wait") (param i32 i64) (result i32)
+                (memory.atomic.wait32 (i32.const 0) (local.get 0) (local.get 1)))
+              (func (export "notify") (result i32) (memory.atomic.notify (i32.const 0) (i32.const 1))))"#);
+        let call = |name, args: &[Value]| instance.invoke(name, args);
+        assert_eq!(call("add", &[Value::I32(0), Value::I32(5)]), Ok(vec![Value::I32(0)]));
+        assert_eq!(call("add", &[Value::I32(0), Value::I32(2)]), Ok(vec![Value::I32(5)]));
+        assert_eq!(call("load", &[Value::I32(0)]), Ok(vec![Value::I32(7)]));
+        assert_eq!(call("load", &[Value::I32(2)]), Err(Error::Trap(Trap::UnalignedAtomic)));
+        assert_eq!(call("load", &[Value::I32(65536)]), Err(Error::Trap(Trap::MemoryOutOfBounds)));
+        // the old value is returned whether or not it matched
+        assert_eq!(call("cmpxchg", &[Value::I32(1), Value::I32(9)]), Ok(vec![Value::I32(7)]));
+        assert_eq!(call("cmpxchg", &[Value::I32(7), Value::I32(9)]), Ok(vec![Value::I32(7)]));
+        assert_eq!(call("load", &[Value::I32(0)]), Ok(vec![Value::I32(9)]));
+        // "not-equal", then "timed-out"; nobody else could ever wake the thread
+        assert_eq!(call("wait", &[Value::I32(1), Value::I64(0)]), Ok(vec![Value::I32(1)]));
+        assert_eq!(call("wait", &[Value::I32(9), Value::I64(0)]), Ok(vec![Value::I32(2)]));
+        assert_eq!(call("wait", &[Value::I32(9), Value::I64(-1)]), Err(Error::Trap(Trap::Deadlock)));
+        assert_eq!(call("notify", &[]), Ok(vec![Value::I32(0)]));
+
+        assert_eq!(trap(r#"(module (memory 1)
+                             (func (export "run") (drop (memory.atomic.wait32 (i32.const 0) (i32.const 0) (i64.const 0)))))"#),
+                   Trap::ExpectedSharedMemory);
+    }
+
+    #[test]
+    fn memory_traps() {
+        assert_eq!(trap(r#"(module (memory 1) (func (export "run") (drop (i32.load (i32.const 65533)))))"#),
+                   Trap::MemoryOutOfBounds);
+        assert_eq!(trap(r#"(module (memory 1) (func (export "run") (drop (i32.load offset=65536 (i32.const 0)))))"#),
+                   Trap::MemoryOutOfBounds);
+        let instance = instance(r#"
+            (module (memory 1 2)
+              (func (export "grow") (param i32) (result i32) (memory.grow (local.get 0)))
+              (func (export "store") (param i32) (i32.store8 (local.get 0) (i32.const 1))))"#);
+        assert_eq!(instance.invoke("store", &[Value::I32(65536)]), Err(Error::Trap(Trap::MemoryOutOfBounds)));
+        assert_eq!(instance.invoke("grow", &[Value::I32(2)]), Ok(vec![Value::I32(-1)]));
+        assert_eq!(instance.invoke("grow", &[Value::I32(1)]), Ok(vec![Value::I32(1)]));
+        assert_eq!(instance.invoke("store", &[Value::I32(65536)]), Ok(vec![]));
+    }
+
+    #[test]
+    fn bulk_memory() {
+        let instance = instance(r#"
+            (module (memory 1)
+              (data $abc "abc")
+              (func (export "init") (param i32 i32 i32) (memory.init $abc (local.get 0) (local.get 1) (local.get 2)))
+              (func (export "drop") (data.drop $abc))
+              (func (export "copy") (param i32 i32 i32) (memory.copy (local.get 0) (local.get 1) (local.get 2)))
+              (func (export "fill") (param i32 i32 i32) (memory.fill (local.get 0) (local.get 1) (local.get 2)))
+              (func (export "load") (param i32) (result i32) (i32.load8_u (local.get 0))))"#);
+        let call = |name, args: &[i32]| {
+            let args: Vec<Value> = args.iter().map(|&a| Value::I32(a)).collect();
+            instance.invoke(name, &args)
+        };
+        let load = |addr| call("load", &[addr]).unwrap().remove(0);
+        assert_eq!(call("init", &[10, 1, 2]), Ok(vec![]));
+        assert_eq!((load(10), load(11), load(12)), (Value::I32(b'b' as i32), Value::I32(b'c' as i32), Value::I32(0)));
+        assert_eq!(call("init", &[0, 2, 2]), Err(Error::Trap(Trap::MemoryOutOfBounds)));
+        // overlapping copies behave like memmove
+        assert_eq!(call("copy", &[11, 10, 2]), Ok(vec![]));
+        assert_eq!((load(11), load(12)), (Value::I32(b'b' as i32), Value::I32(b'c' as i32)));
+        assert_eq!(call("fill", &[65530, 7, 6]), Ok(vec![]));
+        assert_eq!(load(65535), Value::I32(7));
+        assert_eq!(call("fill", &[65530, 7, 7]), Err(Error::Trap(Trap::MemoryOutOfBounds)));
+        assert_eq!(call("copy", &[0, 65535, 2]), Err(Error::Trap(Trap::MemoryOutOfBounds)));
+        // a dropped segment is empty
+        assert_eq!(call("drop", &[]), Ok(vec![]));
+        assert_eq!(call("init", &[0, 0, 0]), Ok(vec![]));
+        assert_eq!(call("init", &[0, 0, 1]), Err(Error::Trap(Trap::MemoryOutOfBounds)));
+    }
+
+    #[test]
+    fn multi_memory() {
+        let instance = instance(r#"
+            (module
+              (memory $a 1)
+              (memory $b 2)
+              (func (export "store") (param i32 i32) (i32.store $b (local.get 0) (local.get 1)))
+              (func (export "load_a") (param i32) (result i32) (i32.load $a (local.get 0)))
+              (func (export "load_b") (param i32) (result i32) (i32.load $b (local.get 0)))
+              (func (export "copy") (param i32 i32 i32) (memory.copy $a $b (local.get 0) (local.get 1) (local.get 2)))
+              (func (export "sizes") (result i32 i32) (memory.size $a) (memory.size $b)))"#);
+        assert_eq!(instance.invoke("store", &[Value::I32(65536), Value::I32(42)]), Ok(vec![]));
+        assert_eq!(instance.invoke("load_b", &[Value::I32(65536)]), Ok(vec![Value::I32(42)]));
+        assert_eq!(instance.invoke("load_a", &[Value::I32(65536)]), Err(Error::Trap(Trap::MemoryOutOfBounds)));
+        assert_eq!(instance.invoke("copy", &[Value::I32(8), Value::I32(65536), Value::I32(4)]), Ok(vec![]));
+        assert_eq!(instance.invoke("load_a", &[Value::I32(8)]), Ok(vec![Value::I32(42)]));
+        assert_eq!(instance.invoke("sizes", &[]), Ok(vec![Value::I32(1), Value::I32(2)]));
+    }
+
+    #[test]
+    fn memory64() {
+        let instance = instance(r#"
+            (module
+              (memory i64 1)
+              (func (export "store") (param i64 i32) (i32.store (local.get 0) (local.get 1)))
+              (func (export "load") (param i64) (result i32) (i32.load (local.get 0)))
+              (func (export "far") (result i32) (i32.load offset=0x100000000 (i64.const 0)))
+              (func (export "grow") (param i64) (result i64) (memory.grow (local.get 0))))"#);
+        assert_eq!(instance.invoke("store", &[Value::I64(65532), Value::I32(9)]), Ok(vec![]));
+        assert_eq!(instance.invoke("load", &[Value::I64(65532)]), Ok(vec![Value::I32(9)]));
+        assert_eq!(instance.invoke("load", &[Value::I64(1 << 32)]), Err(Error::Trap(Trap::MemoryOutOfBounds)));
+        assert_eq!(instance.invoke("load", &[Value::I64(-1)]), Err(Error::Trap(Trap::MemoryOutOfBounds)));
+        assert_eq!(instance.invoke("far", &[]), Err(Error::Trap(Trap::MemoryOutOfBounds)));
+        assert_eq!(instance.invoke("grow", &[Value::I64(1)]), Ok(vec![Value::I64(1)]));
+        assert_eq!(instance.invoke("grow", &[Value::I64(1 << 40)]), Ok(vec![Value::I64(-1)]));
+        assert_eq!(instantiate("(module (memory i64 0x10001))", &Imports::new()).err(),
+                   Some(Error::MemoryTooLarge));
+    }
+
+    #[test]
+    fn table_traps() {
+        let instance = instance(r#"
+            (module
+              (type $v (func))
+              (type $i (func (result i32)))
+              (table 3 funcref)
+              (elem (i32.const 0) $nop $one)
+              (func $nop)
+              (func $one (result i32) (i32.const 1))
+              (func (export "call") (param i32) (result i32)
+                (call_indirect (type $i) (local.get 0))))"#);
+        assert_eq!(instance.invoke("call", &[Value::I32(1)]), Ok(vec![Value::I32(1)]));
+        assert_eq!(instance.invoke("call", &[Value::I32(0)]), Err(Error::Trap(Trap::IndirectCallTypeMismatch)));
+        assert_eq!(instance.invoke("call", &[Value::I32(2)]), Err(Error::Trap(Trap::UninitializedElement)));
+        assert_eq!(instance.invoke("call", &[Value::I32(3)]), Err(Error::Trap(Trap::UndefinedElement)));
+    }
+
+    #[test]
+    fn references() {
+        let instance = instance(r#"
+            (module
+              (table $t 1 externref)
+              (func $f)
+              (elem declare func $f)
+              (func (export "get") (param i32) (result externref) (table.get $t (local.get 0)))
+              (func (export "set") (param i32 externref) (table.set $t (local.get 0) (local.get 1)))
+              (func (export "grow") (param externref i32) (result i32) (table.grow $t (local.get 0) (local.get 1)))
+              (func (export "size") (result i32) (table.size $t))
+              (func (export "is_null") (param externref) (result i32) (ref.is_null (local.get 0)))
+              (func (export "func") (result i32) (ref.is_null (ref.func $f))))"#);
+        let host = ExternRef::new(String::from("host"));
+        let value = Value::ExternRef(Some(host.clone()));
+        let null = Value::ExternRef(None);
+        assert_eq!(instance.invoke("get", &[Value::I32(0)]), Ok(vec![null.clone()]));
+        assert_eq!(instance.invoke("set", &[Value::I32(0), value.clone()]), Ok(vec![]));
+        assert_eq!(instance.invoke("get", &[Value::I32(0)]), Ok(vec![value.clone()]));
+        assert_eq!(instance.invoke("set", &[Value::I32(1), value.clone()]),
+                   Err(Error::Trap(Trap::TableOutOfBounds)));
+        assert_eq!(instance.invoke("grow", &[null.clone(), Value::I32(2)]), Ok(vec![Value::I32(1)]));
+        assert_eq!(instance.invoke("size", &[]), Ok(vec![Value::I32(3)]));
+        assert_eq!(instance.invoke("is_null", &[null]), Ok(vec![Value::I32(1)]));
+        assert_eq!(instance.invoke("is_null", &[value]), Ok(vec![Value::I32(0)]));
+        assert_eq!(instance.invoke("func", &[]), Ok(vec![Value::I32(0)]));
+        match instance.invoke("get", &[Value::I32(0)]) {
+            Ok(ref results) => {
+                match results[..] {
+                    [Value::ExternRef(Some(ref r))] => assert_eq!(r.downcast_ref::<String>().unwrap(), "host"),
+                    _ => panic!("{:?}", results),
+                }
+            }
+            Err(e) => panic!("{}", e),
+        }
+        assert_eq!(host.downcast_ref::<i32>(), None);
+    }
+
+    #[test]
+    fn simd() {
+        let instance = instance(r#"
+            (module (memory 1)
+              (data (i32.const 0) "\00\01\02\03\04\05\06\07\08\09\0a\0b\0c\0d\0e\0f")
+              (func (export "add") (param v128 v128) (result v128) (i32x4.add (local.get 0) (local.get 1)))
+              (func (export "load") (result v128) (v128.load (i32.const 0)))
+              (func (export "shuffle") (result i32)
+                (i8x16.extract_lane_u 0
+                  (i8x16.shuffle 15 14 13 12 11 10 9 8 7 6 5 4 3 2 1 0
+                    (v128.load (i32.const 0)) (v128.const i32x4 0 0 0 0))))
+              (func (export "splat") (result i64) (i64x2.extract_lane 1 (i16x8.splat (i32.const -1))))
+              (func (export "mul") (result f32)
+                (f32x4.extract_lane 2 (f32x4.mul (v128.const f32x4 1 2 3 4) (f32x4.splat (f32.const 0.5)))))
+              (func (export "lt") (result v128)
+                (i8x16.lt_s (v128.const i8x16 -1 0 1 0 0 0 0 0 0 0 0 0 0 0 0 0) (v128.const i8x16 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0))))"#);
+        let lanes = Value::V128(0x00000003_00000002_00000001_ffffffff);
+        let ones = Value::V128(0x00000001_00000001_00000001_00000001);
+        assert_eq!(instance.invoke("add", &[lanes, ones]),
+                   Ok(vec![Value::V128(0x00000004_00000003_00000002_00000000)]));
+        assert_eq!(instance.invoke("load", &[]), Ok(vec![Value::V128(0x0f0e0d0c_0b0a0908_07060504_03020100)]));
+        assert_eq!(instance.invoke("shuffle", &[]), Ok(vec![Value::I32(15)]));
+        assert_eq!(instance.invoke("splat", &[]), Ok(vec![Value::I64(-1)]));
+        assert_eq!(instance.invoke("mul", &[]), Ok(vec![Value::F32(1.5)]));
+        assert_eq!(instance.invoke("lt", &[]), Ok(vec![Value::V128(0xff)]));
+    }
+
+    #[test]
+    fn exceptions() {
+        let tag = Tag::new(funtype!((i32)));
+        let imports = Imports::new().tag("env", "e", tag.clone());
+        let instance = instantiate(r#"
+            (module
+              (import "env" "e" (tag $e (param i32)))
+              (tag $other)
+              (func $throw (export "throw") (param i32) (throw $e (local.get 0)))
+              (func (export "catch") (param i32) (result i32)
+                (try (result i32)
+                  (do (call $throw (local.get 0)) (i32.const 0))
+                  (catch $e)))
+              (func (export "catch_all") (result i32)
+                (try (result i32)
+                  (do (throw $other))
+                  (catch $e)
+                  (catch_all (i32.const 7))))
+              (func (export "rethrow") (param i32)
+                (try
+                  (do (call $throw (local.get 0)))
+                  (catch_all (rethrow 0)))))"#,
+                                   &imports)
+            .unwrap();
+        assert_eq!(instance.invoke("catch", &[Value::I32(5)]), Ok(vec![Value::I32(5)]));
+        assert_eq!(instance.invoke("catch_all", &[]), Ok(vec![Value::I32(7)]));
+        for name in ["throw", "rethrow"].iter() {
+            match instance.invoke(name, &[Value::I32(3)]) {
+                Err(Error::Trap(Trap::Exception(e))) => {
+                    assert_eq!(e.tag(), &tag);
+                    assert_eq!(e.values(), &[Value::I32(3)]);
+                }
+                other => panic!("expected an exception, got {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn eval_const() {
+        let expr = |code: Vec<Op>| InitExpr(Code(code));
+        let globals = [Value::I32(10), Value::I64(-1)];
+        let cases = [(expr(vec![Op::I32Const(i32::MAX), Op::I32Const(1), Op::I32Add, Op::End]), Some(Value::I32(i32::MIN))),
+                     (InitExprBuilder::new()
+                          .get_global(GlobalIndex::new(0))
+                          .constant(3i32)
+                          .i32_mul()
+                          .constant(1i32)
+                          .i32_sub()
+                          .build(),
+                      Some(Value::I32(29))),
+                     (expr(vec![Op::GetGlobal(GlobalIndex::new(1)), Op::End]), Some(Value::I64(-1))),
+                     (expr(vec![Op::RefNull(HeapType::Extern), Op::End]), Some(Value::ExternRef(None))),
+                     (InitExprBuilder::new()
+                          .constant(-1i32)
+                          .ref_i31()
+                          .extern_convert_any()
+                          .any_convert_extern()
+                          .build(),
+                      Some(Value::AnyRef(Some(AnyRef::I31(0x7fff_ffff))))),
+                     // a global past those given, a function reference and invalid expressions
+                     (expr(vec![Op::GetGlobal(GlobalIndex::new(2)), Op::End]), None),
+                     (expr(vec![Op::RefFunc(FunctionIndex::new(0).into()), Op::End]), None),
+                     (expr(vec![Op::I32Const(1), Op::I32Add, Op::End]), None),
+                     (expr(vec![Op::I32Const(1), Op::I64Const(1), Op::I64Add, Op::End]), None),
+                     (expr(vec![Op::I32Const(1), Op::I32Clz, Op::End]), None),
+                     (expr(vec![Op::I32Const(1), Op::AnyConvertExtern, Op::End]), None)];
+        for &(ref expr, ref value) in cases.iter() {
+            assert_eq!(super::eval_const(expr, &globals), *value, "{:?}", expr);
+        }
+    }
+
+    #[test]
+    fn gc_traps() {
+        assert_eq!(trap(r#"(module (type $s (struct (field i32)))
+                             (func (export "run") (drop (struct.get $s 0 (ref.null $s)))))"#),
+                   Trap::NullReference);
+        assert_eq!(trap(r#"(module (func (export "run") (drop (ref.as_non_null (ref.null func)))))"#),
+                   Trap::NullReference);
+        assert_eq!(trap(r#"(module (type $s (struct)) (type $t (struct (field i32)))
+                             (func (export "run") (drop (ref.cast (ref $t) (struct.new $s)))))"#),
+                   Trap::CastFailure);
+        assert_eq!(trap(r#"(module (type $a (array (mut i8)))
+                             (func (export "run") (drop (array.get_u $a (array.new_default $a (i32.const 2)) (i32.const 2)))))"#),
+                   Trap::ArrayOutOfBounds);
+        assert_eq!(trap(r#"(module (type $a (array i8))
+                             (func (export "run") (drop (array.new_default $a (i32.const -1)))))"#),
+                   Trap::AllocationTooLarge);
+        assert_eq!(trap(r#"(module (func (export "run") (drop (i31.get_u (ref.null i31)))))"#),
+                   Trap::NullReference);
+        let instance = instance(r#"
+            (module
+              (type $p (struct (field $x (mut i32)) (field i8)))
+              (func (export "run") (result i32)
+                (local $o (ref null $p))
+                (local.set $o (struct.new $p (i32.const 1) (i32.const -1)))
+                (struct.set $p $x (local.get $o) (i32.const 40))
+                (i32.add (struct.get $p $x (local.get $o)) (struct.get_u $p 1 (local.get $o))))
+              (func (export "new") (result anyref) (struct.new_default $p)))"#);
+        assert_eq!(instance.invoke("run", &[]), Ok(vec![Value::I32(295)]));
+        match instance.invoke("new", &[]) {
+            Ok(ref v) if v.len() == 1 => {
+                match v[0] {
+                    Value::AnyRef(Some(AnyRef::Object(ref o))) => {
+                        assert!(!o.is_array());
+                        assert_eq!((o.ty(), o.len(), o.get(0), o.get(2)), (0, 2, Some(Value::I32(0)), None));
+                    }
+                    ref other => panic!("expected an object, got {:?}", other),
+                }
+            }
+            other => panic!("expected one object, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn i31_and_extern_conversions() {
+        let instance = instance(r#"
+            (module
+              (table $t 2 anyref)
+              (global $seven (ref i31) (ref.i31 (i32.const 7)))
+              (func (export "get_s") (param i32) (result i32) (i31.get_s (ref.i31 (local.get 0))))
+              (func (export "get_u") (param i32) (result i32) (i31.get_u (ref.i31 (local.get 0))))
+              (func (export "eq") (param i32 i32) (result i32) (ref.eq (ref.i31 (local.get 0)) (ref.i31 (local.get 1))))
+              (func (export "round_trip") (param externref) (result externref)
+                (extern.convert_any (any.convert_extern (local.get 0))))
+              (func (export "externalize") (param i32) (result externref) (extern.convert_any (ref.i31 (local.get 0))))
+              (func (export "internalize") (param externref) (result i32)
+                (i31.get_u (ref.cast (ref i31) (any.convert_extern (local.get 0)))))
+              (func (export "table") (result i32)
+                (table.set $t (i32.const 1) (global.get $seven))
+                (i31.get_s (ref.cast i31ref (table.get $t (i32.const 1))))))"#);
+        // the top bit is dropped, and bit 30 is the sign
+        assert_eq!(instance.invoke("get_s", &[Value::I32(-1)]), Ok(vec![Value::I32(-1)]));
+        assert_eq!(instance.invoke("get_s", &[Value::I32(0x4000_0000)]), Ok(vec![Value::I32(-0x4000_0000)]));
+        assert_eq!(instance.invoke("get_u", &[Value::I32(-1)]), Ok(vec![Value::I32(0x7fff_ffff)]));
+        assert_eq!(instance.invoke("eq", &[Value::I32(1), Value::I32(-0x7fff_ffff)]), Ok(vec![Value::I32(1)]));
+
+        let host = Value::from(ExternRef::new("host"));
+        assert_eq!(instance.invoke("round_trip", std::slice::from_ref(&host)), Ok(vec![host]));
+        assert_eq!(instance.invoke("round_trip", &[Value::ExternRef(None)]), Ok(vec![Value::ExternRef(None)]));
+        let five = instance.invoke("externalize", &[Value::I32(5)]).unwrap().remove(0);
+        assert!(matches!(five, Value::ExternRef(Some(_))));
+        assert_eq!(instance.invoke("internalize", &[five]), Ok(vec![Value::I32(5)]));
+        // a host reference is an `anyref` of no other type
+        assert_eq!(instance.invoke("internalize", &[Value::from(ExternRef::new(5))]),
+                   Err(Error::Trap(Trap::CastFailure)));
+        assert_eq!(instance.invoke("table", &[]), Ok(vec![Value::I32(7)]));
+    }
+
+    #[test]
+    fn imports() {
+        let memory = Rc::new(RefCell::new(Memory::new(1, None)));
+        let imports = Imports::new()
+            .function("env", "add", funtype!((i32, i32) -> i32), |_, args| match (&args[0], &args[1]) {
+                (&Value::I32(a), &Value::I32(b)) => Ok(vec![Value::I32(a + b)]),
+                _ => unreachable!(),
+            })
+            .function("env", "store", funtype!((i32)), |caller, args| {
+                let memory = caller.memory().unwrap();
+                match args[0] {
+                    Value::I32(v) => memory.borrow_mut().write(0, &v.to_le_bytes()).map(|_| vec![]),
+                    _ => unreachable!(),
+                }
+            })
+            .function("env", "fail", funtype!(()), |_, _| Err(Trap::Host("failed".into())))
+            .function("env", "wrong", funtype!(() -> i32), |_, _| Ok(vec![Value::I64(0)]))
+            .function("env", "none", funtype!(() -> i32), |_, _| Ok(vec![]))
+            .global("env", "base", Value::I32(10))
+            .memory("env", "memory", memory.clone());
+        let instance = instantiate(r#"
+            (module
+              (import "env" "add" (func $add (param i32 i32) (result i32)))
+              (import "env" "store" (func $store (param i32)))
+              (import "env" "fail" (func $fail))
+              (import "env" "wrong" (func $wrong (result i32)))
+              (import "env" "none" (func $none (result i32)))
+              (import "env" "base" (global $base i32))
+              (import "env" "memory" (memory 1))
+              (global $next i32 (i32.add (global.get $base) (i32.const 1)))
+              (global $start i32 (global.get $next))
+              (func (export "run") (result i32)
+                (call $store (call $add (global.get $start) (i32.const 31)))
+                (i32.load (i32.const 0)))
+              (func (export "fail") (call $fail))
+              (func (export "wrong") (result i32) (call $wrong))
+              (func (export "none") (result i32) (call $none)))"#,
+                                   &imports)
+            .unwrap();
+        assert_eq!(instance.invoke("run", &[]), Ok(vec![Value::I32(42)]));
+        assert_eq!(memory.borrow().data()[0], 42);
+        assert_eq!(instance.invoke("fail", &[]), Err(Error::Trap(Trap::Host("failed".into()))));
+        assert_eq!(instance.invoke("wrong", &[]), Err(Error::Trap(Trap::HostResultMismatch)));
+        assert_eq!(instance.invoke("none", &[]), Err(Error::Trap(Trap::HostResultMismatch)));
+        let host = Func::host(funtype!(() -> i32), |_, _| Ok(vec![Value::F32(0.0)]));
+        assert_eq!(host.call(&[]), Err(Error::Trap(Trap::HostResultMismatch)));
+    }
+
+    #[test]
+    fn instantiation_errors() {
+        let src = r#"(module (import "env" "f" (func (param i32))))"#;
+        assert_eq!(instantiate(src, &Imports::new()).err(),
+                   Some(Error::UnknownImport {
+                            module: "env".into(),
+                            field: "f".into(),
+                        }));
+        let imports = Imports::new().function("env", "f", funtype!((i64)), |_, _| Ok(vec![]));
+        assert_eq!(instantiate(src, &imports).err(),
+                   Some(Error::IncompatibleImport {
+                            module: "env".into(),
+                            field: "f".into(),
+                        }));
+        let imports = Imports::new().global("env", "f", Value::I32(0));
+        assert_eq!(instantiate(src, &imports).err(),
+                   Some(Error::IncompatibleImport {
+                            module: "env".into(),
+                            field: "f".into(),
+                        }));
+        assert_eq!(instantiate(r#"(module (memory 1) (data (i32.const 65535) "ab"))"#, &Imports::new()).err(),
+                   Some(Error::SegmentDoesNotFit));
+        assert_eq!(instantiate(r#"(module (table 1 funcref) (elem (i32.const 1) $f) (func $f))"#, &Imports::new()).err(),
+                   Some(Error::SegmentDoesNotFit));
+        match instantiate(r#"(module (func (result i32)))"#, &Imports::new()) {
+            Err(Error::Invalid(_)) => {}
+            other => panic!("expected a validation error, got {:?}", other.err()),
+        }
+    }
+}
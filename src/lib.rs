@@ -1,13 +1,30 @@
+#![allow(clippy::redundant_field_names, clippy::needless_borrowed_reference, clippy::match_ref_pats)]
+
 mod util;
 mod module;
 mod types;
 mod ops;
+mod decode;
+mod error;
+mod validate;
+mod wat;
 pub mod builder;
+pub mod interpreter;
 
 pub use types::*;
 pub use module::*;
 pub use ops::*;
+pub use decode::{DecodeError, DecodeErrorKind};
+pub use error::Error;
+pub use validate::{Location, ValidationError, ValidationErrorKind};
+pub use wat::{WatError, WatErrorKind, WatStyle};
 
 pub trait Dump {
     fn dump(&self, buf: &mut Vec<u8>) -> usize;
 }
+
+/// The counterpart of `Dump`. Reads a value from `buf` starting at `*pos`
+/// and advances `pos` past it. Errors carry the offset in `buf`.
+pub trait Parse: Sized {
+    fn parse(buf: &[u8], pos: &mut usize) -> Result<Self, DecodeError>;
+}
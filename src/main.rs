@@ -1,26 +1,26 @@
-#[macro_use]
 extern crate web_assembler as wasm;
 
 use wasm::*;
 use wasm::builder::*;
 
+use std::error;
 use std::io::Write;
 use std::fs::File;
 
-fn main() {
+fn main() -> Result<(), Box<dyn error::Error>> {
     let mut mb = ModuleBuilder::new();
-    let f = mb.new_function(FunctionBuilder::new(funtype!((i32, i32) -> i32))
-                                .code(|cb, args| {
-                                          cb.constant(-3256).get_local(args[0]).i32_store(4)
-                                      })
-                                .build());
+    let f = mb.try_new_function(FunctionBuilder::new(funtype!((i32, i32) -> i32))
+                                    .code(|cb, args| {
+                                              cb.constant(-3256).get_local(args[0]).i32_store(4, None)
+                                          })
+                                    .try_build()?)?;
     mb.export("addTwo", f);
-    let module = mb.build();
+    let module = mb.try_build()?;
 
     let mut buf = Vec::new();
-    module.dump(&mut buf);
-
-    let mut out = File::create(std::env::args().nth(1).unwrap()).unwrap();
-    let _ = out.write(&buf).unwrap();
+    module.try_dump(&mut buf)?;
 
+    let mut out = File::create(std::env::args().nth(1).ok_or("argument missing: output file")?)?;
+    out.write_all(&buf)?;
+    Ok(())
 }
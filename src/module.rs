@@ -1,28 +1,93 @@
+use std::collections::BTreeMap;
+
 use util::*;
 use types::*;
-use Dump;
+use decode::*;
+use error::Error;
+use ops::Op;
+use {Dump, Parse};
 
 #[derive(Debug, Clone)]
 pub struct Module {
     //    version: usize
-    pub unknown: Option<String>,
-    pub types: Option<Vec<FuncType>>,
+    pub customs: Option<Vec<CustomSection>>,
+    /// The recursion groups of the type section. Type indices count the types
+    /// of all groups, see `sub_types`.
+    pub types: Option<Vec<RecGroup>>,
     pub imports: Option<Vec<ImportEntry>>,
     pub functions: Option<Vec<Function>>,
     pub tables: Option<Vec<TableType>>,
     pub memories: Option<Vec<MemoryType>>,
+    pub tags: Option<Vec<TagType>>,
     pub globals: Option<Vec<GlobalVariable>>,
     pub exports: Option<Vec<ExportEntry>>,
     pub start: Option<FunctionIndex>,
     pub elements: Option<Vec<ElemSegment>>,
+    /// The number of data segments, which `memory.init` and `data.drop`
+    /// need to be declared ahead of the code.
+    pub data_count: Option<u32>,
     pub codes: Option<Vec<FunctionBody>>,
     pub data: Option<Vec<DataSegment>>,
+    /// Debug names, emitted as a `name` section after all other sections and
+    /// in place of any custom section called `name`.
+    pub names: Option<NameSection>,
 }
 
 impl Module {
+    /// Like `dump`, but reports a module that cannot be encoded instead of
+    /// panicking, and first checks that the functions and their bodies agree
+    /// in number and that `data_count` is present where needed and right, as
+    /// a decoder would reject the module otherwise. Nothing is written on
+    /// error.
+    pub fn try_dump(&self, buf: &mut Vec<u8>) -> Result<usize, Error> {
+        let functions = self.functions.as_ref().map_or(0, |f| f.len());
+        let codes = self.codes.as_ref().map_or(0, |c| c.len());
+        if functions != codes {
+            return Err(Error::FunctionCodeMismatch {
+                           functions: functions,
+                           codes: codes,
+                       });
+        }
+        let data = self.data.as_ref().map_or(0, |d| d.len());
+        match self.data_count {
+            None if self.needs_data_count() => return Err(Error::MissingDataCount),
+            Some(n) if n as usize != data => {
+                return Err(Error::DataCountMismatch {
+                               data_count: n,
+                               data: data,
+                           })
+            }
+            _ => (),
+        }
+        Ok(self.dump(buf))
+    }
+
+    /// Whether the code refers to data segments by index, in which case the
+    /// module needs a `data_count`.
+    pub fn needs_data_count(&self) -> bool {
+        self.codes
+            .iter()
+            .flat_map(|c| c.iter())
+            .flat_map(|c| c.code.0.iter())
+            .any(|op| {
+                matches!(*op,
+                         Op::MemoryInit { .. } | Op::DataDrop(_) | Op::ArrayNewData { .. } | Op::ArrayInitData { .. })
+            })
+    }
+
+    /// The types of the type section in the order of their indices.
+    pub fn sub_types(&self) -> impl Iterator<Item = &SubType> {
+        self.types.iter().flat_map(|t| t.iter()).flat_map(|g| g.types.iter())
+    }
+
+    /// The type at `index` if it is a function type.
+    pub fn func_type(&self, index: u32) -> Option<&FuncType> {
+        self.sub_types().nth(index as usize).and_then(|t| t.func_type())
+    }
+
     pub fn function_index_of(&self, i: ImportIndex) -> Result<FunctionSpaceIndex, ImportIndex> {
         match self.imports {
-            None => return Err(i),
+            None => Err(i),
             Some(ref imports) => {
                 if let Some(&ImportEntry {kind: ImportKind::Function(_) ,..}) =imports.get(*i as usize) {
                     let index = imports.iter()
@@ -31,7 +96,7 @@ impl Module {
                         .count();
                     Ok(FunctionSpaceIndex(InnerFunctionSpaceIndex::Import(ImportedFunctionIndex(index as u32))))
                 } else {
-                    return Err(i)
+                    Err(i)
                 }
             }
         }
@@ -50,10 +115,18 @@ impl Dump for Module {
 
 
         let mut v = Vec::new();
+        macro_rules! do_customs {
+            ($placement: expr) => {{
+                for custom in self.customs.iter().flat_map(|c| c.iter()) {
+                    if custom.placement == $placement && !(self.names.is_some() && custom.name == "name") {
+                        size += custom.dump(buf);
+                    }
+                }
+            }};
+        }
         macro_rules! do_section {
-            ($code: expr, $field: expr) => {{
-                let field = &$field;
-                for xs in field {
+            ($id: expr, $field: expr) => {{
+                if let Some(ref xs) = $field {
                     v.clear();
                     let mut section_size = 0;
                     let sec = &mut v;
@@ -62,40 +135,435 @@ impl Dump for Module {
                         section_size += x.dump(sec);
                     }
 
-                    size += write_uint8(buf, $code);
+                    size += write_uint8(buf, $id.id());
                     size += write_varuint32(buf, section_size as u32);
-                    size += write_slice(buf, &sec);
+                    size += write_slice(buf, sec);
                 }
+                do_customs!(Placement::After($id));
             }};
         }
 
 
-        do_section!(0x01, self.types);
-        do_section!(0x02, self.imports);
-        do_section!(0x03, self.functions);
-        do_section!(0x04, self.tables);
-        do_section!(0x05, self.memories);
-        do_section!(0x06, self.globals);
-        do_section!(0x07, self.exports);
+        do_customs!(Placement::First);
+        do_section!(SectionId::Type, self.types);
+        do_section!(SectionId::Import, self.imports);
+        do_section!(SectionId::Function, self.functions);
+        do_section!(SectionId::Table, self.tables);
+        do_section!(SectionId::Memory, self.memories);
+        do_section!(SectionId::Tag, self.tags);
+        do_section!(SectionId::Global, self.globals);
+        do_section!(SectionId::Export, self.exports);
         {
-            for index in self.start {
+            if let Some(index) = self.start {
                 v.clear();
                 let mut section_size = 0;
                 let sec = &mut v;
                 section_size += write_varuint32(sec, *index);
 
-                size += write_uint8(buf, 0x08);
+                size += write_uint8(buf, SectionId::Start.id());
+                size += write_varuint32(buf, section_size as u32);
+                size += write_slice(buf, sec);
+            }
+            do_customs!(Placement::After(SectionId::Start));
+        }
+        do_section!(SectionId::Element, self.elements);
+        {
+            if let Some(count) = self.data_count {
+                v.clear();
+                let mut section_size = 0;
+                let sec = &mut v;
+                section_size += write_varuint32(sec, count);
+
+                size += write_uint8(buf, SectionId::DataCount.id());
                 size += write_varuint32(buf, section_size as u32);
-                size += write_slice(buf, &sec);
+                size += write_slice(buf, sec);
+            }
+            do_customs!(Placement::After(SectionId::DataCount));
+        }
+        do_section!(SectionId::Code, self.codes);
+        do_section!(SectionId::Data, self.data);
+        if let Some(ref names) = self.names {
+            size += names.dump(buf);
+        }
+        size
+    }
+}
+
+impl Parse for Module {
+    /// Parses a whole module. Custom sections are kept as they are, with the
+    /// known section they follow as their placement, except for a `name`
+    /// section at the end of the module, which is decoded into `names`.
+    fn parse(buf: &[u8], pos: &mut usize) -> Result<Self, DecodeError> {
+        let start = *pos;
+        if read_slice(buf, pos, 4)? != b"\0asm" {
+            return Err(DecodeError::new(start, DecodeErrorKind::BadMagic));
+        }
+        let version = read_uint32(buf, pos)?;
+        if version != 0x01 {
+            return Err(DecodeError::new(start + 4, DecodeErrorKind::UnsupportedVersion(version)));
+        }
+
+        let mut module = Module {
+            customs: None,
+            types: None,
+            imports: None,
+            functions: None,
+            tables: None,
+            memories: None,
+            tags: None,
+            globals: None,
+            exports: None,
+            start: None,
+            elements: None,
+            data_count: None,
+            codes: None,
+            data: None,
+            names: None,
+        };
+
+        let mut last = None;
+        let mut placement = Placement::First;
+        // the start of the last section if it is a `name` section
+        let mut name_section = None;
+        while *pos < buf.len() {
+            let section_start = *pos;
+            name_section = None;
+            let id = read_uint8(buf, pos)?;
+            let section_size = read_varuint32(buf, pos)? as usize;
+            if buf.len() - *pos < section_size {
+                return Err(DecodeError::new(buf.len(), DecodeErrorKind::UnexpectedEof));
+            }
+            let end = *pos + section_size;
+            // the contents may not read past the section
+            let sec = &buf[..end];
+            // sections are ordered by `SectionId`, which puts the tags before the globals and
+            // the data count before the code
+            if let Some(section) = SectionId::from_id(id) {
+                if Some(section) <= last {
+                    return Err(DecodeError::new(section_start,
+                                                DecodeErrorKind::SectionOutOfOrder(id)));
+                }
+                last = Some(section);
+            }
+
+            match id {
+                0x00 => {
+                    let name = read_string(sec, pos)?;
+                    if name == "name" {
+                        name_section = Some(section_start);
+                    }
+                    let custom = CustomSection {
+                        name: name,
+                        placement: placement,
+                        payload: sec[*pos..].to_vec(),
+                    };
+                    module.customs.get_or_insert_with(Vec::new).push(custom);
+                    *pos = end;
+                }
+                0x01 => module.types = Some(parse_vec(sec, pos)?),
+                0x02 => module.imports = Some(parse_vec(sec, pos)?),
+                0x03 => module.functions = Some(parse_vec(sec, pos)?),
+                0x04 => module.tables = Some(parse_vec(sec, pos)?),
+                0x05 => module.memories = Some(parse_vec(sec, pos)?),
+                0x06 => module.globals = Some(parse_vec(sec, pos)?),
+                0x07 => module.exports = Some(parse_vec(sec, pos)?),
+                0x08 => module.start = Some(FunctionIndex(read_varuint32(sec, pos)?)),
+                0x09 => module.elements = Some(parse_vec(sec, pos)?),
+                0x0a => module.codes = Some(parse_vec(sec, pos)?),
+                0x0b => module.data = Some(parse_vec(sec, pos)?),
+                0x0c => module.data_count = Some(read_varuint32(sec, pos)?),
+                0x0d => module.tags = Some(parse_vec(sec, pos)?),
+                _ => return Err(DecodeError::new(section_start, DecodeErrorKind::UnknownSection(id))),
+            }
+            if *pos != end {
+                return Err(DecodeError::new(*pos, DecodeErrorKind::SizeMismatch));
+            }
+            if let Some(id) = SectionId::from_id(id) {
+                placement = Placement::After(id);
             }
         }
-        do_section!(0x09, self.elements);
-        do_section!(0x0a, self.codes);
-        do_section!(0x0b, self.data);
+
+        let functions = module.functions.as_ref().map_or(0, |f| f.len());
+        let codes = module.codes.as_ref().map_or(0, |c| c.len());
+        if functions != codes {
+            return Err(DecodeError::new(*pos,
+                                        DecodeErrorKind::FunctionCodeMismatch {
+                                            functions: functions,
+                                            codes: codes,
+                                        }));
+        }
+
+        let nimports = module.imports
+            .iter()
+            .flat_map(|i| i.iter())
+            .filter(|i| i.kind.is_function())
+            .count() as u32;
+        for f in module.codes.iter_mut().flat_map(|f| f.iter_mut()) {
+            f.classify_functions(nimports)
+        }
+        for g in module.globals.iter_mut().flat_map(|g| g.iter_mut()) {
+            g.init.classify_functions(nimports)
+        }
+        for e in module.elements.iter_mut().flat_map(|e| e.iter_mut()) {
+            e.elems.classify_functions(nimports)
+        }
+        if let Some(start) = name_section {
+            let names = module.customs
+                .as_ref()
+                .and_then(|c| c.last())
+                .and_then(|c| NameSection::decode(&c.payload, nimports));
+            if let Some(names) = names {
+                // keep the section as it is unless dumping gives it back unchanged
+                let mut section = Vec::new();
+                names.dump(&mut section);
+                if section[..] == buf[start..*pos] {
+                    module.customs.as_mut().unwrap().pop();
+                    if module.customs.as_ref().is_some_and(|c| c.is_empty()) {
+                        module.customs = None;
+                    }
+                    module.names = Some(names);
+                }
+            }
+        }
+
+        Ok(module)
+    }
+}
+
+/// The known sections, in the order they appear in a module.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum SectionId {
+    Type,
+    Import,
+    Function,
+    Table,
+    Memory,
+    Tag,
+    Global,
+    Export,
+    Start,
+    Element,
+    DataCount,
+    Code,
+    Data,
+}
+
+impl SectionId {
+    /// The id the section is encoded with.
+    pub fn id(&self) -> u8 {
+        use self::SectionId::*;
+        match *self {
+            Type => 0x01,
+            Import => 0x02,
+            Function => 0x03,
+            Table => 0x04,
+            Memory => 0x05,
+            Tag => 0x0d,
+            Global => 0x06,
+            Export => 0x07,
+            Start => 0x08,
+            Element => 0x09,
+            DataCount => 0x0c,
+            Code => 0x0a,
+            Data => 0x0b,
+        }
+    }
+
+    /// The section that comes right before this one.
+    pub fn previous(&self) -> Option<Self> {
+        use self::SectionId::*;
+        let section = match *self {
+            Type => return None,
+            Import => Type,
+            Function => Import,
+            Table => Function,
+            Memory => Table,
+            Tag => Memory,
+            Global => Tag,
+            Export => Global,
+            Start => Export,
+            Element => Start,
+            DataCount => Element,
+            Code => DataCount,
+            Data => Code,
+        };
+        Some(section)
+    }
+
+    pub fn from_id(id: u8) -> Option<Self> {
+        use self::SectionId::*;
+        let section = match id {
+            0x01 => Type,
+            0x02 => Import,
+            0x03 => Function,
+            0x04 => Table,
+            0x05 => Memory,
+            0x06 => Global,
+            0x07 => Export,
+            0x08 => Start,
+            0x09 => Element,
+            0x0a => Code,
+            0x0b => Data,
+            0x0c => DataCount,
+            0x0d => Tag,
+            _ => return None,
+        };
+        Some(section)
+    }
+}
+
+/// Where a custom section is emitted. Sections that are left out keep their
+/// place, so `After(SectionId::Table)` comes before the memory section even
+/// if the module has no tables.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Placement {
+    /// before all known sections
+    First,
+    After(SectionId),
+}
+
+/// A section with id 0, which engines ignore unless they know its name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CustomSection {
+    pub name: String,
+    pub placement: Placement,
+    pub payload: Vec<u8>,
+}
+
+impl Dump for CustomSection {
+    fn dump(&self, buf: &mut Vec<u8>) -> usize {
+        let mut size = 0;
+        let mut sec = Vec::new();
+        let section_size = write_varuint32(&mut sec, self.name.len() as u32) +
+                           write_slice(&mut sec, self.name.as_bytes()) +
+                           write_slice(&mut sec, &self.payload);
+        size += write_uint8(buf, 0x00);
+        size += write_varuint32(buf, section_size as u32);
+        size += write_slice(buf, &sec);
         size
     }
 }
 
+/// The contents of the `name` custom section. Functions are keyed by their
+/// index in the function space, imports included.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct NameSection {
+    pub module: Option<String>,
+    pub functions: BTreeMap<FunctionSpaceIndex, String>,
+    pub locals: BTreeMap<FunctionSpaceIndex, BTreeMap<LocalIndex, String>>,
+}
+
+impl NameSection {
+    pub fn is_empty(&self) -> bool {
+        self.module.is_none() && self.functions.is_empty() && self.locals.values().all(|l| l.is_empty())
+    }
+
+    /// Decodes the payload of a `name` section in a module importing
+    /// `nimports` functions. Engines ignore a malformed name section, so
+    /// this gives `None` instead of an error, as it does for subsections
+    /// other than module, function and local names.
+    fn decode(payload: &[u8], nimports: u32) -> Option<NameSection> {
+        let function = |index: u32| {
+            let mut index = FunctionSpaceIndex::from(FunctionIndex(index));
+            index.classify(nimports);
+            index
+        };
+        let mut names = NameSection::default();
+        let pos = &mut 0;
+        while *pos < payload.len() {
+            let id = read_uint8(payload, pos).ok()?;
+            let size = read_varuint32(payload, pos).ok()? as usize;
+            let sub = payload.get(..*pos + size)?;
+            match id {
+                0x00 => names.module = Some(read_string(sub, pos).ok()?),
+                0x01 => {
+                    for _ in 0..read_varuint32(sub, pos).ok()? {
+                        let f = function(read_varuint32(sub, pos).ok()?);
+                        names.functions.insert(f, read_string(sub, pos).ok()?);
+                    }
+                }
+                0x02 => {
+                    for _ in 0..read_varuint32(sub, pos).ok()? {
+                        let f = function(read_varuint32(sub, pos).ok()?);
+                        let locals = names.locals.entry(f).or_default();
+                        for _ in 0..read_varuint32(sub, pos).ok()? {
+                            let local = LocalIndex::new(read_varuint32(sub, pos).ok()?);
+                            locals.insert(local, read_string(sub, pos).ok()?);
+                        }
+                    }
+                }
+                _ => return None,
+            }
+            if *pos != sub.len() {
+                return None;
+            }
+        }
+        Some(names)
+    }
+}
+
+fn write_name(buf: &mut Vec<u8>, name: &str) -> usize {
+    write_varuint32(buf, name.len() as u32) + write_slice(buf, name.as_bytes())
+}
+
+/// Writes a name map sorted by index, as the spec requires.
+fn write_name_map<'a, I>(buf: &mut Vec<u8>, names: I) -> usize
+    where I: Iterator<Item = (u32, &'a String)>
+{
+    let mut names = names.collect::<Vec<_>>();
+    names.sort_by_key(|&(index, _)| index);
+    let mut size = write_varuint32(buf, names.len() as u32);
+    for (index, name) in names {
+        size += write_varuint32(buf, index);
+        size += write_name(buf, name);
+    }
+    size
+}
+
+impl Dump for NameSection {
+    fn dump(&self, buf: &mut Vec<u8>) -> usize {
+        let mut payload = Vec::new();
+        let mut v = Vec::new();
+        macro_rules! subsection {
+            ($id: expr) => {{
+                write_uint8(&mut payload, $id);
+                write_varuint32(&mut payload, v.len() as u32);
+                write_slice(&mut payload, &v);
+                v.clear();
+            }};
+        }
+
+        if let Some(ref module) = self.module {
+            write_name(&mut v, module);
+            subsection!(0x00);
+        }
+        if !self.functions.is_empty() {
+            write_name_map(&mut v, self.functions.iter().map(|(f, name)| (**f, name)));
+            subsection!(0x01);
+        }
+        let mut locals = self.locals
+            .iter()
+            .filter(|&(_, l)| !l.is_empty())
+            .collect::<Vec<_>>();
+        if !locals.is_empty() {
+            locals.sort_by_key(|&(f, _)| **f);
+            write_varuint32(&mut v, locals.len() as u32);
+            for (f, names) in locals {
+                write_varuint32(&mut v, **f);
+                write_name_map(&mut v, names.iter().map(|(l, name)| (**l, name)));
+            }
+            subsection!(0x02);
+        }
+
+        let section = CustomSection {
+            name: "name".to_string(),
+            placement: Placement::After(SectionId::Data),
+            payload: payload,
+        };
+        section.dump(buf)
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct ImportEntry {
     pub module: String,
@@ -109,39 +577,28 @@ pub enum ImportKind {
     Table(TableType),
     Memory(MemoryType),
     Global(GlobalType),
+    Tag(TagType),
 }
 
 impl ImportKind {
     pub fn is_function(&self) -> bool {
-        if let &ImportKind::Function(_) = self {
-            true
-        } else {
-            false
-        }
+        matches!(*self, ImportKind::Function(_))
     }
 
     pub fn is_table(&self) -> bool {
-        if let &ImportKind::Table(_) = self {
-            true
-        } else {
-            false
-        }
+        matches!(*self, ImportKind::Table(_))
     }
 
     pub fn is_memory(&self) -> bool {
-        if let &ImportKind::Memory(_) = self {
-            true
-        } else {
-            false
-        }
+        matches!(*self, ImportKind::Memory(_))
     }
 
     pub fn is_global(&self) -> bool {
-        if let &ImportKind::Global(_) = self {
-            true
-        } else {
-            false
-        }
+        matches!(*self, ImportKind::Global(_))
+    }
+
+    pub fn is_tag(&self) -> bool {
+        matches!(*self, ImportKind::Tag(_))
     }
 }
 
@@ -164,6 +621,19 @@ impl Dump for ImportEntry {
     }
 }
 
+impl Parse for ImportEntry {
+    fn parse(buf: &[u8], pos: &mut usize) -> Result<Self, DecodeError> {
+        let module = read_string(buf, pos)?;
+        let field = read_string(buf, pos)?;
+        let kind = ImportKind::parse(buf, pos)?;
+        Ok(ImportEntry {
+               module: module,
+               field: field,
+               kind: kind,
+           })
+    }
+}
+
 impl Dump for ImportKind {
     fn dump(&self, buf: &mut Vec<u8>) -> usize {
         use self::ImportKind::*;
@@ -191,11 +661,31 @@ impl Dump for ImportKind {
                 size
 
             }
+            &Tag(ref tag) => {
+                size += write_uint8(buf, 4);
+                size += tag.dump(buf);
+                size
+            }
 
         }
     }
 }
 
+impl Parse for ImportKind {
+    fn parse(buf: &[u8], pos: &mut usize) -> Result<Self, DecodeError> {
+        use self::ImportKind::*;
+        let start = *pos;
+        match read_uint8(buf, pos)? {
+            0 => Ok(Function(TypeIndex::new(read_varuint32(buf, pos)?))),
+            1 => Ok(Table(TableType::parse(buf, pos)?)),
+            2 => Ok(Memory(MemoryType::parse(buf, pos)?)),
+            3 => Ok(Global(GlobalType::parse(buf, pos)?)),
+            4 => Ok(Tag(TagType::parse(buf, pos)?)),
+            kind => Err(DecodeError::new(start, DecodeErrorKind::InvalidExternalKind(kind))),
+        }
+    }
+}
+
 
 #[derive(Debug, Clone)]
 pub struct Function(pub TypeIndex);
@@ -206,6 +696,12 @@ impl Dump for Function {
     }
 }
 
+impl Parse for Function {
+    fn parse(buf: &[u8], pos: &mut usize) -> Result<Self, DecodeError> {
+        Ok(Function(TypeIndex::new(read_varuint32(buf, pos)?)))
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct GlobalVariable {
     pub ty: GlobalType,
@@ -223,6 +719,17 @@ impl Dump for GlobalVariable {
     }
 }
 
+impl Parse for GlobalVariable {
+    fn parse(buf: &[u8], pos: &mut usize) -> Result<Self, DecodeError> {
+        let ty = GlobalType::parse(buf, pos)?;
+        let init = InitExpr::parse(buf, pos)?;
+        Ok(GlobalVariable {
+               ty: ty,
+               init: init,
+           })
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct ExportEntry {
     pub field: String,
@@ -235,6 +742,7 @@ pub enum ExportKind {
     Table(TableIndex),
     Memory(MemoryIndex),
     Global(GlobalIndex),
+    Tag(TagIndex),
 }
 
 impl Dump for ExportEntry {
@@ -251,6 +759,17 @@ impl Dump for ExportEntry {
     }
 }
 
+impl Parse for ExportEntry {
+    fn parse(buf: &[u8], pos: &mut usize) -> Result<Self, DecodeError> {
+        let field = read_string(buf, pos)?;
+        let kind = ExportKind::parse(buf, pos)?;
+        Ok(ExportEntry {
+               field: field,
+               kind: kind,
+           })
+    }
+}
+
 impl Dump for ExportKind {
     fn dump(&self, buf: &mut Vec<u8>) -> usize {
         use self::ExportKind::*;
@@ -272,35 +791,235 @@ impl Dump for ExportKind {
                 size += write_uint8(buf, 3);
                 size += write_varuint32(buf, **i);
             }
+            &Tag(ref i) => {
+                size += write_uint8(buf, 4);
+                size += write_varuint32(buf, **i);
+            }
         }
         size
     }
 }
 
+impl Parse for ExportKind {
+    fn parse(buf: &[u8], pos: &mut usize) -> Result<Self, DecodeError> {
+        use self::ExportKind::*;
+        let start = *pos;
+        let kind = read_uint8(buf, pos)?;
+        let index = read_varuint32(buf, pos)?;
+        match kind {
+            0 => Ok(Function(FunctionIndex(index))),
+            1 => Ok(Table(TableIndex::new(index))),
+            2 => Ok(Memory(MemoryIndex::new(index))),
+            3 => Ok(Global(GlobalIndex::new(index))),
+            4 => Ok(Tag(TagIndex::new(index))),
+            kind => Err(DecodeError::new(start, DecodeErrorKind::InvalidExternalKind(kind))),
+        }
+    }
+}
+
+/// When the contents of an element segment get into a table.
+#[derive(Debug, Clone)]
+pub enum ElemMode {
+    /// copied into the table at instantiation
+    Active { index: TableIndex, offset: InitExpr },
+    /// copied by `table.init`
+    Passive,
+    /// never copied, only declares the functions it lists as referenced
+    Declarative,
+}
+
+/// The references an element segment holds.
+#[derive(Debug, Clone)]
+pub enum Elements {
+    /// functions by index
+    Functions(Vec<FunctionSpaceIndex>),
+    /// constant expressions evaluating to references of the type, such as
+    /// `ref.func` and `ref.null`
+    Expressions(ElemType, Vec<InitExpr>),
+}
+
+impl Elements {
+    pub fn ty(&self) -> ElemType {
+        match *self {
+            Elements::Functions(_) => ElemType::AnyFunc,
+            Elements::Expressions(ty, _) => ty,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match *self {
+            Elements::Functions(ref fs) => fs.len(),
+            Elements::Expressions(_, ref es) => es.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The functions listed or referred to by `ref.func`.
+    pub fn functions(&self) -> Vec<FunctionSpaceIndex> {
+        match *self {
+            Elements::Functions(ref fs) => fs.clone(),
+            Elements::Expressions(_, ref es) => {
+                es.iter()
+                    .flat_map(|e| (e.0).0.iter())
+                    .filter_map(|op| match *op {
+                                    Op::RefFunc(index) => Some(index),
+                                    _ => None,
+                                })
+                    .collect()
+            }
+        }
+    }
+
+    pub fn resolve_functions(&mut self, nimports: u32) {
+        match *self {
+            Elements::Functions(ref mut fs) => {
+                for f in fs.iter_mut() {
+                    if let InnerFunctionSpaceIndex::Function(ref mut f) = f.0 {
+                        f.0 += nimports;
+                    }
+                }
+            }
+            Elements::Expressions(_, ref mut es) => {
+                for e in es.iter_mut() {
+                    e.resolve_functions(nimports);
+                }
+            }
+        }
+    }
+
+    pub fn classify_functions(&mut self, nimports: u32) {
+        match *self {
+            Elements::Functions(ref mut fs) => {
+                for f in fs.iter_mut() {
+                    f.classify(nimports);
+                }
+            }
+            Elements::Expressions(_, ref mut es) => {
+                for e in es.iter_mut() {
+                    e.classify_functions(nimports);
+                }
+            }
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct ElemSegment {
-    pub index: TableIndex,
-    pub offset: InitExpr,
-    pub elems: Vec<FunctionSpaceIndex>,
+    pub mode: ElemMode,
+    pub elems: Elements,
 }
 
 impl Dump for ElemSegment {
     fn dump(&self, buf: &mut Vec<u8>) -> usize {
         let mut size = 0;
 
-        let elems = &self.elems;
-        assert_eq!(*self.index, 0);
-        size += write_varuint32(buf, *self.index);
-        size += self.offset.dump(buf);
+        // bit 0: passive or declarative, bit 1: explicit table index or
+        // declarative, bit 2: element expressions
+        let exprs = match self.elems {
+            Elements::Functions(_) => 0,
+            Elements::Expressions(..) => 4,
+        };
+        let ty = self.elems.ty();
+        let explicit = match self.mode {
+            ElemMode::Active { ref index, ref offset } => {
+                let explicit = **index != 0 || ty != ElemType::AnyFunc;
+                if explicit {
+                    size += write_varuint32(buf, exprs | 2);
+                    size += write_varuint32(buf, **index);
+                } else {
+                    size += write_varuint32(buf, exprs);
+                }
+                size += offset.dump(buf);
+                explicit
+            }
+            ElemMode::Passive => {
+                size += write_varuint32(buf, exprs | 1);
+                true
+            }
+            ElemMode::Declarative => {
+                size += write_varuint32(buf, exprs | 3);
+                true
+            }
+        };
 
-        size += write_varuint32(buf, elems.len() as u32);
-        for e in elems.iter() {
-            size += write_varuint32(buf, **e);
+        match self.elems {
+            Elements::Functions(ref fs) => {
+                if explicit {
+                    // the element kind of function indices
+                    size += write_uint8(buf, 0x00);
+                }
+                size += write_varuint32(buf, fs.len() as u32);
+                for f in fs.iter() {
+                    size += write_varuint32(buf, **f);
+                }
+            }
+            Elements::Expressions(ref ty, ref es) => {
+                if explicit {
+                    size += ty.dump(buf);
+                }
+                size += write_varuint32(buf, es.len() as u32);
+                for e in es.iter() {
+                    size += e.dump(buf);
+                }
+            }
         }
         size
     }
 }
 
+impl Parse for ElemSegment {
+    fn parse(buf: &[u8], pos: &mut usize) -> Result<Self, DecodeError> {
+        let start = *pos;
+        let flags = read_varuint32(buf, pos)?;
+        if flags > 7 {
+            return Err(DecodeError::new(start, DecodeErrorKind::UnsupportedSegmentFlags(flags)));
+        }
+        let mode = match flags & 3 {
+            0 => {
+                ElemMode::Active {
+                    index: TableIndex::new(0),
+                    offset: InitExpr::parse(buf, pos)?,
+                }
+            }
+            2 => {
+                let index = TableIndex::new(read_varuint32(buf, pos)?);
+                ElemMode::Active {
+                    index: index,
+                    offset: InitExpr::parse(buf, pos)?,
+                }
+            }
+            1 => ElemMode::Passive,
+            _ => ElemMode::Declarative,
+        };
+        // the forms without a table index or an element kind imply functions
+        let explicit = flags & 3 != 0;
+        let elems = if flags & 4 == 0 {
+            if explicit {
+                let kind_start = *pos;
+                if read_uint8(buf, pos)? != 0x00 {
+                    return Err(DecodeError::new(kind_start, DecodeErrorKind::InvalidElemType));
+                }
+            }
+            let len = read_varuint32(buf, pos)?;
+            let mut fs = Vec::new();
+            for _ in 0..len {
+                fs.push(FunctionIndex(read_varuint32(buf, pos)?).into());
+            }
+            Elements::Functions(fs)
+        } else {
+            let ty = if explicit { ElemType::parse(buf, pos)? } else { ElemType::AnyFunc };
+            Elements::Expressions(ty, parse_vec(buf, pos)?)
+        };
+        Ok(ElemSegment {
+               mode: mode,
+               elems: elems,
+           })
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct FunctionBody {
     pub locals: Vec<LocalEntry>,
@@ -313,6 +1032,12 @@ impl FunctionBody {
             op.resolve_functions(nimports)
         }
     }
+
+    pub fn classify_functions(&mut self, nimports: u32) {
+        for op in self.code.0.iter_mut() {
+            op.classify_functions(nimports)
+        }
+    }
 }
 
 
@@ -347,6 +1072,32 @@ impl Dump for FunctionBody {
     }
 }
 
+impl Parse for FunctionBody {
+    /// The terminating `End` is not included in `code`.
+    fn parse(buf: &[u8], pos: &mut usize) -> Result<Self, DecodeError> {
+        let body_size = read_varuint32(buf, pos)? as usize;
+        if buf.len() - *pos < body_size {
+            return Err(DecodeError::new(buf.len(), DecodeErrorKind::UnexpectedEof));
+        }
+        let end = *pos + body_size;
+        let body = &buf[..end];
+
+        let locals = parse_vec(body, pos)?;
+        let mut code = Vec::new();
+        while *pos < end {
+            code.push(Op::parse(body, pos)?);
+        }
+        match code.pop() {
+            Some(Op::End) => (),
+            _ => return Err(DecodeError::new(end, DecodeErrorKind::MissingEnd)),
+        }
+        Ok(FunctionBody {
+               locals: locals,
+               code: Code(code),
+           })
+    }
+}
+
 
 #[derive(Debug, Clone)]
 pub struct LocalEntry {
@@ -366,10 +1117,29 @@ impl Dump for LocalEntry {
     }
 }
 
+impl Parse for LocalEntry {
+    fn parse(buf: &[u8], pos: &mut usize) -> Result<Self, DecodeError> {
+        let count = read_varuint32(buf, pos)?;
+        let ty = ValueType::parse(buf, pos)?;
+        Ok(LocalEntry {
+               count: count,
+               ty: ty,
+           })
+    }
+}
+
+/// When the contents of a data segment get into a memory.
+#[derive(Debug, Clone)]
+pub enum DataMode {
+    /// copied into the memory at instantiation
+    Active { index: MemoryIndex, offset: InitExpr },
+    /// copied by `memory.init`
+    Passive,
+}
+
 #[derive(Debug, Clone)]
 pub struct DataSegment {
-    pub index: MemoryIndex,
-    pub offset: InitExpr,
+    pub mode: DataMode,
     pub data: Vec<u8>,
 }
 
@@ -379,8 +1149,16 @@ impl Dump for DataSegment {
 
         let data = &self.data;
 
-        size += write_varuint32(buf, *self.index);
-        size += self.offset.dump(buf);
+        match self.mode {
+            DataMode::Active { ref index, ref offset } => {
+                if **index != 0 {
+                    size += write_varuint32(buf, 2);
+                }
+                size += write_varuint32(buf, **index);
+                size += offset.dump(buf);
+            }
+            DataMode::Passive => size += write_varuint32(buf, 1),
+        }
 
         size += write_varuint32(buf, data.len() as u32);
         size += write_slice(buf, data);
@@ -388,3 +1166,27 @@ impl Dump for DataSegment {
         size
     }
 }
+
+impl Parse for DataSegment {
+    fn parse(buf: &[u8], pos: &mut usize) -> Result<Self, DecodeError> {
+        let start = *pos;
+        let flags = read_varuint32(buf, pos)?;
+        let mode = match flags {
+            0 | 2 => {
+                let index = if flags == 2 { read_varuint32(buf, pos)? } else { 0 };
+                DataMode::Active {
+                    index: MemoryIndex::new(index),
+                    offset: InitExpr::parse(buf, pos)?,
+                }
+            }
+            1 => DataMode::Passive,
+            _ => return Err(DecodeError::new(start, DecodeErrorKind::UnsupportedSegmentFlags(flags))),
+        };
+        let len = read_varuint32(buf, pos)? as usize;
+        let data = read_slice(buf, pos, len)?.to_vec();
+        Ok(DataSegment {
+               mode: mode,
+               data: data,
+           })
+    }
+}
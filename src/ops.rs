@@ -1,6 +1,7 @@
 use types::*;
 use util::*;
-use Dump;
+use decode::*;
+use {Dump, Parse};
 
 #[derive(Debug, Clone)]
 pub enum Op {
@@ -10,22 +11,41 @@ pub enum Op {
     Loop { sig: BlockType },
     If { sig: BlockType },
     Else,
+    /// a block whose exceptions are handled by the `catch` and `catch_all` clauses
+    /// following it, or passed on by the `delegate` ending it
+    Try { sig: BlockType },
+    Catch(TagIndex),
+    CatchAll,
+    /// ends a `try` block, rethrowing its exceptions in the block `depth` levels out of it
+    Delegate { depth: u32 },
+    Throw(TagIndex),
+    /// throws again the exception caught by the `catch` or `catch_all` of the `try` at `depth`
+    Rethrow { depth: u32 },
     End,
-    // TODO: use relative block index
+    /// `depth` counts the enclosing blocks to skip, 0 being the innermost
     Br { depth: u32 },
     BrIf { depth: u32 },
     BrTable(BrTarget),
     Return,
     Call { index: FunctionSpaceIndex },
-    // TODO: use table index
-    CallIndirect { index: TypeIndex, reserved: bool },
+    CallIndirect { index: TypeIndex, table: TableIndex },
+    /// a tail call, which returns the results of the callee
+    ReturnCall { index: FunctionSpaceIndex },
+    ReturnCallIndirect { index: TypeIndex, table: TableIndex },
+    /// calls the function reference on the stack, of the function type `index`
+    CallRef { index: TypeIndex },
+    ReturnCallRef { index: TypeIndex },
     Drop,
     Select,
+    /// `select` with an explicit operand type, needed for references
+    TypedSelect(ValueType),
     GetLocal(LocalIndex),
     SetLocal(LocalIndex),
     TeeLocal(LocalIndex),
     GetGlobal(GlobalIndex),
     SetGlobal(GlobalIndex),
+    TableGet(TableIndex),
+    TableSet(TableIndex),
     I32Load { imm: MemoryImmediate },
     I64Load { imm: MemoryImmediate },
     F32Load { imm: MemoryImmediate },
@@ -49,8 +69,8 @@ pub enum Op {
     I64Store8 { imm: MemoryImmediate },
     I64Store16 { imm: MemoryImmediate },
     I64Store32 { imm: MemoryImmediate },
-    CurrentMemory { reserved: bool },
-    GrowMemory { reserved: bool },
+    CurrentMemory { memory: MemoryIndex },
+    GrowMemory { memory: MemoryIndex },
     I32Const(i32),
     I64Const(i64),
     F32Const(f32),
@@ -178,21 +198,524 @@ pub enum Op {
     I64ReinterpretF64,
     F32ReinterpretI32,
     F64ReinterpretI64,
+    I32Extend8S,
+    I32Extend16S,
+    I64Extend8S,
+    I64Extend16S,
+    I64Extend32S,
+    RefNull(HeapType),
+    RefIsNull,
+    RefFunc(FunctionSpaceIndex),
+    /// whether two `eqref`s are the same object, or both null
+    RefEq,
+    /// traps on null, and passes other references on as non-nullable
+    RefAsNonNull,
+    /// branches if the reference is null, and passes it on as non-nullable otherwise
+    BrOnNull { depth: u32 },
+    /// branches with the reference unless it is null, which is dropped
+    BrOnNonNull { depth: u32 },
+    /// the `0xfc` prefixed truncations, saturating instead of trapping
+    I32TruncSatSF32,
+    I32TruncSatUF32,
+    I32TruncSatSF64,
+    I32TruncSatUF64,
+    I64TruncSatSF32,
+    I64TruncSatUF32,
+    I64TruncSatSF64,
+    I64TruncSatUF64,
+    /// copies from a passive data segment into memory
+    MemoryInit { segment: DataIndex, memory: MemoryIndex },
+    DataDrop(DataIndex),
+    MemoryCopy { dst: MemoryIndex, src: MemoryIndex },
+    MemoryFill { memory: MemoryIndex },
+    /// copies from a passive element segment into a table
+    TableInit { segment: ElementIndex, table: TableIndex },
+    ElemDrop(ElementIndex),
+    TableCopy { dst: TableIndex, src: TableIndex },
+    TableGrow(TableIndex),
+    TableSize(TableIndex),
+    TableFill(TableIndex),
+    /// the `0xfd` prefixed vector instructions, lane 0 being the lowest bits
+    V128Load { imm: MemoryImmediate },
+    V128Load8x8S { imm: MemoryImmediate },
+    V128Load8x8U { imm: MemoryImmediate },
+    V128Load16x4S { imm: MemoryImmediate },
+    V128Load16x4U { imm: MemoryImmediate },
+    V128Load32x2S { imm: MemoryImmediate },
+    V128Load32x2U { imm: MemoryImmediate },
+    V128Load8Splat { imm: MemoryImmediate },
+    V128Load16Splat { imm: MemoryImmediate },
+    V128Load32Splat { imm: MemoryImmediate },
+    V128Load64Splat { imm: MemoryImmediate },
+    V128Store { imm: MemoryImmediate },
+    V128Const(u128),
+    I8x16Shuffle([u8; 16]),
+    I8x16Swizzle,
+    I8x16Splat,
+    I16x8Splat,
+    I32x4Splat,
+    I64x2Splat,
+    F32x4Splat,
+    F64x2Splat,
+    I8x16ExtractLaneS(u8),
+    I8x16ExtractLaneU(u8),
+    I8x16ReplaceLane(u8),
+    I16x8ExtractLaneS(u8),
+    I16x8ExtractLaneU(u8),
+    I16x8ReplaceLane(u8),
+    I32x4ExtractLane(u8),
+    I32x4ReplaceLane(u8),
+    I64x2ExtractLane(u8),
+    I64x2ReplaceLane(u8),
+    F32x4ExtractLane(u8),
+    F32x4ReplaceLane(u8),
+    F64x2ExtractLane(u8),
+    F64x2ReplaceLane(u8),
+    I8x16Eq,
+    I8x16Ne,
+    I8x16LtS,
+    I8x16LtU,
+    I8x16GtS,
+    I8x16GtU,
+    I8x16LeS,
+    I8x16LeU,
+    I8x16GeS,
+    I8x16GeU,
+    I16x8Eq,
+    I16x8Ne,
+    I16x8LtS,
+    I16x8LtU,
+    I16x8GtS,
+    I16x8GtU,
+    I16x8LeS,
+    I16x8LeU,
+    I16x8GeS,
+    I16x8GeU,
+    I32x4Eq,
+    I32x4Ne,
+    I32x4LtS,
+    I32x4LtU,
+    I32x4GtS,
+    I32x4GtU,
+    I32x4LeS,
+    I32x4LeU,
+    I32x4GeS,
+    I32x4GeU,
+    F32x4Eq,
+    F32x4Ne,
+    F32x4Lt,
+    F32x4Gt,
+    F32x4Le,
+    F32x4Ge,
+    F64x2Eq,
+    F64x2Ne,
+    F64x2Lt,
+    F64x2Gt,
+    F64x2Le,
+    F64x2Ge,
+    V128Not,
+    V128And,
+    V128Andnot,
+    V128Or,
+    V128Xor,
+    V128Bitselect,
+    V128AnyTrue,
+    V128Load8Lane { imm: MemoryImmediate, lane: u8 },
+    V128Load16Lane { imm: MemoryImmediate, lane: u8 },
+    V128Load32Lane { imm: MemoryImmediate, lane: u8 },
+    V128Load64Lane { imm: MemoryImmediate, lane: u8 },
+    V128Store8Lane { imm: MemoryImmediate, lane: u8 },
+    V128Store16Lane { imm: MemoryImmediate, lane: u8 },
+    V128Store32Lane { imm: MemoryImmediate, lane: u8 },
+    V128Store64Lane { imm: MemoryImmediate, lane: u8 },
+    V128Load32Zero { imm: MemoryImmediate },
+    V128Load64Zero { imm: MemoryImmediate },
+    F32x4DemoteF64x2Zero,
+    F64x2PromoteLowF32x4,
+    I8x16Abs,
+    I8x16Neg,
+    I8x16Popcnt,
+    I8x16AllTrue,
+    I8x16Bitmask,
+    I8x16NarrowI16x8S,
+    I8x16NarrowI16x8U,
+    F32x4Ceil,
+    F32x4Floor,
+    F32x4Trunc,
+    F32x4Nearest,
+    I8x16Shl,
+    I8x16ShrS,
+    I8x16ShrU,
+    I8x16Add,
+    I8x16AddSatS,
+    I8x16AddSatU,
+    I8x16Sub,
+    I8x16SubSatS,
+    I8x16SubSatU,
+    F64x2Ceil,
+    F64x2Floor,
+    I8x16MinS,
+    I8x16MinU,
+    I8x16MaxS,
+    I8x16MaxU,
+    F64x2Trunc,
+    I8x16AvgrU,
+    I16x8ExtaddPairwiseI8x16S,
+    I16x8ExtaddPairwiseI8x16U,
+    I32x4ExtaddPairwiseI16x8S,
+    I32x4ExtaddPairwiseI16x8U,
+    I16x8Abs,
+    I16x8Neg,
+    I16x8Q15mulrSatS,
+    I16x8AllTrue,
+    I16x8Bitmask,
+    I16x8NarrowI32x4S,
+    I16x8NarrowI32x4U,
+    I16x8ExtendLowI8x16S,
+    I16x8ExtendHighI8x16S,
+    I16x8ExtendLowI8x16U,
+    I16x8ExtendHighI8x16U,
+    I16x8Shl,
+    I16x8ShrS,
+    I16x8ShrU,
+    I16x8Add,
+    I16x8AddSatS,
+    I16x8AddSatU,
+    I16x8Sub,
+    I16x8SubSatS,
+    I16x8SubSatU,
+    F64x2Nearest,
+    I16x8Mul,
+    I16x8MinS,
+    I16x8MinU,
+    I16x8MaxS,
+    I16x8MaxU,
+    I16x8AvgrU,
+    I16x8ExtmulLowI8x16S,
+    I16x8ExtmulHighI8x16S,
+    I16x8ExtmulLowI8x16U,
+    I16x8ExtmulHighI8x16U,
+    I32x4Abs,
+    I32x4Neg,
+    I32x4AllTrue,
+    I32x4Bitmask,
+    I32x4ExtendLowI16x8S,
+    I32x4ExtendHighI16x8S,
+    I32x4ExtendLowI16x8U,
+    I32x4ExtendHighI16x8U,
+    I32x4Shl,
+    I32x4ShrS,
+    I32x4ShrU,
+    I32x4Add,
+    I32x4Sub,
+    I32x4Mul,
+    I32x4MinS,
+    I32x4MinU,
+    I32x4MaxS,
+    I32x4MaxU,
+    I32x4DotI16x8S,
+    I32x4ExtmulLowI16x8S,
+    I32x4ExtmulHighI16x8S,
+    I32x4ExtmulLowI16x8U,
+    I32x4ExtmulHighI16x8U,
+    I64x2Abs,
+    I64x2Neg,
+    I64x2AllTrue,
+    I64x2Bitmask,
+    I64x2ExtendLowI32x4S,
+    I64x2ExtendHighI32x4S,
+    I64x2ExtendLowI32x4U,
+    I64x2ExtendHighI32x4U,
+    I64x2Shl,
+    I64x2ShrS,
+    I64x2ShrU,
+    I64x2Add,
+    I64x2Sub,
+    I64x2Mul,
+    I64x2Eq,
+    I64x2Ne,
+    I64x2LtS,
+    I64x2GtS,
+    I64x2LeS,
+    I64x2GeS,
+    I64x2ExtmulLowI32x4S,
+    I64x2ExtmulHighI32x4S,
+    I64x2ExtmulLowI32x4U,
+    I64x2ExtmulHighI32x4U,
+    F32x4Abs,
+    F32x4Neg,
+    F32x4Sqrt,
+    F32x4Add,
+    F32x4Sub,
+    F32x4Mul,
+    F32x4Div,
+    F32x4Min,
+    F32x4Max,
+    F32x4Pmin,
+    F32x4Pmax,
+    F64x2Abs,
+    F64x2Neg,
+    F64x2Sqrt,
+    F64x2Add,
+    F64x2Sub,
+    F64x2Mul,
+    F64x2Div,
+    F64x2Min,
+    F64x2Max,
+    F64x2Pmin,
+    F64x2Pmax,
+    I32x4TruncSatF32x4S,
+    I32x4TruncSatF32x4U,
+    F32x4ConvertI32x4S,
+    F32x4ConvertI32x4U,
+    I32x4TruncSatF64x2SZero,
+    I32x4TruncSatF64x2UZero,
+    F64x2ConvertLowI32x4S,
+    F64x2ConvertLowI32x4U,
+    /// the `0xfe` prefixed atomic instructions, which need naturally aligned addresses
+    MemoryAtomicNotify { imm: MemoryImmediate },
+    MemoryAtomicWait32 { imm: MemoryImmediate },
+    MemoryAtomicWait64 { imm: MemoryImmediate },
+    /// `reserved` is set only when a decoded module has a non-zero byte, which does not validate
+    AtomicFence { reserved: bool },
+    I32AtomicLoad { imm: MemoryImmediate },
+    I64AtomicLoad { imm: MemoryImmediate },
+    I32AtomicLoad8U { imm: MemoryImmediate },
+    I32AtomicLoad16U { imm: MemoryImmediate },
+    I64AtomicLoad8U { imm: MemoryImmediate },
+    I64AtomicLoad16U { imm: MemoryImmediate },
+    I64AtomicLoad32U { imm: MemoryImmediate },
+    I32AtomicStore { imm: MemoryImmediate },
+    I64AtomicStore { imm: MemoryImmediate },
+    I32AtomicStore8 { imm: MemoryImmediate },
+    I32AtomicStore16 { imm: MemoryImmediate },
+    I64AtomicStore8 { imm: MemoryImmediate },
+    I64AtomicStore16 { imm: MemoryImmediate },
+    I64AtomicStore32 { imm: MemoryImmediate },
+    I32AtomicRmwAdd { imm: MemoryImmediate },
+    I64AtomicRmwAdd { imm: MemoryImmediate },
+    I32AtomicRmw8AddU { imm: MemoryImmediate },
+    I32AtomicRmw16AddU { imm: MemoryImmediate },
+    I64AtomicRmw8AddU { imm: MemoryImmediate },
+    I64AtomicRmw16AddU { imm: MemoryImmediate },
+    I64AtomicRmw32AddU { imm: MemoryImmediate },
+    I32AtomicRmwSub { imm: MemoryImmediate },
+    I64AtomicRmwSub { imm: MemoryImmediate },
+    I32AtomicRmw8SubU { imm: MemoryImmediate },
+    I32AtomicRmw16SubU { imm: MemoryImmediate },
+    I64AtomicRmw8SubU { imm: MemoryImmediate },
+    I64AtomicRmw16SubU { imm: MemoryImmediate },
+    I64AtomicRmw32SubU { imm: MemoryImmediate },
+    I32AtomicRmwAnd { imm: MemoryImmediate },
+    I64AtomicRmwAnd { imm: MemoryImmediate },
+    I32AtomicRmw8AndU { imm: MemoryImmediate },
+    I32AtomicRmw16AndU { imm: MemoryImmediate },
+    I64AtomicRmw8AndU { imm: MemoryImmediate },
+    I64AtomicRmw16AndU { imm: MemoryImmediate },
+    I64AtomicRmw32AndU { imm: MemoryImmediate },
+    I32AtomicRmwOr { imm: MemoryImmediate },
+    I64AtomicRmwOr { imm: MemoryImmediate },
+    I32AtomicRmw8OrU { imm: MemoryImmediate },
+    I32AtomicRmw16OrU { imm: MemoryImmediate },
+    I64AtomicRmw8OrU { imm: MemoryImmediate },
+    I64AtomicRmw16OrU { imm: MemoryImmediate },
+    I64AtomicRmw32OrU { imm: MemoryImmediate },
+    I32AtomicRmwXor { imm: MemoryImmediate },
+    I64AtomicRmwXor { imm: MemoryImmediate },
+    I32AtomicRmw8XorU { imm: MemoryImmediate },
+    I32AtomicRmw16XorU { imm: MemoryImmediate },
+    I64AtomicRmw8XorU { imm: MemoryImmediate },
+    I64AtomicRmw16XorU { imm: MemoryImmediate },
+    I64AtomicRmw32XorU { imm: MemoryImmediate },
+    I32AtomicRmwXchg { imm: MemoryImmediate },
+    I64AtomicRmwXchg { imm: MemoryImmediate },
+    I32AtomicRmw8XchgU { imm: MemoryImmediate },
+    I32AtomicRmw16XchgU { imm: MemoryImmediate },
+    I64AtomicRmw8XchgU { imm: MemoryImmediate },
+    I64AtomicRmw16XchgU { imm: MemoryImmediate },
+    I64AtomicRmw32XchgU { imm: MemoryImmediate },
+    I32AtomicRmwCmpxchg { imm: MemoryImmediate },
+    I64AtomicRmwCmpxchg { imm: MemoryImmediate },
+    I32AtomicRmw8CmpxchgU { imm: MemoryImmediate },
+    I32AtomicRmw16CmpxchgU { imm: MemoryImmediate },
+    I64AtomicRmw8CmpxchgU { imm: MemoryImmediate },
+    I64AtomicRmw16CmpxchgU { imm: MemoryImmediate },
+    I64AtomicRmw32CmpxchgU { imm: MemoryImmediate },
+    /// the `0xfb` prefixed instructions on structs and arrays, whose packed fields
+    /// are read with the `_s` and `_u` variants
+    StructNew { index: TypeIndex },
+    StructNewDefault { index: TypeIndex },
+    StructGet { index: TypeIndex, field: u32 },
+    StructGetS { index: TypeIndex, field: u32 },
+    StructGetU { index: TypeIndex, field: u32 },
+    StructSet { index: TypeIndex, field: u32 },
+    /// an array of a given length filled with one value
+    ArrayNew { index: TypeIndex },
+    ArrayNewDefault { index: TypeIndex },
+    /// an array of the `len` values on the stack
+    ArrayNewFixed { index: TypeIndex, len: u32 },
+    ArrayNewData { index: TypeIndex, segment: DataIndex },
+    ArrayNewElem { index: TypeIndex, segment: ElementIndex },
+    ArrayGet { index: TypeIndex },
+    ArrayGetS { index: TypeIndex },
+    ArrayGetU { index: TypeIndex },
+    ArraySet { index: TypeIndex },
+    ArrayLen,
+    ArrayFill { index: TypeIndex },
+    ArrayCopy { dst: TypeIndex, src: TypeIndex },
+    ArrayInitData { index: TypeIndex, segment: DataIndex },
+    ArrayInitElem { index: TypeIndex, segment: ElementIndex },
+    /// whether the reference on the stack is of the type
+    RefTest(RefType),
+    /// traps unless the reference on the stack is of the type
+    RefCast(RefType),
+    /// branches with the reference of type `from` if it is of type `to`
+    BrOnCast { depth: u32, from: RefType, to: RefType },
+    /// branches with the reference of type `from` unless it is of type `to`
+    BrOnCastFail { depth: u32, from: RefType, to: RefType },
+    /// an `anyref` of the `externref` on the stack, null if it is null
+    AnyConvertExtern,
+    /// an `externref` of the `anyref` on the stack, null if it is null
+    ExternConvertAny,
+    /// an `i31ref` of the low 31 bits of the `i32` on the stack
+    RefI31,
+    I31GetS,
+    I31GetU,
 }
 
 impl Op {
     pub fn resolve_functions(&mut self, nimports: u32) {
-        match *self {
-            Op::Call { ref mut index } => {
-                use InnerFunctionSpaceIndex::*;
-                match index.0 {
-                    Function(ref mut f) => {
-                        f.0 += nimports;
-                    }
-                    _ => (),
-                }
+        if let Op::Call { ref mut index } | Op::ReturnCall { ref mut index } | Op::RefFunc(ref mut index) = *self {
+            if let InnerFunctionSpaceIndex::Function(ref mut f) = index.0 {
+                f.0 += nimports;
             }
-            _ => (),
+        }
+    }
+
+    /// The immediate of loads and stores.
+    pub fn memory_immediate(&self) -> Option<&MemoryImmediate> {
+        use self::Op::*;
+        match *self {
+            I32Load { ref imm } |
+            I64Load { ref imm } |
+            F32Load { ref imm } |
+            F64Load { ref imm } |
+            I32Load8S { ref imm } |
+            I32Load8U { ref imm } |
+            I32Load16S { ref imm } |
+            I32Load16U { ref imm } |
+            I64Load8S { ref imm } |
+            I64Load8U { ref imm } |
+            I64Load16S { ref imm } |
+            I64Load16U { ref imm } |
+            I64load32S { ref imm } |
+            I64load32U { ref imm } |
+            I32Store { ref imm } |
+            I64Store { ref imm } |
+            F32Store { ref imm } |
+            F64Store { ref imm } |
+            I32Store8 { ref imm } |
+            I32Store16 { ref imm } |
+            I64Store8 { ref imm } |
+            I64Store16 { ref imm } |
+            I64Store32 { ref imm } |
+            V128Load { ref imm } |
+            V128Load8x8S { ref imm } |
+            V128Load8x8U { ref imm } |
+            V128Load16x4S { ref imm } |
+            V128Load16x4U { ref imm } |
+            V128Load32x2S { ref imm } |
+            V128Load32x2U { ref imm } |
+            V128Load8Splat { ref imm } |
+            V128Load16Splat { ref imm } |
+            V128Load32Splat { ref imm } |
+            V128Load64Splat { ref imm } |
+            V128Store { ref imm } |
+            V128Load8Lane { ref imm, .. } |
+            V128Load16Lane { ref imm, .. } |
+            V128Load32Lane { ref imm, .. } |
+            V128Load64Lane { ref imm, .. } |
+            V128Store8Lane { ref imm, .. } |
+            V128Store16Lane { ref imm, .. } |
+            V128Store32Lane { ref imm, .. } |
+            V128Store64Lane { ref imm, .. } |
+            V128Load32Zero { ref imm } |
+            V128Load64Zero { ref imm } |
+            MemoryAtomicNotify { ref imm } |
+            MemoryAtomicWait32 { ref imm } |
+            MemoryAtomicWait64 { ref imm } |
+            I32AtomicLoad { ref imm } |
+            I64AtomicLoad { ref imm } |
+            I32AtomicLoad8U { ref imm } |
+            I32AtomicLoad16U { ref imm } |
+            I64AtomicLoad8U { ref imm } |
+            I64AtomicLoad16U { ref imm } |
+            I64AtomicLoad32U { ref imm } |
+            I32AtomicStore { ref imm } |
+            I64AtomicStore { ref imm } |
+            I32AtomicStore8 { ref imm } |
+            I32AtomicStore16 { ref imm } |
+            I64AtomicStore8 { ref imm } |
+            I64AtomicStore16 { ref imm } |
+            I64AtomicStore32 { ref imm } |
+            I32AtomicRmwAdd { ref imm } |
+            I64AtomicRmwAdd { ref imm } |
+            I32AtomicRmw8AddU { ref imm } |
+            I32AtomicRmw16AddU { ref imm } |
+            I64AtomicRmw8AddU { ref imm } |
+            I64AtomicRmw16AddU { ref imm } |
+            I64AtomicRmw32AddU { ref imm } |
+            I32AtomicRmwSub { ref imm } |
+            I64AtomicRmwSub { ref imm } |
+            I32AtomicRmw8SubU { ref imm } |
+            I32AtomicRmw16SubU { ref imm } |
+            I64AtomicRmw8SubU { ref imm } |
+            I64AtomicRmw16SubU { ref imm } |
+            I64AtomicRmw32SubU { ref imm } |
+            I32AtomicRmwAnd { ref imm } |
+            I64AtomicRmwAnd { ref imm } |
+            I32AtomicRmw8AndU { ref imm } |
+            I32AtomicRmw16AndU { ref imm } |
+            I64AtomicRmw8AndU { ref imm } |
+            I64AtomicRmw16AndU { ref imm } |
+            I64AtomicRmw32AndU { ref imm } |
+            I32AtomicRmwOr { ref imm } |
+            I64AtomicRmwOr { ref imm } |
+            I32AtomicRmw8OrU { ref imm } |
+            I32AtomicRmw16OrU { ref imm } |
+            I64AtomicRmw8OrU { ref imm } |
+            I64AtomicRmw16OrU { ref imm } |
+            I64AtomicRmw32OrU { ref imm } |
+            I32AtomicRmwXor { ref imm } |
+            I64AtomicRmwXor { ref imm } |
+            I32AtomicRmw8XorU { ref imm } |
+            I32AtomicRmw16XorU { ref imm } |
+            I64AtomicRmw8XorU { ref imm } |
+            I64AtomicRmw16XorU { ref imm } |
+            I64AtomicRmw32XorU { ref imm } |
+            I32AtomicRmwXchg { ref imm } |
+            I64AtomicRmwXchg { ref imm } |
+            I32AtomicRmw8XchgU { ref imm } |
+            I32AtomicRmw16XchgU { ref imm } |
+            I64AtomicRmw8XchgU { ref imm } |
+            I64AtomicRmw16XchgU { ref imm } |
+            I64AtomicRmw32XchgU { ref imm } |
+            I32AtomicRmwCmpxchg { ref imm } |
+            I64AtomicRmwCmpxchg { ref imm } |
+            I32AtomicRmw8CmpxchgU { ref imm } |
+            I32AtomicRmw16CmpxchgU { ref imm } |
+            I64AtomicRmw8CmpxchgU { ref imm } |
+            I64AtomicRmw16CmpxchgU { ref imm } |
+            I64AtomicRmw32CmpxchgU { ref imm } => Some(imm),
+            _ => None,
+        }
+    }
+
+    /// The inverse of `resolve_functions` for decoded code: calls to one of the
+    /// first `nimports` functions are turned into calls to the imports.
+    pub fn classify_functions(&mut self, nimports: u32) {
+        if let Op::Call { ref mut index } | Op::ReturnCall { ref mut index } | Op::RefFunc(ref mut index) = *self {
+            index.classify(nimports)
         }
     }
 }
@@ -208,7 +731,29 @@ impl Dump for Op {
 
             size
 
-        };
+        }
+        fn do_vector_imm(buf: &mut Vec<u8>, imm: &MemoryImmediate, code: u32) -> usize {
+            write_prefixed(buf, 0xfd, code) + imm.dump(buf)
+        }
+        fn do_atomic_imm(buf: &mut Vec<u8>, imm: &MemoryImmediate, code: u32) -> usize {
+            write_prefixed(buf, 0xfe, code) + imm.dump(buf)
+        }
+        fn do_gc(buf: &mut Vec<u8>, code: u32, immediates: &[u32]) -> usize {
+            let mut size = write_prefixed(buf, 0xfb, code);
+            for i in immediates {
+                size += write_varuint32(buf, *i);
+            }
+            size
+        }
+        fn do_br_on_cast(buf: &mut Vec<u8>, code: u32, depth: u32, from: &RefType, to: &RefType) -> usize {
+            let mut size = write_prefixed(buf, 0xfb, code);
+            // the nullability of both types
+            size += write_uint8(buf, from.nullable as u8 | (to.nullable as u8) << 1);
+            size += write_varuint32(buf, depth);
+            size += from.heap.dump(buf);
+            size += to.heap.dump(buf);
+            size
+        }
         let mut size = 0;
 
         match self {
@@ -227,6 +772,27 @@ impl Dump for Op {
                 size += sig.dump(buf);
             }
             &Else => size += write_uint8(buf, 0x05),
+            &Try { ref sig } => {
+                size += write_uint8(buf, 0x06);
+                size += sig.dump(buf);
+            }
+            &Catch(ref tag) => {
+                size += write_uint8(buf, 0x07);
+                size += write_varuint32(buf, **tag);
+            }
+            &Throw(ref tag) => {
+                size += write_uint8(buf, 0x08);
+                size += write_varuint32(buf, **tag);
+            }
+            &Rethrow { ref depth } => {
+                size += write_uint8(buf, 0x09);
+                size += write_varuint32(buf, *depth);
+            }
+            &Delegate { ref depth } => {
+                size += write_uint8(buf, 0x18);
+                size += write_varuint32(buf, *depth);
+            }
+            &CatchAll => size += write_uint8(buf, 0x19),
             &End => size += write_uint8(buf, 0x0b),
             &Br { ref depth } => {
                 size += write_uint8(buf, 0x0c);
@@ -246,16 +812,35 @@ impl Dump for Op {
                 size += write_varuint32(buf, **index);
             }
 
-            &CallIndirect {
-                 ref index,
-                 ref reserved,
-             } => {
+            &CallIndirect { ref index, ref table } => {
                 size += write_uint8(buf, 0x11);
                 size += write_varuint32(buf, **index);
-                size += write_varuint1(buf, *reserved as u8);
+                size += write_varuint32(buf, **table);
+            }
+            &ReturnCall { ref index } => {
+                size += write_uint8(buf, 0x12);
+                size += write_varuint32(buf, **index);
+            }
+            &ReturnCallIndirect { ref index, ref table } => {
+                size += write_uint8(buf, 0x13);
+                size += write_varuint32(buf, **index);
+                size += write_varuint32(buf, **table);
+            }
+            &CallRef { ref index } => {
+                size += write_uint8(buf, 0x14);
+                size += write_varuint32(buf, **index);
+            }
+            &ReturnCallRef { ref index } => {
+                size += write_uint8(buf, 0x15);
+                size += write_varuint32(buf, **index);
             }
             &Drop => size += write_uint8(buf, 0x1a),
             &Select => size += write_uint8(buf, 0x1b),
+            &TypedSelect(ref ty) => {
+                size += write_uint8(buf, 0x1c);
+                size += write_varuint32(buf, 1);
+                size += ty.dump(buf);
+            }
             &GetLocal(ref i) => {
                 size += write_uint8(buf, 0x20);
                 size += write_varuint32(buf, **i);
@@ -276,6 +861,14 @@ impl Dump for Op {
                 size += write_uint8(buf, 0x24);
                 size += write_varuint32(buf, **i);
             }
+            &TableGet(ref i) => {
+                size += write_uint8(buf, 0x25);
+                size += write_varuint32(buf, **i);
+            }
+            &TableSet(ref i) => {
+                size += write_uint8(buf, 0x26);
+                size += write_varuint32(buf, **i);
+            }
             &I32Load { ref imm } => size += do_imm(buf, imm, 0x28),
             &I64Load { ref imm } => size += do_imm(buf, imm, 0x29),
             &F32Load { ref imm } => size += do_imm(buf, imm, 0x2a),
@@ -299,13 +892,13 @@ impl Dump for Op {
             &I64Store8 { ref imm } => size += do_imm(buf, imm, 0x3c),
             &I64Store16 { ref imm } => size += do_imm(buf, imm, 0x3d),
             &I64Store32 { ref imm } => size += do_imm(buf, imm, 0x3e),
-            &CurrentMemory { ref reserved } => {
+            &CurrentMemory { ref memory } => {
                 size += write_uint8(buf, 0x3f);
-                size += write_varuint1(buf, *reserved as u8);
+                size += write_varuint32(buf, **memory);
             }
-            &GrowMemory { ref reserved } => {
+            &GrowMemory { ref memory } => {
                 size += write_uint8(buf, 0x40);
-                size += write_varuint1(buf, *reserved as u8);
+                size += write_varuint32(buf, **memory);
             }
             &I32Const(ref i) => {
                 size += write_uint8(buf, 0x41);
@@ -317,15 +910,11 @@ impl Dump for Op {
             }
             &F32Const(ref f) => {
                 size += write_uint8(buf, 0x43);
-                unsafe {
-                    size += write_uint32(buf, ::std::mem::transmute::<f32, u32>(*f));
-                }
+                size += write_uint32(buf, f.to_bits());
             }
             &F64Const(ref f) => {
                 size += write_uint8(buf, 0x44);
-                unsafe {
-                    size += write_uint64(buf, ::std::mem::transmute::<f64, u64>(*f));
-                }
+                size += write_uint64(buf, f.to_bits());
             }
             &I32Eqz => size += write_uint8(buf, 0x45),
             &I32Eq => size += write_uint8(buf, 0x46),
@@ -450,11 +1039,1233 @@ impl Dump for Op {
             &I64ReinterpretF64 => size += write_uint8(buf, 0xbd),
             &F32ReinterpretI32 => size += write_uint8(buf, 0xbe),
             &F64ReinterpretI64 => size += write_uint8(buf, 0xbf),
+            &I32Extend8S => size += write_uint8(buf, 0xc0),
+            &I32Extend16S => size += write_uint8(buf, 0xc1),
+            &I64Extend8S => size += write_uint8(buf, 0xc2),
+            &I64Extend16S => size += write_uint8(buf, 0xc3),
+            &I64Extend32S => size += write_uint8(buf, 0xc4),
+            &RefNull(ref ty) => {
+                size += write_uint8(buf, 0xd0);
+                size += ty.dump(buf);
+            }
+            &RefIsNull => size += write_uint8(buf, 0xd1),
+            &RefFunc(ref index) => {
+                size += write_uint8(buf, 0xd2);
+                size += write_varuint32(buf, **index);
+            }
+            &RefEq => size += write_uint8(buf, 0xd3),
+            &RefAsNonNull => size += write_uint8(buf, 0xd4),
+            &BrOnNull { ref depth } => {
+                size += write_uint8(buf, 0xd5);
+                size += write_varuint32(buf, *depth);
+            }
+            &BrOnNonNull { ref depth } => {
+                size += write_uint8(buf, 0xd6);
+                size += write_varuint32(buf, *depth);
+            }
+            &I32TruncSatSF32 => size += write_prefixed(buf, 0xfc, 0),
+            &I32TruncSatUF32 => size += write_prefixed(buf, 0xfc, 1),
+            &I32TruncSatSF64 => size += write_prefixed(buf, 0xfc, 2),
+            &I32TruncSatUF64 => size += write_prefixed(buf, 0xfc, 3),
+            &I64TruncSatSF32 => size += write_prefixed(buf, 0xfc, 4),
+            &I64TruncSatUF32 => size += write_prefixed(buf, 0xfc, 5),
+            &I64TruncSatSF64 => size += write_prefixed(buf, 0xfc, 6),
+            &I64TruncSatUF64 => size += write_prefixed(buf, 0xfc, 7),
+            &MemoryInit { ref segment, ref memory } => {
+                size += write_prefixed(buf, 0xfc, 8);
+                size += write_varuint32(buf, **segment);
+                size += write_varuint32(buf, **memory);
+            }
+            &DataDrop(ref segment) => {
+                size += write_prefixed(buf, 0xfc, 9);
+                size += write_varuint32(buf, **segment);
+            }
+            &MemoryCopy { ref dst, ref src } => {
+                size += write_prefixed(buf, 0xfc, 10);
+                size += write_varuint32(buf, **dst);
+                size += write_varuint32(buf, **src);
+            }
+            &MemoryFill { ref memory } => {
+                size += write_prefixed(buf, 0xfc, 11);
+                size += write_varuint32(buf, **memory);
+            }
+            &TableInit { ref segment, ref table } => {
+                size += write_prefixed(buf, 0xfc, 12);
+                size += write_varuint32(buf, **segment);
+                size += write_varuint32(buf, **table);
+            }
+            &ElemDrop(ref segment) => {
+                size += write_prefixed(buf, 0xfc, 13);
+                size += write_varuint32(buf, **segment);
+            }
+            &TableCopy { ref dst, ref src } => {
+                size += write_prefixed(buf, 0xfc, 14);
+                size += write_varuint32(buf, **dst);
+                size += write_varuint32(buf, **src);
+            }
+            &TableGrow(ref i) => {
+                size += write_prefixed(buf, 0xfc, 15);
+                size += write_varuint32(buf, **i);
+            }
+            &TableSize(ref i) => {
+                size += write_prefixed(buf, 0xfc, 16);
+                size += write_varuint32(buf, **i);
+            }
+            &TableFill(ref i) => {
+                size += write_prefixed(buf, 0xfc, 17);
+                size += write_varuint32(buf, **i);
+            }
+            &V128Load { ref imm } => size += do_vector_imm(buf, imm, 0x00),
+            &V128Load8x8S { ref imm } => size += do_vector_imm(buf, imm, 0x01),
+            &V128Load8x8U { ref imm } => size += do_vector_imm(buf, imm, 0x02),
+            &V128Load16x4S { ref imm } => size += do_vector_imm(buf, imm, 0x03),
+            &V128Load16x4U { ref imm } => size += do_vector_imm(buf, imm, 0x04),
+            &V128Load32x2S { ref imm } => size += do_vector_imm(buf, imm, 0x05),
+            &V128Load32x2U { ref imm } => size += do_vector_imm(buf, imm, 0x06),
+            &V128Load8Splat { ref imm } => size += do_vector_imm(buf, imm, 0x07),
+            &V128Load16Splat { ref imm } => size += do_vector_imm(buf, imm, 0x08),
+            &V128Load32Splat { ref imm } => size += do_vector_imm(buf, imm, 0x09),
+            &V128Load64Splat { ref imm } => size += do_vector_imm(buf, imm, 0x0a),
+            &V128Store { ref imm } => size += do_vector_imm(buf, imm, 0x0b),
+            &V128Const(ref v) => {
+                size += write_prefixed(buf, 0xfd, 0x0c);
+                size += write_uint128(buf, *v);
+            }
+            &I8x16Shuffle(ref lanes) => {
+                size += write_prefixed(buf, 0xfd, 0x0d);
+                size += write_slice(buf, lanes);
+            }
+            &I8x16Swizzle => size += write_prefixed(buf, 0xfd, 0x0e),
+            &I8x16Splat => size += write_prefixed(buf, 0xfd, 0x0f),
+            &I16x8Splat => size += write_prefixed(buf, 0xfd, 0x10),
+            &I32x4Splat => size += write_prefixed(buf, 0xfd, 0x11),
+            &I64x2Splat => size += write_prefixed(buf, 0xfd, 0x12),
+            &F32x4Splat => size += write_prefixed(buf, 0xfd, 0x13),
+            &F64x2Splat => size += write_prefixed(buf, 0xfd, 0x14),
+            &I8x16ExtractLaneS(ref lane) => {
+                size += write_prefixed(buf, 0xfd, 0x15);
+                size += write_uint8(buf, *lane);
+            }
+            &I8x16ExtractLaneU(ref lane) => {
+                size += write_prefixed(buf, 0xfd, 0x16);
+                size += write_uint8(buf, *lane);
+            }
+            &I8x16ReplaceLane(ref lane) => {
+                size += write_prefixed(buf, 0xfd, 0x17);
+                size += write_uint8(buf, *lane);
+            }
+            &I16x8ExtractLaneS(ref lane) => {
+                size += write_prefixed(buf, 0xfd, 0x18);
+                size += write_uint8(buf, *lane);
+            }
+            &I16x8ExtractLaneU(ref lane) => {
+                size += write_prefixed(buf, 0xfd, 0x19);
+                size += write_uint8(buf, *lane);
+            }
+            &I16x8ReplaceLane(ref lane) => {
+                size += write_prefixed(buf, 0xfd, 0x1a);
+                size += write_uint8(buf, *lane);
+            }
+            &I32x4ExtractLane(ref lane) => {
+                size += write_prefixed(buf, 0xfd, 0x1b);
+                size += write_uint8(buf, *lane);
+            }
+            &I32x4ReplaceLane(ref lane) => {
+                size += write_prefixed(buf, 0xfd, 0x1c);
+                size += write_uint8(buf, *lane);
+            }
+            &I64x2ExtractLane(ref lane) => {
+                size += write_prefixed(buf, 0xfd, 0x1d);
+                size += write_uint8(buf, *lane);
+            }
+            &I64x2ReplaceLane(ref lane) => {
+                size += write_prefixed(buf, 0xfd, 0x1e);
+                size += write_uint8(buf, *lane);
+            }
+            &F32x4ExtractLane(ref lane) => {
+                size += write_prefixed(buf, 0xfd, 0x1f);
+                size += write_uint8(buf, *lane);
+            }
+            &F32x4ReplaceLane(ref lane) => {
+                size += write_prefixed(buf, 0xfd, 0x20);
+                size += write_uint8(buf, *lane);
+            }
+            &F64x2ExtractLane(ref lane) => {
+                size += write_prefixed(buf, 0xfd, 0x21);
+                size += write_uint8(buf, *lane);
+            }
+            &F64x2ReplaceLane(ref lane) => {
+                size += write_prefixed(buf, 0xfd, 0x22);
+                size += write_uint8(buf, *lane);
+            }
+            &I8x16Eq => size += write_prefixed(buf, 0xfd, 0x23),
+            &I8x16Ne => size += write_prefixed(buf, 0xfd, 0x24),
+            &I8x16LtS => size += write_prefixed(buf, 0xfd, 0x25),
+            &I8x16LtU => size += write_prefixed(buf, 0xfd, 0x26),
+            &I8x16GtS => size += write_prefixed(buf, 0xfd, 0x27),
+            &I8x16GtU => size += write_prefixed(buf, 0xfd, 0x28),
+            &I8x16LeS => size += write_prefixed(buf, 0xfd, 0x29),
+            &I8x16LeU => size += write_prefixed(buf, 0xfd, 0x2a),
+            &I8x16GeS => size += write_prefixed(buf, 0xfd, 0x2b),
+            &I8x16GeU => size += write_prefixed(buf, 0xfd, 0x2c),
+            &I16x8Eq => size += write_prefixed(buf, 0xfd, 0x2d),
+            &I16x8Ne => size += write_prefixed(buf, 0xfd, 0x2e),
+            &I16x8LtS => size += write_prefixed(buf, 0xfd, 0x2f),
+            &I16x8LtU => size += write_prefixed(buf, 0xfd, 0x30),
+            &I16x8GtS => size += write_prefixed(buf, 0xfd, 0x31),
+            &I16x8GtU => size += write_prefixed(buf, 0xfd, 0x32),
+            &I16x8LeS => size += write_prefixed(buf, 0xfd, 0x33),
+            &I16x8LeU => size += write_prefixed(buf, 0xfd, 0x34),
+            &I16x8GeS => size += write_prefixed(buf, 0xfd, 0x35),
+            &I16x8GeU => size += write_prefixed(buf, 0xfd, 0x36),
+            &I32x4Eq => size += write_prefixed(buf, 0xfd, 0x37),
+            &I32x4Ne => size += write_prefixed(buf, 0xfd, 0x38),
+            &I32x4LtS => size += write_prefixed(buf, 0xfd, 0x39),
+            &I32x4LtU => size += write_prefixed(buf, 0xfd, 0x3a),
+            &I32x4GtS => size += write_prefixed(buf, 0xfd, 0x3b),
+            &I32x4GtU => size += write_prefixed(buf, 0xfd, 0x3c),
+            &I32x4LeS => size += write_prefixed(buf, 0xfd, 0x3d),
+            &I32x4LeU => size += write_prefixed(buf, 0xfd, 0x3e),
+            &I32x4GeS => size += write_prefixed(buf, 0xfd, 0x3f),
+            &I32x4GeU => size += write_prefixed(buf, 0xfd, 0x40),
+            &F32x4Eq => size += write_prefixed(buf, 0xfd, 0x41),
+            &F32x4Ne => size += write_prefixed(buf, 0xfd, 0x42),
+            &F32x4Lt => size += write_prefixed(buf, 0xfd, 0x43),
+            &F32x4Gt => size += write_prefixed(buf, 0xfd, 0x44),
+            &F32x4Le => size += write_prefixed(buf, 0xfd, 0x45),
+            &F32x4Ge => size += write_prefixed(buf, 0xfd, 0x46),
+            &F64x2Eq => size += write_prefixed(buf, 0xfd, 0x47),
+            &F64x2Ne => size += write_prefixed(buf, 0xfd, 0x48),
+            &F64x2Lt => size += write_prefixed(buf, 0xfd, 0x49),
+            &F64x2Gt => size += write_prefixed(buf, 0xfd, 0x4a),
+            &F64x2Le => size += write_prefixed(buf, 0xfd, 0x4b),
+            &F64x2Ge => size += write_prefixed(buf, 0xfd, 0x4c),
+            &V128Not => size += write_prefixed(buf, 0xfd, 0x4d),
+            &V128And => size += write_prefixed(buf, 0xfd, 0x4e),
+            &V128Andnot => size += write_prefixed(buf, 0xfd, 0x4f),
+            &V128Or => size += write_prefixed(buf, 0xfd, 0x50),
+            &V128Xor => size += write_prefixed(buf, 0xfd, 0x51),
+            &V128Bitselect => size += write_prefixed(buf, 0xfd, 0x52),
+            &V128AnyTrue => size += write_prefixed(buf, 0xfd, 0x53),
+            &V128Load8Lane { ref imm, ref lane } => {
+                size += do_vector_imm(buf, imm, 0x54);
+                size += write_uint8(buf, *lane);
+            }
+            &V128Load16Lane { ref imm, ref lane } => {
+                size += do_vector_imm(buf, imm, 0x55);
+                size += write_uint8(buf, *lane);
+            }
+            &V128Load32Lane { ref imm, ref lane } => {
+                size += do_vector_imm(buf, imm, 0x56);
+                size += write_uint8(buf, *lane);
+            }
+            &V128Load64Lane { ref imm, ref lane } => {
+                size += do_vector_imm(buf, imm, 0x57);
+                size += write_uint8(buf, *lane);
+            }
+            &V128Store8Lane { ref imm, ref lane } => {
+                size += do_vector_imm(buf, imm, 0x58);
+                size += write_uint8(buf, *lane);
+            }
+            &V128Store16Lane { ref imm, ref lane } => {
+                size += do_vector_imm(buf, imm, 0x59);
+                size += write_uint8(buf, *lane);
+            }
+            &V128Store32Lane { ref imm, ref lane } => {
+                size += do_vector_imm(buf, imm, 0x5a);
+                size += write_uint8(buf, *lane);
+            }
+            &V128Store64Lane { ref imm, ref lane } => {
+                size += do_vector_imm(buf, imm, 0x5b);
+                size += write_uint8(buf, *lane);
+            }
+            &V128Load32Zero { ref imm } => size += do_vector_imm(buf, imm, 0x5c),
+            &V128Load64Zero { ref imm } => size += do_vector_imm(buf, imm, 0x5d),
+            &F32x4DemoteF64x2Zero => size += write_prefixed(buf, 0xfd, 0x5e),
+            &F64x2PromoteLowF32x4 => size += write_prefixed(buf, 0xfd, 0x5f),
+            &I8x16Abs => size += write_prefixed(buf, 0xfd, 0x60),
+            &I8x16Neg => size += write_prefixed(buf, 0xfd, 0x61),
+            &I8x16Popcnt => size += write_prefixed(buf, 0xfd, 0x62),
+            &I8x16AllTrue => size += write_prefixed(buf, 0xfd, 0x63),
+            &I8x16Bitmask => size += write_prefixed(buf, 0xfd, 0x64),
+            &I8x16NarrowI16x8S => size += write_prefixed(buf, 0xfd, 0x65),
+            &I8x16NarrowI16x8U => size += write_prefixed(buf, 0xfd, 0x66),
+            &F32x4Ceil => size += write_prefixed(buf, 0xfd, 0x67),
+            &F32x4Floor => size += write_prefixed(buf, 0xfd, 0x68),
+            &F32x4Trunc => size += write_prefixed(buf, 0xfd, 0x69),
+            &F32x4Nearest => size += write_prefixed(buf, 0xfd, 0x6a),
+            &I8x16Shl => size += write_prefixed(buf, 0xfd, 0x6b),
+            &I8x16ShrS => size += write_prefixed(buf, 0xfd, 0x6c),
+            &I8x16ShrU => size += write_prefixed(buf, 0xfd, 0x6d),
+            &I8x16Add => size += write_prefixed(buf, 0xfd, 0x6e),
+            &I8x16AddSatS => size += write_prefixed(buf, 0xfd, 0x6f),
+            &I8x16AddSatU => size += write_prefixed(buf, 0xfd, 0x70),
+            &I8x16Sub => size += write_prefixed(buf, 0xfd, 0x71),
+            &I8x16SubSatS => size += write_prefixed(buf, 0xfd, 0x72),
+            &I8x16SubSatU => size += write_prefixed(buf, 0xfd, 0x73),
+            &F64x2Ceil => size += write_prefixed(buf, 0xfd, 0x74),
+            &F64x2Floor => size += write_prefixed(buf, 0xfd, 0x75),
+            &I8x16MinS => size += write_prefixed(buf, 0xfd, 0x76),
+            &I8x16MinU => size += write_prefixed(buf, 0xfd, 0x77),
+            &I8x16MaxS => size += write_prefixed(buf, 0xfd, 0x78),
+            &I8x16MaxU => size += write_prefixed(buf, 0xfd, 0x79),
+            &F64x2Trunc => size += write_prefixed(buf, 0xfd, 0x7a),
+            &I8x16AvgrU => size += write_prefixed(buf, 0xfd, 0x7b),
+            &I16x8ExtaddPairwiseI8x16S => size += write_prefixed(buf, 0xfd, 0x7c),
+            &I16x8ExtaddPairwiseI8x16U => size += write_prefixed(buf, 0xfd, 0x7d),
+            &I32x4ExtaddPairwiseI16x8S => size += write_prefixed(buf, 0xfd, 0x7e),
+            &I32x4ExtaddPairwiseI16x8U => size += write_prefixed(buf, 0xfd, 0x7f),
+            &I16x8Abs => size += write_prefixed(buf, 0xfd, 0x80),
+            &I16x8Neg => size += write_prefixed(buf, 0xfd, 0x81),
+            &I16x8Q15mulrSatS => size += write_prefixed(buf, 0xfd, 0x82),
+            &I16x8AllTrue => size += write_prefixed(buf, 0xfd, 0x83),
+            &I16x8Bitmask => size += write_prefixed(buf, 0xfd, 0x84),
+            &I16x8NarrowI32x4S => size += write_prefixed(buf, 0xfd, 0x85),
+            &I16x8NarrowI32x4U => size += write_prefixed(buf, 0xfd, 0x86),
+            &I16x8ExtendLowI8x16S => size += write_prefixed(buf, 0xfd, 0x87),
+            &I16x8ExtendHighI8x16S => size += write_prefixed(buf, 0xfd, 0x88),
+            &I16x8ExtendLowI8x16U => size += write_prefixed(buf, 0xfd, 0x89),
+            &I16x8ExtendHighI8x16U => size += write_prefixed(buf, 0xfd, 0x8a),
+            &I16x8Shl => size += write_prefixed(buf, 0xfd, 0x8b),
+            &I16x8ShrS => size += write_prefixed(buf, 0xfd, 0x8c),
+            &I16x8ShrU => size += write_prefixed(buf, 0xfd, 0x8d),
+            &I16x8Add => size += write_prefixed(buf, 0xfd, 0x8e),
+            &I16x8AddSatS => size += write_prefixed(buf, 0xfd, 0x8f),
+            &I16x8AddSatU => size += write_prefixed(buf, 0xfd, 0x90),
+            &I16x8Sub => size += write_prefixed(buf, 0xfd, 0x91),
+            &I16x8SubSatS => size += write_prefixed(buf, 0xfd, 0x92),
+            &I16x8SubSatU => size += write_prefixed(buf, 0xfd, 0x93),
+            &F64x2Nearest => size += write_prefixed(buf, 0xfd, 0x94),
+            &I16x8Mul => size += write_prefixed(buf, 0xfd, 0x95),
+            &I16x8MinS => size += write_prefixed(buf, 0xfd, 0x96),
+            &I16x8MinU => size += write_prefixed(buf, 0xfd, 0x97),
+            &I16x8MaxS => size += write_prefixed(buf, 0xfd, 0x98),
+            &I16x8MaxU => size += write_prefixed(buf, 0xfd, 0x99),
+            &I16x8AvgrU => size += write_prefixed(buf, 0xfd, 0x9b),
+            &I16x8ExtmulLowI8x16S => size += write_prefixed(buf, 0xfd, 0x9c),
+            &I16x8ExtmulHighI8x16S => size += write_prefixed(buf, 0xfd, 0x9d),
+            &I16x8ExtmulLowI8x16U => size += write_prefixed(buf, 0xfd, 0x9e),
+            &I16x8ExtmulHighI8x16U => size += write_prefixed(buf, 0xfd, 0x9f),
+            &I32x4Abs => size += write_prefixed(buf, 0xfd, 0xa0),
+            &I32x4Neg => size += write_prefixed(buf, 0xfd, 0xa1),
+            &I32x4AllTrue => size += write_prefixed(buf, 0xfd, 0xa3),
+            &I32x4Bitmask => size += write_prefixed(buf, 0xfd, 0xa4),
+            &I32x4ExtendLowI16x8S => size += write_prefixed(buf, 0xfd, 0xa7),
+            &I32x4ExtendHighI16x8S => size += write_prefixed(buf, 0xfd, 0xa8),
+            &I32x4ExtendLowI16x8U => size += write_prefixed(buf, 0xfd, 0xa9),
+            &I32x4ExtendHighI16x8U => size += write_prefixed(buf, 0xfd, 0xaa),
+            &I32x4Shl => size += write_prefixed(buf, 0xfd, 0xab),
+            &I32x4ShrS => size += write_prefixed(buf, 0xfd, 0xac),
+            &I32x4ShrU => size += write_prefixed(buf, 0xfd, 0xad),
+            &I32x4Add => size += write_prefixed(buf, 0xfd, 0xae),
+            &I32x4Sub => size += write_prefixed(buf, 0xfd, 0xb1),
+            &I32x4Mul => size += write_prefixed(buf, 0xfd, 0xb5),
+            &I32x4MinS => size += write_prefixed(buf, 0xfd, 0xb6),
+            &I32x4MinU => size += write_prefixed(buf, 0xfd, 0xb7),
+            &I32x4MaxS => size += write_prefixed(buf, 0xfd, 0xb8),
+            &I32x4MaxU => size += write_prefixed(buf, 0xfd, 0xb9),
+            &I32x4DotI16x8S => size += write_prefixed(buf, 0xfd, 0xba),
+            &I32x4ExtmulLowI16x8S => size += write_prefixed(buf, 0xfd, 0xbc),
+            &I32x4ExtmulHighI16x8S => size += write_prefixed(buf, 0xfd, 0xbd),
+            &I32x4ExtmulLowI16x8U => size += write_prefixed(buf, 0xfd, 0xbe),
+            &I32x4ExtmulHighI16x8U => size += write_prefixed(buf, 0xfd, 0xbf),
+            &I64x2Abs => size += write_prefixed(buf, 0xfd, 0xc0),
+            &I64x2Neg => size += write_prefixed(buf, 0xfd, 0xc1),
+            &I64x2AllTrue => size += write_prefixed(buf, 0xfd, 0xc3),
+            &I64x2Bitmask => size += write_prefixed(buf, 0xfd, 0xc4),
+            &I64x2ExtendLowI32x4S => size += write_prefixed(buf, 0xfd, 0xc7),
+            &I64x2ExtendHighI32x4S => size += write_prefixed(buf, 0xfd, 0xc8),
+            &I64x2ExtendLowI32x4U => size += write_prefixed(buf, 0xfd, 0xc9),
+            &I64x2ExtendHighI32x4U => size += write_prefixed(buf, 0xfd, 0xca),
+            &I64x2Shl => size += write_prefixed(buf, 0xfd, 0xcb),
+            &I64x2ShrS => size += write_prefixed(buf, 0xfd, 0xcc),
+            &I64x2ShrU => size += write_prefixed(buf, 0xfd, 0xcd),
+            &I64x2Add => size += write_prefixed(buf, 0xfd, 0xce),
+            &I64x2Sub => size += write_prefixed(buf, 0xfd, 0xd1),
+            &I64x2Mul => size += write_prefixed(buf, 0xfd, 0xd5),
+            &I64x2Eq => size += write_prefixed(buf, 0xfd, 0xd6),
+            &I64x2Ne => size += write_prefixed(buf, 0xfd, 0xd7),
+            &I64x2LtS => size += write_prefixed(buf, 0xfd, 0xd8),
+            &I64x2GtS => size += write_prefixed(buf, 0xfd, 0xd9),
+            &I64x2LeS => size += write_prefixed(buf, 0xfd, 0xda),
+            &I64x2GeS => size += write_prefixed(buf, 0xfd, 0xdb),
+            &I64x2ExtmulLowI32x4S => size += write_prefixed(buf, 0xfd, 0xdc),
+            &I64x2ExtmulHighI32x4S => size += write_prefixed(buf, 0xfd, 0xdd),
+            &I64x2ExtmulLowI32x4U => size += write_prefixed(buf, 0xfd, 0xde),
+            &I64x2ExtmulHighI32x4U => size += write_prefixed(buf, 0xfd, 0xdf),
+            &F32x4Abs => size += write_prefixed(buf, 0xfd, 0xe0),
+            &F32x4Neg => size += write_prefixed(buf, 0xfd, 0xe1),
+            &F32x4Sqrt => size += write_prefixed(buf, 0xfd, 0xe3),
+            &F32x4Add => size += write_prefixed(buf, 0xfd, 0xe4),
+            &F32x4Sub => size += write_prefixed(buf, 0xfd, 0xe5),
+            &F32x4Mul => size += write_prefixed(buf, 0xfd, 0xe6),
+            &F32x4Div => size += write_prefixed(buf, 0xfd, 0xe7),
+            &F32x4Min => size += write_prefixed(buf, 0xfd, 0xe8),
+            &F32x4Max => size += write_prefixed(buf, 0xfd, 0xe9),
+            &F32x4Pmin => size += write_prefixed(buf, 0xfd, 0xea),
+            &F32x4Pmax => size += write_prefixed(buf, 0xfd, 0xeb),
+            &F64x2Abs => size += write_prefixed(buf, 0xfd, 0xec),
+            &F64x2Neg => size += write_prefixed(buf, 0xfd, 0xed),
+            &F64x2Sqrt => size += write_prefixed(buf, 0xfd, 0xef),
+            &F64x2Add => size += write_prefixed(buf, 0xfd, 0xf0),
+            &F64x2Sub => size += write_prefixed(buf, 0xfd, 0xf1),
+            &F64x2Mul => size += write_prefixed(buf, 0xfd, 0xf2),
+            &F64x2Div => size += write_prefixed(buf, 0xfd, 0xf3),
+            &F64x2Min => size += write_prefixed(buf, 0xfd, 0xf4),
+            &F64x2Max => size += write_prefixed(buf, 0xfd, 0xf5),
+            &F64x2Pmin => size += write_prefixed(buf, 0xfd, 0xf6),
+            &F64x2Pmax => size += write_prefixed(buf, 0xfd, 0xf7),
+            &I32x4TruncSatF32x4S => size += write_prefixed(buf, 0xfd, 0xf8),
+            &I32x4TruncSatF32x4U => size += write_prefixed(buf, 0xfd, 0xf9),
+            &F32x4ConvertI32x4S => size += write_prefixed(buf, 0xfd, 0xfa),
+            &F32x4ConvertI32x4U => size += write_prefixed(buf, 0xfd, 0xfb),
+            &I32x4TruncSatF64x2SZero => size += write_prefixed(buf, 0xfd, 0xfc),
+            &I32x4TruncSatF64x2UZero => size += write_prefixed(buf, 0xfd, 0xfd),
+            &F64x2ConvertLowI32x4S => size += write_prefixed(buf, 0xfd, 0xfe),
+            &F64x2ConvertLowI32x4U => size += write_prefixed(buf, 0xfd, 0xff),
+            &MemoryAtomicNotify { ref imm } => size += do_atomic_imm(buf, imm, 0x00),
+            &MemoryAtomicWait32 { ref imm } => size += do_atomic_imm(buf, imm, 0x01),
+            &MemoryAtomicWait64 { ref imm } => size += do_atomic_imm(buf, imm, 0x02),
+            &AtomicFence { ref reserved } => {
+                size += write_prefixed(buf, 0xfe, 0x03);
+                size += write_varuint1(buf, *reserved as u8);
+            }
+            &I32AtomicLoad { ref imm } => size += do_atomic_imm(buf, imm, 0x10),
+            &I64AtomicLoad { ref imm } => size += do_atomic_imm(buf, imm, 0x11),
+            &I32AtomicLoad8U { ref imm } => size += do_atomic_imm(buf, imm, 0x12),
+            &I32AtomicLoad16U { ref imm } => size += do_atomic_imm(buf, imm, 0x13),
+            &I64AtomicLoad8U { ref imm } => size += do_atomic_imm(buf, imm, 0x14),
+            &I64AtomicLoad16U { ref imm } => size += do_atomic_imm(buf, imm, 0x15),
+            &I64AtomicLoad32U { ref imm } => size += do_atomic_imm(buf, imm, 0x16),
+            &I32AtomicStore { ref imm } => size += do_atomic_imm(buf, imm, 0x17),
+            &I64AtomicStore { ref imm } => size += do_atomic_imm(buf, imm, 0x18),
+            &I32AtomicStore8 { ref imm } => size += do_atomic_imm(buf, imm, 0x19),
+            &I32AtomicStore16 { ref imm } => size += do_atomic_imm(buf, imm, 0x1a),
+            &I64AtomicStore8 { ref imm } => size += do_atomic_imm(buf, imm, 0x1b),
+            &I64AtomicStore16 { ref imm } => size += do_atomic_imm(buf, imm, 0x1c),
+            &I64AtomicStore32 { ref imm } => size += do_atomic_imm(buf, imm, 0x1d),
+            &I32AtomicRmwAdd { ref imm } => size += do_atomic_imm(buf, imm, 0x1e),
+            &I64AtomicRmwAdd { ref imm } => size += do_atomic_imm(buf, imm, 0x1f),
+            &I32AtomicRmw8AddU { ref imm } => size += do_atomic_imm(buf, imm, 0x20),
+            &I32AtomicRmw16AddU { ref imm } => size += do_atomic_imm(buf, imm, 0x21),
+            &I64AtomicRmw8AddU { ref imm } => size += do_atomic_imm(buf, imm, 0x22),
+            &I64AtomicRmw16AddU { ref imm } => size += do_atomic_imm(buf, imm, 0x23),
+            &I64AtomicRmw32AddU { ref imm } => size += do_atomic_imm(buf, imm, 0x24),
+            &I32AtomicRmwSub { ref imm } => size += do_atomic_imm(buf, imm, 0x25),
+            &I64AtomicRmwSub { ref imm } => size += do_atomic_imm(buf, imm, 0x26),
+            &I32AtomicRmw8SubU { ref imm } => size += do_atomic_imm(buf, imm, 0x27),
+            &I32AtomicRmw16SubU { ref imm } => size += do_atomic_imm(buf, imm, 0x28),
+            &I64AtomicRmw8SubU { ref imm } => size += do_atomic_imm(buf, imm, 0x29),
+            &I64AtomicRmw16SubU { ref imm } => size += do_atomic_imm(buf, imm, 0x2a),
+            &I64AtomicRmw32SubU { ref imm } => size += do_atomic_imm(buf, imm, 0x2b),
+            &I32AtomicRmwAnd { ref imm } => size += do_atomic_imm(buf, imm, 0x2c),
+            &I64AtomicRmwAnd { ref imm } => size += do_atomic_imm(buf, imm, 0x2d),
+            &I32AtomicRmw8AndU { ref imm } => size += do_atomic_imm(buf, imm, 0x2e),
+            &I32AtomicRmw16AndU { ref imm } => size += do_atomic_imm(buf, imm, 0x2f),
+            &I64AtomicRmw8AndU { ref imm } => size += do_atomic_imm(buf, imm, 0x30),
+            &I64AtomicRmw16AndU { ref imm } => size += do_atomic_imm(buf, imm, 0x31),
+            &I64AtomicRmw32AndU { ref imm } => size += do_atomic_imm(buf, imm, 0x32),
+            &I32AtomicRmwOr { ref imm } => size += do_atomic_imm(buf, imm, 0x33),
+            &I64AtomicRmwOr { ref imm } => size += do_atomic_imm(buf, imm, 0x34),
+            &I32AtomicRmw8OrU { ref imm } => size += do_atomic_imm(buf, imm, 0x35),
+            &I32AtomicRmw16OrU { ref imm } => size += do_atomic_imm(buf, imm, 0x36),
+            &I64AtomicRmw8OrU { ref imm } => size += do_atomic_imm(buf, imm, 0x37),
+            &I64AtomicRmw16OrU { ref imm } => size += do_atomic_imm(buf, imm, 0x38),
+            &I64AtomicRmw32OrU { ref imm } => size += do_atomic_imm(buf, imm, 0x39),
+            &I32AtomicRmwXor { ref imm } => size += do_atomic_imm(buf, imm, 0x3a),
+            &I64AtomicRmwXor { ref imm } => size += do_atomic_imm(buf, imm, 0x3b),
+            &I32AtomicRmw8XorU { ref imm } => size += do_atomic_imm(buf, imm, 0x3c),
+            &I32AtomicRmw16XorU { ref imm } => size += do_atomic_imm(buf, imm, 0x3d),
+            &I64AtomicRmw8XorU { ref imm } => size += do_atomic_imm(buf, imm, 0x3e),
+            &I64AtomicRmw16XorU { ref imm } => size += do_atomic_imm(buf, imm, 0x3f),
+            &I64AtomicRmw32XorU { ref imm } => size += do_atomic_imm(buf, imm, 0x40),
+            &I32AtomicRmwXchg { ref imm } => size += do_atomic_imm(buf, imm, 0x41),
+            &I64AtomicRmwXchg { ref imm } => size += do_atomic_imm(buf, imm, 0x42),
+            &I32AtomicRmw8XchgU { ref imm } => size += do_atomic_imm(buf, imm, 0x43),
+            &I32AtomicRmw16XchgU { ref imm } => size += do_atomic_imm(buf, imm, 0x44),
+            &I64AtomicRmw8XchgU { ref imm } => size += do_atomic_imm(buf, imm, 0x45),
+            &I64AtomicRmw16XchgU { ref imm } => size += do_atomic_imm(buf, imm, 0x46),
+            &I64AtomicRmw32XchgU { ref imm } => size += do_atomic_imm(buf, imm, 0x47),
+            &I32AtomicRmwCmpxchg { ref imm } => size += do_atomic_imm(buf, imm, 0x48),
+            &I64AtomicRmwCmpxchg { ref imm } => size += do_atomic_imm(buf, imm, 0x49),
+            &I32AtomicRmw8CmpxchgU { ref imm } => size += do_atomic_imm(buf, imm, 0x4a),
+            &I32AtomicRmw16CmpxchgU { ref imm } => size += do_atomic_imm(buf, imm, 0x4b),
+            &I64AtomicRmw8CmpxchgU { ref imm } => size += do_atomic_imm(buf, imm, 0x4c),
+            &I64AtomicRmw16CmpxchgU { ref imm } => size += do_atomic_imm(buf, imm, 0x4d),
+            &I64AtomicRmw32CmpxchgU { ref imm } => size += do_atomic_imm(buf, imm, 0x4e),
+            &StructNew { ref index } => size += do_gc(buf, 0x00, &[**index]),
+            &StructNewDefault { ref index } => size += do_gc(buf, 0x01, &[**index]),
+            &StructGet { ref index, field } => size += do_gc(buf, 0x02, &[**index, field]),
+            &StructGetS { ref index, field } => size += do_gc(buf, 0x03, &[**index, field]),
+            &StructGetU { ref index, field } => size += do_gc(buf, 0x04, &[**index, field]),
+            &StructSet { ref index, field } => size += do_gc(buf, 0x05, &[**index, field]),
+            &ArrayNew { ref index } => size += do_gc(buf, 0x06, &[**index]),
+            &ArrayNewDefault { ref index } => size += do_gc(buf, 0x07, &[**index]),
+            &ArrayNewFixed { ref index, len } => size += do_gc(buf, 0x08, &[**index, len]),
+            &ArrayNewData { ref index, ref segment } => size += do_gc(buf, 0x09, &[**index, **segment]),
+            &ArrayNewElem { ref index, ref segment } => size += do_gc(buf, 0x0a, &[**index, **segment]),
+            &ArrayGet { ref index } => size += do_gc(buf, 0x0b, &[**index]),
+            &ArrayGetS { ref index } => size += do_gc(buf, 0x0c, &[**index]),
+            &ArrayGetU { ref index } => size += do_gc(buf, 0x0d, &[**index]),
+            &ArraySet { ref index } => size += do_gc(buf, 0x0e, &[**index]),
+            &ArrayLen => size += do_gc(buf, 0x0f, &[]),
+            &ArrayFill { ref index } => size += do_gc(buf, 0x10, &[**index]),
+            &ArrayCopy { ref dst, ref src } => size += do_gc(buf, 0x11, &[**dst, **src]),
+            &ArrayInitData { ref index, ref segment } => size += do_gc(buf, 0x12, &[**index, **segment]),
+            &ArrayInitElem { ref index, ref segment } => size += do_gc(buf, 0x13, &[**index, **segment]),
+            &RefTest(ref ty) => {
+                size += do_gc(buf, if ty.nullable { 0x15 } else { 0x14 }, &[]);
+                size += ty.heap.dump(buf);
+            }
+            &RefCast(ref ty) => {
+                size += do_gc(buf, if ty.nullable { 0x17 } else { 0x16 }, &[]);
+                size += ty.heap.dump(buf);
+            }
+            &BrOnCast { depth, ref from, ref to } => size += do_br_on_cast(buf, 0x18, depth, from, to),
+            &BrOnCastFail { depth, ref from, ref to } => size += do_br_on_cast(buf, 0x19, depth, from, to),
+            &AnyConvertExtern => size += do_gc(buf, 0x1a, &[]),
+            &ExternConvertAny => size += do_gc(buf, 0x1b, &[]),
+            &RefI31 => size += do_gc(buf, 0x1c, &[]),
+            &I31GetS => size += do_gc(buf, 0x1d, &[]),
+            &I31GetU => size += do_gc(buf, 0x1e, &[]),
         };
         size
     }
 }
 
+impl Parse for Op {
+    fn parse(buf: &[u8], pos: &mut usize) -> Result<Self, DecodeError> {
+        use self::Op::*;
+        let start = *pos;
+        let op = match read_uint8(buf, pos)? {
+            0x02 => Block { sig: BlockType::parse(buf, pos)? },
+            0x03 => Loop { sig: BlockType::parse(buf, pos)? },
+            0x04 => If { sig: BlockType::parse(buf, pos)? },
+            0x06 => Try { sig: BlockType::parse(buf, pos)? },
+            0x07 => Catch(TagIndex::new(read_varuint32(buf, pos)?)),
+            0x08 => Throw(TagIndex::new(read_varuint32(buf, pos)?)),
+            0x09 => Rethrow { depth: read_varuint32(buf, pos)? },
+            0x18 => Delegate { depth: read_varuint32(buf, pos)? },
+            0x0c => Br { depth: read_varuint32(buf, pos)? },
+            0x0d => BrIf { depth: read_varuint32(buf, pos)? },
+            0x0e => BrTable(BrTarget::parse(buf, pos)?),
+            0x10 => {
+                let index = FunctionIndex(read_varuint32(buf, pos)?);
+                Call { index: index.into() }
+            }
+            0x11 => {
+                let index = TypeIndex::new(read_varuint32(buf, pos)?);
+                CallIndirect {
+                    index: index,
+                    table: TableIndex::new(read_varuint32(buf, pos)?),
+                }
+            }
+            0x12 => {
+                let index = FunctionIndex(read_varuint32(buf, pos)?);
+                ReturnCall { index: index.into() }
+            }
+            0x13 => {
+                let index = TypeIndex::new(read_varuint32(buf, pos)?);
+                ReturnCallIndirect {
+                    index: index,
+                    table: TableIndex::new(read_varuint32(buf, pos)?),
+                }
+            }
+            0x14 => CallRef { index: TypeIndex::new(read_varuint32(buf, pos)?) },
+            0x15 => ReturnCallRef { index: TypeIndex::new(read_varuint32(buf, pos)?) },
+            0x1c => {
+                let arity = read_varuint32(buf, pos)?;
+                if arity != 1 {
+                    return Err(DecodeError::new(start, DecodeErrorKind::InvalidSelectArity(arity)));
+                }
+                TypedSelect(ValueType::parse(buf, pos)?)
+            }
+            0x20 => GetLocal(LocalIndex::new(read_varuint32(buf, pos)?)),
+            0x21 => SetLocal(LocalIndex::new(read_varuint32(buf, pos)?)),
+            0x22 => TeeLocal(LocalIndex::new(read_varuint32(buf, pos)?)),
+            0x23 => GetGlobal(GlobalIndex::new(read_varuint32(buf, pos)?)),
+            0x24 => SetGlobal(GlobalIndex::new(read_varuint32(buf, pos)?)),
+            0x25 => TableGet(TableIndex::new(read_varuint32(buf, pos)?)),
+            0x26 => TableSet(TableIndex::new(read_varuint32(buf, pos)?)),
+            0x28 => I32Load { imm: MemoryImmediate::parse(buf, pos)? },
+            0x29 => I64Load { imm: MemoryImmediate::parse(buf, pos)? },
+            0x2a => F32Load { imm: MemoryImmediate::parse(buf, pos)? },
+            0x2b => F64Load { imm: MemoryImmediate::parse(buf, pos)? },
+            0x2c => I32Load8S { imm: MemoryImmediate::parse(buf, pos)? },
+            0x2d => I32Load8U { imm: MemoryImmediate::parse(buf, pos)? },
+            0x2e => I32Load16S { imm: MemoryImmediate::parse(buf, pos)? },
+            0x2f => I32Load16U { imm: MemoryImmediate::parse(buf, pos)? },
+            0x30 => I64Load8S { imm: MemoryImmediate::parse(buf, pos)? },
+            0x31 => I64Load8U { imm: MemoryImmediate::parse(buf, pos)? },
+            0x32 => I64Load16S { imm: MemoryImmediate::parse(buf, pos)? },
+            0x33 => I64Load16U { imm: MemoryImmediate::parse(buf, pos)? },
+            0x34 => I64load32S { imm: MemoryImmediate::parse(buf, pos)? },
+            0x35 => I64load32U { imm: MemoryImmediate::parse(buf, pos)? },
+            0x36 => I32Store { imm: MemoryImmediate::parse(buf, pos)? },
+            0x37 => I64Store { imm: MemoryImmediate::parse(buf, pos)? },
+            0x38 => F32Store { imm: MemoryImmediate::parse(buf, pos)? },
+            0x39 => F64Store { imm: MemoryImmediate::parse(buf, pos)? },
+            0x3a => I32Store8 { imm: MemoryImmediate::parse(buf, pos)? },
+            0x3b => I32Store16 { imm: MemoryImmediate::parse(buf, pos)? },
+            0x3c => I64Store8 { imm: MemoryImmediate::parse(buf, pos)? },
+            0x3d => I64Store16 { imm: MemoryImmediate::parse(buf, pos)? },
+            0x3e => I64Store32 { imm: MemoryImmediate::parse(buf, pos)? },
+            0x3f => CurrentMemory { memory: MemoryIndex::new(read_varuint32(buf, pos)?) },
+            0x40 => GrowMemory { memory: MemoryIndex::new(read_varuint32(buf, pos)?) },
+            0x41 => I32Const(read_varint32(buf, pos)?),
+            0x42 => I64Const(read_varint64(buf, pos)?),
+            0x43 => F32Const(f32::from_bits(read_uint32(buf, pos)?)),
+            0x44 => F64Const(f64::from_bits(read_uint64(buf, pos)?)),
+            0x00 => Unreachable,
+            0x01 => Nop,
+            0x05 => Else,
+            0x19 => CatchAll,
+            0x0b => End,
+            0x0f => Return,
+            0x1a => Drop,
+            0x1b => Select,
+            0x45 => I32Eqz,
+            0x46 => I32Eq,
+            0x47 => I32NE,
+            0x48 => I32LtS,
+            0x49 => I32LtU,
+            0x4a => I32GtS,
+            0x4b => I32GtU,
+            0x4c => I32LeS,
+            0x4d => I32LeU,
+            0x4e => I32GeS,
+            0x4f => I32GeU,
+            0x50 => I64Eqz,
+            0x51 => I64Eq,
+            0x52 => I64Ne,
+            0x53 => I64LtS,
+            0x54 => I64LtU,
+            0x55 => I64GtS,
+            0x56 => I64GtU,
+            0x57 => I64LeS,
+            0x58 => I64LeU,
+            0x59 => I64GeS,
+            0x5a => I64GeU,
+            0x5b => F32Eq,
+            0x5c => F32Ne,
+            0x5d => F32Lt,
+            0x5e => F32Gt,
+            0x5f => F32Le,
+            0x60 => F32Ge,
+            0x61 => F64Eq,
+            0x62 => F64Ne,
+            0x63 => F64Lt,
+            0x64 => F64Gt,
+            0x65 => F64Le,
+            0x66 => F64Ge,
+            0x67 => I32Clz,
+            0x68 => I32Ctz,
+            0x69 => I32Popcnt,
+            0x6a => I32Add,
+            0x6b => I32Sub,
+            0x6c => I32Mul,
+            0x6d => I32DivS,
+            0x6e => I32DivU,
+            0x6f => I32RemS,
+            0x70 => I32RemU,
+            0x71 => I32And,
+            0x72 => I32Or,
+            0x73 => I32Xor,
+            0x74 => I32Shl,
+            0x75 => I32ShrS,
+            0x76 => I32ShrU,
+            0x77 => I32Rotl,
+            0x78 => I32Rotr,
+            0x79 => I64Clz,
+            0x7a => I64Ctz,
+            0x7b => I64Popcnt,
+            0x7c => I64Add,
+            0x7d => I64Sub,
+            0x7e => I64Mul,
+            0x7f => I64DivS,
+            0x80 => I64DivU,
+            0x81 => I64RemS,
+            0x82 => I64RemU,
+            0x83 => I64And,
+            0x84 => I64Or,
+            0x85 => I64Xor,
+            0x86 => I64Shl,
+            0x87 => I64ShrS,
+            0x88 => I64ShrU,
+            0x89 => I64Rotl,
+            0x8a => I64Rotr,
+            0x8b => F32Abs,
+            0x8c => F32Neg,
+            0x8d => F32Ceil,
+            0x8e => F32Floor,
+            0x8f => F32Trunc,
+            0x90 => F32Nearest,
+            0x91 => F32Sqrt,
+            0x92 => F32Add,
+            0x93 => F32Sub,
+            0x94 => F32Mul,
+            0x95 => F32Div,
+            0x96 => F32Min,
+            0x97 => F32Max,
+            0x98 => F32Copysign,
+            0x99 => F64Abs,
+            0x9a => F64Neg,
+            0x9b => F64Ceil,
+            0x9c => F64Floor,
+            0x9d => F64Trunc,
+            0x9e => F64Nearest,
+            0x9f => F64Sqrt,
+            0xa0 => F64Add,
+            0xa1 => F64Sub,
+            0xa2 => F64Mul,
+            0xa3 => F64Div,
+            0xa4 => F64Min,
+            0xa5 => F64Max,
+            0xa6 => F64Copysign,
+            0xa7 => I32wrapI64,
+            0xa8 => I32TruncSF32,
+            0xa9 => I32TruncUF32,
+            0xaa => I32TruncSF64,
+            0xab => I32TruncUF64,
+            0xac => I64ExtendSI32,
+            0xad => I64ExtendUI32,
+            0xae => I64TruncSF32,
+            0xaf => I64TruncUF32,
+            0xb0 => I64TruncSF64,
+            0xb1 => I64TruncUF64,
+            0xb2 => F32ConvertSI32,
+            0xb3 => F32ConvertUI32,
+            0xb4 => F32ConvertSI64,
+            0xb5 => F32ConvertUI64,
+            0xb6 => F32DemoteF64,
+            0xb7 => F64ConvertSI32,
+            0xb8 => F64ConvertUI32,
+            0xb9 => F64ConvertSI64,
+            0xba => F64ConvertUI64,
+            0xbb => F64PromoteF32,
+            0xbc => I32ReinterpretF32,
+            0xbd => I64ReinterpretF64,
+            0xbe => F32ReinterpretI32,
+            0xbf => F64ReinterpretI64,
+            0xc0 => I32Extend8S,
+            0xc1 => I32Extend16S,
+            0xc2 => I64Extend8S,
+            0xc3 => I64Extend16S,
+            0xc4 => I64Extend32S,
+            0xd0 => RefNull(HeapType::parse(buf, pos)?),
+            0xd1 => RefIsNull,
+            0xd2 => RefFunc(FunctionIndex(read_varuint32(buf, pos)?).into()),
+            0xd3 => RefEq,
+            0xd4 => RefAsNonNull,
+            0xd5 => BrOnNull { depth: read_varuint32(buf, pos)? },
+            0xd6 => BrOnNonNull { depth: read_varuint32(buf, pos)? },
+            0xfb => {
+                fn index(buf: &[u8], pos: &mut usize) -> Result<TypeIndex, DecodeError> {
+                    read_varuint32(buf, pos).map(TypeIndex::new)
+                }
+                match read_varuint32(buf, pos)? {
+                    0x00 => StructNew { index: index(buf, pos)? },
+                    0x01 => StructNewDefault { index: index(buf, pos)? },
+                    0x02 => StructGet { index: index(buf, pos)?, field: read_varuint32(buf, pos)? },
+                    0x03 => StructGetS { index: index(buf, pos)?, field: read_varuint32(buf, pos)? },
+                    0x04 => StructGetU { index: index(buf, pos)?, field: read_varuint32(buf, pos)? },
+                    0x05 => StructSet { index: index(buf, pos)?, field: read_varuint32(buf, pos)? },
+                    0x06 => ArrayNew { index: index(buf, pos)? },
+                    0x07 => ArrayNewDefault { index: index(buf, pos)? },
+                    0x08 => ArrayNewFixed { index: index(buf, pos)?, len: read_varuint32(buf, pos)? },
+                    0x09 => {
+                        ArrayNewData {
+                            index: index(buf, pos)?,
+                            segment: DataIndex::new(read_varuint32(buf, pos)?),
+                        }
+                    }
+                    0x0a => {
+                        ArrayNewElem {
+                            index: index(buf, pos)?,
+                            segment: ElementIndex::new(read_varuint32(buf, pos)?),
+                        }
+                    }
+                    0x0b => ArrayGet { index: index(buf, pos)? },
+                    0x0c => ArrayGetS { index: index(buf, pos)? },
+                    0x0d => ArrayGetU { index: index(buf, pos)? },
+                    0x0e => ArraySet { index: index(buf, pos)? },
+                    0x0f => ArrayLen,
+                    0x10 => ArrayFill { index: index(buf, pos)? },
+                    0x11 => ArrayCopy { dst: index(buf, pos)?, src: index(buf, pos)? },
+                    0x12 => {
+                        ArrayInitData {
+                            index: index(buf, pos)?,
+                            segment: DataIndex::new(read_varuint32(buf, pos)?),
+                        }
+                    }
+                    0x13 => {
+                        ArrayInitElem {
+                            index: index(buf, pos)?,
+                            segment: ElementIndex::new(read_varuint32(buf, pos)?),
+                        }
+                    }
+                    code @ 0x14..=0x17 => {
+                        let ty = RefType {
+                            nullable: code & 1 == 1,
+                            heap: HeapType::parse(buf, pos)?,
+                        };
+                        if code < 0x16 { RefTest(ty) } else { RefCast(ty) }
+                    }
+                    code @ 0x18..=0x19 => {
+                        let flags = read_uint8(buf, pos)?;
+                        let depth = read_varuint32(buf, pos)?;
+                        let from = RefType {
+                            nullable: flags & 1 == 1,
+                            heap: HeapType::parse(buf, pos)?,
+                        };
+                        let to = RefType {
+                            nullable: flags & 2 == 2,
+                            heap: HeapType::parse(buf, pos)?,
+                        };
+                        if code == 0x18 {
+                            BrOnCast { depth: depth, from: from, to: to }
+                        } else {
+                            BrOnCastFail { depth: depth, from: from, to: to }
+                        }
+                    }
+                    0x1a => AnyConvertExtern,
+                    0x1b => ExternConvertAny,
+                    0x1c => RefI31,
+                    0x1d => I31GetS,
+                    0x1e => I31GetU,
+                    code => {
+                        let kind = DecodeErrorKind::UnknownPrefixedOpcode(0xfb, code);
+                        return Err(DecodeError::new(start, kind));
+                    }
+                }
+            }
+            0xfc => {
+                match read_varuint32(buf, pos)? {
+                    0 => I32TruncSatSF32,
+                    1 => I32TruncSatUF32,
+                    2 => I32TruncSatSF64,
+                    3 => I32TruncSatUF64,
+                    4 => I64TruncSatSF32,
+                    5 => I64TruncSatUF32,
+                    6 => I64TruncSatSF64,
+                    7 => I64TruncSatUF64,
+                    8 => {
+                        let segment = DataIndex::new(read_varuint32(buf, pos)?);
+                        MemoryInit {
+                            segment: segment,
+                            memory: MemoryIndex::new(read_varuint32(buf, pos)?),
+                        }
+                    }
+                    9 => DataDrop(DataIndex::new(read_varuint32(buf, pos)?)),
+                    10 => {
+                        let dst = MemoryIndex::new(read_varuint32(buf, pos)?);
+                        MemoryCopy {
+                            dst: dst,
+                            src: MemoryIndex::new(read_varuint32(buf, pos)?),
+                        }
+                    }
+                    11 => MemoryFill { memory: MemoryIndex::new(read_varuint32(buf, pos)?) },
+                    12 => {
+                        let segment = ElementIndex::new(read_varuint32(buf, pos)?);
+                        TableInit {
+                            segment: segment,
+                            table: TableIndex::new(read_varuint32(buf, pos)?),
+                        }
+                    }
+                    13 => ElemDrop(ElementIndex::new(read_varuint32(buf, pos)?)),
+                    14 => {
+                        let dst = TableIndex::new(read_varuint32(buf, pos)?);
+                        TableCopy {
+                            dst: dst,
+                            src: TableIndex::new(read_varuint32(buf, pos)?),
+                        }
+                    }
+                    15 => TableGrow(TableIndex::new(read_varuint32(buf, pos)?)),
+                    16 => TableSize(TableIndex::new(read_varuint32(buf, pos)?)),
+                    17 => TableFill(TableIndex::new(read_varuint32(buf, pos)?)),
+                    code => {
+                        let kind = DecodeErrorKind::UnknownPrefixedOpcode(0xfc, code);
+                        return Err(DecodeError::new(start, kind));
+                    }
+                }
+            }
+            0xfd => {
+                match read_varuint32(buf, pos)? {
+                    0x00 => V128Load { imm: MemoryImmediate::parse(buf, pos)? },
+                    0x01 => V128Load8x8S { imm: MemoryImmediate::parse(buf, pos)? },
+                    0x02 => V128Load8x8U { imm: MemoryImmediate::parse(buf, pos)? },
+                    0x03 => V128Load16x4S { imm: MemoryImmediate::parse(buf, pos)? },
+                    0x04 => V128Load16x4U { imm: MemoryImmediate::parse(buf, pos)? },
+                    0x05 => V128Load32x2S { imm: MemoryImmediate::parse(buf, pos)? },
+                    0x06 => V128Load32x2U { imm: MemoryImmediate::parse(buf, pos)? },
+                    0x07 => V128Load8Splat { imm: MemoryImmediate::parse(buf, pos)? },
+                    0x08 => V128Load16Splat { imm: MemoryImmediate::parse(buf, pos)? },
+                    0x09 => V128Load32Splat { imm: MemoryImmediate::parse(buf, pos)? },
+                    0x0a => V128Load64Splat { imm: MemoryImmediate::parse(buf, pos)? },
+                    0x0b => V128Store { imm: MemoryImmediate::parse(buf, pos)? },
+                    0x0c => V128Const(read_uint128(buf, pos)?),
+                    0x0d => {
+                        let mut lanes = [0; 16];
+                        lanes.copy_from_slice(read_slice(buf, pos, 16)?);
+                        I8x16Shuffle(lanes)
+                    }
+                    0x0e => I8x16Swizzle,
+                    0x0f => I8x16Splat,
+                    0x10 => I16x8Splat,
+                    0x11 => I32x4Splat,
+                    0x12 => I64x2Splat,
+                    0x13 => F32x4Splat,
+                    0x14 => F64x2Splat,
+                    0x15 => I8x16ExtractLaneS(read_uint8(buf, pos)?),
+                    0x16 => I8x16ExtractLaneU(read_uint8(buf, pos)?),
+                    0x17 => I8x16ReplaceLane(read_uint8(buf, pos)?),
+                    0x18 => I16x8ExtractLaneS(read_uint8(buf, pos)?),
+                    0x19 => I16x8ExtractLaneU(read_uint8(buf, pos)?),
+                    0x1a => I16x8ReplaceLane(read_uint8(buf, pos)?),
+                    0x1b => I32x4ExtractLane(read_uint8(buf, pos)?),
+                    0x1c => I32x4ReplaceLane(read_uint8(buf, pos)?),
+                    0x1d => I64x2ExtractLane(read_uint8(buf, pos)?),
+                    0x1e => I64x2ReplaceLane(read_uint8(buf, pos)?),
+                    0x1f => F32x4ExtractLane(read_uint8(buf, pos)?),
+                    0x20 => F32x4ReplaceLane(read_uint8(buf, pos)?),
+                    0x21 => F64x2ExtractLane(read_uint8(buf, pos)?),
+                    0x22 => F64x2ReplaceLane(read_uint8(buf, pos)?),
+                    0x23 => I8x16Eq,
+                    0x24 => I8x16Ne,
+                    0x25 => I8x16LtS,
+                    0x26 => I8x16LtU,
+                    0x27 => I8x16GtS,
+                    0x28 => I8x16GtU,
+                    0x29 => I8x16LeS,
+                    0x2a => I8x16LeU,
+                    0x2b => I8x16GeS,
+                    0x2c => I8x16GeU,
+                    0x2d => I16x8Eq,
+                    0x2e => I16x8Ne,
+                    0x2f => I16x8LtS,
+                    0x30 => I16x8LtU,
+                    0x31 => I16x8GtS,
+                    0x32 => I16x8GtU,
+                    0x33 => I16x8LeS,
+                    0x34 => I16x8LeU,
+                    0x35 => I16x8GeS,
+                    0x36 => I16x8GeU,
+                    0x37 => I32x4Eq,
+                    0x38 => I32x4Ne,
+                    0x39 => I32x4LtS,
+                    0x3a => I32x4LtU,
+                    0x3b => I32x4GtS,
+                    0x3c => I32x4GtU,
+                    0x3d => I32x4LeS,
+                    0x3e => I32x4LeU,
+                    0x3f => I32x4GeS,
+                    0x40 => I32x4GeU,
+                    0x41 => F32x4Eq,
+                    0x42 => F32x4Ne,
+                    0x43 => F32x4Lt,
+                    0x44 => F32x4Gt,
+                    0x45 => F32x4Le,
+                    0x46 => F32x4Ge,
+                    0x47 => F64x2Eq,
+                    0x48 => F64x2Ne,
+                    0x49 => F64x2Lt,
+                    0x4a => F64x2Gt,
+                    0x4b => F64x2Le,
+                    0x4c => F64x2Ge,
+                    0x4d => V128Not,
+                    0x4e => V128And,
+                    0x4f => V128Andnot,
+                    0x50 => V128Or,
+                    0x51 => V128Xor,
+                    0x52 => V128Bitselect,
+                    0x53 => V128AnyTrue,
+                    0x54 => {
+                        let imm = MemoryImmediate::parse(buf, pos)?;
+                        V128Load8Lane {
+                            imm: imm,
+                            lane: read_uint8(buf, pos)?,
+                        }
+                    }
+                    0x55 => {
+                        let imm = MemoryImmediate::parse(buf, pos)?;
+                        V128Load16Lane {
+                            imm: imm,
+                            lane: read_uint8(buf, pos)?,
+                        }
+                    }
+                    0x56 => {
+                        let imm = MemoryImmediate::parse(buf, pos)?;
+                        V128Load32Lane {
+                            imm: imm,
+                            lane: read_uint8(buf, pos)?,
+                        }
+                    }
+                    0x57 => {
+                        let imm = MemoryImmediate::parse(buf, pos)?;
+                        V128Load64Lane {
+                            imm: imm,
+                            lane: read_uint8(buf, pos)?,
+                        }
+                    }
+                    0x58 => {
+                        let imm = MemoryImmediate::parse(buf, pos)?;
+                        V128Store8Lane {
+                            imm: imm,
+                            lane: read_uint8(buf, pos)?,
+                        }
+                    }
+                    0x59 => {
+                        let imm = MemoryImmediate::parse(buf, pos)?;
+                        V128Store16Lane {
+                            imm: imm,
+                            lane: read_uint8(buf, pos)?,
+                        }
+                    }
+                    0x5a => {
+                        let imm = MemoryImmediate::parse(buf, pos)?;
+                        V128Store32Lane {
+                            imm: imm,
+                            lane: read_uint8(buf, pos)?,
+                        }
+                    }
+                    0x5b => {
+                        let imm = MemoryImmediate::parse(buf, pos)?;
+                        V128Store64Lane {
+                            imm: imm,
+                            lane: read_uint8(buf, pos)?,
+                        }
+                    }
+                    0x5c => V128Load32Zero { imm: MemoryImmediate::parse(buf, pos)? },
+                    0x5d => V128Load64Zero { imm: MemoryImmediate::parse(buf, pos)? },
+                    0x5e => F32x4DemoteF64x2Zero,
+                    0x5f => F64x2PromoteLowF32x4,
+                    0x60 => I8x16Abs,
+                    0x61 => I8x16Neg,
+                    0x62 => I8x16Popcnt,
+                    0x63 => I8x16AllTrue,
+                    0x64 => I8x16Bitmask,
+                    0x65 => I8x16NarrowI16x8S,
+                    0x66 => I8x16NarrowI16x8U,
+                    0x67 => F32x4Ceil,
+                    0x68 => F32x4Floor,
+                    0x69 => F32x4Trunc,
+                    0x6a => F32x4Nearest,
+                    0x6b => I8x16Shl,
+                    0x6c => I8x16ShrS,
+                    0x6d => I8x16ShrU,
+                    0x6e => I8x16Add,
+                    0x6f => I8x16AddSatS,
+                    0x70 => I8x16AddSatU,
+                    0x71 => I8x16Sub,
+                    0x72 => I8x16SubSatS,
+                    0x73 => I8x16SubSatU,
+                    0x74 => F64x2Ceil,
+                    0x75 => F64x2Floor,
+                    0x76 => I8x16MinS,
+                    0x77 => I8x16MinU,
+                    0x78 => I8x16MaxS,
+                    0x79 => I8x16MaxU,
+                    0x7a => F64x2Trunc,
+                    0x7b => I8x16AvgrU,
+                    0x7c => I16x8ExtaddPairwiseI8x16S,
+                    0x7d => I16x8ExtaddPairwiseI8x16U,
+                    0x7e => I32x4ExtaddPairwiseI16x8S,
+                    0x7f => I32x4ExtaddPairwiseI16x8U,
+                    0x80 => I16x8Abs,
+                    0x81 => I16x8Neg,
+                    0x82 => I16x8Q15mulrSatS,
+                    0x83 => I16x8AllTrue,
+                    0x84 => I16x8Bitmask,
+                    0x85 => I16x8NarrowI32x4S,
+                    0x86 => I16x8NarrowI32x4U,
+                    0x87 => I16x8ExtendLowI8x16S,
+                    0x88 => I16x8ExtendHighI8x16S,
+                    0x89 => I16x8ExtendLowI8x16U,
+                    0x8a => I16x8ExtendHighI8x16U,
+                    0x8b => I16x8Shl,
+                    0x8c => I16x8ShrS,
+                    0x8d => I16x8ShrU,
+                    0x8e => I16x8Add,
+                    0x8f => I16x8AddSatS,
+                    0x90 => I16x8AddSatU,
+                    0x91 => I16x8Sub,
+                    0x92 => I16x8SubSatS,
+                    0x93 => I16x8SubSatU,
+                    0x94 => F64x2Nearest,
+                    0x95 => I16x8Mul,
+                    0x96 => I16x8MinS,
+                    0x97 => I16x8MinU,
+                    0x98 => I16x8MaxS,
+                    0x99 => I16x8MaxU,
+                    0x9b => I16x8AvgrU,
+                    0x9c => I16x8ExtmulLowI8x16S,
+                    0x9d => I16x8ExtmulHighI8x16S,
+                    0x9e => I16x8ExtmulLowI8x16U,
+                    0x9f => I16x8ExtmulHighI8x16U,
+                    0xa0 => I32x4Abs,
+                    0xa1 => I32x4Neg,
+                    0xa3 => I32x4AllTrue,
+                    0xa4 => I32x4Bitmask,
+                    0xa7 => I32x4ExtendLowI16x8S,
+                    0xa8 => I32x4ExtendHighI16x8S,
+                    0xa9 => I32x4ExtendLowI16x8U,
+                    0xaa => I32x4ExtendHighI16x8U,
+                    0xab => I32x4Shl,
+                    0xac => I32x4ShrS,
+                    0xad => I32x4ShrU,
+                    0xae => I32x4Add,
+                    0xb1 => I32x4Sub,
+                    0xb5 => I32x4Mul,
+                    0xb6 => I32x4MinS,
+                    0xb7 => I32x4MinU,
+                    0xb8 => I32x4MaxS,
+                    0xb9 => I32x4MaxU,
+                    0xba => I32x4DotI16x8S,
+                    0xbc => I32x4ExtmulLowI16x8S,
+                    0xbd => I32x4ExtmulHighI16x8S,
+                    0xbe => I32x4ExtmulLowI16x8U,
+                    0xbf => I32x4ExtmulHighI16x8U,
+                    0xc0 => I64x2Abs,
+                    0xc1 => I64x2Neg,
+                    0xc3 => I64x2AllTrue,
+                    0xc4 => I64x2Bitmask,
+                    0xc7 => I64x2ExtendLowI32x4S,
+                    0xc8 => I64x2ExtendHighI32x4S,
+                    0xc9 => I64x2ExtendLowI32x4U,
+                    0xca => I64x2ExtendHighI32x4U,
+                    0xcb => I64x2Shl,
+                    0xcc => I64x2ShrS,
+                    0xcd => I64x2ShrU,
+                    0xce => I64x2Add,
+                    0xd1 => I64x2Sub,
+                    0xd5 => I64x2Mul,
+                    0xd6 => I64x2Eq,
+                    0xd7 => I64x2Ne,
+                    0xd8 => I64x2LtS,
+                    0xd9 => I64x2GtS,
+                    0xda => I64x2LeS,
+                    0xdb => I64x2GeS,
+                    0xdc => I64x2ExtmulLowI32x4S,
+                    0xdd => I64x2ExtmulHighI32x4S,
+                    0xde => I64x2ExtmulLowI32x4U,
+                    0xdf => I64x2ExtmulHighI32x4U,
+                    0xe0 => F32x4Abs,
+                    0xe1 => F32x4Neg,
+                    0xe3 => F32x4Sqrt,
+                    0xe4 => F32x4Add,
+                    0xe5 => F32x4Sub,
+                    0xe6 => F32x4Mul,
+                    0xe7 => F32x4Div,
+                    0xe8 => F32x4Min,
+                    0xe9 => F32x4Max,
+                    0xea => F32x4Pmin,
+                    0xeb => F32x4Pmax,
+                    0xec => F64x2Abs,
+                    0xed => F64x2Neg,
+                    0xef => F64x2Sqrt,
+                    0xf0 => F64x2Add,
+                    0xf1 => F64x2Sub,
+                    0xf2 => F64x2Mul,
+                    0xf3 => F64x2Div,
+                    0xf4 => F64x2Min,
+                    0xf5 => F64x2Max,
+                    0xf6 => F64x2Pmin,
+                    0xf7 => F64x2Pmax,
+                    0xf8 => I32x4TruncSatF32x4S,
+                    0xf9 => I32x4TruncSatF32x4U,
+                    0xfa => F32x4ConvertI32x4S,
+                    0xfb => F32x4ConvertI32x4U,
+                    0xfc => I32x4TruncSatF64x2SZero,
+                    0xfd => I32x4TruncSatF64x2UZero,
+                    0xfe => F64x2ConvertLowI32x4S,
+                    0xff => F64x2ConvertLowI32x4U,
+                    code => {
+                        let kind = DecodeErrorKind::UnknownPrefixedOpcode(0xfd, code);
+                        return Err(DecodeError::new(start, kind));
+                    }
+                }
+            }
+            0xfe => {
+                match read_varuint32(buf, pos)? {
+                    0x00 => MemoryAtomicNotify { imm: MemoryImmediate::parse(buf, pos)? },
+                    0x01 => MemoryAtomicWait32 { imm: MemoryImmediate::parse(buf, pos)? },
+                    0x02 => MemoryAtomicWait64 { imm: MemoryImmediate::parse(buf, pos)? },
+                    0x03 => AtomicFence { reserved: read_varuint1(buf, pos)? == 1 },
+                    0x10 => I32AtomicLoad { imm: MemoryImmediate::parse(buf, pos)? },
+                    0x11 => I64AtomicLoad { imm: MemoryImmediate::parse(buf, pos)? },
+                    0x12 => I32AtomicLoad8U { imm: MemoryImmediate::parse(buf, pos)? },
+                    0x13 => I32AtomicLoad16U { imm: MemoryImmediate::parse(buf, pos)? },
+                    0x14 => I64AtomicLoad8U { imm: MemoryImmediate::parse(buf, pos)? },
+                    0x15 => I64AtomicLoad16U { imm: MemoryImmediate::parse(buf, pos)? },
+                    0x16 => I64AtomicLoad32U { imm: MemoryImmediate::parse(buf, pos)? },
+                    0x17 => I32AtomicStore { imm: MemoryImmediate::parse(buf, pos)? },
+                    0x18 => I64AtomicStore { imm: MemoryImmediate::parse(buf, pos)? },
+                    0x19 => I32AtomicStore8 { imm: MemoryImmediate::parse(buf, pos)? },
+                    0x1a => I32AtomicStore16 { imm: MemoryImmediate::parse(buf, pos)? },
+                    0x1b => I64AtomicStore8 { imm: MemoryImmediate::parse(buf, pos)? },
+                    0x1c => I64AtomicStore16 { imm: MemoryImmediate::parse(buf, pos)? },
+                    0x1d => I64AtomicStore32 { imm: MemoryImmediate::parse(buf, pos)? },
+                    0x1e => I32AtomicRmwAdd { imm: MemoryImmediate::parse(buf, pos)? },
+                    0x1f => I64AtomicRmwAdd { imm: MemoryImmediate::parse(buf, pos)? },
+                    0x20 => I32AtomicRmw8AddU { imm: MemoryImmediate::parse(buf, pos)? },
+                    0x21 => I32AtomicRmw16AddU { imm: MemoryImmediate::parse(buf, pos)? },
+                    0x22 => I64AtomicRmw8AddU { imm: MemoryImmediate::parse(buf, pos)? },
+                    0x23 => I64AtomicRmw16AddU { imm: MemoryImmediate::parse(buf, pos)? },
+                    0x24 => I64AtomicRmw32AddU { imm: MemoryImmediate::parse(buf, pos)? },
+                    0x25 => I32AtomicRmwSub { imm: MemoryImmediate::parse(buf, pos)? },
+                    0x26 => I64AtomicRmwSub { imm: MemoryImmediate::parse(buf, pos)? },
+                    0x27 => I32AtomicRmw8SubU { imm: MemoryImmediate::parse(buf, pos)? },
+                    0x28 => I32AtomicRmw16SubU { imm: MemoryImmediate::parse(buf, pos)? },
+                    0x29 => I64AtomicRmw8SubU { imm: MemoryImmediate::parse(buf, pos)? },
+                    0x2a => I64AtomicRmw16SubU { imm: MemoryImmediate::parse(buf, pos)? },
+                    0x2b => I64AtomicRmw32SubU { imm: MemoryImmediate::parse(buf, pos)? },
+                    0x2c => I32AtomicRmwAnd { imm: MemoryImmediate::parse(buf, pos)? },
+                    0x2d => I64AtomicRmwAnd { imm: MemoryImmediate::parse(buf, pos)? },
+                    0x2e => I32AtomicRmw8AndU { imm: MemoryImmediate::parse(buf, pos)? },
+                    0x2f => I32AtomicRmw16AndU { imm: MemoryImmediate::parse(buf, pos)? },
+                    0x30 => I64AtomicRmw8AndU { imm: MemoryImmediate::parse(buf, pos)? },
+                    0x31 => I64AtomicRmw16AndU { imm: MemoryImmediate::parse(buf, pos)? },
+                    0x32 => I64AtomicRmw32AndU { imm: MemoryImmediate::parse(buf, pos)? },
+                    0x33 => I32AtomicRmwOr { imm: MemoryImmediate::parse(buf, pos)? },
+                    0x34 => I64AtomicRmwOr { imm: MemoryImmediate::parse(buf, pos)? },
+                    0x35 => I32AtomicRmw8OrU { imm: MemoryImmediate::parse(buf, pos)? },
+                    0x36 => I32AtomicRmw16OrU { imm: MemoryImmediate::parse(buf, pos)? },
+                    0x37 => I64AtomicRmw8OrU { imm: MemoryImmediate::parse(buf, pos)? },
+                    0x38 => I64AtomicRmw16OrU { imm: MemoryImmediate::parse(buf, pos)? },
+                    0x39 => I64AtomicRmw32OrU { imm: MemoryImmediate::parse(buf, pos)? },
+                    0x3a => I32AtomicRmwXor { imm: MemoryImmediate::parse(buf, pos)? },
+                    0x3b => I64AtomicRmwXor { imm: MemoryImmediate::parse(buf, pos)? },
+                    0x3c => I32AtomicRmw8XorU { imm: MemoryImmediate::parse(buf, pos)? },
+                    0x3d => I32AtomicRmw16XorU { imm: MemoryImmediate::parse(buf, pos)? },
+                    0x3e => I64AtomicRmw8XorU { imm: MemoryImmediate::parse(buf, pos)? },
+                    0x3f => I64AtomicRmw16XorU { imm: MemoryImmediate::parse(buf, pos)? },
+                    0x40 => I64AtomicRmw32XorU { imm: MemoryImmediate::parse(buf, pos)? },
+                    0x41 => I32AtomicRmwXchg { imm: MemoryImmediate::parse(buf, pos)? },
+                    0x42 => I64AtomicRmwXchg { imm: MemoryImmediate::parse(buf, pos)? },
+                    0x43 => I32AtomicRmw8XchgU { imm: MemoryImmediate::parse(buf, pos)? },
+                    0x44 => I32AtomicRmw16XchgU { imm: MemoryImmediate::parse(buf, pos)? },
+                    0x45 => I64AtomicRmw8XchgU { imm: MemoryImmediate::parse(buf, pos)? },
+                    0x46 => I64AtomicRmw16XchgU { imm: MemoryImmediate::parse(buf, pos)? },
+                    0x47 => I64AtomicRmw32XchgU { imm: MemoryImmediate::parse(buf, pos)? },
+                    0x48 => I32AtomicRmwCmpxchg { imm: MemoryImmediate::parse(buf, pos)? },
+                    0x49 => I64AtomicRmwCmpxchg { imm: MemoryImmediate::parse(buf, pos)? },
+                    0x4a => I32AtomicRmw8CmpxchgU { imm: MemoryImmediate::parse(buf, pos)? },
+                    0x4b => I32AtomicRmw16CmpxchgU { imm: MemoryImmediate::parse(buf, pos)? },
+                    0x4c => I64AtomicRmw8CmpxchgU { imm: MemoryImmediate::parse(buf, pos)? },
+                    0x4d => I64AtomicRmw16CmpxchgU { imm: MemoryImmediate::parse(buf, pos)? },
+                    0x4e => I64AtomicRmw32CmpxchgU { imm: MemoryImmediate::parse(buf, pos)? },
+                    code => {
+                        let kind = DecodeErrorKind::UnknownPrefixedOpcode(0xfe, code);
+                        return Err(DecodeError::new(start, kind));
+                    }
+                }
+            }
+            code => return Err(DecodeError::new(start, DecodeErrorKind::UnknownOpcode(code))),
+        };
+        Ok(op)
+    }
+}
+
 impl From<i32> for Op {
     fn from(i: i32) -> Self {
         Op::I32Const(i)
@@ -475,13 +2286,24 @@ impl From<f64> for Op {
         Op::F64Const(f)
     }
 }
+impl From<u128> for Op {
+    fn from(v: u128) -> Self {
+        Op::V128Const(v)
+    }
+}
+
 
 
+/// Bit 6 of the flags of a `MemoryImmediate` marks an explicit memory index.
+const MEMORY_INDEX_FLAG: u32 = 0x40;
 
 #[derive(Debug, Clone)]
 pub struct MemoryImmediate {
     pub flags: u32,
-    pub offset: u32,
+    /// at most `u32::MAX` for a 32-bit memory
+    pub offset: u64,
+    /// only encoded if it is not 0
+    pub memory: MemoryIndex,
 }
 
 
@@ -489,17 +2311,39 @@ impl Dump for MemoryImmediate {
     fn dump(&self, buf: &mut Vec<u8>) -> usize {
         let mut size = 0;
 
-        size += write_varuint32(buf, self.flags);
-        size += write_varuint32(buf, self.offset);
+        if *self.memory == 0 {
+            size += write_varuint32(buf, self.flags);
+        } else {
+            size += write_varuint32(buf, self.flags | MEMORY_INDEX_FLAG);
+            size += write_varuint32(buf, *self.memory);
+        }
+        size += write_varuint64(buf, self.offset);
 
         size
     }
 }
 
+impl Parse for MemoryImmediate {
+    fn parse(buf: &[u8], pos: &mut usize) -> Result<Self, DecodeError> {
+        let mut flags = read_varuint32(buf, pos)?;
+        let mut memory = 0;
+        if flags & MEMORY_INDEX_FLAG != 0 {
+            flags &= !MEMORY_INDEX_FLAG;
+            memory = read_varuint32(buf, pos)?;
+        }
+        let offset = read_varuint64(buf, pos)?;
+        Ok(MemoryImmediate {
+               flags: flags,
+               offset: offset,
+               memory: MemoryIndex::new(memory),
+           })
+    }
+}
+
 
 #[derive(Debug, Clone)]
 pub struct BrTarget {
-    // TODO: use relative block index
+    /// relative depths, as in `Op::Br`
     pub table: Vec<u32>,
     pub default_target: u32,
 }
@@ -519,3 +2363,18 @@ impl Dump for BrTarget {
         size
     }
 }
+
+impl Parse for BrTarget {
+    fn parse(buf: &[u8], pos: &mut usize) -> Result<Self, DecodeError> {
+        let len = read_varuint32(buf, pos)?;
+        let mut table = Vec::new();
+        for _ in 0..len {
+            table.push(read_varuint32(buf, pos)?);
+        }
+        let default_target = read_varuint32(buf, pos)?;
+        Ok(BrTarget {
+               table: table,
+               default_target: default_target,
+           })
+    }
+}
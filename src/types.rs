@@ -1,28 +1,268 @@
 use std::ops::Deref;
 use util::*;
 use ops::Op;
-use Dump;
+use decode::*;
+use {Dump, Parse};
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum ValueType {
     I32,
     I64,
     F32,
     F64,
+    /// a 128-bit vector, read as lanes by the instructions on it
+    V128,
+    /// a reference to a function, or null
+    FuncRef,
+    /// a reference passed in by the host and opaque to the module, or null
+    ExternRef,
+    /// any other reference, typed by its heap type. The nullable `func` and
+    /// `extern` references are always `FuncRef` and `ExternRef`.
+    Ref(RefType),
+}
+
+impl ValueType {
+    pub fn is_reference(&self) -> bool {
+        matches!(*self, ValueType::FuncRef | ValueType::ExternRef | ValueType::Ref(_))
+    }
+
+    /// The value type of the references of `ty`, `FuncRef` and `ExternRef` for `funcref` and `externref`.
+    pub fn reference(ty: RefType) -> Self {
+        match ty {
+            RefType { nullable: true, heap: HeapType::Func } => ValueType::FuncRef,
+            RefType { nullable: true, heap: HeapType::Extern } => ValueType::ExternRef,
+            ty => ValueType::Ref(ty),
+        }
+    }
+
+    pub fn ref_type(&self) -> Option<RefType> {
+        match *self {
+            ValueType::FuncRef => Some(RefType::nullable(HeapType::Func)),
+            ValueType::ExternRef => Some(RefType::nullable(HeapType::Extern)),
+            ValueType::Ref(ty) => Some(ty),
+            _ => None,
+        }
+    }
+
+    /// Whether locals of the type start with a value, the zero or null. Non-nullable
+    /// references have none.
+    pub fn is_defaultable(&self) -> bool {
+        self.ref_type().is_none_or(|ty| ty.nullable)
+    }
 }
 
+impl From<RefType> for ValueType {
+    fn from(ty: RefType) -> Self {
+        ValueType::reference(ty)
+    }
+}
 
+/// The type of a reference, `ref null heap` if it is `nullable` and `ref heap` otherwise.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct RefType {
+    pub nullable: bool,
+    pub heap: HeapType,
+}
+
+impl RefType {
+    pub fn nullable(heap: HeapType) -> Self {
+        RefType {
+            nullable: true,
+            heap: heap,
+        }
+    }
+
+    pub fn non_null(heap: HeapType) -> Self {
+        RefType {
+            nullable: false,
+            heap: heap,
+        }
+    }
+}
+
+/// What a reference refers to. The abstract heap types form three hierarchies, with
+/// `func`, `extern` and `any` at the top and `nofunc`, `noextern` and `none` at the
+/// bottom. A defined type is below `func`, `struct` or `array`, by its kind.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum HeapType {
+    Func,
+    Extern,
+    Any,
+    /// the references `ref.eq` compares
+    Eq,
+    I31,
+    Struct,
+    Array,
+    None,
+    NoFunc,
+    NoExtern,
+    Type(TypeIndex),
+}
+
+impl HeapType {
+    /// The code of an abstract heap type, also the shorthand of its nullable reference type.
+    fn code(&self) -> Option<i8> {
+        let code = match *self {
+            HeapType::Func => -0x10,
+            HeapType::Extern => -0x11,
+            HeapType::Any => -0x12,
+            HeapType::Eq => -0x13,
+            HeapType::I31 => -0x14,
+            HeapType::Struct => -0x15,
+            HeapType::Array => -0x16,
+            HeapType::None => -0x0f,
+            HeapType::NoFunc => -0x0d,
+            HeapType::NoExtern => -0x0e,
+            HeapType::Type(_) => return Option::None,
+        };
+        Some(code)
+    }
+
+    fn from_code(code: i64) -> Option<Self> {
+        let heap = match code {
+            -0x10 => HeapType::Func,
+            -0x11 => HeapType::Extern,
+            -0x12 => HeapType::Any,
+            -0x13 => HeapType::Eq,
+            -0x14 => HeapType::I31,
+            -0x15 => HeapType::Struct,
+            -0x16 => HeapType::Array,
+            -0x0f => HeapType::None,
+            -0x0d => HeapType::NoFunc,
+            -0x0e => HeapType::NoExtern,
+            _ => return Option::None,
+        };
+        Some(heap)
+    }
+}
+
+
+/// The signature of a block. Blocks that take parameters or produce more
+/// than one value refer to a function type.
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
-pub struct BlockType(pub Option<ValueType>);
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum BlockType {
+    Empty,
+    Value(ValueType),
+    Type(TypeIndex),
+}
+/// The type of the references a table holds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum ElemType {
     AnyFunc,
+    ExternRef,
+}
+
+impl From<ElemType> for ValueType {
+    fn from(ty: ElemType) -> Self {
+        match ty {
+            ElemType::AnyFunc => ValueType::FuncRef,
+            ElemType::ExternRef => ValueType::ExternRef,
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct FuncType {
     pub params: Vec<ValueType>,
-    pub ret: Option<ValueType>,
+    pub results: Vec<ValueType>,
+}
+
+impl From<ElemType> for RefType {
+    fn from(ty: ElemType) -> Self {
+        RefType::nullable(ty.into())
+    }
+}
+
+impl From<ElemType> for HeapType {
+    fn from(ty: ElemType) -> Self {
+        match ty {
+            ElemType::AnyFunc => HeapType::Func,
+            ElemType::ExternRef => HeapType::Extern,
+        }
+    }
+}
+
+/// The type of a struct field or of the elements of an array. Packed fields are
+/// read and written as `i32`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum StorageType {
+    I8,
+    I16,
+    Val(ValueType),
+}
+
+impl StorageType {
+    pub fn is_packed(&self) -> bool {
+        !matches!(*self, StorageType::Val(_))
+    }
+
+    /// The type of the values read from and written to a field of the type.
+    pub fn unpacked(&self) -> ValueType {
+        match *self {
+            StorageType::I8 | StorageType::I16 => ValueType::I32,
+            StorageType::Val(ty) => ty,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FieldType {
+    pub storage: StorageType,
+    pub mutable: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum CompositeType {
+    Func(FuncType),
+    Struct(Vec<FieldType>),
+    Array(FieldType),
+}
+
+/// A type of the type section. Types declared without `sub` are final and have no supertypes.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SubType {
+    /// no type may declare a final type as its supertype
+    pub is_final: bool,
+    /// at most one, which must come earlier in the type section
+    pub supertypes: Vec<TypeIndex>,
+    pub composite: CompositeType,
+}
+
+impl SubType {
+    pub fn func_type(&self) -> Option<&FuncType> {
+        match self.composite {
+            CompositeType::Func(ref ty) => Some(ty),
+            _ => None,
+        }
+    }
+
+    /// A final type without supertypes, as declared without `sub`.
+    pub fn from_composite(composite: CompositeType) -> Self {
+        SubType {
+            is_final: true,
+            supertypes: Vec::new(),
+            composite: composite,
+        }
+    }
+}
+
+impl From<FuncType> for SubType {
+    fn from(ty: FuncType) -> Self {
+        SubType::from_composite(CompositeType::Func(ty))
+    }
+}
+
+/// The types of a recursion group may refer to each other, and to the types before
+/// the group. A type declared outside of a `rec` is a group of its own.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct RecGroup {
+    pub types: Vec<SubType>,
+}
+
+impl<T: Into<SubType>> From<T> for RecGroup {
+    fn from(ty: T) -> Self {
+        RecGroup { types: vec![ty.into()] }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
@@ -33,7 +273,8 @@ pub struct GlobalType {
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct TableType {
-    pub element: ElemType,
+    /// any nullable reference type
+    pub element: RefType,
     pub limits: ResizableLimits,
 }
 
@@ -41,13 +282,24 @@ pub struct TableType {
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct MemoryType {
     pub limits: ResizableLimits,
+    /// a memory the atomic instructions may share between threads, it needs a maximum
+    pub shared: bool,
+    /// a memory addressed with `i64` rather than `i32`, its limits may exceed 4 GiB
+    pub memory64: bool,
+}
+
+/// An exception tag. The values thrown with it are the parameters of its function type,
+/// which has no results.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct TagType {
+    pub ty: TypeIndex,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct ResizableLimits {
     pub flags: u32,
-    pub initial: u32,
-    pub maximum: Option<u32>,
+    pub initial: u64,
+    pub maximum: Option<u64>,
 }
 
 #[derive(Debug, Clone)]
@@ -65,15 +317,108 @@ impl Dump for ValueType {
             &I64 => write_varint7(buf, -0x02),
             &F32 => write_varint7(buf, -0x03),
             &F64 => write_varint7(buf, -0x04),
+            &V128 => write_varint7(buf, -0x05),
+            &FuncRef => write_varint7(buf, -0x10),
+            &ExternRef => write_varint7(buf, -0x11),
+            &Ref(ref ty) => ty.dump(buf),
+        }
+    }
+}
+
+impl Dump for RefType {
+    fn dump(&self, buf: &mut Vec<u8>) -> usize {
+        match self.heap.code() {
+            // the shorthands of the nullable abstract references
+            Some(code) if self.nullable => write_varint7(buf, code),
+            _ => write_uint8(buf, if self.nullable { 0x63 } else { 0x64 }) + self.heap.dump(buf),
+        }
+    }
+}
+
+impl Dump for HeapType {
+    fn dump(&self, buf: &mut Vec<u8>) -> usize {
+        match *self {
+            // a positive s33
+            HeapType::Type(ref index) => write_varint64(buf, **index as i64),
+            ref heap => write_varint7(buf, heap.code().expect("abstract heap type")),
+        }
+    }
+}
+
+impl Dump for StorageType {
+    fn dump(&self, buf: &mut Vec<u8>) -> usize {
+        match *self {
+            StorageType::I8 => write_varint7(buf, -0x08),
+            StorageType::I16 => write_varint7(buf, -0x09),
+            StorageType::Val(ref ty) => ty.dump(buf),
+        }
+    }
+}
+
+impl Dump for FieldType {
+    fn dump(&self, buf: &mut Vec<u8>) -> usize {
+        self.storage.dump(buf) + write_varuint1(buf, self.mutable as u8)
+    }
+}
+
+impl Dump for CompositeType {
+    fn dump(&self, buf: &mut Vec<u8>) -> usize {
+        let mut size = 0;
+        match *self {
+            CompositeType::Func(ref ty) => size += ty.dump(buf),
+            CompositeType::Struct(ref fields) => {
+                size += write_varint7(buf, -0x21);
+                size += write_varuint32(buf, fields.len() as u32);
+                for field in fields {
+                    size += field.dump(buf);
+                }
+            }
+            CompositeType::Array(ref field) => {
+                size += write_varint7(buf, -0x22);
+                size += field.dump(buf);
+            }
+        }
+        size
+    }
+}
+
+impl Dump for SubType {
+    fn dump(&self, buf: &mut Vec<u8>) -> usize {
+        let mut size = 0;
+        if !self.is_final || !self.supertypes.is_empty() {
+            size += write_varint7(buf, if self.is_final { -0x31 } else { -0x30 });
+            size += write_varuint32(buf, self.supertypes.len() as u32);
+            for index in self.supertypes.iter() {
+                size += write_varuint32(buf, **index);
+            }
+        }
+        size += self.composite.dump(buf);
+        size
+    }
+}
+
+impl Dump for RecGroup {
+    fn dump(&self, buf: &mut Vec<u8>) -> usize {
+        let mut size = 0;
+        if self.types.len() != 1 {
+            size += write_varint7(buf, -0x32);
+            size += write_varuint32(buf, self.types.len() as u32);
+        }
+        for ty in self.types.iter() {
+            size += ty.dump(buf);
         }
+        size
     }
 }
 
 impl Dump for BlockType {
     fn dump(&self, buf: &mut Vec<u8>) -> usize {
-        match &self.0 {
-            &Some(ref v) => v.dump(buf),
-            &None => write_varint7(buf, -0x40),
+        use self::BlockType::*;
+        match self {
+            &Empty => write_varint7(buf, -0x40),
+            &Value(ref v) => v.dump(buf),
+            // a positive s33
+            &Type(ref index) => write_varint64(buf, **index as i64),
         }
     }
 }
@@ -83,13 +428,14 @@ impl Dump for ElemType {
         use self::ElemType::*;
         match self {
             &AnyFunc => write_varint7(buf, -0x10),
+            &ExternRef => write_varint7(buf, -0x11),
         }
     }
 }
 impl Dump for FuncType {
     fn dump(&self, buf: &mut Vec<u8>) -> usize {
         let params = &self.params;
-        let ret = &self.ret;
+        let results = &self.results;
 
         let mut size = 0;
         size += write_varint7(buf, -0x20);
@@ -99,9 +445,9 @@ impl Dump for FuncType {
             size += param.dump(buf);
         }
 
-        size += write_varuint1(buf, ret.is_some() as u8);
-        for ret in ret {
-            size += ret.dump(buf);
+        size += write_varuint32(buf, results.len() as u32);
+        for result in results {
+            size += result.dump(buf);
         }
         size
     }
@@ -127,13 +473,36 @@ impl Dump for TableType {
 
 impl Dump for MemoryType {
     fn dump(&self, buf: &mut Vec<u8>) -> usize {
-        self.limits.dump(buf)
+        let mut limits = self.limits.clone();
+        limits.flags |= (self.shared as u32) << 1;
+        limits.flags |= (self.memory64 as u32) << 2;
+        limits.dump(buf)
     }
 }
 
+impl Dump for TagType {
+    fn dump(&self, buf: &mut Vec<u8>) -> usize {
+        let mut size = 0;
+        // the attribute of exceptions, the only kind of tag
+        size += write_uint8(buf, 0);
+        size += write_varuint32(buf, *self.ty);
+        size
+    }
+}
+
+impl MemoryType {
+    /// The type of addresses into the memory, `i64` for a 64-bit memory and `i32` otherwise.
+    pub fn index_type(&self) -> ValueType {
+        if self.memory64 {
+            ValueType::I64
+        } else {
+            ValueType::I32
+        }
+    }
+}
 
 impl ResizableLimits {
-    pub fn new(limit: u32) -> Self {
+    pub fn new(limit: u64) -> Self {
         ResizableLimits {
             flags: 0,
             initial: limit,
@@ -141,12 +510,13 @@ impl ResizableLimits {
         }
     }
 
-    pub fn max(mut self, maximum: u32) -> Self {
+    pub fn max(mut self, maximum: u64) -> Self {
         self.maximum = Some(maximum);
         self
     }
 
-    /// unstable
+    /// Sets the flags other than the maximum bit, which follows `maximum`, and the shared
+    /// and 64-bit bits, which follow `MemoryType::shared` and `MemoryType::memory64`.
     pub fn flags(mut self, flags: u32) -> Self {
         self.flags = flags;
         self
@@ -158,9 +528,10 @@ impl Dump for ResizableLimits {
         let mut size = 0;
         let flags = self.flags | (self.maximum.is_some() as u32);
         size += write_varuint32(buf, flags);
-        size += write_varuint32(buf, self.initial);
+        // the same bytes as a varuint32 for the limits of 32-bit memories
+        size += write_varuint64(buf, self.initial);
         if let Some(m) = self.maximum {
-            size += write_varuint32(buf, m);
+            size += write_varuint64(buf, m);
         }
         size
     }
@@ -177,12 +548,284 @@ impl Dump for Code {
 }
 
 
+impl InitExpr {
+    pub fn resolve_functions(&mut self, nimports: u32) {
+        for op in (self.0).0.iter_mut() {
+            op.resolve_functions(nimports)
+        }
+    }
+
+    pub fn classify_functions(&mut self, nimports: u32) {
+        for op in (self.0).0.iter_mut() {
+            op.classify_functions(nimports)
+        }
+    }
+}
+
+impl From<Code> for InitExpr {
+    fn from(code: Code) -> Self {
+        InitExpr(code)
+    }
+}
+
 impl Dump for InitExpr {
     fn dump(&self, buf: &mut Vec<u8>) -> usize {
         self.0.dump(buf)
     }
 }
 
+impl Parse for ValueType {
+    fn parse(buf: &[u8], pos: &mut usize) -> Result<Self, DecodeError> {
+        use self::ValueType::*;
+        let start = *pos;
+        match read_varint7(buf, pos)? {
+            -0x01 => Ok(I32),
+            -0x02 => Ok(I64),
+            -0x03 => Ok(F32),
+            -0x04 => Ok(F64),
+            -0x05 => Ok(V128),
+            -0x10 => Ok(FuncRef),
+            -0x11 => Ok(ExternRef),
+            code @ -0x1d..=-0x1c => {
+                let heap = HeapType::parse(buf, pos)?;
+                Ok(ValueType::reference(RefType {
+                                            nullable: code == -0x1d,
+                                            heap: heap,
+                                        }))
+            }
+            code => {
+                match HeapType::from_code(code.into()) {
+                    Some(heap) => Ok(Ref(RefType::nullable(heap))),
+                    None => Err(DecodeError::new(start, DecodeErrorKind::InvalidValueType)),
+                }
+            }
+        }
+    }
+}
+
+impl Parse for HeapType {
+    fn parse(buf: &[u8], pos: &mut usize) -> Result<Self, DecodeError> {
+        let start = *pos;
+        match read_varint64(buf, pos)? {
+            index @ 0..=0xffff_ffff => Ok(HeapType::Type(TypeIndex(index as u32))),
+            code => HeapType::from_code(code).ok_or_else(|| DecodeError::new(start, DecodeErrorKind::InvalidHeapType)),
+        }
+    }
+}
+
+impl Parse for StorageType {
+    fn parse(buf: &[u8], pos: &mut usize) -> Result<Self, DecodeError> {
+        let start = *pos;
+        match read_varint7(buf, pos)? {
+            -0x08 => Ok(StorageType::I8),
+            -0x09 => Ok(StorageType::I16),
+            _ => {
+                *pos = start;
+                Ok(StorageType::Val(ValueType::parse(buf, pos)?))
+            }
+        }
+    }
+}
+
+impl Parse for FieldType {
+    fn parse(buf: &[u8], pos: &mut usize) -> Result<Self, DecodeError> {
+        let storage = StorageType::parse(buf, pos)?;
+        let mutable = read_varuint1(buf, pos)? == 1;
+        Ok(FieldType {
+               storage: storage,
+               mutable: mutable,
+           })
+    }
+}
+
+impl Parse for CompositeType {
+    fn parse(buf: &[u8], pos: &mut usize) -> Result<Self, DecodeError> {
+        let start = *pos;
+        match read_varint7(buf, pos)? {
+            -0x20 => {
+                *pos = start;
+                Ok(CompositeType::Func(FuncType::parse(buf, pos)?))
+            }
+            -0x21 => Ok(CompositeType::Struct(parse_vec(buf, pos)?)),
+            -0x22 => Ok(CompositeType::Array(FieldType::parse(buf, pos)?)),
+            _ => Err(DecodeError::new(start, DecodeErrorKind::InvalidFuncTypeForm)),
+        }
+    }
+}
+
+impl Parse for SubType {
+    fn parse(buf: &[u8], pos: &mut usize) -> Result<Self, DecodeError> {
+        let start = *pos;
+        let is_final = match read_varint7(buf, pos)? {
+            -0x30 => false,
+            -0x31 => true,
+            _ => {
+                *pos = start;
+                return Ok(SubType::from_composite(CompositeType::parse(buf, pos)?));
+            }
+        };
+        let len = read_varuint32(buf, pos)?;
+        let mut supertypes = Vec::new();
+        for _ in 0..len {
+            supertypes.push(TypeIndex(read_varuint32(buf, pos)?));
+        }
+        Ok(SubType {
+               is_final: is_final,
+               supertypes: supertypes,
+               composite: CompositeType::parse(buf, pos)?,
+           })
+    }
+}
+
+impl Parse for RecGroup {
+    fn parse(buf: &[u8], pos: &mut usize) -> Result<Self, DecodeError> {
+        let start = *pos;
+        if read_varint7(buf, pos)? == -0x32 {
+            return Ok(RecGroup { types: parse_vec(buf, pos)? });
+        }
+        *pos = start;
+        Ok(RecGroup { types: vec![SubType::parse(buf, pos)?] })
+    }
+}
+
+impl Parse for BlockType {
+    fn parse(buf: &[u8], pos: &mut usize) -> Result<Self, DecodeError> {
+        let start = *pos;
+        let byte = read_uint8(buf, pos)?;
+        *pos = start;
+        // negative single bytes are the empty type and value types, the
+        // rest is a type index encoded as an s33
+        if byte & 0xc0 == 0x40 {
+            if byte == 0x40 {
+                *pos += 1;
+                return Ok(BlockType::Empty);
+            }
+            return Ok(BlockType::Value(ValueType::parse(buf, pos)?));
+        }
+        match read_varint64(buf, pos)? {
+            index @ 0..=0xffff_ffff => Ok(BlockType::Type(TypeIndex(index as u32))),
+            _ => Err(DecodeError::new(start, DecodeErrorKind::InvalidLeb128)),
+        }
+    }
+}
+
+impl Parse for ElemType {
+    fn parse(buf: &[u8], pos: &mut usize) -> Result<Self, DecodeError> {
+        let start = *pos;
+        match read_varint7(buf, pos)? {
+            -0x10 => Ok(ElemType::AnyFunc),
+            -0x11 => Ok(ElemType::ExternRef),
+            _ => Err(DecodeError::new(start, DecodeErrorKind::InvalidElemType)),
+        }
+    }
+}
+
+impl Parse for FuncType {
+    fn parse(buf: &[u8], pos: &mut usize) -> Result<Self, DecodeError> {
+        let start = *pos;
+        if read_varint7(buf, pos)? != -0x20 {
+            return Err(DecodeError::new(start, DecodeErrorKind::InvalidFuncTypeForm));
+        }
+        let params = parse_vec(buf, pos)?;
+        let results = parse_vec(buf, pos)?;
+        Ok(FuncType {
+               params: params,
+               results: results,
+           })
+    }
+}
+
+impl Parse for GlobalType {
+    fn parse(buf: &[u8], pos: &mut usize) -> Result<Self, DecodeError> {
+        let content = ValueType::parse(buf, pos)?;
+        let mutable = read_varuint1(buf, pos)? == 1;
+        Ok(GlobalType {
+               content: content,
+               mutable: mutable,
+           })
+    }
+}
+
+impl Parse for TableType {
+    fn parse(buf: &[u8], pos: &mut usize) -> Result<Self, DecodeError> {
+        let start = *pos;
+        let element = ValueType::parse(buf, pos)?
+            .ref_type()
+            .ok_or_else(|| DecodeError::new(start, DecodeErrorKind::InvalidElemType))?;
+        let limits = ResizableLimits::parse(buf, pos)?;
+        Ok(TableType {
+               element: element,
+               limits: limits,
+           })
+    }
+}
+
+impl Parse for MemoryType {
+    fn parse(buf: &[u8], pos: &mut usize) -> Result<Self, DecodeError> {
+        let mut limits = ResizableLimits::parse(buf, pos)?;
+        let shared = limits.flags & 2 == 2;
+        let memory64 = limits.flags & 4 == 4;
+        limits.flags &= !6;
+        Ok(MemoryType {
+               limits: limits,
+               shared: shared,
+               memory64: memory64,
+           })
+    }
+}
+
+impl Parse for TagType {
+    fn parse(buf: &[u8], pos: &mut usize) -> Result<Self, DecodeError> {
+        let start = *pos;
+        let attribute = read_uint8(buf, pos)?;
+        if attribute != 0 {
+            return Err(DecodeError::new(start, DecodeErrorKind::InvalidTagAttribute(attribute)));
+        }
+        Ok(TagType { ty: TypeIndex(read_varuint32(buf, pos)?) })
+    }
+}
+
+impl Parse for ResizableLimits {
+    fn parse(buf: &[u8], pos: &mut usize) -> Result<Self, DecodeError> {
+        let flags = read_varuint32(buf, pos)?;
+        // 64-bit limits are only allowed with the 64-bit flag
+        let read = |pos: &mut usize| if flags & 4 == 4 {
+            read_varuint64(buf, pos)
+        } else {
+            read_varuint32(buf, pos).map(u64::from)
+        };
+        let initial = read(pos)?;
+        let maximum = if flags & 1 == 1 {
+            Some(read(pos)?)
+        } else {
+            None
+        };
+        Ok(ResizableLimits {
+               flags: flags & !1,
+               initial: initial,
+               maximum: maximum,
+           })
+    }
+}
+
+impl Parse for InitExpr {
+    /// Reads instructions up to and including the terminating `End`.
+    fn parse(buf: &[u8], pos: &mut usize) -> Result<Self, DecodeError> {
+        let mut code = Vec::new();
+        loop {
+            if *pos == buf.len() {
+                return Err(DecodeError::new(*pos, DecodeErrorKind::MissingEnd));
+            }
+            let op = Op::parse(buf, pos)?;
+            let is_end = matches!(op, Op::End);
+            code.push(op);
+            if is_end {
+                return Ok(InitExpr(Code(code)));
+            }
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct TypeIndex(u32);
 impl Deref for TypeIndex {
@@ -227,14 +870,26 @@ impl Deref for FunctionSpaceIndex {
         use self::InnerFunctionSpaceIndex::*;
         match self.0 {
             Import(ref i) => &i.0,
-            Function(ref f) => &**f,
+            Function(ref f) => f,
         }
     }
 }
 
-impl Into<FunctionSpaceIndex> for FunctionIndex {
-    fn into(self) -> FunctionSpaceIndex {
-        FunctionSpaceIndex(InnerFunctionSpaceIndex::Function(self))
+impl From<FunctionIndex> for FunctionSpaceIndex {
+    fn from(f: FunctionIndex) -> Self {
+        FunctionSpaceIndex(InnerFunctionSpaceIndex::Function(f))
+    }
+}
+
+impl FunctionSpaceIndex {
+    /// Turns an index that points into the first `nimports` functions into an import index.
+    pub fn classify(&mut self, nimports: u32) {
+        use self::InnerFunctionSpaceIndex::*;
+        if let Function(f) = self.0 {
+            if f.0 < nimports {
+                self.0 = Import(ImportedFunctionIndex(f.0));
+            }
+        }
     }
 }
 
@@ -264,6 +919,14 @@ impl Deref for GlobalIndex {
     }
 }
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct TagIndex(u32);
+impl Deref for TagIndex {
+    type Target = u32;
+    fn deref(&self) -> &u32 {
+        &self.0
+    }
+}
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct ExportIndex(u32);
 impl Deref for ExportIndex {
     type Target = u32;
@@ -296,6 +959,15 @@ impl Deref for DataIndex {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct CustomSectionIndex(u32);
+impl Deref for CustomSectionIndex {
+    type Target = u32;
+    fn deref(&self) -> &u32 {
+        &self.0
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct LocalIndex(u32);
 impl Deref for LocalIndex {
@@ -324,10 +996,12 @@ pub mod internal {
     impl_new!(TableIndex);
     impl_new!(MemoryIndex);
     impl_new!(GlobalIndex);
+    impl_new!(TagIndex);
     impl_new!(ExportIndex);
     impl_new!(ElementIndex);
     impl_new!(CodeIndex);
     impl_new!(DataIndex);
+    impl_new!(CustomSectionIndex);
 
     impl_new!(LocalIndex);
 }
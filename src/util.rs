@@ -1,3 +1,5 @@
+use decode::{DecodeError, DecodeErrorKind};
+
 #[inline]
 pub fn write_uint8(buf: &mut Vec<u8>, u: u8) -> usize {
     buf.push(u);
@@ -28,6 +30,14 @@ pub fn write_uint64(buf: &mut Vec<u8>, u: u64) -> usize {
     size
 }
 
+#[inline]
+pub fn write_uint128(buf: &mut Vec<u8>, u: u128) -> usize {
+    let mut size = 0;
+    size += write_uint64(buf, u as u64);
+    size += write_uint64(buf, (u >> 64) as u64);
+    size
+}
+
 #[inline]
 pub fn write_varuint1(buf: &mut Vec<u8>, u: u8) -> usize {
     write_uint8(buf, u)
@@ -45,38 +55,326 @@ pub fn write_varint7(buf: &mut Vec<u8>, i: i8) -> usize {
 }
 
 macro_rules! gen_write_var {
-    ($name: ident, $ty: ty) => {
+    (unsigned, $name: ident, $ty: ty) => {
         #[inline]
-        #[allow(unused_comparisons)]
-        #[allow(overflowing_literals)]
         pub fn $name(buf: &mut Vec<u8>, mut u: $ty) -> usize {
-            let end: i8 = if u < 0 { 0xff } else { 0 };
-
             let mut size = 0;
-            let bit7 = 0b01111111;
-            let mut cur: u8 = (u & bit7) as u8;
-            // rust generates sar for i32 by >>
-            u >>= 7;
-            while u != (end as $ty) {
-                size += write_uint8(buf, cur | 0x80);
-                cur = (u & bit7) as u8;
+            loop {
+                let cur = (u & 0x7f) as u8;
                 u >>= 7;
+                if u == 0 {
+                    size += write_uint8(buf, cur);
+                    return size;
+                }
+                size += write_uint8(buf, cur | 0x80);
             }
-            size += write_uint8(buf, cur);
-            size
         }
+    };
 
-    }
+    (signed, $name: ident, $ty: ty) => {
+        #[inline]
+        pub fn $name(buf: &mut Vec<u8>, mut i: $ty) -> usize {
+            let mut size = 0;
+            loop {
+                let cur = (i & 0x7f) as u8;
+                // arithmetic shift, so that negative values end in -1
+                i >>= 7;
+                // done once the rest is the sign extension of the 7th bit
+                if (i == 0 && cur & 0x40 == 0) || (i == -1 && cur & 0x40 != 0) {
+                    size += write_uint8(buf, cur);
+                    return size;
+                }
+                size += write_uint8(buf, cur | 0x80);
+            }
+        }
+    };
 }
 
-gen_write_var!(write_varuint32, u32);
-gen_write_var!(write_varint32, i32);
-gen_write_var!(write_varint64, i64);
+gen_write_var!(unsigned, write_varuint32, u32);
+gen_write_var!(unsigned, write_varuint64, u64);
+gen_write_var!(signed, write_varint32, i32);
+gen_write_var!(signed, write_varint64, i64);
 
 
 
+/// An opcode from one of the prefixed spaces: the prefix byte and a varuint32.
+#[inline]
+pub fn write_prefixed(buf: &mut Vec<u8>, prefix: u8, code: u32) -> usize {
+    write_uint8(buf, prefix) + write_varuint32(buf, code)
+}
+
 #[inline]
 pub fn write_slice(buf: &mut Vec<u8>, u: &[u8]) -> usize {
     buf.extend_from_slice(u);
     u.len()
 }
+
+#[inline]
+pub fn read_uint8(buf: &[u8], pos: &mut usize) -> Result<u8, DecodeError> {
+    match buf.get(*pos) {
+        Some(&u) => {
+            *pos += 1;
+            Ok(u)
+        }
+        None => Err(DecodeError::new(*pos, DecodeErrorKind::UnexpectedEof)),
+    }
+}
+
+#[inline]
+pub fn read_uint16(buf: &[u8], pos: &mut usize) -> Result<u16, DecodeError> {
+    let lo = read_uint8(buf, pos)? as u16;
+    let hi = read_uint8(buf, pos)? as u16;
+    Ok(lo | (hi << 8))
+}
+
+#[inline]
+pub fn read_uint32(buf: &[u8], pos: &mut usize) -> Result<u32, DecodeError> {
+    let lo = read_uint16(buf, pos)? as u32;
+    let hi = read_uint16(buf, pos)? as u32;
+    Ok(lo | (hi << 16))
+}
+
+#[inline]
+pub fn read_uint64(buf: &[u8], pos: &mut usize) -> Result<u64, DecodeError> {
+    let lo = read_uint32(buf, pos)? as u64;
+    let hi = read_uint32(buf, pos)? as u64;
+    Ok(lo | (hi << 32))
+}
+
+#[inline]
+pub fn read_uint128(buf: &[u8], pos: &mut usize) -> Result<u128, DecodeError> {
+    let lo = read_uint64(buf, pos)? as u128;
+    let hi = read_uint64(buf, pos)? as u128;
+    Ok(lo | (hi << 64))
+}
+
+#[inline]
+pub fn read_varuint1(buf: &[u8], pos: &mut usize) -> Result<u8, DecodeError> {
+    let start = *pos;
+    match read_uint8(buf, pos)? {
+        u @ 0..=1 => Ok(u),
+        _ => Err(DecodeError::new(start, DecodeErrorKind::InvalidLeb128)),
+    }
+}
+
+#[allow(dead_code)]
+#[inline]
+pub fn read_varuint7(buf: &[u8], pos: &mut usize) -> Result<u8, DecodeError> {
+    let start = *pos;
+    match read_uint8(buf, pos)? {
+        u @ 0..=0x7f => Ok(u),
+        _ => Err(DecodeError::new(start, DecodeErrorKind::InvalidLeb128)),
+    }
+}
+
+#[inline]
+pub fn read_varint7(buf: &[u8], pos: &mut usize) -> Result<i8, DecodeError> {
+    let start = *pos;
+    match read_uint8(buf, pos)? {
+        // sign extend from the 7th bit
+        u @ 0..=0x7f => Ok(((u << 1) as i8) >> 1),
+        _ => Err(DecodeError::new(start, DecodeErrorKind::InvalidLeb128)),
+    }
+}
+
+macro_rules! gen_read_var {
+    (unsigned, $name: ident, $ty: ty, $bits: expr) => {
+        pub fn $name(buf: &[u8], pos: &mut usize) -> Result<$ty, DecodeError> {
+            let start = *pos;
+            let mut result: $ty = 0;
+            let mut shift = 0;
+            loop {
+                let byte = read_uint8(buf, pos)?;
+                // the last byte may only carry the bits left over
+                if shift + 7 > $bits && (byte & 0x80 != 0 || (byte & 0x7f) >> ($bits - shift) != 0) {
+                    return Err(DecodeError::new(start, DecodeErrorKind::InvalidLeb128));
+                }
+                result |= ((byte & 0x7f) as $ty) << shift;
+                if byte & 0x80 == 0 {
+                    return Ok(result);
+                }
+                shift += 7;
+            }
+        }
+    };
+
+    (signed, $name: ident, $ty: ty, $bits: expr) => {
+        pub fn $name(buf: &[u8], pos: &mut usize) -> Result<$ty, DecodeError> {
+            let start = *pos;
+            let mut result: $ty = 0;
+            let mut shift = 0;
+            loop {
+                let byte = read_uint8(buf, pos)?;
+                // the bits of the last byte beyond the value must be a sign extension
+                if shift + 7 > $bits {
+                    let unused = (byte & 0x7f) >> ($bits - shift - 1);
+                    if byte & 0x80 != 0 || (unused != 0 && unused != 0x7f >> ($bits - shift - 1)) {
+                        return Err(DecodeError::new(start, DecodeErrorKind::InvalidLeb128));
+                    }
+                }
+                result |= ((byte & 0x7f) as $ty) << shift;
+                shift += 7;
+                if byte & 0x80 == 0 {
+                    if shift < $bits && byte & 0x40 != 0 {
+                        result |= !0 << shift;
+                    }
+                    return Ok(result);
+                }
+            }
+        }
+    };
+}
+
+gen_read_var!(unsigned, read_varuint32, u32, 32);
+gen_read_var!(unsigned, read_varuint64, u64, 64);
+gen_read_var!(signed, read_varint32, i32, 32);
+gen_read_var!(signed, read_varint64, i64, 64);
+
+#[inline]
+pub fn read_slice<'a>(buf: &'a [u8], pos: &mut usize, len: usize) -> Result<&'a [u8], DecodeError> {
+    if buf.len() - *pos < len {
+        return Err(DecodeError::new(buf.len(), DecodeErrorKind::UnexpectedEof));
+    }
+    let slice = &buf[*pos..*pos + len];
+    *pos += len;
+    Ok(slice)
+}
+
+#[inline]
+pub fn read_string(buf: &[u8], pos: &mut usize) -> Result<String, DecodeError> {
+    let len = read_varuint32(buf, pos)? as usize;
+    let start = *pos;
+    let bytes = read_slice(buf, pos, len)?;
+    String::from_utf8(bytes.to_vec()).map_err(|_| DecodeError::new(start, DecodeErrorKind::InvalidUtf8))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Values around every power of two, and their negations.
+    fn boundaries(bits: u32) -> Vec<i64> {
+        let mut vs = vec![0, 1, -1, i64::MIN, i64::MAX];
+        for k in 0..bits {
+            let p = 1i64.wrapping_shl(k);
+            for &v in &[p.wrapping_sub(1), p, p.wrapping_add(1)] {
+                vs.push(v);
+                vs.push(v.wrapping_neg());
+            }
+        }
+        vs
+    }
+
+    /// A deterministic sweep of values with every bit length.
+    fn sweep() -> Vec<i64> {
+        let mut x = 0x2545_f491_4f6c_dd1du64;
+        (0..10_000)
+            .map(|n| {
+                     x ^= x << 13;
+                     x ^= x >> 7;
+                     x ^= x << 17;
+                     (x >> (n % 64)) as i64
+                 })
+            .collect()
+    }
+
+    /// The number of bytes the shortest encoding of `bits` significant bits takes.
+    fn len_of(bits: u32) -> usize {
+        (bits.max(1) as usize).div_ceil(7)
+    }
+
+    #[test]
+    fn varuint32_round_trip() {
+        for v in boundaries(32).into_iter().chain(sweep()) {
+            let u = v as u32;
+            let mut buf = Vec::new();
+            let size = write_varuint32(&mut buf, u);
+            assert_eq!(size, buf.len());
+            assert_eq!(size, len_of(32 - u.leading_zeros()), "{}", u);
+            let mut pos = 0;
+            assert_eq!(read_varuint32(&buf, &mut pos).unwrap(), u);
+            assert_eq!(pos, buf.len());
+        }
+    }
+
+    #[test]
+    fn varuint64_round_trip() {
+        for v in boundaries(64).into_iter().chain(sweep()) {
+            let u = v as u64;
+            let mut buf = Vec::new();
+            let size = write_varuint64(&mut buf, u);
+            assert_eq!(size, buf.len());
+            assert_eq!(size, len_of(64 - u.leading_zeros()), "{}", u);
+            let mut pos = 0;
+            assert_eq!(read_varuint64(&buf, &mut pos).unwrap(), u);
+            assert_eq!(pos, buf.len());
+        }
+    }
+
+    #[test]
+    fn varint32_round_trip() {
+        for v in boundaries(32).into_iter().chain(sweep()) {
+            let i = v as i32;
+            let mut buf = Vec::new();
+            let size = write_varint32(&mut buf, i);
+            assert_eq!(size, buf.len());
+            let significant = 33 - if i < 0 { i.leading_ones() } else { i.leading_zeros() };
+            assert_eq!(size, len_of(significant), "{}", i);
+            let mut pos = 0;
+            assert_eq!(read_varint32(&buf, &mut pos).unwrap(), i);
+            assert_eq!(pos, buf.len());
+        }
+    }
+
+    #[test]
+    fn varint64_round_trip() {
+        for i in boundaries(64).into_iter().chain(sweep()) {
+            let mut buf = Vec::new();
+            let size = write_varint64(&mut buf, i);
+            assert_eq!(size, buf.len());
+            let significant = 65 - if i < 0 { i.leading_ones() } else { i.leading_zeros() };
+            assert_eq!(size, len_of(significant), "{}", i);
+            let mut pos = 0;
+            assert_eq!(read_varint64(&buf, &mut pos).unwrap(), i);
+            assert_eq!(pos, buf.len());
+        }
+    }
+
+    #[test]
+    fn sign_bit_of_last_byte() {
+        let cases: &[(i64, &[u8])] = &[(63, &[0x3f]),
+                                       (64, &[0xc0, 0x00]),
+                                       (-64, &[0x40]),
+                                       (-65, &[0xbf, 0x7f]),
+                                       (8191, &[0xff, 0x3f]),
+                                       (8192, &[0x80, 0xc0, 0x00])];
+        for &(i, bytes) in cases {
+            let mut buf = Vec::new();
+            write_varint32(&mut buf, i as i32);
+            assert_eq!(buf, bytes, "{}", i);
+            buf.clear();
+            write_varint64(&mut buf, i);
+            assert_eq!(buf, bytes, "{}", i);
+        }
+    }
+
+    #[test]
+    fn malformed() {
+        let cases: &[&[u8]] = &[&[0x80],
+                                &[0xff, 0xff, 0xff, 0xff, 0x1f],
+                                &[0x80, 0x80, 0x80, 0x80, 0x80, 0x00]];
+        for &bytes in cases {
+            assert!(read_varuint32(bytes, &mut 0).is_err(), "{:?}", bytes);
+        }
+        assert!(read_varuint64(&[0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x02], &mut 0).is_err());
+        assert_eq!(read_varuint64(&[0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01], &mut 0).unwrap(),
+                   u64::MAX);
+        // the unused bits of the last byte must extend the sign
+        assert!(read_varint32(&[0xff, 0xff, 0xff, 0xff, 0x4f], &mut 0).is_err());
+        assert!(read_varint32(&[0x80, 0x80, 0x80, 0x80, 0x70], &mut 0).is_err());
+        assert_eq!(read_varint32(&[0x80, 0x80, 0x80, 0x80, 0x78], &mut 0).unwrap(), i32::MIN);
+        assert!(read_varint64(&[0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01], &mut 0).is_err());
+        assert_eq!(read_varint64(&[0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x7f], &mut 0).unwrap(),
+                   -1);
+    }
+}
@@ -0,0 +1,2692 @@
+use std::collections::{HashMap, HashSet};
+use std::error;
+use std::fmt;
+
+use module::*;
+use ops::{MemoryImmediate, Op};
+use types::*;
+
+/// Where in the module a `ValidationError` was found. Indices are in the
+/// index spaces of the module, imports included.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Location {
+    Module,
+    Type(u32),
+    Import(u32),
+    /// `position` is the index of the instruction in `FunctionBody::code`,
+    /// `code.len()` meaning the implicit `end` of the body.
+    Function { index: u32, position: usize },
+    Table(u32),
+    Memory(u32),
+    Tag(u32),
+    Global(u32),
+    Export(u32),
+    Start,
+    Element(u32),
+    Data(u32),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ValidationErrorKind {
+    UnknownType(u32),
+    UnknownFunction(u32),
+    UnknownTable(u32),
+    UnknownMemory(u32),
+    UnknownGlobal(u32),
+    UnknownTag(u32),
+    UnknownLocal(u32),
+    UnknownLabel(u32),
+    UnknownElement(u32),
+    UnknownData(u32),
+    /// a field index past the fields of the struct type
+    UnknownField(u32),
+    /// an instruction on functions, structs or arrays refers to a type of another kind
+    ExpectedFuncType(u32),
+    ExpectedStructType(u32),
+    ExpectedArrayType(u32),
+    /// the supertype is not an earlier type, is final or does not match the type
+    InvalidSupertype(u32),
+    FunctionCodeMismatch { functions: usize, codes: usize },
+    /// the data count section does not match the data section
+    DataCountMismatch { count: u32, segments: usize },
+    /// `memory.init` and `data.drop` need a data count section
+    MissingDataCount,
+    InvalidLimits,
+    DuplicateExport(String),
+    InvalidStartFunction,
+    ImmutableGlobal(u32),
+    NonZeroReserved,
+    InvalidAlignment,
+    /// an offset of a memory access past 4 GiB into a 32-bit memory
+    OffsetOutOfRange(u64),
+    /// the alignment of an atomic instruction differs from its natural alignment
+    UnnaturalAtomicAlignment,
+    /// a shared memory must declare its maximum size
+    SharedMemoryWithoutMaximum,
+    /// the function type of a tag has results
+    TagWithResults,
+    /// a lane index past the lanes of the vector shape
+    InvalidLaneIndex(u8),
+    /// a write to an immutable struct field or array
+    ImmutableField,
+    /// `struct.get` or `array.get` on packed fields, or their `_s` and `_u` variants on others
+    PackedFieldAccess,
+    /// `array.new_data` and `array.init_data` on an array of references
+    NonNumericArray(u32),
+    /// a field or array element of the type has no default value
+    NonDefaultableType(ValueType),
+    /// `local.get` of a non-nullable reference before it is set
+    UninitializedLocal(u32),
+    /// `br_on_non_null` or a cast branch to a label whose last value is not a reference
+    LabelWithoutReference(u32),
+    /// only constant instructions are allowed in an `InitExpr`
+    NonConstantExpr,
+    /// the value on the stack does not have the expected type
+    TypeMismatch {
+        expected: ValueType,
+        found: ValueType,
+    },
+    /// `ref.is_null` on a value that is not a reference
+    ExpectedReference(ValueType),
+    /// `select` without a type on references
+    NonNumericSelect,
+    /// `ref.func` in code on a function that no element segment, export
+    /// or global refers to
+    UndeclaredFunctionReference(u32),
+    /// the instruction needs more operands than the stack has
+    StackUnderflow,
+    /// a tail call to a function whose results differ from those of the caller
+    ReturnCallResultMismatch,
+    /// the targets of a `br_table` take different numbers of values
+    BrTableArityMismatch,
+    /// values are left on the stack at the end of a block
+    ValuesRemaining,
+    /// an `if` with a result needs an `else`
+    MissingElse,
+    ElseWithoutIf,
+    /// `catch`, `catch_all` or `delegate` outside of a `try` block, or after its `catch_all`
+    CatchWithoutTry,
+    /// `rethrow` to a label other than that of a `catch` or `catch_all`
+    InvalidRethrowLabel(u32),
+    /// a block or an init expr is not terminated by `end`
+    MissingEnd,
+    /// instructions follow the `end` that closes the function or the init expr
+    TrailingCode,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValidationError {
+    pub location: Location,
+    pub kind: ValidationErrorKind,
+}
+
+impl ValidationError {
+    pub fn new(location: Location, kind: ValidationErrorKind) -> Self {
+        ValidationError {
+            location: location,
+            kind: kind,
+        }
+    }
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::Location::*;
+        match *self {
+            Module => write!(f, "module"),
+            Type(i) => write!(f, "type {}", i),
+            Import(i) => write!(f, "import {}", i),
+            Function { index, position } => write!(f, "function {} at instruction {}", index, position),
+            Table(i) => write!(f, "table {}", i),
+            Memory(i) => write!(f, "memory {}", i),
+            Tag(i) => write!(f, "tag {}", i),
+            Global(i) => write!(f, "global {}", i),
+            Export(i) => write!(f, "export {}", i),
+            Start => write!(f, "start function"),
+            Element(i) => write!(f, "element segment {}", i),
+            Data(i) => write!(f, "data segment {}", i),
+        }
+    }
+}
+
+impl fmt::Display for ValidationErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::ValidationErrorKind::*;
+        match *self {
+            UnknownType(i) => write!(f, "unknown type {}", i),
+            UnknownFunction(i) => write!(f, "unknown function {}", i),
+            UnknownTable(i) => write!(f, "unknown table {}", i),
+            UnknownMemory(i) => write!(f, "unknown memory {}", i),
+            UnknownGlobal(i) => write!(f, "unknown global {}", i),
+            UnknownTag(i) => write!(f, "unknown tag {}", i),
+            UnknownLocal(i) => write!(f, "unknown local {}", i),
+            UnknownLabel(i) => write!(f, "unknown label {}", i),
+            UnknownElement(i) => write!(f, "unknown element segment {}", i),
+            UnknownData(i) => write!(f, "unknown data segment {}", i),
+            UnknownField(i) => write!(f, "unknown field {}", i),
+            ExpectedFuncType(i) => write!(f, "type {} is not a function type", i),
+            ExpectedStructType(i) => write!(f, "type {} is not a struct type", i),
+            ExpectedArrayType(i) => write!(f, "type {} is not an array type", i),
+            InvalidSupertype(i) => write!(f, "type {} is not a valid supertype", i),
+            FunctionCodeMismatch { functions, codes } => {
+                write!(f,
+                       "{} functions are declared but {} bodies are given",
+                       functions,
+                       codes)
+            }
+            DataCountMismatch { count, segments } => {
+                write!(f,
+                       "the data count is {} but {} data segments are given",
+                       count,
+                       segments)
+            }
+            MissingDataCount => write!(f, "data segments are used without a data count section"),
+            InvalidLimits => write!(f, "invalid limits"),
+            DuplicateExport(ref name) => write!(f, "duplicate export name {:?}", name),
+            InvalidStartFunction => write!(f, "start function must not take or return values"),
+            ImmutableGlobal(i) => write!(f, "global {} is immutable", i),
+            NonZeroReserved => write!(f, "reserved immediate must be zero"),
+            InvalidAlignment => write!(f, "alignment must not be larger than natural"),
+            OffsetOutOfRange(offset) => write!(f, "offset {} is out of range for a 32-bit memory", offset),
+            UnnaturalAtomicAlignment => write!(f, "alignment of an atomic instruction must be natural"),
+            SharedMemoryWithoutMaximum => write!(f, "shared memory must have a maximum"),
+            TagWithResults => write!(f, "tag type must not have results"),
+            InvalidLaneIndex(i) => write!(f, "lane index {} is out of range", i),
+            ImmutableField => write!(f, "field is immutable"),
+            PackedFieldAccess => write!(f, "packed fields are read with the `_s` and `_u` instructions only"),
+            NonNumericArray(i) => write!(f, "array type {} does not hold numbers", i),
+            NonDefaultableType(ty) => write!(f, "{:?} has no default value", ty),
+            UninitializedLocal(i) => write!(f, "local {} is read before it is set", i),
+            LabelWithoutReference(i) => write!(f, "label {} does not take a reference", i),
+            NonConstantExpr => write!(f, "constant expression required"),
+            TypeMismatch { expected, found } => {
+                write!(f, "type mismatch: expected {:?}, found {:?}", expected, found)
+            }
+            ExpectedReference(found) => write!(f, "expected a reference, found {:?}", found),
+            NonNumericSelect => write!(f, "`select` without a type needs numeric operands"),
+            UndeclaredFunctionReference(i) => write!(f, "undeclared function reference {}", i),
+            StackUnderflow => write!(f, "not enough operands on the stack"),
+            ReturnCallResultMismatch => write!(f, "tail call results differ from the function results"),
+            BrTableArityMismatch => write!(f, "`br_table` targets have different arities"),
+            ValuesRemaining => write!(f, "values remaining on the stack at the end of a block"),
+            MissingElse => write!(f, "`if` with a result requires an `else`"),
+            ElseWithoutIf => write!(f, "`else` without a matching `if`"),
+            CatchWithoutTry => write!(f, "`catch` or `delegate` without a matching `try`"),
+            InvalidRethrowLabel(i) => write!(f, "`rethrow` label {} is not a `catch` block", i),
+            MissingEnd => write!(f, "missing `end`"),
+            TrailingCode => write!(f, "instructions after the final `end`"),
+        }
+    }
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} in {}", self.kind, self.location)
+    }
+}
+
+impl error::Error for ValidationError {}
+
+const I32: ValueType = ValueType::I32;
+const I64: ValueType = ValueType::I64;
+const F32: ValueType = ValueType::F32;
+const F64: ValueType = ValueType::F64;
+const V128: ValueType = ValueType::V128;
+
+/// The operand and result types of instructions whose typing doesn't depend on the module.
+pub fn simple_signature(op: &Op) -> Option<(&'static [ValueType], &'static [ValueType])> {
+    use ops::Op::*;
+    let sig: (&'static [ValueType], &'static [ValueType]) = match *op {
+        Nop => (&[], &[]),
+        I32Const(_) => (&[], &[I32]),
+        I64Const(_) => (&[], &[I64]),
+        F32Const(_) => (&[], &[F32]),
+        F64Const(_) => (&[], &[F64]),
+
+        I32Eqz => (&[I32], &[I32]),
+        I32Eq | I32NE | I32LtS | I32LtU | I32GtS | I32GtU | I32LeS | I32LeU | I32GeS |
+        I32GeU => (&[I32, I32], &[I32]),
+        I64Eqz => (&[I64], &[I32]),
+        I64Eq | I64Ne | I64LtS | I64LtU | I64GtS | I64GtU | I64LeS | I64LeU | I64GeS |
+        I64GeU => (&[I64, I64], &[I32]),
+        F32Eq | F32Ne | F32Lt | F32Gt | F32Le | F32Ge => (&[F32, F32], &[I32]),
+        F64Eq | F64Ne | F64Lt | F64Gt | F64Le | F64Ge => (&[F64, F64], &[I32]),
+
+        I32Clz | I32Ctz | I32Popcnt => (&[I32], &[I32]),
+        I32Add | I32Sub | I32Mul | I32DivS | I32DivU | I32RemS | I32RemU | I32And | I32Or |
+        I32Xor | I32Shl | I32ShrS | I32ShrU | I32Rotl | I32Rotr => (&[I32, I32], &[I32]),
+        I64Clz | I64Ctz | I64Popcnt => (&[I64], &[I64]),
+        I64Add | I64Sub | I64Mul | I64DivS | I64DivU | I64RemS | I64RemU | I64And | I64Or |
+        I64Xor | I64Shl | I64ShrS | I64ShrU | I64Rotl | I64Rotr => (&[I64, I64], &[I64]),
+        F32Abs | F32Neg | F32Ceil | F32Floor | F32Trunc | F32Nearest | F32Sqrt => (&[F32], &[F32]),
+        F32Add | F32Sub | F32Mul | F32Div | F32Min | F32Max | F32Copysign => (&[F32, F32], &[F32]),
+        F64Abs | F64Neg | F64Ceil | F64Floor | F64Trunc | F64Nearest | F64Sqrt => (&[F64], &[F64]),
+        F64Add | F64Sub | F64Mul | F64Div | F64Min | F64Max | F64Copysign => (&[F64, F64], &[F64]),
+
+        I32wrapI64 => (&[I64], &[I32]),
+        I32TruncSF32 | I32TruncUF32 => (&[F32], &[I32]),
+        I32TruncSF64 | I32TruncUF64 => (&[F64], &[I32]),
+        I64ExtendSI32 | I64ExtendUI32 => (&[I32], &[I64]),
+        I64TruncSF32 | I64TruncUF32 => (&[F32], &[I64]),
+        I64TruncSF64 | I64TruncUF64 => (&[F64], &[I64]),
+        F32ConvertSI32 | F32ConvertUI32 => (&[I32], &[F32]),
+        F32ConvertSI64 | F32ConvertUI64 => (&[I64], &[F32]),
+        F32DemoteF64 => (&[F64], &[F32]),
+        F64ConvertSI32 | F64ConvertUI32 => (&[I32], &[F64]),
+        F64ConvertSI64 | F64ConvertUI64 => (&[I64], &[F64]),
+        F64PromoteF32 => (&[F32], &[F64]),
+        I32ReinterpretF32 => (&[F32], &[I32]),
+        I64ReinterpretF64 => (&[F64], &[I64]),
+        F32ReinterpretI32 => (&[I32], &[F32]),
+        F64ReinterpretI64 => (&[I64], &[F64]),
+        I32Extend8S | I32Extend16S => (&[I32], &[I32]),
+        I64Extend8S | I64Extend16S | I64Extend32S => (&[I64], &[I64]),
+        I32TruncSatSF32 | I32TruncSatUF32 => (&[F32], &[I32]),
+        I32TruncSatSF64 | I32TruncSatUF64 => (&[F64], &[I32]),
+        I64TruncSatSF32 | I64TruncSatUF32 => (&[F32], &[I64]),
+        I64TruncSatSF64 | I64TruncSatUF64 => (&[F64], &[I64]),
+        I8x16Shuffle(_) | I8x16Swizzle | I8x16Eq | I8x16Ne | I8x16LtS | I8x16LtU | I8x16GtS |
+        I8x16GtU | I8x16LeS | I8x16LeU | I8x16GeS | I8x16GeU | I16x8Eq | I16x8Ne | I16x8LtS |
+        I16x8LtU | I16x8GtS | I16x8GtU | I16x8LeS | I16x8LeU | I16x8GeS | I16x8GeU | I32x4Eq |
+        I32x4Ne | I32x4LtS | I32x4LtU | I32x4GtS | I32x4GtU | I32x4LeS | I32x4LeU | I32x4GeS |
+        I32x4GeU | F32x4Eq | F32x4Ne | F32x4Lt | F32x4Gt | F32x4Le | F32x4Ge | F64x2Eq | F64x2Ne |
+        F64x2Lt | F64x2Gt | F64x2Le | F64x2Ge | V128And | V128Andnot | V128Or | V128Xor |
+        I8x16NarrowI16x8S | I8x16NarrowI16x8U | I8x16Add | I8x16AddSatS | I8x16AddSatU | I8x16Sub |
+        I8x16SubSatS | I8x16SubSatU | I8x16MinS | I8x16MinU | I8x16MaxS | I8x16MaxU | I8x16AvgrU |
+        I16x8Q15mulrSatS | I16x8NarrowI32x4S | I16x8NarrowI32x4U | I16x8Add | I16x8AddSatS |
+        I16x8AddSatU | I16x8Sub | I16x8SubSatS | I16x8SubSatU | I16x8Mul | I16x8MinS | I16x8MinU |
+        I16x8MaxS | I16x8MaxU | I16x8AvgrU | I16x8ExtmulLowI8x16S | I16x8ExtmulHighI8x16S |
+        I16x8ExtmulLowI8x16U | I16x8ExtmulHighI8x16U | I32x4Add | I32x4Sub | I32x4Mul | I32x4MinS |
+        I32x4MinU | I32x4MaxS | I32x4MaxU | I32x4DotI16x8S | I32x4ExtmulLowI16x8S |
+        I32x4ExtmulHighI16x8S | I32x4ExtmulLowI16x8U | I32x4ExtmulHighI16x8U | I64x2Add |
+        I64x2Sub | I64x2Mul | I64x2Eq | I64x2Ne | I64x2LtS | I64x2GtS | I64x2LeS | I64x2GeS |
+        I64x2ExtmulLowI32x4S | I64x2ExtmulHighI32x4S | I64x2ExtmulLowI32x4U |
+        I64x2ExtmulHighI32x4U | F32x4Add | F32x4Sub | F32x4Mul | F32x4Div | F32x4Min | F32x4Max |
+        F32x4Pmin | F32x4Pmax | F64x2Add | F64x2Sub | F64x2Mul | F64x2Div | F64x2Min | F64x2Max |
+        F64x2Pmin | F64x2Pmax => (&[V128, V128], &[V128]),
+        I8x16Splat | I16x8Splat | I32x4Splat => (&[I32], &[V128]),
+        I64x2Splat => (&[I64], &[V128]),
+        F32x4Splat => (&[F32], &[V128]),
+        F64x2Splat => (&[F64], &[V128]),
+        V128Not | F32x4DemoteF64x2Zero | F64x2PromoteLowF32x4 | I8x16Abs | I8x16Neg | I8x16Popcnt |
+        F32x4Ceil | F32x4Floor | F32x4Trunc | F32x4Nearest | F64x2Ceil | F64x2Floor | F64x2Trunc |
+        I16x8ExtaddPairwiseI8x16S | I16x8ExtaddPairwiseI8x16U | I32x4ExtaddPairwiseI16x8S |
+        I32x4ExtaddPairwiseI16x8U | I16x8Abs | I16x8Neg | I16x8ExtendLowI8x16S |
+        I16x8ExtendHighI8x16S | I16x8ExtendLowI8x16U | I16x8ExtendHighI8x16U | F64x2Nearest |
+        I32x4Abs | I32x4Neg | I32x4ExtendLowI16x8S | I32x4ExtendHighI16x8S | I32x4ExtendLowI16x8U |
+        I32x4ExtendHighI16x8U | I64x2Abs | I64x2Neg | I64x2ExtendLowI32x4S |
+        I64x2ExtendHighI32x4S | I64x2ExtendLowI32x4U | I64x2ExtendHighI32x4U | F32x4Abs |
+        F32x4Neg | F32x4Sqrt | F64x2Abs | F64x2Neg | F64x2Sqrt | I32x4TruncSatF32x4S |
+        I32x4TruncSatF32x4U | F32x4ConvertI32x4S | F32x4ConvertI32x4U | I32x4TruncSatF64x2SZero |
+        I32x4TruncSatF64x2UZero | F64x2ConvertLowI32x4S | F64x2ConvertLowI32x4U =>
+            (&[V128], &[V128]),
+        V128Bitselect => (&[V128, V128, V128], &[V128]),
+        V128AnyTrue | I8x16AllTrue | I8x16Bitmask | I16x8AllTrue | I16x8Bitmask | I32x4AllTrue |
+        I32x4Bitmask | I64x2AllTrue | I64x2Bitmask | I8x16ExtractLaneS(_) | I8x16ExtractLaneU(_) |
+        I16x8ExtractLaneS(_) | I16x8ExtractLaneU(_) | I32x4ExtractLane(_) => (&[V128], &[I32]),
+        I8x16Shl | I8x16ShrS | I8x16ShrU | I16x8Shl | I16x8ShrS | I16x8ShrU | I32x4Shl |
+        I32x4ShrS | I32x4ShrU | I64x2Shl | I64x2ShrS | I64x2ShrU | I8x16ReplaceLane(_) |
+        I16x8ReplaceLane(_) | I32x4ReplaceLane(_) => (&[V128, I32], &[V128]),
+        I64x2ExtractLane(_) => (&[V128], &[I64]),
+        I64x2ReplaceLane(_) => (&[V128, I64], &[V128]),
+        F32x4ExtractLane(_) => (&[V128], &[F32]),
+        F32x4ReplaceLane(_) => (&[V128, F32], &[V128]),
+        F64x2ExtractLane(_) => (&[V128], &[F64]),
+        F64x2ReplaceLane(_) => (&[V128, F64], &[V128]),
+        V128Const(_) => (&[], &[V128]),
+        _ => return None,
+    };
+    Some(sig)
+}
+
+/// The value type and the natural alignment (as log2 of bytes) of a load or a store.
+/// The boolean is true for stores.
+pub fn memory_access(op: &Op) -> Option<(ValueType, u32, bool)> {
+    use ops::Op::*;
+    let access = match *op {
+        I32Load { .. } => (I32, 2, false),
+        I64Load { .. } => (I64, 3, false),
+        F32Load { .. } => (F32, 2, false),
+        F64Load { .. } => (F64, 3, false),
+        I32Load8S { .. } | I32Load8U { .. } => (I32, 0, false),
+        I32Load16S { .. } | I32Load16U { .. } => (I32, 1, false),
+        I64Load8S { .. } | I64Load8U { .. } => (I64, 0, false),
+        I64Load16S { .. } | I64Load16U { .. } => (I64, 1, false),
+        I64load32S { .. } | I64load32U { .. } => (I64, 2, false),
+        I32Store { .. } => (I32, 2, true),
+        I64Store { .. } => (I64, 3, true),
+        F32Store { .. } => (F32, 2, true),
+        F64Store { .. } => (F64, 3, true),
+        I32Store8 { .. } => (I32, 0, true),
+        I32Store16 { .. } => (I32, 1, true),
+        I64Store8 { .. } => (I64, 0, true),
+        I64Store16 { .. } => (I64, 1, true),
+        I64Store32 { .. } => (I64, 2, true),
+        V128Load { .. } => (V128, 4, false),
+        V128Load8x8S { .. } => (V128, 3, false),
+        V128Load8x8U { .. } => (V128, 3, false),
+        V128Load16x4S { .. } => (V128, 3, false),
+        V128Load16x4U { .. } => (V128, 3, false),
+        V128Load32x2S { .. } => (V128, 3, false),
+        V128Load32x2U { .. } => (V128, 3, false),
+        V128Load8Splat { .. } => (V128, 0, false),
+        V128Load16Splat { .. } => (V128, 1, false),
+        V128Load32Splat { .. } => (V128, 2, false),
+        V128Load64Splat { .. } => (V128, 3, false),
+        V128Store { .. } => (V128, 4, true),
+        V128Load32Zero { .. } => (V128, 2, false),
+        V128Load64Zero { .. } => (V128, 3, false),
+        _ => return None,
+    };
+    Some(access)
+}
+
+/// The natural alignment of the vector instructions loading or storing a single lane.
+/// The boolean is true for stores.
+pub fn lane_access(op: &Op) -> Option<(u32, bool)> {
+    use ops::Op::*;
+    let access = match *op {
+        V128Load8Lane { .. } => (0, false),
+        V128Load16Lane { .. } => (1, false),
+        V128Load32Lane { .. } => (2, false),
+        V128Load64Lane { .. } => (3, false),
+        V128Store8Lane { .. } => (0, true),
+        V128Store16Lane { .. } => (1, true),
+        V128Store32Lane { .. } => (2, true),
+        V128Store64Lane { .. } => (3, true),
+        _ => return None,
+    };
+    Some(access)
+}
+
+/// The natural alignment (as log2 of bytes) of an atomic memory instruction, which its
+/// alignment must equal, and its signature. The address comes first, as the `i32` of a
+/// 32-bit memory.
+pub fn atomic_access(op: &Op) -> Option<(u32, &'static [ValueType], &'static [ValueType])> {
+    use ops::Op::*;
+    let access: (u32, &'static [ValueType], &'static [ValueType]) = match *op {
+        MemoryAtomicNotify { .. } |
+        I32AtomicRmwAdd { .. } |
+        I32AtomicRmwSub { .. } |
+        I32AtomicRmwAnd { .. } |
+        I32AtomicRmwOr { .. } |
+        I32AtomicRmwXor { .. } |
+        I32AtomicRmwXchg { .. } => (2, &[I32, I32], &[I32]),
+        MemoryAtomicWait32 { .. } => (2, &[I32, I32, I64], &[I32]),
+        MemoryAtomicWait64 { .. } => (3, &[I32, I64, I64], &[I32]),
+        I32AtomicLoad { .. } => (2, &[I32], &[I32]),
+        I64AtomicLoad { .. } => (3, &[I32], &[I64]),
+        I32AtomicLoad8U { .. } => (0, &[I32], &[I32]),
+        I32AtomicLoad16U { .. } => (1, &[I32], &[I32]),
+        I64AtomicLoad8U { .. } => (0, &[I32], &[I64]),
+        I64AtomicLoad16U { .. } => (1, &[I32], &[I64]),
+        I64AtomicLoad32U { .. } => (2, &[I32], &[I64]),
+        I32AtomicStore { .. } => (2, &[I32, I32], &[]),
+        I64AtomicStore { .. } => (3, &[I32, I64], &[]),
+        I32AtomicStore8 { .. } => (0, &[I32, I32], &[]),
+        I32AtomicStore16 { .. } => (1, &[I32, I32], &[]),
+        I64AtomicStore8 { .. } => (0, &[I32, I64], &[]),
+        I64AtomicStore16 { .. } => (1, &[I32, I64], &[]),
+        I64AtomicStore32 { .. } => (2, &[I32, I64], &[]),
+        I64AtomicRmwAdd { .. } |
+        I64AtomicRmwSub { .. } |
+        I64AtomicRmwAnd { .. } |
+        I64AtomicRmwOr { .. } |
+        I64AtomicRmwXor { .. } |
+        I64AtomicRmwXchg { .. } => (3, &[I32, I64], &[I64]),
+        I32AtomicRmw8AddU { .. } |
+        I32AtomicRmw8SubU { .. } |
+        I32AtomicRmw8AndU { .. } |
+        I32AtomicRmw8OrU { .. } |
+        I32AtomicRmw8XorU { .. } |
+        I32AtomicRmw8XchgU { .. } => (0, &[I32, I32], &[I32]),
+        I32AtomicRmw16AddU { .. } |
+        I32AtomicRmw16SubU { .. } |
+        I32AtomicRmw16AndU { .. } |
+        I32AtomicRmw16OrU { .. } |
+        I32AtomicRmw16XorU { .. } |
+        I32AtomicRmw16XchgU { .. } => (1, &[I32, I32], &[I32]),
+        I64AtomicRmw8AddU { .. } |
+        I64AtomicRmw8SubU { .. } |
+        I64AtomicRmw8AndU { .. } |
+        I64AtomicRmw8OrU { .. } |
+        I64AtomicRmw8XorU { .. } |
+        I64AtomicRmw8XchgU { .. } => (0, &[I32, I64], &[I64]),
+        I64AtomicRmw16AddU { .. } |
+        I64AtomicRmw16SubU { .. } |
+        I64AtomicRmw16AndU { .. } |
+        I64AtomicRmw16OrU { .. } |
+        I64AtomicRmw16XorU { .. } |
+        I64AtomicRmw16XchgU { .. } => (1, &[I32, I64], &[I64]),
+        I64AtomicRmw32AddU { .. } |
+        I64AtomicRmw32SubU { .. } |
+        I64AtomicRmw32AndU { .. } |
+        I64AtomicRmw32OrU { .. } |
+        I64AtomicRmw32XorU { .. } |
+        I64AtomicRmw32XchgU { .. } => (2, &[I32, I64], &[I64]),
+        I32AtomicRmwCmpxchg { .. } => (2, &[I32, I32, I32], &[I32]),
+        I64AtomicRmwCmpxchg { .. } => (3, &[I32, I64, I64], &[I64]),
+        I32AtomicRmw8CmpxchgU { .. } => (0, &[I32, I32, I32], &[I32]),
+        I32AtomicRmw16CmpxchgU { .. } => (1, &[I32, I32, I32], &[I32]),
+        I64AtomicRmw8CmpxchgU { .. } => (0, &[I32, I64, I64], &[I64]),
+        I64AtomicRmw16CmpxchgU { .. } => (1, &[I32, I64, I64], &[I64]),
+        I64AtomicRmw32CmpxchgU { .. } => (2, &[I32, I64, I64], &[I64]),
+        _ => return None,
+    };
+    Some(access)
+}
+
+/// The lane index of a vector instruction and the number of lanes of its shape.
+pub fn lane_immediate(op: &Op) -> Option<(u8, u8)> {
+    use ops::Op::*;
+    let lane = match *op {
+        V128Load8Lane { lane, .. } => (lane, 16),
+        V128Load16Lane { lane, .. } => (lane, 8),
+        V128Load32Lane { lane, .. } => (lane, 4),
+        V128Load64Lane { lane, .. } => (lane, 2),
+        V128Store8Lane { lane, .. } => (lane, 16),
+        V128Store16Lane { lane, .. } => (lane, 8),
+        V128Store32Lane { lane, .. } => (lane, 4),
+        V128Store64Lane { lane, .. } => (lane, 2),
+        I8x16ExtractLaneS(lane) => (lane, 16),
+        I8x16ExtractLaneU(lane) => (lane, 16),
+        I8x16ReplaceLane(lane) => (lane, 16),
+        I16x8ExtractLaneS(lane) => (lane, 8),
+        I16x8ExtractLaneU(lane) => (lane, 8),
+        I16x8ReplaceLane(lane) => (lane, 8),
+        I32x4ExtractLane(lane) => (lane, 4),
+        I32x4ReplaceLane(lane) => (lane, 4),
+        I64x2ExtractLane(lane) => (lane, 2),
+        I64x2ReplaceLane(lane) => (lane, 2),
+        F32x4ExtractLane(lane) => (lane, 4),
+        F32x4ReplaceLane(lane) => (lane, 4),
+        F64x2ExtractLane(lane) => (lane, 2),
+        F64x2ReplaceLane(lane) => (lane, 2),
+        _ => return None,
+    };
+    Some(lane)
+}
+
+/// The subtyping between the types of a module. Types defined alike in recursion groups
+/// defined alike are the same type, and types are below the types they declare as supertypes.
+#[derive(Debug, Clone, Default)]
+pub struct Subtyping {
+    /// the first of the types each type is the same as
+    canonical: Vec<u32>,
+    supertypes: Vec<Option<u32>>,
+    /// the abstract heap type right above each type, `func`, `struct` or `array`
+    kinds: Vec<HeapType>,
+}
+
+impl Subtyping {
+    pub fn new(groups: &[RecGroup]) -> Self {
+        let mut subtyping = Subtyping::default();
+        let mut groups_seen = HashMap::new();
+        for group in groups {
+            let start = subtyping.canonical.len() as u32;
+            // a group is the same as another if it is once the types before it are
+            // replaced by their canonical ones and those in it are counted from its start
+            let mut key = group.clone();
+            for ty in key.types.iter_mut() {
+                map_type_indices(ty,
+                                 &mut |index| if index < start {
+                                          subtyping.canonical[index as usize]
+                                      } else {
+                                          u32::MAX - (index - start)
+                                      });
+            }
+            let first = *groups_seen.entry(key).or_insert(start);
+            for (i, ty) in group.types.iter().enumerate() {
+                subtyping.canonical.push(first + i as u32);
+                subtyping.supertypes.push(ty.supertypes.first().map(|s| **s));
+                subtyping.kinds.push(match ty.composite {
+                                         CompositeType::Func(_) => HeapType::Func,
+                                         CompositeType::Struct(_) => HeapType::Struct,
+                                         CompositeType::Array(_) => HeapType::Array,
+                                     });
+            }
+        }
+        subtyping
+    }
+
+    /// The top of the hierarchy of `heap`, `func`, `extern` or `any`. `None` for an unknown type.
+    pub fn top(&self, heap: HeapType) -> Option<HeapType> {
+        match heap {
+            HeapType::Func | HeapType::NoFunc => Some(HeapType::Func),
+            HeapType::Extern | HeapType::NoExtern => Some(HeapType::Extern),
+            HeapType::Type(index) => self.kinds.get(*index as usize).and_then(|&k| self.top(k)),
+            _ => Some(HeapType::Any),
+        }
+    }
+
+    pub fn is_type_subtype(&self, sub: u32, sup: u32) -> bool {
+        let canonical = match self.canonical.get(sup as usize) {
+            Some(&c) => c,
+            None => return false,
+        };
+        let mut sub = sub;
+        while let Some(&c) = self.canonical.get(sub as usize) {
+            if c == canonical {
+                return true;
+            }
+            match self.supertypes[sub as usize] {
+                // checked to come earlier in valid modules
+                Some(s) if s < sub => sub = s,
+                _ => return false,
+            }
+        }
+        false
+    }
+
+    pub fn is_heap_subtype(&self, sub: HeapType, sup: HeapType) -> bool {
+        match (sub, sup) {
+            (HeapType::Type(a), HeapType::Type(b)) => self.is_type_subtype(*a, *b),
+            (HeapType::Type(a), sup) => {
+                self.kinds.get(*a as usize).is_some_and(|&kind| self.is_heap_subtype(kind, sup))
+            }
+            // the bottom types
+            (HeapType::None, sup) => self.top(sup) == Some(HeapType::Any),
+            (HeapType::NoFunc, sup) => self.top(sup) == Some(HeapType::Func),
+            (HeapType::NoExtern, sup) => self.top(sup) == Some(HeapType::Extern),
+            (sub, sup) if sub == sup => true,
+            (HeapType::I31, HeapType::Eq) |
+            (HeapType::Struct, HeapType::Eq) |
+            (HeapType::Array, HeapType::Eq) => true,
+            (HeapType::I31, HeapType::Any) |
+            (HeapType::Struct, HeapType::Any) |
+            (HeapType::Array, HeapType::Any) |
+            (HeapType::Eq, HeapType::Any) => true,
+            _ => false,
+        }
+    }
+
+    pub fn is_ref_subtype(&self, sub: RefType, sup: RefType) -> bool {
+        (sup.nullable || !sub.nullable) && self.is_heap_subtype(sub.heap, sup.heap)
+    }
+
+    /// Whether values of `sub` may be used where `sup` is expected.
+    pub fn is_subtype(&self, sub: ValueType, sup: ValueType) -> bool {
+        match (sub.ref_type(), sup.ref_type()) {
+            (Some(a), Some(b)) => self.is_ref_subtype(a, b),
+            _ => sub == sup,
+        }
+    }
+
+    fn is_storage_subtype(&self, sub: StorageType, sup: StorageType) -> bool {
+        match (sub, sup) {
+            (StorageType::Val(a), StorageType::Val(b)) => self.is_subtype(a, b),
+            (a, b) => a == b,
+        }
+    }
+
+    /// Mutable fields are read and written, so their types must be the same.
+    fn is_field_subtype(&self, sub: &FieldType, sup: &FieldType) -> bool {
+        sub.mutable == sup.mutable && self.is_storage_subtype(sub.storage, sup.storage) &&
+        (!sub.mutable || self.is_storage_subtype(sup.storage, sub.storage))
+    }
+
+    fn is_composite_subtype(&self, sub: &CompositeType, sup: &CompositeType) -> bool {
+        match (sub, sup) {
+            (&CompositeType::Func(ref a), &CompositeType::Func(ref b)) => {
+                a.params.len() == b.params.len() && a.results.len() == b.results.len() &&
+                a.params.iter().zip(b.params.iter()).all(|(a, b)| self.is_subtype(*b, *a)) &&
+                a.results.iter().zip(b.results.iter()).all(|(a, b)| self.is_subtype(*a, *b))
+            }
+            (&CompositeType::Struct(ref a), &CompositeType::Struct(ref b)) => {
+                a.len() >= b.len() && a.iter().zip(b.iter()).all(|(a, b)| self.is_field_subtype(a, b))
+            }
+            (&CompositeType::Array(ref a), &CompositeType::Array(ref b)) => self.is_field_subtype(a, b),
+            _ => false,
+        }
+    }
+}
+
+/// Applies `f` to the type indices `ty` refers to.
+fn map_type_indices<F: FnMut(u32) -> u32>(ty: &mut SubType, f: &mut F) {
+    fn value_type<F: FnMut(u32) -> u32>(ty: &mut ValueType, f: &mut F) {
+        if let ValueType::Ref(RefType { heap: HeapType::Type(ref mut index), .. }) = *ty {
+            *index = TypeIndex::new(f(**index));
+        }
+    }
+    fn field<F: FnMut(u32) -> u32>(field: &mut FieldType, f: &mut F) {
+        if let StorageType::Val(ref mut ty) = field.storage {
+            value_type(ty, f);
+        }
+    }
+    for index in ty.supertypes.iter_mut() {
+        *index = TypeIndex::new(f(**index));
+    }
+    match ty.composite {
+        CompositeType::Func(ref mut func) => {
+            for ty in func.params.iter_mut().chain(func.results.iter_mut()) {
+                value_type(ty, f);
+            }
+        }
+        CompositeType::Struct(ref mut fields) => {
+            for ty in fields.iter_mut() {
+                field(ty, f);
+            }
+        }
+        CompositeType::Array(ref mut ty) => field(ty, f),
+    }
+}
+
+/// The type indices `ty` refers to.
+fn type_indices(ty: &SubType) -> Vec<u32> {
+    let mut indices = Vec::new();
+    map_type_indices(&mut ty.clone(),
+                     &mut |index| {
+                              indices.push(index);
+                              index
+                          });
+    indices
+}
+
+/// The index spaces of a module, imports first.
+pub struct Context<'a> {
+    /// the types of all recursion groups
+    pub types: Vec<&'a SubType>,
+    pub subtyping: Subtyping,
+    pub functions: Vec<&'a FuncType>,
+    /// the type index of each function
+    pub function_types: Vec<u32>,
+    pub tables: Vec<&'a TableType>,
+    pub memories: Vec<&'a MemoryType>,
+    /// the function types of the tags
+    pub tags: Vec<&'a FuncType>,
+    pub globals: Vec<&'a GlobalType>,
+    pub nimported_globals: usize,
+    pub elements: Vec<&'a ElemSegment>,
+    pub data_count: Option<u32>,
+    /// the functions `ref.func` may refer to in code
+    pub references: HashSet<u32>,
+}
+
+impl<'a> Context<'a> {
+    /// Collects the index spaces. Fails if a function refers to an unknown type.
+    pub fn new(module: &'a Module) -> Result<Self, ValidationError> {
+        let groups = module.types.as_ref().map_or(&[][..], |t| &t[..]);
+        let mut ctx = Context {
+            types: module.sub_types().collect(),
+            subtyping: Subtyping::new(groups),
+            functions: Vec::new(),
+            function_types: Vec::new(),
+            tables: Vec::new(),
+            memories: Vec::new(),
+            tags: Vec::new(),
+            globals: Vec::new(),
+            nimported_globals: 0,
+            elements: module.elements.iter().flat_map(|e| e.iter()).collect(),
+            data_count: module.data_count,
+            references: HashSet::new(),
+        };
+        for e in module.elements.iter().flat_map(|e| e.iter()) {
+            ctx.references.extend(e.elems.functions().iter().map(|f| **f));
+        }
+        for e in module.exports.iter().flat_map(|e| e.iter()) {
+            if let ExportKind::Function(ref f) = e.kind {
+                ctx.references.insert(**f);
+            }
+        }
+        for g in module.globals.iter().flat_map(|g| g.iter()) {
+            for op in (g.init.0).0.iter() {
+                if let Op::RefFunc(ref f) = *op {
+                    ctx.references.insert(**f);
+                }
+            }
+        }
+        for (i, import) in module.imports.iter().flat_map(|i| i.iter()).enumerate() {
+            match import.kind {
+                ImportKind::Function(ref t) => {
+                    let ty = ctx.func_type(**t)
+                        .map_err(|kind| ValidationError::new(Location::Import(i as u32), kind))?;
+                    ctx.functions.push(ty);
+                    ctx.function_types.push(**t);
+                }
+                ImportKind::Table(ref t) => ctx.tables.push(t),
+                ImportKind::Memory(ref m) => ctx.memories.push(m),
+                ImportKind::Global(ref g) => ctx.globals.push(g),
+                ImportKind::Tag(ref t) => {
+                    let ty = ctx.func_type(*t.ty)
+                        .map_err(|kind| ValidationError::new(Location::Import(i as u32), kind))?;
+                    ctx.tags.push(ty);
+                }
+            }
+        }
+        ctx.nimported_globals = ctx.globals.len();
+        let nimported_functions = ctx.functions.len();
+        for (i, f) in module.functions.iter().flat_map(|f| f.iter()).enumerate() {
+            let ty = ctx.func_type(*f.0)
+                .map_err(|kind| {
+                             let location = Location::Function {
+                                 index: (nimported_functions + i) as u32,
+                                 position: 0,
+                             };
+                             ValidationError::new(location, kind)
+                         })?;
+            ctx.functions.push(ty);
+            ctx.function_types.push(*f.0);
+        }
+        ctx.tables.extend(module.tables.iter().flat_map(|t| t.iter()));
+        ctx.memories.extend(module.memories.iter().flat_map(|m| m.iter()));
+        let nimported_tags = ctx.tags.len();
+        for (i, t) in module.tags.iter().flat_map(|t| t.iter()).enumerate() {
+            let ty = ctx.func_type(*t.ty)
+                .map_err(|kind| ValidationError::new(Location::Tag((nimported_tags + i) as u32), kind))?;
+            ctx.tags.push(ty);
+        }
+        ctx.globals.extend(module.globals.iter().flat_map(|g| g.iter()).map(|g| &g.ty));
+        Ok(ctx)
+    }
+
+    pub fn sub_type(&self, index: u32) -> Result<&'a SubType, ValidationErrorKind> {
+        self.types.get(index as usize).cloned().ok_or(ValidationErrorKind::UnknownType(index))
+    }
+
+    pub fn func_type(&self, index: u32) -> Result<&'a FuncType, ValidationErrorKind> {
+        self.sub_type(index)?.func_type().ok_or(ValidationErrorKind::ExpectedFuncType(index))
+    }
+
+    pub fn struct_type(&self, index: u32) -> Result<&'a [FieldType], ValidationErrorKind> {
+        match self.sub_type(index)?.composite {
+            CompositeType::Struct(ref fields) => Ok(fields),
+            _ => Err(ValidationErrorKind::ExpectedStructType(index)),
+        }
+    }
+
+    pub fn array_type(&self, index: u32) -> Result<&'a FieldType, ValidationErrorKind> {
+        match self.sub_type(index)?.composite {
+            CompositeType::Array(ref field) => Ok(field),
+            _ => Err(ValidationErrorKind::ExpectedArrayType(index)),
+        }
+    }
+
+    pub fn field(&self, index: u32, field: u32) -> Result<&'a FieldType, ValidationErrorKind> {
+        self.struct_type(index)?.get(field as usize).ok_or(ValidationErrorKind::UnknownField(field))
+    }
+
+    /// Fails if `ty` refers to an unknown type.
+    pub fn check_value_type(&self, ty: ValueType) -> Result<(), ValidationErrorKind> {
+        match ty.ref_type() {
+            Some(ty) => self.check_heap_type(ty.heap),
+            None => Ok(()),
+        }
+    }
+
+    pub fn check_heap_type(&self, heap: HeapType) -> Result<(), ValidationErrorKind> {
+        match heap {
+            HeapType::Type(index) => self.sub_type(*index).map(|_| ()),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn is_subtype(&self, sub: ValueType, sup: ValueType) -> bool {
+        self.subtyping.is_subtype(sub, sup)
+    }
+
+    /// Checks a type of the type section, at `index` in the recursion group ending at `end`.
+    fn check_sub_type(&self, ty: &SubType, index: u32, end: u32) -> Result<(), ValidationErrorKind> {
+        use self::ValidationErrorKind::*;
+        if let Some(&unknown) = type_indices(ty).iter().find(|&&i| i >= end) {
+            return Err(UnknownType(unknown));
+        }
+        if ty.supertypes.len() > 1 {
+            return Err(InvalidSupertype(*ty.supertypes[1]));
+        }
+        if let Some(sup) = ty.supertypes.first() {
+            let supertype = self.sub_type(**sup)?;
+            if **sup >= index || supertype.is_final ||
+               !self.subtyping.is_composite_subtype(&ty.composite, &supertype.composite) {
+                return Err(InvalidSupertype(**sup));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn function(&self, index: u32) -> Result<&'a FuncType, ValidationErrorKind> {
+        self.functions.get(index as usize).cloned().ok_or(ValidationErrorKind::UnknownFunction(index))
+    }
+
+    pub fn table(&self, index: u32) -> Result<&'a TableType, ValidationErrorKind> {
+        self.tables.get(index as usize).cloned().ok_or(ValidationErrorKind::UnknownTable(index))
+    }
+
+    pub fn memory(&self, index: u32) -> Result<&'a MemoryType, ValidationErrorKind> {
+        self.memories.get(index as usize).cloned().ok_or(ValidationErrorKind::UnknownMemory(index))
+    }
+
+    pub fn global(&self, index: u32) -> Result<&'a GlobalType, ValidationErrorKind> {
+        self.globals.get(index as usize).cloned().ok_or(ValidationErrorKind::UnknownGlobal(index))
+    }
+
+    pub fn tag(&self, index: u32) -> Result<&'a FuncType, ValidationErrorKind> {
+        self.tags.get(index as usize).cloned().ok_or(ValidationErrorKind::UnknownTag(index))
+    }
+
+    pub fn element(&self, index: u32) -> Result<&'a ElemSegment, ValidationErrorKind> {
+        self.elements.get(index as usize).cloned().ok_or(ValidationErrorKind::UnknownElement(index))
+    }
+
+    /// Data segments are checked against the data count, since the code
+    /// comes before the data section.
+    pub fn data(&self, index: u32) -> Result<(), ValidationErrorKind> {
+        match self.data_count {
+            None => Err(ValidationErrorKind::MissingDataCount),
+            Some(count) if index >= count => Err(ValidationErrorKind::UnknownData(index)),
+            Some(_) => Ok(()),
+        }
+    }
+}
+
+impl Module {
+    /// Checks the module against the typing rules of the WebAssembly MVP
+    /// and returns the first error found.
+    pub fn validate(&self) -> Result<(), ValidationError> {
+        use self::ValidationErrorKind::*;
+        let ctx = Context::new(self)?;
+        let err = |location, kind| Err(ValidationError::new(location, kind));
+
+        let mut index = 0;
+        for group in self.types.iter().flat_map(|t| t.iter()) {
+            let end = index + group.types.len() as u32;
+            for ty in group.types.iter() {
+                ctx.check_sub_type(ty, index, end)
+                    .map_err(|kind| ValidationError::new(Location::Type(index), kind))?;
+                index += 1;
+            }
+        }
+
+        let functions = self.functions.as_ref().map_or(0, |f| f.len());
+        let codes = self.codes.as_ref().map_or(0, |c| c.len());
+        if functions != codes {
+            return err(Location::Module,
+                       FunctionCodeMismatch {
+                           functions: functions,
+                           codes: codes,
+                       });
+        }
+        for (i, import) in self.imports.iter().flat_map(|i| i.iter()).enumerate() {
+            let valid = match import.kind {
+                ImportKind::Table(ref t) => check_table(&ctx, t),
+                ImportKind::Memory(ref m) => check_memory(m),
+                ImportKind::Global(ref g) => ctx.check_value_type(g.content),
+                _ => Ok(()),
+            };
+            if let Err(kind) = valid {
+                return err(Location::Import(i as u32), kind);
+            }
+        }
+        let tables = self.tables.as_ref().map_or(&[][..], |t| &t[..]);
+        for (i, t) in tables.iter().enumerate() {
+            if let Err(kind) = check_table(&ctx, t) {
+                let index = ctx.tables.len() - tables.len() + i;
+                return err(Location::Table(index as u32), kind);
+            }
+        }
+        let memories = self.memories.as_ref().map_or(&[][..], |m| &m[..]);
+        for (i, m) in memories.iter().enumerate() {
+            if let Err(kind) = check_memory(m) {
+                let index = ctx.memories.len() - memories.len() + i;
+                return err(Location::Memory(index as u32), kind);
+            }
+        }
+        for (i, ty) in ctx.tags.iter().enumerate() {
+            if !ty.results.is_empty() {
+                return err(Location::Tag(i as u32), TagWithResults);
+            }
+        }
+
+        for (i, g) in self.globals.iter().flat_map(|g| g.iter()).enumerate() {
+            let index = (ctx.nimported_globals + i) as u32;
+            ctx.check_value_type(g.ty.content)
+                .and_then(|_| check_init_expr(&ctx, &g.init, g.ty.content, index as usize))
+                .map_err(|kind| ValidationError::new(Location::Global(index), kind))?;
+        }
+
+        let mut names = HashSet::new();
+        for (i, e) in self.exports.iter().flat_map(|e| e.iter()).enumerate() {
+            let location = Location::Export(i as u32);
+            let checked = match e.kind {
+                ExportKind::Function(ref f) => ctx.function(**f).map(|_| ()),
+                ExportKind::Table(ref t) => ctx.table(**t).map(|_| ()),
+                ExportKind::Memory(ref m) => ctx.memory(**m).map(|_| ()),
+                ExportKind::Global(ref g) => ctx.global(**g).map(|_| ()),
+                ExportKind::Tag(ref t) => ctx.tag(**t).map(|_| ()),
+            };
+            checked.map_err(|kind| ValidationError::new(location.clone(), kind))?;
+            if !names.insert(&e.field) {
+                return err(location, DuplicateExport(e.field.clone()));
+            }
+        }
+
+        if let Some(ref start) = self.start {
+            let ty = ctx.function(**start).map_err(|kind| ValidationError::new(Location::Start, kind))?;
+            if !ty.params.is_empty() || !ty.results.is_empty() {
+                return err(Location::Start, InvalidStartFunction);
+            }
+        }
+
+        for (i, e) in self.elements.iter().flat_map(|e| e.iter()).enumerate() {
+            let location = Location::Element(i as u32);
+            if let ElemMode::Active { ref index, ref offset } = e.mode {
+                let table = ctx.table(**index).map_err(|kind| ValidationError::new(location.clone(), kind))?;
+                check_elem_type(&ctx, table.element, e.elems.ty().into())
+                    .map_err(|kind| ValidationError::new(location.clone(), kind))?;
+                check_init_expr(&ctx, offset, I32, ctx.globals.len())
+                    .map_err(|kind| ValidationError::new(location.clone(), kind))?;
+            }
+            match e.elems {
+                Elements::Functions(ref fs) => {
+                    for f in fs.iter() {
+                        ctx.function(**f).map_err(|kind| ValidationError::new(location.clone(), kind))?;
+                    }
+                }
+                Elements::Expressions(ty, ref es) => {
+                    for expr in es.iter() {
+                        check_init_expr(&ctx, expr, ty.into(), ctx.globals.len())
+                            .map_err(|kind| ValidationError::new(location.clone(), kind))?;
+                    }
+                }
+            }
+        }
+
+        let data = self.data.as_ref().map_or(&[][..], |d| &d[..]);
+        if let Some(count) = self.data_count {
+            if count as usize != data.len() {
+                return err(Location::Module,
+                           DataCountMismatch {
+                               count: count,
+                               segments: data.len(),
+                           });
+            }
+        }
+        for (i, d) in data.iter().enumerate() {
+            let location = Location::Data(i as u32);
+            if let DataMode::Active { ref index, ref offset } = d.mode {
+                let memory = ctx.memory(**index).map_err(|kind| ValidationError::new(location.clone(), kind))?;
+                check_init_expr(&ctx, offset, memory.index_type(), ctx.globals.len())
+                    .map_err(|kind| ValidationError::new(location.clone(), kind))?;
+            }
+        }
+
+        let nimported_functions = ctx.functions.len() - functions;
+        for (i, body) in self.codes.iter().flat_map(|c| c.iter()).enumerate() {
+            let index = (nimported_functions + i) as u32;
+            FunctionValidator::new(&ctx, ctx.functions[index as usize], body)
+                .validate()
+                .map_err(|(position, kind)| {
+                             let location = Location::Function {
+                                 index: index,
+                                 position: position,
+                             };
+                             ValidationError::new(location, kind)
+                         })?;
+        }
+
+        Ok(())
+    }
+}
+
+fn check_limits(limits: &ResizableLimits, bound: u64) -> bool {
+    limits.initial <= bound &&
+    limits.maximum.is_none_or(|max| limits.initial <= max && max <= bound)
+}
+
+fn check_memory(memory: &MemoryType) -> Result<(), ValidationErrorKind> {
+    let bound = if memory.memory64 { 1 << 48 } else { 1 << 16 };
+    if !check_limits(&memory.limits, bound) {
+        return Err(ValidationErrorKind::InvalidLimits);
+    }
+    if memory.shared && memory.limits.maximum.is_none() {
+        return Err(ValidationErrorKind::SharedMemoryWithoutMaximum);
+    }
+    Ok(())
+}
+
+/// Tables start out filled with null, so they only hold nullable references.
+fn check_table(ctx: &Context, table: &TableType) -> Result<(), ValidationErrorKind> {
+    if !check_limits(&table.limits, u32::MAX.into()) {
+        return Err(ValidationErrorKind::InvalidLimits);
+    }
+    let ty = ValueType::reference(table.element);
+    ctx.check_value_type(ty)?;
+    if !table.element.nullable {
+        return Err(ValidationErrorKind::NonDefaultableType(ty));
+    }
+    Ok(())
+}
+
+/// References of type `found` can be stored in a table of `expected`.
+fn check_elem_type(ctx: &Context, expected: RefType, found: RefType) -> Result<(), ValidationErrorKind> {
+    if !ctx.subtyping.is_ref_subtype(found, expected) {
+        return Err(ValidationErrorKind::TypeMismatch {
+                       expected: ValueType::reference(expected),
+                       found: ValueType::reference(found),
+                   });
+    }
+    Ok(())
+}
+
+/// The non-nullable reference to the type `index`, which instructions allocating or taking
+/// a reference to a function produce.
+fn type_ref(index: TypeIndex) -> ValueType {
+    ValueType::Ref(RefType::non_null(HeapType::Type(index)))
+}
+
+/// The operand of instructions reading or writing objects of type `index`.
+fn nullable_ref(index: TypeIndex) -> ValueType {
+    ValueType::Ref(RefType::nullable(HeapType::Type(index)))
+}
+
+fn check_defaultable<'a, I: IntoIterator<Item = &'a FieldType>>(fields: I) -> Result<(), ValidationErrorKind> {
+    match fields.into_iter().map(|f| f.storage.unpacked()).find(|ty| !ty.is_defaultable()) {
+        Some(ty) => Err(ValidationErrorKind::NonDefaultableType(ty)),
+        None => Ok(()),
+    }
+}
+
+/// Checks a constant expression, which may read the immutable globals below `nglobals`:
+/// those defined before the global it initializes, or all of them in a segment.
+fn check_init_expr(ctx: &Context, expr: &InitExpr, ty: ValueType, nglobals: usize) -> Result<(), ValidationErrorKind> {
+    use ops::Op::*;
+    use self::ValidationErrorKind::*;
+    let code = &(expr.0).0;
+    let mut stack = Vec::new();
+    let pop = |stack: &mut Vec<ValueType>, expected: ValueType| match stack.pop() {
+        None => Err(StackUnderflow),
+        Some(t) if !ctx.is_subtype(t, expected) => {
+            Err(TypeMismatch {
+                    expected: expected,
+                    found: t,
+                })
+        }
+        Some(_) => Ok(()),
+    };
+    for (i, op) in code.iter().enumerate() {
+        match *op {
+            End => {
+                if i != code.len() - 1 {
+                    return Err(TrailingCode);
+                }
+                pop(&mut stack, ty)?;
+                return if stack.is_empty() { Ok(()) } else { Err(ValuesRemaining) };
+            }
+            I32Const(_) => stack.push(I32),
+            I64Const(_) => stack.push(I64),
+            F32Const(_) => stack.push(F32),
+            F64Const(_) => stack.push(F64),
+            V128Const(_) => stack.push(V128),
+            GetGlobal(ref g) => {
+                // globals are initialized in order, and mutable ones may change after that
+                let global = ctx.global(**g)?;
+                if **g as usize >= nglobals || global.mutable {
+                    return Err(NonConstantExpr);
+                }
+                stack.push(global.content);
+            }
+            RefNull(heap) => {
+                ctx.check_heap_type(heap)?;
+                stack.push(ValueType::reference(RefType::nullable(heap)));
+            }
+            RefFunc(ref f) => {
+                ctx.function(**f)?;
+                stack.push(type_ref(TypeIndex::new(ctx.function_types[**f as usize])));
+            }
+            // the extended constant expressions
+            I32Add | I32Sub | I32Mul | I64Add | I64Sub | I64Mul => {
+                let ty = if let I32Add | I32Sub | I32Mul = *op { I32 } else { I64 };
+                pop(&mut stack, ty)?;
+                pop(&mut stack, ty)?;
+                stack.push(ty);
+            }
+            // structs and arrays allocated at instantiation
+            StructNew { index } => {
+                for field in ctx.struct_type(*index)?.iter().rev() {
+                    pop(&mut stack, field.storage.unpacked())?;
+                }
+                stack.push(type_ref(index));
+            }
+            StructNewDefault { index } => {
+                check_defaultable(ctx.struct_type(*index)?)?;
+                stack.push(type_ref(index));
+            }
+            ArrayNew { index } => {
+                let field = ctx.array_type(*index)?;
+                pop(&mut stack, I32)?;
+                pop(&mut stack, field.storage.unpacked())?;
+                stack.push(type_ref(index));
+            }
+            ArrayNewDefault { index } => {
+                check_defaultable(Some(ctx.array_type(*index)?))?;
+                pop(&mut stack, I32)?;
+                stack.push(type_ref(index));
+            }
+            ArrayNewFixed { index, len } => {
+                let field = ctx.array_type(*index)?;
+                for _ in 0..len {
+                    pop(&mut stack, field.storage.unpacked())?;
+                }
+                stack.push(type_ref(index));
+            }
+            AnyConvertExtern | ExternConvertAny => {
+                let (from, to) = match *op {
+                    AnyConvertExtern => (HeapType::Extern, HeapType::Any),
+                    _ => (HeapType::Any, HeapType::Extern),
+                };
+                let nullable = match stack.last().and_then(|t| t.ref_type()) {
+                    Some(ty) => ty.nullable,
+                    None => true,
+                };
+                pop(&mut stack, ValueType::reference(RefType::nullable(from)))?;
+                stack.push(ValueType::reference(RefType {
+                                                    nullable: nullable,
+                                                    heap: to,
+                                                }));
+            }
+            RefI31 => {
+                pop(&mut stack, I32)?;
+                stack.push(ValueType::Ref(RefType::non_null(HeapType::I31)));
+            }
+            _ => return Err(NonConstantExpr),
+        }
+    }
+    Err(MissingEnd)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FrameKind {
+    Function,
+    Block,
+    Loop,
+    If,
+    Else,
+    Try,
+    Catch,
+    CatchAll,
+}
+
+struct Frame {
+    kind: FrameKind,
+    params: Vec<ValueType>,
+    results: Vec<ValueType>,
+    height: usize,
+    /// the number of locals set when the block was entered, which it is left with
+    init_height: usize,
+    unreachable: bool,
+}
+
+impl Frame {
+    fn label_types(&self) -> &[ValueType] {
+        if self.kind == FrameKind::Loop {
+            &self.params
+        } else {
+            &self.results
+        }
+    }
+}
+
+type CheckResult<T> = Result<T, ValidationErrorKind>;
+
+/// Type checks a function body with the algorithm from the appendix of the spec.
+/// `None` on the value stack is the unknown type of unreachable code.
+struct FunctionValidator<'a> {
+    ctx: &'a Context<'a>,
+    ty: &'a FuncType,
+    body: &'a FunctionBody,
+    stack: Vec<Option<ValueType>>,
+    frames: Vec<Frame>,
+    /// the non-defaultable locals set so far, in the order they were set
+    initialized: Vec<u32>,
+}
+
+impl<'a> FunctionValidator<'a> {
+    fn new(ctx: &'a Context<'a>, ty: &'a FuncType, body: &'a FunctionBody) -> Self {
+        FunctionValidator {
+            ctx: ctx,
+            ty: ty,
+            body: body,
+            stack: Vec::new(),
+            frames: Vec::new(),
+            initialized: Vec::new(),
+        }
+    }
+
+    fn validate(mut self) -> Result<(), (usize, ValidationErrorKind)> {
+        let code = &self.body.code.0;
+        for entry in self.body.locals.iter() {
+            self.ctx.check_value_type(entry.ty).map_err(|kind| (0, kind))?;
+        }
+        self.push_frame(FrameKind::Function, Vec::new(), self.ty.results.clone());
+        for (position, op) in code.iter().enumerate() {
+            if self.frames.is_empty() {
+                return Err((position, ValidationErrorKind::TrailingCode));
+            }
+            self.check_op(op).map_err(|kind| (position, kind))?;
+        }
+        // the implicit `end` of the body
+        match self.frames.len() {
+            0 => return Err((code.len(), ValidationErrorKind::TrailingCode)),
+            1 => (),
+            _ => return Err((code.len(), ValidationErrorKind::MissingEnd)),
+        }
+        self.check_op(&Op::End).map_err(|kind| (code.len(), kind))
+    }
+
+    fn push(&mut self, ty: ValueType) {
+        self.stack.push(Some(ty))
+    }
+
+    fn pop(&mut self) -> CheckResult<Option<ValueType>> {
+        let frame = self.frames.last().expect("a frame is open");
+        if self.stack.len() == frame.height {
+            if frame.unreachable {
+                return Ok(None);
+            }
+            return Err(ValidationErrorKind::StackUnderflow);
+        }
+        Ok(self.stack.pop().expect("stack is higher than the frame"))
+    }
+
+    fn pop_expect(&mut self, expected: ValueType) -> CheckResult<Option<ValueType>> {
+        match self.pop()? {
+            Some(found) if !self.ctx.is_subtype(found, expected) => {
+                Err(ValidationErrorKind::TypeMismatch {
+                        expected: expected,
+                        found: found,
+                    })
+            }
+            actual => Ok(actual.or(Some(expected))),
+        }
+    }
+
+    fn pop_all(&mut self, expected: &[ValueType]) -> CheckResult<()> {
+        for ty in expected.iter().rev() {
+            self.pop_expect(*ty)?;
+        }
+        Ok(())
+    }
+
+    /// Checks that the values on top of the stack match `expected`, leaving them there
+    /// as they are.
+    fn peek_all(&mut self, expected: &[ValueType]) -> CheckResult<()> {
+        let mut popped = Vec::new();
+        for ty in expected.iter().rev() {
+            let found = self.pop()?;
+            if let Some(found) = found {
+                self.check_subtype(found, *ty)?;
+            }
+            popped.push(found);
+        }
+        self.stack.extend(popped.into_iter().rev());
+        Ok(())
+    }
+
+    /// Pops a reference, `None` if it is unknown.
+    fn pop_ref(&mut self) -> CheckResult<Option<RefType>> {
+        match self.pop()? {
+            None => Ok(None),
+            Some(ty) => ty.ref_type().map(Some).ok_or(ValidationErrorKind::ExpectedReference(ty)),
+        }
+    }
+
+    /// The reference type a branch of `br_on_non_null` or a cast passes to the label at
+    /// `depth` last, and the types it passes before.
+    fn ref_label(&self, depth: u32) -> CheckResult<(Vec<ValueType>, ValueType)> {
+        let mut types = self.label_types(depth)?;
+        match types.pop() {
+            Some(ty) if ty.is_reference() => Ok((types, ty)),
+            _ => Err(ValidationErrorKind::LabelWithoutReference(depth)),
+        }
+    }
+
+    fn check_subtype(&self, sub: ValueType, sup: ValueType) -> CheckResult<()> {
+        if !self.ctx.is_subtype(sub, sup) {
+            return Err(ValidationErrorKind::TypeMismatch {
+                           expected: sup,
+                           found: sub,
+                       });
+        }
+        Ok(())
+    }
+
+    /// The results of a tail call replace those of the function.
+    fn check_return_call(&self, results: &[ValueType]) -> CheckResult<()> {
+        if results.len() != self.ty.results.len() ||
+           results.iter().zip(self.ty.results.iter()).any(|(a, b)| !self.ctx.is_subtype(*a, *b)) {
+            return Err(ValidationErrorKind::ReturnCallResultMismatch);
+        }
+        Ok(())
+    }
+
+    /// Opens a frame, pushing its parameters back on the stack.
+    fn push_frame(&mut self, kind: FrameKind, params: Vec<ValueType>, results: Vec<ValueType>) {
+        let height = self.stack.len();
+        for ty in params.iter() {
+            self.push(*ty);
+        }
+        self.frames.push(Frame {
+                             kind: kind,
+                             params: params,
+                             results: results,
+                             height: height,
+                             init_height: self.initialized.len(),
+                             unreachable: false,
+                         });
+    }
+
+    fn block_type(&self, sig: &BlockType) -> CheckResult<(Vec<ValueType>, Vec<ValueType>)> {
+        match *sig {
+            BlockType::Empty => Ok((Vec::new(), Vec::new())),
+            BlockType::Value(ty) => {
+                self.ctx.check_value_type(ty)?;
+                Ok((Vec::new(), vec![ty]))
+            }
+            BlockType::Type(ref index) => {
+                let ty = self.ctx.func_type(**index)?;
+                Ok((ty.params.clone(), ty.results.clone()))
+            }
+        }
+    }
+
+    fn push_block(&mut self, kind: FrameKind, sig: &BlockType) -> CheckResult<()> {
+        let (params, results) = self.block_type(sig)?;
+        self.pop_all(&params)?;
+        self.push_frame(kind, params, results);
+        Ok(())
+    }
+
+    fn pop_frame(&mut self) -> CheckResult<Frame> {
+        let results = self.frames.last().expect("a frame is open").results.clone();
+        self.pop_all(&results)?;
+        let frame = self.frames.pop().expect("a frame is open");
+        if self.stack.len() != frame.height {
+            return Err(ValidationErrorKind::ValuesRemaining);
+        }
+        self.initialized.truncate(frame.init_height);
+        Ok(frame)
+    }
+
+    /// The memory an access refers to, whose offset must fit its address type.
+    fn accessed_memory(&self, imm: &MemoryImmediate) -> CheckResult<&'a MemoryType> {
+        let memory = self.ctx.memory(*imm.memory)?;
+        if !memory.memory64 && imm.offset > u32::MAX as u64 {
+            return Err(ValidationErrorKind::OffsetOutOfRange(imm.offset));
+        }
+        Ok(memory)
+    }
+
+    fn set_unreachable(&mut self) {
+        let frame = self.frames.last_mut().expect("a frame is open");
+        self.stack.truncate(frame.height);
+        frame.unreachable = true;
+    }
+
+    fn label_types(&self, depth: u32) -> CheckResult<Vec<ValueType>> {
+        let len = self.frames.len();
+        if depth as usize >= len {
+            return Err(ValidationErrorKind::UnknownLabel(depth));
+        }
+        Ok(self.frames[len - 1 - depth as usize].label_types().to_vec())
+    }
+
+    fn local(&self, index: u32) -> CheckResult<ValueType> {
+        let params = &self.ty.params;
+        if (index as usize) < params.len() {
+            return Ok(params[index as usize]);
+        }
+        let mut rest = index as u64 - params.len() as u64;
+        for entry in self.body.locals.iter() {
+            if rest < entry.count as u64 {
+                return Ok(entry.ty);
+            }
+            rest -= entry.count as u64;
+        }
+        Err(ValidationErrorKind::UnknownLocal(index))
+    }
+
+    /// Whether the local has a value, as parameters and defaultable locals always do.
+    fn is_initialized(&self, index: u32, ty: ValueType) -> bool {
+        (index as usize) < self.ty.params.len() || ty.is_defaultable() || self.initialized.contains(&index)
+    }
+
+    /// Marks a local as set until the end of the innermost block.
+    fn set_initialized(&mut self, index: u32, ty: ValueType) {
+        if !self.is_initialized(index, ty) {
+            self.initialized.push(index);
+        }
+    }
+
+    /// The element type of an array type whose elements may be written.
+    fn mutable_array(&self, index: TypeIndex) -> CheckResult<&'a FieldType> {
+        let ty = self.ctx.array_type(*index)?;
+        if !ty.mutable {
+            return Err(ValidationErrorKind::ImmutableField);
+        }
+        Ok(ty)
+    }
+
+    /// Data segments only initialize arrays of numbers and vectors.
+    fn numeric_array(&self, index: TypeIndex) -> CheckResult<()> {
+        if self.ctx.array_type(*index)?.storage.unpacked().is_reference() {
+            return Err(ValidationErrorKind::NonNumericArray(*index));
+        }
+        Ok(())
+    }
+
+    fn check_op(&mut self, op: &Op) -> CheckResult<()> {
+        use ops::Op::*;
+        use self::ValidationErrorKind::*;
+
+        if let Some((lane, count)) = lane_immediate(op) {
+            if lane >= count {
+                return Err(InvalidLaneIndex(lane));
+            }
+        }
+        if let I8x16Shuffle(ref lanes) = *op {
+            if let Some(&lane) = lanes.iter().find(|&&l| l >= 32) {
+                return Err(InvalidLaneIndex(lane));
+            }
+        }
+
+        if let Some((params, results)) = simple_signature(op) {
+            self.pop_all(params)?;
+            for ty in results {
+                self.push(*ty);
+            }
+            return Ok(());
+        }
+
+        if let Some((ty, natural, store)) = memory_access(op) {
+            let imm = op.memory_immediate().expect("memory access has an immediate");
+            let memory = self.accessed_memory(imm)?;
+            if imm.flags > natural {
+                return Err(InvalidAlignment);
+            }
+            if store {
+                self.pop_expect(ty)?;
+                self.pop_expect(memory.index_type())?;
+            } else {
+                self.pop_expect(memory.index_type())?;
+                self.push(ty);
+            }
+            return Ok(());
+        }
+
+        if let Some((natural, store)) = lane_access(op) {
+            let imm = op.memory_immediate().expect("memory access has an immediate");
+            let memory = self.accessed_memory(imm)?;
+            if imm.flags > natural {
+                return Err(InvalidAlignment);
+            }
+            self.pop_expect(V128)?;
+            self.pop_expect(memory.index_type())?;
+            if !store {
+                self.push(V128);
+            }
+            return Ok(());
+        }
+
+        if let Some((natural, params, results)) = atomic_access(op) {
+            let imm = op.memory_immediate().expect("memory access has an immediate");
+            let memory = self.accessed_memory(imm)?;
+            if imm.flags != natural {
+                return Err(UnnaturalAtomicAlignment);
+            }
+            // the address comes first, typed by the memory
+            self.pop_all(&params[1..])?;
+            self.pop_expect(memory.index_type())?;
+            for ty in results {
+                self.push(*ty);
+            }
+            return Ok(());
+        }
+
+        match *op {
+            Unreachable => self.set_unreachable(),
+            Block { ref sig } => self.push_block(FrameKind::Block, sig)?,
+            Loop { ref sig } => self.push_block(FrameKind::Loop, sig)?,
+            If { ref sig } => {
+                self.pop_expect(I32)?;
+                self.push_block(FrameKind::If, sig)?;
+            }
+            Else => {
+                if self.frames.last().map(|f| f.kind) != Some(FrameKind::If) {
+                    return Err(ElseWithoutIf);
+                }
+                let frame = self.pop_frame()?;
+                self.push_frame(FrameKind::Else, frame.params, frame.results);
+            }
+            Try { ref sig } => self.push_block(FrameKind::Try, sig)?,
+            Catch(ref tag) => {
+                if !matches!(self.frames.last().map(|f| f.kind), Some(FrameKind::Try) | Some(FrameKind::Catch)) {
+                    return Err(CatchWithoutTry);
+                }
+                let params = self.ctx.tag(**tag)?.params.clone();
+                let frame = self.pop_frame()?;
+                // the handler starts with the values thrown with the tag
+                self.push_frame(FrameKind::Catch, params, frame.results);
+            }
+            CatchAll => {
+                if !matches!(self.frames.last().map(|f| f.kind), Some(FrameKind::Try) | Some(FrameKind::Catch)) {
+                    return Err(CatchWithoutTry);
+                }
+                let frame = self.pop_frame()?;
+                self.push_frame(FrameKind::CatchAll, Vec::new(), frame.results);
+            }
+            Delegate { depth } => {
+                if self.frames.last().map(|f| f.kind) != Some(FrameKind::Try) {
+                    return Err(CatchWithoutTry);
+                }
+                let frame = self.pop_frame()?;
+                // the label is counted from outside of the `try`
+                self.label_types(depth)?;
+                for ty in frame.results {
+                    self.push(ty);
+                }
+            }
+            Throw(ref tag) => {
+                let params = self.ctx.tag(**tag)?.params.clone();
+                self.pop_all(&params)?;
+                self.set_unreachable();
+            }
+            Rethrow { depth } => {
+                self.label_types(depth)?;
+                let kind = self.frames[self.frames.len() - 1 - depth as usize].kind;
+                if kind != FrameKind::Catch && kind != FrameKind::CatchAll {
+                    return Err(InvalidRethrowLabel(depth));
+                }
+                self.set_unreachable();
+            }
+            End => {
+                let frame = self.pop_frame()?;
+                // without `else` the parameters are passed through as results
+                if frame.kind == FrameKind::If && frame.params != frame.results {
+                    return Err(MissingElse);
+                }
+                for ty in frame.results {
+                    self.push(ty);
+                }
+            }
+            Br { depth } => {
+                let types = self.label_types(depth)?;
+                self.pop_all(&types)?;
+                self.set_unreachable();
+            }
+            BrIf { depth } => {
+                self.pop_expect(I32)?;
+                let types = self.label_types(depth)?;
+                self.pop_all(&types)?;
+                for ty in types {
+                    self.push(ty);
+                }
+            }
+            BrTable(ref target) => {
+                self.pop_expect(I32)?;
+                let types = self.label_types(target.default_target)?;
+                // the values need to match each label, which may take supertypes of them
+                for depth in target.table.iter() {
+                    let other = self.label_types(*depth)?;
+                    if other.len() != types.len() {
+                        return Err(BrTableArityMismatch);
+                    }
+                    self.peek_all(&other)?;
+                }
+                self.pop_all(&types)?;
+                self.set_unreachable();
+            }
+            Return => {
+                let results = self.ty.results.clone();
+                self.pop_all(&results)?;
+                self.set_unreachable();
+            }
+            Call { ref index } => {
+                let ty = self.ctx.function(**index)?;
+                self.pop_all(&ty.params)?;
+                for ty in ty.results.iter() {
+                    self.push(*ty);
+                }
+            }
+            CallIndirect { ref index, ref table } => {
+                check_elem_type(self.ctx, ElemType::AnyFunc.into(), self.ctx.table(**table)?.element)?;
+                let ty = self.ctx.func_type(**index)?;
+                self.pop_expect(I32)?;
+                self.pop_all(&ty.params)?;
+                for ty in ty.results.iter() {
+                    self.push(*ty);
+                }
+            }
+            ReturnCall { ref index } => {
+                let ty = self.ctx.function(**index)?;
+                self.check_return_call(&ty.results)?;
+                self.pop_all(&ty.params)?;
+                self.set_unreachable();
+            }
+            ReturnCallIndirect { ref index, ref table } => {
+                check_elem_type(self.ctx, ElemType::AnyFunc.into(), self.ctx.table(**table)?.element)?;
+                let ty = self.ctx.func_type(**index)?;
+                self.check_return_call(&ty.results)?;
+                self.pop_expect(I32)?;
+                self.pop_all(&ty.params)?;
+                self.set_unreachable();
+            }
+            CallRef { index } => {
+                let ty = self.ctx.func_type(*index)?;
+                self.pop_expect(nullable_ref(index))?;
+                self.pop_all(&ty.params)?;
+                for ty in ty.results.iter() {
+                    self.push(*ty);
+                }
+            }
+            ReturnCallRef { index } => {
+                let ty = self.ctx.func_type(*index)?;
+                self.check_return_call(&ty.results)?;
+                self.pop_expect(nullable_ref(index))?;
+                self.pop_all(&ty.params)?;
+                self.set_unreachable();
+            }
+            Drop => {
+                self.pop()?;
+            }
+            Select => {
+                self.pop_expect(I32)?;
+                let t1 = self.pop()?;
+                let t2 = match t1 {
+                    Some(t) => self.pop_expect(t)?,
+                    None => self.pop()?,
+                };
+                if t1.or(t2).is_some_and(|t| t.is_reference()) {
+                    return Err(NonNumericSelect);
+                }
+                self.stack.push(t1.or(t2));
+            }
+            TypedSelect(ty) => {
+                self.ctx.check_value_type(ty)?;
+                self.pop_expect(I32)?;
+                self.pop_expect(ty)?;
+                self.pop_expect(ty)?;
+                self.push(ty);
+            }
+            GetLocal(ref i) => {
+                let ty = self.local(**i)?;
+                if !self.is_initialized(**i, ty) {
+                    return Err(UninitializedLocal(**i));
+                }
+                self.push(ty);
+            }
+            SetLocal(ref i) => {
+                let ty = self.local(**i)?;
+                self.pop_expect(ty)?;
+                self.set_initialized(**i, ty);
+            }
+            TeeLocal(ref i) => {
+                let ty = self.local(**i)?;
+                self.pop_expect(ty)?;
+                self.set_initialized(**i, ty);
+                self.push(ty);
+            }
+            GetGlobal(ref i) => {
+                let global = self.ctx.global(**i)?;
+                self.push(global.content);
+            }
+            SetGlobal(ref i) => {
+                let global = self.ctx.global(**i)?;
+                if !global.mutable {
+                    return Err(ImmutableGlobal(**i));
+                }
+                self.pop_expect(global.content)?;
+            }
+            TableGet(ref t) => {
+                let ty = self.ctx.table(**t)?.element.into();
+                self.pop_expect(I32)?;
+                self.push(ty);
+            }
+            TableSet(ref t) => {
+                let ty = self.ctx.table(**t)?.element.into();
+                self.pop_expect(ty)?;
+                self.pop_expect(I32)?;
+            }
+            AtomicFence { reserved } => {
+                if reserved {
+                    return Err(NonZeroReserved);
+                }
+            }
+            CurrentMemory { ref memory } => {
+                let memory = self.ctx.memory(**memory)?;
+                self.push(memory.index_type());
+            }
+            GrowMemory { ref memory } => {
+                let ty = self.ctx.memory(**memory)?.index_type();
+                self.pop_expect(ty)?;
+                self.push(ty);
+            }
+            MemoryInit { ref segment, ref memory } => {
+                let ty = self.ctx.memory(**memory)?.index_type();
+                self.ctx.data(**segment)?;
+                self.pop_all(&[ty, I32, I32])?;
+            }
+            DataDrop(ref segment) => self.ctx.data(**segment)?,
+            MemoryCopy { ref dst, ref src } => {
+                let dst = self.ctx.memory(**dst)?;
+                let src = self.ctx.memory(**src)?;
+                // the length fits both memories
+                let len = if dst.memory64 && src.memory64 { I64 } else { I32 };
+                self.pop_all(&[dst.index_type(), src.index_type(), len])?;
+            }
+            MemoryFill { ref memory } => {
+                let ty = self.ctx.memory(**memory)?.index_type();
+                self.pop_all(&[ty, I32, ty])?;
+            }
+            TableInit { ref segment, ref table } => {
+                let table = self.ctx.table(**table)?;
+                let segment = self.ctx.element(**segment)?;
+                check_elem_type(self.ctx, table.element, segment.elems.ty().into())?;
+                self.pop_all(&[I32, I32, I32])?;
+            }
+            ElemDrop(ref segment) => {
+                self.ctx.element(**segment)?;
+            }
+            TableCopy { ref dst, ref src } => {
+                let dst = self.ctx.table(**dst)?;
+                let src = self.ctx.table(**src)?;
+                check_elem_type(self.ctx, dst.element, src.element)?;
+                self.pop_all(&[I32, I32, I32])?;
+            }
+            TableGrow(ref t) => {
+                let ty = self.ctx.table(**t)?.element.into();
+                self.pop_expect(I32)?;
+                self.pop_expect(ty)?;
+                self.push(I32);
+            }
+            TableSize(ref t) => {
+                self.ctx.table(**t)?;
+                self.push(I32);
+            }
+            TableFill(ref t) => {
+                let ty = self.ctx.table(**t)?.element.into();
+                self.pop_expect(I32)?;
+                self.pop_expect(ty)?;
+                self.pop_expect(I32)?;
+            }
+            RefNull(heap) => {
+                self.ctx.check_heap_type(heap)?;
+                self.push(ValueType::reference(RefType::nullable(heap)));
+            }
+            RefIsNull => {
+                if let Some(ty) = self.pop()? {
+                    if !ty.is_reference() {
+                        return Err(ExpectedReference(ty));
+                    }
+                }
+                self.push(I32);
+            }
+            RefFunc(ref f) => {
+                self.ctx.function(**f)?;
+                if !self.ctx.references.contains(&**f) {
+                    return Err(UndeclaredFunctionReference(**f));
+                }
+                self.push(type_ref(TypeIndex::new(self.ctx.function_types[**f as usize])));
+            }
+            RefEq => {
+                let eq = ValueType::Ref(RefType::nullable(HeapType::Eq));
+                self.pop_all(&[eq, eq])?;
+                self.push(I32);
+            }
+            RefAsNonNull => {
+                match self.pop_ref()? {
+                    Some(ty) => self.push(ValueType::Ref(RefType::non_null(ty.heap))),
+                    None => self.stack.push(None),
+                }
+            }
+            BrOnNull { depth } => {
+                let ty = self.pop_ref()?;
+                let types = self.label_types(depth)?;
+                self.peek_all(&types)?;
+                match ty {
+                    Some(ty) => self.push(ValueType::Ref(RefType::non_null(ty.heap))),
+                    None => self.stack.push(None),
+                }
+            }
+            BrOnNonNull { depth } => {
+                let (types, label) = self.ref_label(depth)?;
+                if let Some(ty) = self.pop_ref()? {
+                    self.check_subtype(ValueType::Ref(RefType::non_null(ty.heap)), label)?;
+                }
+                self.peek_all(&types)?;
+            }
+            StructNew { index } => {
+                let types: Vec<_> = self.ctx.struct_type(*index)?.iter().map(|f| f.storage.unpacked()).collect();
+                self.pop_all(&types)?;
+                self.push(type_ref(index));
+            }
+            StructNewDefault { index } => {
+                check_defaultable(self.ctx.struct_type(*index)?)?;
+                self.push(type_ref(index));
+            }
+            StructGet { index, field } | StructGetS { index, field } | StructGetU { index, field } => {
+                let ty = self.ctx.field(*index, field)?;
+                if ty.storage.is_packed() == matches!(*op, StructGet { .. }) {
+                    return Err(PackedFieldAccess);
+                }
+                self.pop_expect(nullable_ref(index))?;
+                self.push(ty.storage.unpacked());
+            }
+            StructSet { index, field } => {
+                let ty = self.ctx.field(*index, field)?;
+                if !ty.mutable {
+                    return Err(ImmutableField);
+                }
+                self.pop_all(&[nullable_ref(index), ty.storage.unpacked()])?;
+            }
+            ArrayNew { index } => {
+                let ty = self.ctx.array_type(*index)?;
+                self.pop_all(&[ty.storage.unpacked(), I32])?;
+                self.push(type_ref(index));
+            }
+            ArrayNewDefault { index } => {
+                check_defaultable(Some(self.ctx.array_type(*index)?))?;
+                self.pop_expect(I32)?;
+                self.push(type_ref(index));
+            }
+            ArrayNewFixed { index, len } => {
+                let ty = self.ctx.array_type(*index)?.storage.unpacked();
+                // the polymorphic stack of unreachable code stands in for any number of values
+                let frame = self.frames.last().expect("a frame is open");
+                let available = self.stack.len() - frame.height;
+                if len as usize > available && !frame.unreachable {
+                    return Err(StackUnderflow);
+                }
+                for _ in 0..available.min(len as usize) {
+                    self.pop_expect(ty)?;
+                }
+                self.push(type_ref(index));
+            }
+            ArrayNewData { index, segment } => {
+                self.numeric_array(index)?;
+                self.ctx.data(*segment)?;
+                self.pop_all(&[I32, I32])?;
+                self.push(type_ref(index));
+            }
+            ArrayNewElem { index, segment } => {
+                let ty = self.ctx.array_type(*index)?.storage.unpacked();
+                self.check_subtype(self.ctx.element(*segment)?.elems.ty().into(), ty)?;
+                self.pop_all(&[I32, I32])?;
+                self.push(type_ref(index));
+            }
+            ArrayGet { index } | ArrayGetS { index } | ArrayGetU { index } => {
+                let ty = self.ctx.array_type(*index)?;
+                if ty.storage.is_packed() == matches!(*op, ArrayGet { .. }) {
+                    return Err(PackedFieldAccess);
+                }
+                self.pop_all(&[nullable_ref(index), I32])?;
+                self.push(ty.storage.unpacked());
+            }
+            ArraySet { index } => {
+                let ty = self.mutable_array(index)?;
+                self.pop_all(&[nullable_ref(index), I32, ty.storage.unpacked()])?;
+            }
+            ArrayLen => {
+                self.pop_expect(ValueType::Ref(RefType::nullable(HeapType::Array)))?;
+                self.push(I32);
+            }
+            ArrayFill { index } => {
+                let ty = self.mutable_array(index)?;
+                self.pop_all(&[nullable_ref(index), I32, ty.storage.unpacked(), I32])?;
+            }
+            ArrayCopy { dst, src } => {
+                let dst_ty = self.mutable_array(dst)?;
+                let src_ty = self.ctx.array_type(*src)?;
+                if !self.ctx.subtyping.is_storage_subtype(src_ty.storage, dst_ty.storage) {
+                    return Err(TypeMismatch {
+                                   expected: dst_ty.storage.unpacked(),
+                                   found: src_ty.storage.unpacked(),
+                               });
+                }
+                self.pop_all(&[nullable_ref(dst), I32, nullable_ref(src), I32, I32])?;
+            }
+            ArrayInitData { index, segment } => {
+                self.mutable_array(index)?;
+                self.numeric_array(index)?;
+                self.ctx.data(*segment)?;
+                self.pop_all(&[nullable_ref(index), I32, I32, I32])?;
+            }
+            ArrayInitElem { index, segment } => {
+                let ty = self.mutable_array(index)?.storage.unpacked();
+                self.check_subtype(self.ctx.element(*segment)?.elems.ty().into(), ty)?;
+                self.pop_all(&[nullable_ref(index), I32, I32, I32])?;
+            }
+            RefTest(ty) | RefCast(ty) => {
+                self.ctx.check_heap_type(ty.heap)?;
+                let top = self.ctx.subtyping.top(ty.heap).expect("a known heap type");
+                self.pop_expect(ValueType::reference(RefType::nullable(top)))?;
+                if let RefTest(_) = *op {
+                    self.push(I32);
+                } else {
+                    self.push(ValueType::reference(ty));
+                }
+            }
+            BrOnCast { depth, from, to } | BrOnCastFail { depth, from, to } => {
+                self.ctx.check_heap_type(from.heap)?;
+                self.ctx.check_heap_type(to.heap)?;
+                let (from_ty, to_ty) = (ValueType::reference(from), ValueType::reference(to));
+                self.check_subtype(to_ty, from_ty)?;
+                // what is left of `from` when the reference is not of `to`
+                let rest = ValueType::reference(RefType {
+                                                    nullable: from.nullable && !to.nullable,
+                                                    heap: from.heap,
+                                                });
+                let (branch, fallthrough) = if let BrOnCast { .. } = *op { (to_ty, rest) } else { (rest, to_ty) };
+                let (types, label) = self.ref_label(depth)?;
+                self.check_subtype(branch, label)?;
+                self.pop_expect(from_ty)?;
+                self.peek_all(&types)?;
+                self.push(fallthrough);
+            }
+            AnyConvertExtern | ExternConvertAny => {
+                let (from, to) = match *op {
+                    AnyConvertExtern => (HeapType::Extern, HeapType::Any),
+                    _ => (HeapType::Any, HeapType::Extern),
+                };
+                // the result is null only if the operand may be
+                let nullable = match self.pop_ref()? {
+                    Some(ty) => {
+                        self.check_subtype(ValueType::reference(ty), ValueType::reference(RefType::nullable(from)))?;
+                        ty.nullable
+                    }
+                    None => false,
+                };
+                self.push(ValueType::reference(RefType {
+                                                   nullable: nullable,
+                                                   heap: to,
+                                               }));
+            }
+            RefI31 => {
+                self.pop_expect(I32)?;
+                self.push(ValueType::Ref(RefType::non_null(HeapType::I31)));
+            }
+            I31GetS | I31GetU => {
+                self.pop_expect(ValueType::Ref(RefType::nullable(HeapType::I31)))?;
+                self.push(I32);
+            }
+            _ => unreachable!("simple instructions and memory accesses are handled above"),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::ValidationErrorKind::*;
+    use builder::*;
+    use ops::{BrTarget, MemoryImmediate};
+    use ops::Op::*;
+    use {funtype, ty, ty_vec};
+
+    /// A memory, a table, the mutable `i64` global 0 and the immutable `i32` global 1.
+    fn fixture() -> ModuleBuilder {
+        let mut mb = ModuleBuilder::new();
+        mb.new_memory(1..);
+        mb.new_table(ElemType::AnyFunc, 1..);
+        mb.new_global(GlobalType {
+                          content: ValueType::I64,
+                          mutable: true,
+                      },
+                      Code(vec![I64Const(0), End]));
+        mb.new_global(GlobalType {
+                          content: ValueType::I32,
+                          mutable: false,
+                      },
+                      Code(vec![I32Const(0), End]));
+        mb
+    }
+
+    /// The fixture with a function of type `ty` whose code ends implicitly.
+    fn function(ty: FuncType, locals: Vec<ValueType>, code: Vec<Op>) -> Module {
+        let mut mb = fixture();
+        let body = FunctionBody {
+            locals: locals.into_iter().map(|ty| LocalEntry { count: 1, ty: ty }).collect(),
+            code: Code(code),
+        };
+        mb.new_function((ty, body));
+        mb.build()
+    }
+
+    fn validate_fn(ty: FuncType, code: Vec<Op>) -> Result<(), ValidationErrorKind> {
+        function(ty, Vec::new(), code).validate().map_err(|e| e.kind)
+    }
+
+    fn validate(module: &Module) -> Result<(), ValidationErrorKind> {
+        module.validate().map_err(|e| e.kind)
+    }
+
+    fn validate_wat(src: &str) -> Result<(), ValidationErrorKind> {
+        validate(&Module::from_wat(src).unwrap())
+    }
+
+    fn memarg(flags: u32, offset: u64) -> MemoryImmediate {
+        MemoryImmediate {
+            flags: flags,
+            offset: offset,
+            memory: MemoryIndex::new(0),
+        }
+    }
+
+    fn i32_block() -> BlockType {
+        BlockType::Value(ValueType::I32)
+    }
+
+    #[test]
+    fn valid() {
+        let cases = [(funtype!((i32) -> i32), vec![GetLocal(LocalIndex::new(0))]),
+                     (funtype!(() -> i32), vec![Block { sig: i32_block() }, I32Const(1), Br { depth: 0 }, End]),
+                     (funtype!((i32) -> i64),
+                      vec![GetLocal(LocalIndex::new(0)),
+                           If { sig: BlockType::Value(ValueType::I64) },
+                           I64Const(1),
+                           Else,
+                           I64Const(2),
+                           End]),
+                     (funtype!(() -> i32), vec![Loop { sig: i32_block() }, I32Const(0), BrIf { depth: 0 }, I32Const(1), End]),
+                     (funtype!((i32)),
+                      vec![GetLocal(LocalIndex::new(0)), I32Const(1), I32Store { imm: memarg(2, 4) }]),
+                     (funtype!(() -> i32), vec![CurrentMemory { memory: MemoryIndex::new(0) }, GrowMemory { memory: MemoryIndex::new(0) }]),
+                     (funtype!(()), vec![I64Const(1), SetGlobal(GlobalIndex::new(0))]),
+                     (funtype!(() -> i32), vec![GetGlobal(GlobalIndex::new(1))]),
+                     (funtype!(()),
+                      vec![I32Const(0),
+                           CallIndirect {
+                               index: TypeIndex::new(0),
+                               table: TableIndex::new(0),
+                           }]),
+                     (funtype!(()), vec![Call { index: FunctionIndex::new(0).into() }])];
+        for &(ref ty, ref code) in cases.iter() {
+            assert_eq!(validate_fn(ty.clone(), code.clone()), Ok(()), "{:?}", code);
+        }
+    }
+
+    #[test]
+    fn polymorphic_stack() {
+        // anything may be popped after an unconditional branch
+        let cases = [vec![Unreachable, I32Add],
+                     vec![I32Const(1), Return, Drop],
+                     vec![Block { sig: i32_block() }, I32Const(1), Br { depth: 0 }, I64Add, Drop, End],
+                     vec![Block { sig: BlockType::Empty },
+                          I32Const(0),
+                          BrTable(BrTarget {
+                                      table: vec![0],
+                                      default_target: 0,
+                                  }),
+                          End,
+                          I32Const(0)],
+                     vec![Unreachable, Select]];
+        for code in cases.iter() {
+            assert_eq!(validate_fn(funtype!(() -> i32), code.clone()), Ok(()), "{:?}", code);
+        }
+        // but values pushed after it still have to match
+        assert_eq!(validate_fn(funtype!(() -> i32), vec![Unreachable, I64Const(1)]),
+                   Err(TypeMismatch {
+                           expected: ValueType::I32,
+                           found: ValueType::I64,
+                       }));
+        assert_eq!(validate_fn(funtype!(()), vec![Unreachable, I32Const(1), I64Const(1), I32Add, Drop]),
+                   Err(TypeMismatch {
+                           expected: ValueType::I32,
+                           found: ValueType::I64,
+                       }));
+    }
+
+    #[test]
+    fn operands() {
+        assert_eq!(validate_fn(funtype!(() -> i32), vec![I64Const(0)]),
+                   Err(TypeMismatch {
+                           expected: ValueType::I32,
+                           found: ValueType::I64,
+                       }));
+        assert_eq!(validate_fn(funtype!(()), vec![Drop]), Err(StackUnderflow));
+        assert_eq!(validate_fn(funtype!(() -> i32), vec![]), Err(StackUnderflow));
+        assert_eq!(validate_fn(funtype!(()), vec![I32Const(1)]), Err(ValuesRemaining));
+        assert_eq!(validate_fn(funtype!(()), vec![Block { sig: BlockType::Empty }, I32Const(1), End]),
+                   Err(ValuesRemaining));
+        // values outside of a block are out of its reach
+        assert_eq!(validate_fn(funtype!(()), vec![I32Const(1), Block { sig: BlockType::Empty }, Drop, End, Drop]),
+                   Err(StackUnderflow));
+        assert_eq!(validate_fn(funtype!(() -> i32), vec![I32Const(1), If { sig: i32_block() }, I32Const(1), End]),
+                   Err(MissingElse));
+        let br_table = BrTable(BrTarget {
+                                   table: vec![0],
+                                   default_target: 1,
+                               });
+        assert_eq!(validate_fn(funtype!(() -> i32),
+                               vec![Block { sig: BlockType::Empty }, I32Const(1), I32Const(0), br_table, End, I32Const(0)]),
+                   Err(BrTableArityMismatch));
+    }
+
+    #[test]
+    fn bulk_memory() {
+        let data = DataIndex::new(0);
+        let with = |code: Vec<Op>| {
+            let mut mb = fixture();
+            mb.new_passive_data(vec![1, 2, 3]);
+            mb.add_element(ElemSegment {
+                               mode: ElemMode::Passive,
+                               elems: Elements::Functions(vec![]),
+                           });
+            let body = FunctionBody {
+                locals: Vec::new(),
+                code: Code(code),
+            };
+            mb.new_function((funtype!(()), body));
+            mb.build()
+        };
+        let zeros = [I32Const(0), I32Const(0), I32Const(0)];
+        let init = |segment| {
+            MemoryInit {
+                segment: DataIndex::new(segment),
+                memory: MemoryIndex::new(0),
+            }
+        };
+        let table_init = |segment| {
+            TableInit {
+                segment: ElementIndex::new(segment),
+                table: TableIndex::new(0),
+            }
+        };
+        let cases = [vec![DataDrop(data)],
+                     vec![ElemDrop(ElementIndex::new(0))],
+                     [&zeros[..], &[init(0)]].concat(),
+                     [&zeros[..],
+                      &[MemoryCopy {
+                            dst: MemoryIndex::new(0),
+                            src: MemoryIndex::new(0),
+                        }]]
+                             .concat(),
+                     [&zeros[..], &[MemoryFill { memory: MemoryIndex::new(0) }]].concat(),
+                     [&zeros[..], &[table_init(0)]].concat(),
+                     [&zeros[..],
+                      &[TableCopy {
+                            dst: TableIndex::new(0),
+                            src: TableIndex::new(0),
+                        }]]
+                             .concat()];
+        for code in cases.iter() {
+            assert_eq!(validate(&with(code.clone())), Ok(()), "{:?}", code);
+        }
+        assert_eq!(validate(&with([&zeros[..], &[init(1)]].concat())), Err(UnknownData(1)));
+        assert_eq!(validate(&with([&zeros[..], &[table_init(1)]].concat())), Err(UnknownElement(1)));
+        assert_eq!(validate(&with(vec![I32Const(0), I64Const(0), I32Const(0), MemoryFill { memory: MemoryIndex::new(0) }])),
+                   Err(TypeMismatch {
+                           expected: ValueType::I32,
+                           found: ValueType::I64,
+                       }));
+        let mut module = with(vec![DataDrop(data)]);
+        module.data_count = None;
+        assert_eq!(validate(&module), Err(MissingDataCount));
+        module.data_count = Some(2);
+        assert_eq!(validate(&module),
+                   Err(DataCountMismatch {
+                           count: 2,
+                           segments: 1,
+                       }));
+    }
+
+    #[test]
+    fn multi_value() {
+        let ty = funtype!((i32) -> (i32, i32));
+        let x = GetLocal(LocalIndex::new(0));
+        // type 0 is the type of the function, and of the blocks taking its parameter
+        let block = || BlockType::Type(TypeIndex::new(0));
+        let cases = [vec![x.clone(), x.clone()],
+                     vec![x.clone(), Block { sig: block() }, I32Const(1), End],
+                     vec![x.clone(), Loop { sig: block() }, Br { depth: 0 }, End],
+                     vec![x.clone(), x.clone(), If { sig: block() }, I32Const(1), Else, I32Const(2), End],
+                     vec![x.clone(), Block { sig: block() }, I32Const(1), Br { depth: 0 }, End]];
+        for code in cases.iter() {
+            assert_eq!(validate_fn(ty.clone(), code.clone()), Ok(()), "{:?}", code);
+        }
+        assert_eq!(validate_fn(ty.clone(), vec![Block { sig: block() }, I32Const(1), I32Const(1), End]),
+                   Err(StackUnderflow));
+        assert_eq!(validate_fn(ty.clone(), vec![x.clone(), I64Const(1)]),
+                   Err(TypeMismatch {
+                           expected: ValueType::I32,
+                           found: ValueType::I64,
+                       }));
+        // the parameters of a block are out of reach of the values outside of it
+        assert_eq!(validate_fn(ty.clone(), vec![x.clone(), x.clone(), Block { sig: block() }, I32Add, End]),
+                   Err(StackUnderflow));
+        assert_eq!(validate_fn(funtype!(()), vec![Block { sig: BlockType::Type(TypeIndex::new(5)) }, End]),
+                   Err(UnknownType(5)));
+    }
+
+    #[test]
+    fn unknown_indices() {
+        let cases = [(vec![Call { index: FunctionIndex::new(1).into() }], UnknownFunction(1)),
+                     (vec![GetLocal(LocalIndex::new(0)), Drop], UnknownLocal(0)),
+                     (vec![GetGlobal(GlobalIndex::new(2)), Drop], UnknownGlobal(2)),
+                     (vec![Br { depth: 1 }], UnknownLabel(1)),
+                     (vec![I32Const(0),
+                           CallIndirect {
+                               index: TypeIndex::new(3),
+                               table: TableIndex::new(0),
+                           }],
+                      UnknownType(3))];
+        for &(ref code, ref kind) in cases.iter() {
+            assert_eq!(validate_fn(funtype!(()), code.clone()).as_ref(), Err(kind), "{:?}", code);
+        }
+        let locals = vec![ValueType::I64];
+        assert_eq!(validate(&function(funtype!((i32)), locals, vec![I64Const(0), SetLocal(LocalIndex::new(2))])),
+                   Err(UnknownLocal(2)));
+
+        let mut module = function(funtype!(()),
+                                  Vec::new(),
+                                  vec![I32Const(0),
+                                       CallIndirect {
+                                           index: TypeIndex::new(0),
+                                           table: TableIndex::new(0),
+                                       }]);
+        module.tables = None;
+        assert_eq!(validate(&module), Err(UnknownTable(0)));
+        let mut module = function(funtype!(() -> i32), Vec::new(), vec![CurrentMemory { memory: MemoryIndex::new(0) }]);
+        module.memories = None;
+        assert_eq!(validate(&module), Err(UnknownMemory(0)));
+
+        let mut mb = fixture();
+        mb.export("f", FunctionIndex::new(0));
+        assert_eq!(validate(&mb.build()), Err(UnknownFunction(0)));
+        let mut mb = fixture();
+        mb.start(FunctionIndex::new(0));
+        assert_eq!(validate(&mb.build()), Err(UnknownFunction(0)));
+    }
+
+    #[test]
+    fn threads() {
+        let imm = |flags| memarg(flags, 0);
+        assert_eq!(validate_fn(funtype!(() -> i64),
+                               vec![I32Const(0), I64Const(1), I64AtomicRmwXchg { imm: imm(3) }]),
+                   Ok(()));
+        assert_eq!(validate_fn(funtype!(() -> i32),
+                               vec![I32Const(0), I32Const(1), I32AtomicRmw16SubU { imm: imm(1) }]),
+                   Ok(()));
+        // atomic accesses have to be aligned naturally, neither less nor more
+        assert_eq!(validate_fn(funtype!(() -> i32), vec![I32Const(0), I32AtomicLoad { imm: imm(1) }]),
+                   Err(UnnaturalAtomicAlignment));
+        assert_eq!(validate_fn(funtype!(() -> i32), vec![I32Const(0), I32AtomicLoad8U { imm: imm(1) }]),
+                   Err(UnnaturalAtomicAlignment));
+        assert_eq!(validate_fn(funtype!(() -> i32),
+                               vec![I32Const(0), I32Const(0), I32Const(0), MemoryAtomicWait32 { imm: imm(2) }]),
+                   Err(TypeMismatch {
+                           expected: ValueType::I64,
+                           found: ValueType::I32,
+                       }));
+
+        let mut module = fixture().build();
+        module.memories.as_mut().unwrap()[0].shared = true;
+        assert_eq!(validate(&module), Err(SharedMemoryWithoutMaximum));
+        let mut mb = ModuleBuilder::new();
+        mb.new_shared_memory(1..1);
+        assert_eq!(validate(&mb.build()), Ok(()));
+    }
+
+    #[test]
+    fn tail_calls() {
+        let self_call = || ReturnCall { index: FunctionIndex::new(0).into() };
+        let indirect = |index| {
+            ReturnCallIndirect {
+                index: TypeIndex::new(index),
+                table: TableIndex::new(0),
+            }
+        };
+        assert_eq!(validate_fn(funtype!((i32) -> i32), vec![GetLocal(LocalIndex::new(0)), self_call()]),
+                   Ok(()));
+        // anything may follow, as after `return`
+        assert_eq!(validate_fn(funtype!((i32) -> i32),
+                               vec![GetLocal(LocalIndex::new(0)), I32Const(0), indirect(0), Drop]),
+                   Ok(()));
+        let mut mb = fixture();
+        mb.add_type(funtype!(() -> i32));
+        let f = mb.new_function((funtype!(() -> i64),
+                                 FunctionBody {
+                                     locals: Vec::new(),
+                                     code: Code(vec![I32Const(0), indirect(0)]),
+                                 }));
+        assert_eq!(*f, 0);
+        assert_eq!(validate(&mb.build()), Err(ReturnCallResultMismatch));
+        assert_eq!(validate_fn(funtype!((i32) -> i32), vec![self_call()]), Err(StackUnderflow));
+        assert_eq!(validate_fn(funtype!(() -> i32), vec![I32Const(0), indirect(5)]), Err(UnknownType(5)));
+    }
+
+    #[test]
+    fn exceptions() {
+        let with = |code: Vec<Op>| {
+            let mut mb = fixture();
+            mb.new_tag(funtype!((i64)));
+            let body = FunctionBody {
+                locals: Vec::new(),
+                code: Code(code),
+            };
+            mb.new_function((funtype!(() -> i64), body));
+            validate(&mb.build())
+        };
+        let tag = TagIndex::new(0);
+        let try_ = || Try { sig: BlockType::Value(ValueType::I64) };
+        assert_eq!(with(vec![try_(), I64Const(1), Throw(tag), Catch(tag), End]), Ok(()));
+        assert_eq!(with(vec![try_(), I64Const(1), CatchAll, I64Const(2), Rethrow { depth: 0 }, End]), Ok(()));
+        assert_eq!(with(vec![Block { sig: BlockType::Value(ValueType::I64) }, try_(), I64Const(1), Delegate { depth: 0 }, End]),
+                   Ok(()));
+        assert_eq!(with(vec![I32Const(1), Throw(tag)]),
+                   Err(TypeMismatch {
+                           expected: ValueType::I64,
+                           found: ValueType::I32,
+                       }));
+        assert_eq!(with(vec![I64Const(1), Throw(TagIndex::new(1))]), Err(UnknownTag(1)));
+        assert_eq!(with(vec![Block { sig: BlockType::Value(ValueType::I64) }, Catch(tag), End]),
+                   Err(CatchWithoutTry));
+        assert_eq!(with(vec![try_(), Rethrow { depth: 0 }, End]), Err(InvalidRethrowLabel(0)));
+
+        let mut mb = fixture();
+        mb.new_tag(funtype!(() -> i32));
+        assert_eq!(validate(&mb.build()), Err(TagWithResults));
+    }
+
+    #[test]
+    fn multi_memory() {
+        let imm = |memory| {
+            MemoryImmediate {
+                flags: 2,
+                offset: 0,
+                memory: MemoryIndex::new(memory),
+            }
+        };
+        // the fixture with a second memory
+        let with = |code: Vec<Op>| {
+            let mut module = function(funtype!(() -> i32), Vec::new(), code);
+            module.memories.as_mut().unwrap().push(MemoryType {
+                                                       limits: ResizableLimits::new(1),
+                                                       shared: false,
+                                                       memory64: false,
+                                                   });
+            validate(&module)
+        };
+        assert_eq!(with(vec![I32Const(0), I32Load { imm: imm(1) }]), Ok(()));
+        assert_eq!(with(vec![CurrentMemory { memory: MemoryIndex::new(1) }]), Ok(()));
+        assert_eq!(with(vec![I32Const(0), I32Load { imm: imm(2) }]), Err(UnknownMemory(2)));
+        assert_eq!(with(vec![I32Const(0),
+                             I32Const(0),
+                             I32Const(0),
+                             MemoryCopy {
+                                 dst: MemoryIndex::new(2),
+                                 src: MemoryIndex::new(0),
+                             },
+                             I32Const(0)]),
+                   Err(UnknownMemory(2)));
+    }
+
+    #[test]
+    fn memory64() {
+        let with = |ty: FuncType, code: Vec<Op>| {
+            let mut module = function(ty, Vec::new(), code);
+            module.memories.as_mut().unwrap()[0].memory64 = true;
+            validate(&module)
+        };
+        assert_eq!(with(funtype!(() -> i32), vec![I64Const(0), I32Load { imm: memarg(2, 1 << 32) }]), Ok(()));
+        assert_eq!(with(funtype!(() -> i64), vec![I64Const(1), GrowMemory { memory: MemoryIndex::new(0) }]),
+                   Ok(()));
+        assert_eq!(with(funtype!(() -> i32), vec![I32Const(0), I32Load { imm: memarg(2, 0) }]),
+                   Err(TypeMismatch {
+                           expected: ValueType::I64,
+                           found: ValueType::I32,
+                       }));
+        // a 32-bit memory cannot be reached past 4 GiB
+        assert_eq!(validate_fn(funtype!(() -> i32), vec![I32Const(0), I32Load { imm: memarg(2, 1 << 32) }]),
+                   Err(OffsetOutOfRange(1 << 32)));
+        let mut mb = fixture();
+        mb.new_memory64(1..1 << 48);
+        assert_eq!(validate(&mb.build()), Ok(()));
+        let mut mb = fixture();
+        mb.new_memory64(1..(1 << 48) + 1);
+        assert_eq!(validate(&mb.build()), Err(InvalidLimits));
+    }
+
+    #[test]
+    fn global_order() {
+        // a segment may read all immutable globals, a global only those before it
+        assert_eq!(validate_wat("(module (global i32 (i32.const 1)) (global i32 (i32.add (global.get 0) (i32.const 1)))
+                                   (memory 1) (data (global.get 1) \"\"))"),
+                   Ok(()));
+        assert_eq!(validate_wat("(module (global i32 (global.get 1)) (global i32 (i32.const 0)))"),
+                   Err(NonConstantExpr));
+        assert_eq!(validate_wat("(module (global i32 (global.get 0)))"), Err(NonConstantExpr));
+    }
+
+    #[test]
+    fn gc() {
+        assert_eq!(validate_wat(r#"
+            (module
+              (rec (type $list (struct (field $head i32) (field $tail (ref null $list)))))
+              (type $point (sub (struct (field $x (mut i32)))))
+              (type $point3 (sub final $point (struct (field $x (mut i32)) (field i8))))
+              (type $bytes (array (mut i8)))
+              (type $f (func (param (ref $point)) (result i32)))
+              (global $origin (ref $point3) (struct.new $point3 (i32.const 0) (i32.const 0)))
+              (global $empty (ref $list) (struct.new $list (i32.const 0) (ref.null $list)))
+              (global $one (ref i31) (ref.i31 (i32.const 1)))
+              (global $host externref (extern.convert_any (global.get $one)))
+              (table $points 1 (ref null $point))
+              (table $anys 1 anyref)
+              (func $x (type $f) (struct.get $point $x (local.get 0)))
+              (elem declare func $x)
+              (func (param $p (ref null $point)) (result i32)
+                (local $b (ref $bytes))
+                (local.set $b (array.new_default $bytes (i32.const 4)))
+                (array.set $bytes (local.get $b) (i32.const 0) (i32.const 255))
+                (struct.set $point $x (global.get $origin) (array.get_u $bytes (local.get $b) (i32.const 0)))
+                (table.set $points (i32.const 0) (global.get $origin))
+                (table.copy $anys $points (i32.const 0) (i32.const 0) (i32.const 1))
+                (drop (i31.get_u (ref.cast (ref null i31) (any.convert_extern (global.get $host)))))
+                (block $null
+                  (br_on_null $null (local.get $p))
+                  (return (call_ref $f (ref.cast (ref $point) (global.get $origin)) (ref.func $x))))
+                (struct.get_s $point3 1 (ref.cast (ref $point3) (global.get $origin)))))"#),
+                   Ok(()));
+        let cases = [("(module (type (sub final (struct))) (type (sub 0 (struct))))", InvalidSupertype(0)),
+                     ("(module (type (sub (struct (field i32)))) (type (sub 0 (struct (field i64)))))",
+                      InvalidSupertype(0)),
+                     ("(module (type (struct (field i32)))
+                        (func (param (ref 0)) (struct.set 0 0 (local.get 0) (i32.const 1))))",
+                      ImmutableField),
+                     ("(module (type (array i32)) (func (param (ref 0)) (array.set 0 (local.get 0) (i32.const 0) (i32.const 1))))",
+                      ImmutableField),
+                     ("(module (type (struct (field i8))) (func (param (ref 0)) (result i32) (struct.get 0 0 (local.get 0))))",
+                      PackedFieldAccess),
+                     ("(module (type (struct (field i32))) (func (param (ref 0)) (result i32) (struct.get_u 0 0 (local.get 0))))",
+                      PackedFieldAccess),
+                     ("(module (type (struct (field i32))) (func (param (ref 0)) (result i32) (struct.get 0 1 (local.get 0))))",
+                      UnknownField(1)),
+                     ("(module (type (array funcref)) (data \"\") (func (drop (array.new_data 0 0 (i32.const 0) (i32.const 0)))))",
+                      NonNumericArray(0)),
+                     ("(module (type (struct (field (ref func)))) (func (drop (struct.new_default 0))))",
+                      NonDefaultableType(ValueType::Ref(RefType::non_null(HeapType::Func)))),
+                     ("(module (func (local (ref func)) (drop (local.get 0))))", UninitializedLocal(0)),
+                     ("(module (func (param funcref) (block (br_on_non_null 0 (local.get 0)))))",
+                      LabelWithoutReference(0)),
+                     ("(module (type (array i8)) (func (drop (struct.new_default 0))))", ExpectedStructType(0)),
+                     ("(module (type (struct)) (func (drop (array.new_default 0 (i32.const 1)))))", ExpectedArrayType(0)),
+                     ("(module (type (struct)) (func (param funcref) (call_ref 0 (local.get 0))))", ExpectedFuncType(0)),
+                     ("(module (type (struct)) (global (ref 0) (ref.null 0)))",
+                      TypeMismatch {
+                          expected: ValueType::Ref(RefType::non_null(HeapType::Type(TypeIndex::new(0)))),
+                          found: ValueType::Ref(RefType::nullable(HeapType::Type(TypeIndex::new(0)))),
+                      }),
+                     ("(module (func (result i32) (i31.get_s (ref.null any))))",
+                      TypeMismatch {
+                          expected: ValueType::Ref(RefType::nullable(HeapType::I31)),
+                          found: ValueType::Ref(RefType::nullable(HeapType::Any)),
+                      }),
+                     ("(module (func (result anyref) (any.convert_extern (ref.null func))))",
+                      TypeMismatch {
+                          expected: ValueType::ExternRef,
+                          found: ValueType::FuncRef,
+                      }),
+                     ("(module (table 1 (ref i31)))", NonDefaultableType(ValueType::Ref(RefType::non_null(HeapType::I31)))),
+                     ("(module (table 1 i31ref) (table 1 anyref) (func (table.copy 0 1 (i32.const 0) (i32.const 0) (i32.const 1))))",
+                      TypeMismatch {
+                          expected: ValueType::Ref(RefType::nullable(HeapType::I31)),
+                          found: ValueType::Ref(RefType::nullable(HeapType::Any)),
+                      }),
+                     ("(module (type (func)) (table 1 anyref) (func (call_indirect (type 0) (i32.const 0))))",
+                      TypeMismatch {
+                          expected: ValueType::FuncRef,
+                          found: ValueType::Ref(RefType::nullable(HeapType::Any)),
+                      })];
+        for &(src, ref kind) in cases.iter() {
+            assert_eq!(validate_wat(src).as_ref(), Err(kind), "{}", src);
+        }
+    }
+
+    #[test]
+    fn module_fields() {
+        let mut mb = fixture();
+        let f = mb.new_function(FunctionBuilder::new(funtype!(())).build());
+        mb.export("f", f);
+        mb.export("f", MemoryIndex::new(0));
+        assert_eq!(validate(&mb.build()), Err(DuplicateExport("f".to_string())));
+
+        let mut mb = fixture();
+        let f = mb.new_function(FunctionBuilder::new(funtype!((i32))).build());
+        mb.start(f);
+        assert_eq!(validate(&mb.build()), Err(InvalidStartFunction));
+
+        // any number of tables and memories is allowed
+        let mut mb = fixture();
+        mb.new_table(ElemType::AnyFunc, 1..);
+        mb.new_memory(1..2);
+        assert_eq!(validate(&mb.build()), Ok(()));
+        for limits in [ResizableLimits::new(2).max(1), ResizableLimits::new(65537)].iter() {
+            let mut module = fixture().build();
+            module.memories = Some(vec![MemoryType {
+                                            limits: limits.clone(),
+                                            shared: false,
+                                            memory64: false,
+                                        }]);
+            assert_eq!(validate(&module), Err(InvalidLimits));
+        }
+
+        assert_eq!(validate_fn(funtype!(()), vec![I32Const(1), SetGlobal(GlobalIndex::new(1))]),
+                   Err(ImmutableGlobal(1)));
+        assert_eq!(validate_fn(funtype!(() -> i32), vec![I32Const(0), I32Load { imm: memarg(3, 0) }]),
+                   Err(InvalidAlignment));
+        assert_eq!(validate_fn(funtype!(() -> i32), vec![CurrentMemory { memory: MemoryIndex::new(1) }]),
+                   Err(UnknownMemory(1)));
+    }
+
+    #[test]
+    fn constant_expressions() {
+        let global = |content: ValueType, init: Vec<Op>| {
+            let mut mb = fixture();
+            mb.new_global(GlobalType {
+                              content: content,
+                              mutable: false,
+                          },
+                          Code(init));
+            validate(&mb.build())
+        };
+        assert_eq!(global(ValueType::I32, vec![I32Const(1), I32Clz, End]), Err(NonConstantExpr));
+        // extended constant expressions add, subtract and multiply
+        assert_eq!(global(ValueType::I64, vec![I64Const(2), I64Const(3), I64Mul, I64Const(1), I64Sub, End]),
+                   Ok(()));
+        assert_eq!(global(ValueType::I32, vec![I32Const(6), I32Const(3), I32DivS, End]), Err(NonConstantExpr));
+        assert_eq!(global(ValueType::I32, vec![I32Const(1), I64Const(1), I32Add, End]),
+                   Err(TypeMismatch {
+                           expected: ValueType::I32,
+                           found: ValueType::I64,
+                       }));
+        // a global may read the immutable globals defined before it, but not itself or a mutable one
+        assert_eq!(global(ValueType::I32, vec![GetGlobal(GlobalIndex::new(1)), End]), Ok(()));
+        assert_eq!(global(ValueType::I32, vec![GetGlobal(GlobalIndex::new(2)), End]), Err(NonConstantExpr));
+        assert_eq!(global(ValueType::I64, vec![GetGlobal(GlobalIndex::new(0)), End]), Err(NonConstantExpr));
+        assert_eq!(global(ValueType::I64, vec![I32Const(0), End]),
+                   Err(TypeMismatch {
+                           expected: ValueType::I64,
+                           found: ValueType::I32,
+                       }));
+        assert_eq!(global(ValueType::I32, vec![I32Const(0), I32Const(1), End]), Err(ValuesRemaining));
+        assert_eq!(global(ValueType::I32, vec![I32Const(0)]), Err(MissingEnd));
+        assert_eq!(global(ValueType::I32, vec![I32Const(0), End, Nop]), Err(TrailingCode));
+
+        let mut mb = ModuleBuilder::new();
+        let imported = GlobalType {
+            content: ValueType::I32,
+            mutable: false,
+        };
+        mb.import("m", "g", imported.clone());
+        mb.import("m", "h", GlobalType { mutable: true, ..imported.clone() });
+        mb.new_global(imported.clone(), Code(vec![GetGlobal(GlobalIndex::new(0)), End]));
+        assert_eq!(validate(&mb.build()), Ok(()));
+        mb = ModuleBuilder::new();
+        mb.import("m", "h", GlobalType { mutable: true, ..imported.clone() });
+        mb.new_global(imported, Code(vec![GetGlobal(GlobalIndex::new(0)), End]));
+        assert_eq!(validate(&mb.build()), Err(NonConstantExpr));
+
+        let mut mb = fixture();
+        mb.new_data(MemoryIndex::new(0), Code(vec![I64Const(0), End]), Vec::new());
+        assert_eq!(validate(&mb.build()),
+                   Err(TypeMismatch {
+                           expected: ValueType::I32,
+                           found: ValueType::I64,
+                       }));
+    }
+
+    #[test]
+    fn section_counts() {
+        let mut module = function(funtype!(()), Vec::new(), Vec::new());
+        module.codes = None;
+        assert_eq!(validate(&module),
+                   Err(FunctionCodeMismatch {
+                           functions: 1,
+                           codes: 0,
+                       }));
+    }
+
+    #[test]
+    fn block_structure() {
+        let block = || Block { sig: BlockType::Empty };
+        assert_eq!(validate_fn(funtype!(()), vec![Nop]), Ok(()));
+        assert_eq!(validate_fn(funtype!(()), vec![block(), End]), Ok(()));
+        assert_eq!(validate_fn(funtype!(()), vec![block()]), Err(MissingEnd));
+        assert_eq!(validate_fn(funtype!(()), vec![End]), Err(TrailingCode));
+        assert_eq!(validate_fn(funtype!(()), vec![Else]), Err(ElseWithoutIf));
+        assert_eq!(validate_fn(funtype!(()), vec![block(), Else, End]), Err(ElseWithoutIf));
+    }
+
+    #[test]
+    fn error_location() {
+        let mut mb = fixture();
+        mb.new_function(FunctionBuilder::new(funtype!(())).build());
+        let body = FunctionBody {
+            locals: Vec::new(),
+            code: Code(vec![I32Const(1), I64Const(2), I32Add, Drop]),
+        };
+        mb.new_function((funtype!(()), body));
+        assert_eq!(mb.build().validate().unwrap_err(),
+                   ValidationError::new(Location::Function {
+                                            index: 1,
+                                            position: 2,
+                                        },
+                                        TypeMismatch {
+                                            expected: ValueType::I32,
+                                            found: ValueType::I64,
+                                        }));
+        // the implicit `end` is at the position past the code
+        assert_eq!(function(funtype!(()), Vec::new(), vec![I32Const(1)]).validate().unwrap_err().location,
+                   Location::Function {
+                       index: 0,
+                       position: 1,
+                   });
+        let mut mb = fixture();
+        mb.new_global(GlobalType {
+                          content: ValueType::I32,
+                          mutable: false,
+                      },
+                      Code(vec![F32Const(0.0), End]));
+        assert_eq!(mb.build().validate().unwrap_err().location, Location::Global(2));
+    }
+}
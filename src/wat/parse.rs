@@ -0,0 +1,2785 @@
+use std::collections::HashMap;
+use std::error;
+use std::fmt;
+
+use module::*;
+use ops::{BrTarget, MemoryImmediate, Op};
+use types::*;
+use validate::{atomic_access, lane_access, memory_access};
+use super::{section_name, HEAP_TYPES};
+
+/// An error raised while parsing the text format, with the position it occurred at.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WatError {
+    /// 1-based
+    pub line: usize,
+    /// 1-based, in characters
+    pub column: usize,
+    pub kind: WatErrorKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WatErrorKind {
+    UnexpectedEof,
+    UnexpectedToken(String),
+    UnterminatedString,
+    UnterminatedComment,
+    InvalidEscape,
+    InvalidUtf8,
+    InvalidNumber(String),
+    InvalidAlignment(u32),
+    UnknownInstruction(String),
+    /// a `$name` that is not bound in its index space
+    UnknownName(String),
+    DuplicateName(String),
+    /// the label after `end` or `else` is not the one of the block it closes
+    LabelMismatch(String),
+    /// an inline signature disagrees with the `(type ..)` it is used with
+    TypeMismatch,
+    /// imports have to precede all function, table, memory and global definitions
+    ImportAfterDefinition,
+    /// valid text that cannot be represented in a `Module`
+    Unsupported(&'static str),
+}
+
+impl fmt::Display for WatErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::WatErrorKind::*;
+        match *self {
+            UnexpectedEof => write!(f, "unexpected end of input"),
+            UnexpectedToken(ref t) => write!(f, "unexpected `{}`", t),
+            UnterminatedString => write!(f, "unterminated string"),
+            UnterminatedComment => write!(f, "unterminated block comment"),
+            InvalidEscape => write!(f, "invalid escape in string"),
+            InvalidUtf8 => write!(f, "name is not valid UTF-8"),
+            InvalidNumber(ref n) => write!(f, "invalid number `{}`", n),
+            InvalidAlignment(a) => write!(f, "alignment {} is not a power of two", a),
+            UnknownInstruction(ref i) => write!(f, "unknown instruction `{}`", i),
+            UnknownName(ref n) => write!(f, "unknown name `${}`", n),
+            DuplicateName(ref n) => write!(f, "duplicate name `${}`", n),
+            LabelMismatch(ref l) => write!(f, "label `${}` does not match the block", l),
+            TypeMismatch => write!(f, "inline signature does not match the type"),
+            ImportAfterDefinition => write!(f, "import after a definition"),
+            Unsupported(what) => write!(f, "{} are not supported", what),
+        }
+    }
+}
+
+impl fmt::Display for WatError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at {}:{}", self.kind, self.line, self.column)
+    }
+}
+
+impl error::Error for WatError {}
+
+impl Module {
+    /// Assembles a module from the WebAssembly text format.
+    ///
+    /// Both `(module ..)` and a bare list of module fields are accepted.
+    /// The result has the shape `ModuleBuilder::build` returns: sections
+    /// that are not used are `None`, every local has its own entry and
+    /// calls to imported functions are `Import` indices.
+    pub fn from_wat(text: &str) -> ::std::result::Result<Module, WatError> {
+        let fail_at = |Fail(offset, kind): Fail| {
+            let before = &text[..offset];
+            let line_start = before.rfind('\n').map(|i| i + 1).unwrap_or(0);
+            WatError {
+                line: before.matches('\n').count() + 1,
+                column: before[line_start..].chars().count() + 1,
+                kind: kind,
+            }
+        };
+        let sexps = Reader {
+                src: text.as_bytes(),
+                pos: 0,
+            }
+            .read_all()
+            .map_err(&fail_at)?;
+        assemble(&sexps, text.len()).map_err(&fail_at)
+    }
+}
+
+struct Fail(usize, WatErrorKind);
+
+type Result<T> = ::std::result::Result<T, Fail>;
+
+/// An optional `$name` with its offset.
+type Name = Option<(String, usize)>;
+
+enum Sexp {
+    List(Vec<Sexp>, usize),
+    /// keywords, numbers and `key=value` pairs
+    Atom(String, usize),
+    /// `$name`, stored without the `$`
+    Id(String, usize),
+    Str(Vec<u8>, usize),
+}
+
+impl Sexp {
+    fn offset(&self) -> usize {
+        match *self {
+            Sexp::List(_, o) | Sexp::Atom(_, o) | Sexp::Id(_, o) | Sexp::Str(_, o) => o,
+        }
+    }
+
+    fn unexpected(&self) -> Fail {
+        let text = match *self {
+            Sexp::List(ref items, _) => {
+                match items.first() {
+                    Some(&Sexp::Atom(ref head, _)) => format!("({}", head),
+                    _ => "(".to_string(),
+                }
+            }
+            Sexp::Atom(ref a, _) => a.clone(),
+            Sexp::Id(ref i, _) => format!("${}", i),
+            Sexp::Str(..) => "string".to_string(),
+        };
+        Fail(self.offset(), WatErrorKind::UnexpectedToken(text))
+    }
+
+    /// The keyword a list starts with.
+    fn head(&self) -> Option<&str> {
+        match *self {
+            Sexp::List(ref items, _) => {
+                match items.first() {
+                    Some(&Sexp::Atom(ref head, _)) => Some(head),
+                    _ => None,
+                }
+            }
+            _ => None,
+        }
+    }
+}
+
+struct Reader<'a> {
+    src: &'a [u8],
+    pos: usize,
+}
+
+fn is_idchar(c: u8) -> bool {
+    c.is_ascii_alphanumeric() || b"!#$%&'*+-./:<=>?@\\^_`|~".contains(&c)
+}
+
+impl<'a> Reader<'a> {
+    fn read_all(&mut self) -> Result<Vec<Sexp>> {
+        let mut stack: Vec<(Vec<Sexp>, usize)> = Vec::new();
+        let mut top = Vec::new();
+        loop {
+            self.skip_blank()?;
+            let start = self.pos;
+            let c = match self.src.get(self.pos) {
+                Some(&c) => c,
+                None => break,
+            };
+            let sexp = match c {
+                b'(' => {
+                    self.pos += 1;
+                    stack.push((::std::mem::take(&mut top), start));
+                    continue;
+                }
+                b')' => {
+                    self.pos += 1;
+                    let (parent, open) = stack.pop()
+                        .ok_or_else(|| Fail(start, WatErrorKind::UnexpectedToken(")".to_string())))?;
+                    let items = ::std::mem::replace(&mut top, parent);
+                    Sexp::List(items, open)
+                }
+                b'"' => Sexp::Str(self.string()?, start),
+                c if is_idchar(c) => {
+                    while self.src.get(self.pos).is_some_and(|&c| is_idchar(c)) {
+                        self.pos += 1;
+                    }
+                    let word = String::from_utf8_lossy(&self.src[start..self.pos]).into_owned();
+                    if c == b'$' {
+                        if word.len() == 1 {
+                            return Err(Fail(start, WatErrorKind::UnexpectedToken(word)));
+                        }
+                        Sexp::Id(word[1..].to_string(), start)
+                    } else {
+                        Sexp::Atom(word, start)
+                    }
+                }
+                _ => {
+                    let ch = String::from_utf8_lossy(&self.src[start..]).chars().next().unwrap();
+                    return Err(Fail(start, WatErrorKind::UnexpectedToken(ch.to_string())));
+                }
+            };
+            top.push(sexp);
+        }
+        if !stack.is_empty() {
+            return Err(Fail(self.pos, WatErrorKind::UnexpectedEof));
+        }
+        Ok(top)
+    }
+
+    fn skip_blank(&mut self) -> Result<()> {
+        loop {
+            match (self.src.get(self.pos), self.src.get(self.pos + 1)) {
+                (Some(&b' '), _) | (Some(&b'\t'), _) | (Some(&b'\n'), _) | (Some(&b'\r'), _) => {
+                    self.pos += 1
+                }
+                (Some(&b';'), Some(&b';')) => {
+                    while self.src.get(self.pos).is_some_and(|&c| c != b'\n') {
+                        self.pos += 1;
+                    }
+                }
+                (Some(&b'('), Some(&b';')) => self.block_comment()?,
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    fn block_comment(&mut self) -> Result<()> {
+        let start = self.pos;
+        let mut depth = 0;
+        loop {
+            match (self.src.get(self.pos), self.src.get(self.pos + 1)) {
+                (Some(&b'('), Some(&b';')) => {
+                    depth += 1;
+                    self.pos += 2;
+                }
+                (Some(&b';'), Some(&b')')) => {
+                    depth -= 1;
+                    self.pos += 2;
+                    if depth == 0 {
+                        return Ok(());
+                    }
+                }
+                (Some(_), _) => self.pos += 1,
+                (None, _) => return Err(Fail(start, WatErrorKind::UnterminatedComment)),
+            }
+        }
+    }
+
+    fn string(&mut self) -> Result<Vec<u8>> {
+        let start = self.pos;
+        self.pos += 1;
+        let mut bytes = Vec::new();
+        loop {
+            let c = *self.src.get(self.pos).ok_or(Fail(start, WatErrorKind::UnterminatedString))?;
+            self.pos += 1;
+            match c {
+                b'"' => return Ok(bytes),
+                b'\n' => return Err(Fail(start, WatErrorKind::UnterminatedString)),
+                b'\\' => {
+                    let escape = self.pos - 1;
+                    let invalid = || Fail(escape, WatErrorKind::InvalidEscape);
+                    let e = *self.src.get(self.pos).ok_or_else(invalid)?;
+                    self.pos += 1;
+                    match e {
+                        b't' => bytes.push(b'\t'),
+                        b'n' => bytes.push(b'\n'),
+                        b'r' => bytes.push(b'\r'),
+                        b'"' | b'\'' | b'\\' => bytes.push(e),
+                        b'u' => {
+                            if self.src.get(self.pos) != Some(&b'{') {
+                                return Err(invalid());
+                            }
+                            let digits = self.pos + 1;
+                            let close = self.src[digits..]
+                                .iter()
+                                .position(|&c| c == b'}')
+                                .ok_or_else(invalid)?;
+                            let hex = ::std::str::from_utf8(&self.src[digits..digits + close])
+                                .map_err(|_| invalid())?;
+                            let c = parse_u64(hex, 16)
+                                .and_then(|c| ::std::char::from_u32(c as u32).filter(|_| c <= 0x10ffff))
+                                .ok_or_else(invalid)?;
+                            let mut utf8 = [0; 4];
+                            bytes.extend_from_slice(c.encode_utf8(&mut utf8).as_bytes());
+                            self.pos = digits + close + 1;
+                        }
+                        _ => {
+                            let lo = self.src.get(self.pos).and_then(|&c| (c as char).to_digit(16));
+                            match ((e as char).to_digit(16), lo) {
+                                (Some(hi), Some(lo)) => {
+                                    bytes.push((hi * 16 + lo) as u8);
+                                    self.pos += 1;
+                                }
+                                _ => return Err(invalid()),
+                            }
+                        }
+                    }
+                }
+                c => bytes.push(c),
+            }
+        }
+    }
+}
+
+/// Digits in `radix`, with single `_` allowed between them.
+fn is_digits(s: &str, radix: u32) -> bool {
+    !s.is_empty() && !s.starts_with('_') && !s.ends_with('_') && !s.contains("__") &&
+    s.chars().all(|c| c == '_' || c.is_digit(radix))
+}
+
+fn parse_u64(s: &str, radix: u32) -> Option<u64> {
+    if !is_digits(s, radix) {
+        return None;
+    }
+    let mut n: u64 = 0;
+    for c in s.chars().filter(|&c| c != '_') {
+        let d = c.to_digit(radix)?;
+        n = n.checked_mul(radix as u64)?.checked_add(d as u64)?;
+    }
+    Some(n)
+}
+
+fn split_sign(s: &str) -> (bool, &str) {
+    if let Some(rest) = s.strip_prefix('-') {
+        (true, rest)
+    } else {
+        (false, s.strip_prefix('+').unwrap_or(s))
+    }
+}
+
+fn parse_nat(s: &str) -> Option<u64> {
+    match s.strip_prefix("0x") {
+        Some(hex) => parse_u64(hex, 16),
+        None => parse_u64(s, 10),
+    }
+}
+
+/// An integer of `bits` width, signed or unsigned, as its two's complement bit pattern.
+fn parse_int(s: &str, bits: u32) -> Option<u64> {
+    let (neg, digits) = split_sign(s);
+    let n = parse_nat(digits)?;
+    let mask = u64::MAX >> (64 - bits);
+    if neg {
+        if n > 1 << (bits - 1) {
+            return None;
+        }
+        Some(n.wrapping_neg() & mask)
+    } else if n <= mask {
+        Some(n)
+    } else {
+        None
+    }
+}
+
+/// A float with `mant_bits` explicit significand bits and `exp_bits` exponent bits, as bits.
+fn parse_float(s: &str, mant_bits: u32, exp_bits: u32) -> Option<u64> {
+    let (neg, body) = split_sign(s);
+    let exp_max = (1u64 << exp_bits) - 1;
+    let magnitude = if body == "inf" {
+        exp_max << mant_bits
+    } else if body == "nan" {
+        exp_max << mant_bits | 1 << (mant_bits - 1)
+    } else if let Some(payload) = body.strip_prefix("nan:0x") {
+        let payload = parse_u64(payload, 16)?;
+        if payload == 0 || payload >> mant_bits != 0 {
+            return None;
+        }
+        exp_max << mant_bits | payload
+    } else if let Some(hex) = body.strip_prefix("0x") {
+        parse_hex_float(hex, mant_bits, exp_bits)?
+    } else {
+        if !body.starts_with(|c: char| c.is_ascii_digit()) ||
+           !body.chars().all(|c| c.is_ascii_digit() || "._eE+-".contains(c)) ||
+           body.contains("__") || body.contains("._") || body.contains("_.") {
+            return None;
+        }
+        let plain: String = body.chars().filter(|&c| c != '_').collect();
+        if mant_bits == 23 {
+            let f = plain.parse::<f32>().ok().filter(|f| f.is_finite())?;
+            f.to_bits() as u64
+        } else {
+            let f = plain.parse::<f64>().ok().filter(|f| f.is_finite())?;
+            f.to_bits()
+        }
+    };
+    Some(magnitude | (neg as u64) << (mant_bits + exp_bits))
+}
+
+/// Rounds a hexadecimal float to nearest even, rejecting values that round to infinity.
+fn parse_hex_float(s: &str, mant_bits: u32, exp_bits: u32) -> Option<u64> {
+    let (mantissa, exp) = match s.find(['p', 'P']) {
+        Some(i) => {
+            let (neg, e) = split_sign(&s[i + 1..]);
+            let e = parse_u64(e, 10)?.min(100_000) as i64;
+            (&s[..i], if neg { -e } else { e })
+        }
+        None => (s, 0),
+    };
+    let (int, frac) = match mantissa.find('.') {
+        Some(i) => (&mantissa[..i], &mantissa[i + 1..]),
+        None => (mantissa, ""),
+    };
+    if !is_digits(int, 16) || (!frac.is_empty() && !is_digits(frac, 16)) {
+        return None;
+    }
+
+    // value = m * 2^e, `sticky` records nonzero digits that did not fit into m
+    let mut m: u64 = 0;
+    let mut e = exp;
+    let mut sticky = false;
+    for (c, is_frac) in int.chars().map(|c| (c, false)).chain(frac.chars().map(|c| (c, true))) {
+        let d = match c.to_digit(16) {
+            Some(d) => d as u64,
+            None => continue,
+        };
+        if m >> 60 == 0 {
+            m = m * 16 + d;
+            if is_frac {
+                e -= 4;
+            }
+        } else {
+            sticky |= d != 0;
+            if !is_frac {
+                e += 4;
+            }
+        }
+    }
+    if m == 0 {
+        return Some(0);
+    }
+
+    let bias = (1i64 << (exp_bits - 1)) - 1;
+    let msb = 63 - m.leading_zeros() as i64;
+    // exponent of the last significand bit, fixed at the minimum for subnormals
+    let mut ulp = (msb + e).max(1 - bias) - mant_bits as i64;
+    let shift = ulp - e;
+    let mut m = m as u128;
+    if shift > 0 {
+        if shift > 65 {
+            m = 0;
+        } else {
+            let dropped = m & ((1u128 << shift) - 1);
+            let half = 1u128 << (shift - 1);
+            m >>= shift;
+            if dropped > half || (dropped == half && (sticky || m & 1 == 1)) {
+                m += 1;
+            }
+        }
+    } else {
+        m <<= -shift;
+    }
+    if m >> (mant_bits + 1) != 0 {
+        m >>= 1;
+        ulp += 1;
+    }
+    let m = m as u64;
+    if m >> mant_bits == 0 {
+        // subnormal or zero
+        return Some(m);
+    }
+    let biased = ulp + mant_bits as i64 + bias;
+    if biased >= (1 << exp_bits) - 1 {
+        return None;
+    }
+    Some((biased as u64) << mant_bits | (m & ((1 << mant_bits) - 1)))
+}
+
+fn plain_instr(name: &str) -> Option<Op> {
+    use ops::Op::*;
+    let op = match name {
+        "unreachable" => Unreachable,
+        "nop" => Nop,
+        "return" => Return,
+        "drop" => Drop,
+        "i32.eqz" => I32Eqz,
+        "i32.eq" => I32Eq,
+        "i32.ne" => I32NE,
+        "i32.lt_s" => I32LtS,
+        "i32.lt_u" => I32LtU,
+        "i32.gt_s" => I32GtS,
+        "i32.gt_u" => I32GtU,
+        "i32.le_s" => I32LeS,
+        "i32.le_u" => I32LeU,
+        "i32.ge_s" => I32GeS,
+        "i32.ge_u" => I32GeU,
+        "i64.eqz" => I64Eqz,
+        "i64.eq" => I64Eq,
+        "i64.ne" => I64Ne,
+        "i64.lt_s" => I64LtS,
+        "i64.lt_u" => I64LtU,
+        "i64.gt_s" => I64GtS,
+        "i64.gt_u" => I64GtU,
+        "i64.le_s" => I64LeS,
+        "i64.le_u" => I64LeU,
+        "i64.ge_s" => I64GeS,
+        "i64.ge_u" => I64GeU,
+        "f32.eq" => F32Eq,
+        "f32.ne" => F32Ne,
+        "f32.lt" => F32Lt,
+        "f32.gt" => F32Gt,
+        "f32.le" => F32Le,
+        "f32.ge" => F32Ge,
+        "f64.eq" => F64Eq,
+        "f64.ne" => F64Ne,
+        "f64.lt" => F64Lt,
+        "f64.gt" => F64Gt,
+        "f64.le" => F64Le,
+        "f64.ge" => F64Ge,
+        "i32.clz" => I32Clz,
+        "i32.ctz" => I32Ctz,
+        "i32.popcnt" => I32Popcnt,
+        "i32.add" => I32Add,
+        "i32.sub" => I32Sub,
+        "i32.mul" => I32Mul,
+        "i32.div_s" => I32DivS,
+        "i32.div_u" => I32DivU,
+        "i32.rem_s" => I32RemS,
+        "i32.rem_u" => I32RemU,
+        "i32.and" => I32And,
+        "i32.or" => I32Or,
+        "i32.xor" => I32Xor,
+        "i32.shl" => I32Shl,
+        "i32.shr_s" => I32ShrS,
+        "i32.shr_u" => I32ShrU,
+        "i32.rotl" => I32Rotl,
+        "i32.rotr" => I32Rotr,
+        "i64.clz" => I64Clz,
+        "i64.ctz" => I64Ctz,
+        "i64.popcnt" => I64Popcnt,
+        "i64.add" => I64Add,
+        "i64.sub" => I64Sub,
+        "i64.mul" => I64Mul,
+        "i64.div_s" => I64DivS,
+        "i64.div_u" => I64DivU,
+        "i64.rem_s" => I64RemS,
+        "i64.rem_u" => I64RemU,
+        "i64.and" => I64And,
+        "i64.or" => I64Or,
+        "i64.xor" => I64Xor,
+        "i64.shl" => I64Shl,
+        "i64.shr_s" => I64ShrS,
+        "i64.shr_u" => I64ShrU,
+        "i64.rotl" => I64Rotl,
+        "i64.rotr" => I64Rotr,
+        "f32.abs" => F32Abs,
+        "f32.neg" => F32Neg,
+        "f32.ceil" => F32Ceil,
+        "f32.floor" => F32Floor,
+        "f32.trunc" => F32Trunc,
+        "f32.nearest" => F32Nearest,
+        "f32.sqrt" => F32Sqrt,
+        "f32.add" => F32Add,
+        "f32.sub" => F32Sub,
+        "f32.mul" => F32Mul,
+        "f32.div" => F32Div,
+        "f32.min" => F32Min,
+        "f32.max" => F32Max,
+        "f32.copysign" => F32Copysign,
+        "f64.abs" => F64Abs,
+        "f64.neg" => F64Neg,
+        "f64.ceil" => F64Ceil,
+        "f64.floor" => F64Floor,
+        "f64.trunc" => F64Trunc,
+        "f64.nearest" => F64Nearest,
+        "f64.sqrt" => F64Sqrt,
+        "f64.add" => F64Add,
+        "f64.sub" => F64Sub,
+        "f64.mul" => F64Mul,
+        "f64.div" => F64Div,
+        "f64.min" => F64Min,
+        "f64.max" => F64Max,
+        "f64.copysign" => F64Copysign,
+        "i32.wrap_i64" | "i32.wrap/i64" => I32wrapI64,
+        "i32.trunc_f32_s" | "i32.trunc_s/f32" => I32TruncSF32,
+        "i32.trunc_f32_u" | "i32.trunc_u/f32" => I32TruncUF32,
+        "i32.trunc_f64_s" | "i32.trunc_s/f64" => I32TruncSF64,
+        "i32.trunc_f64_u" | "i32.trunc_u/f64" => I32TruncUF64,
+        "i64.extend_i32_s" | "i64.extend_s/i32" => I64ExtendSI32,
+        "i64.extend_i32_u" | "i64.extend_u/i32" => I64ExtendUI32,
+        "i64.trunc_f32_s" | "i64.trunc_s/f32" => I64TruncSF32,
+        "i64.trunc_f32_u" | "i64.trunc_u/f32" => I64TruncUF32,
+        "i64.trunc_f64_s" | "i64.trunc_s/f64" => I64TruncSF64,
+        "i64.trunc_f64_u" | "i64.trunc_u/f64" => I64TruncUF64,
+        "f32.convert_i32_s" | "f32.convert_s/i32" => F32ConvertSI32,
+        "f32.convert_i32_u" | "f32.convert_u/i32" => F32ConvertUI32,
+        "f32.convert_i64_s" | "f32.convert_s/i64" => F32ConvertSI64,
+        "f32.convert_i64_u" | "f32.convert_u/i64" => F32ConvertUI64,
+        "f32.demote_f64" | "f32.demote/f64" => F32DemoteF64,
+        "f64.convert_i32_s" | "f64.convert_s/i32" => F64ConvertSI32,
+        "f64.convert_i32_u" | "f64.convert_u/i32" => F64ConvertUI32,
+        "f64.convert_i64_s" | "f64.convert_s/i64" => F64ConvertSI64,
+        "f64.convert_i64_u" | "f64.convert_u/i64" => F64ConvertUI64,
+        "f64.promote_f32" | "f64.promote/f32" => F64PromoteF32,
+        "i32.reinterpret_f32" | "i32.reinterpret/f32" => I32ReinterpretF32,
+        "i64.reinterpret_f64" | "i64.reinterpret/f64" => I64ReinterpretF64,
+        "f32.reinterpret_i32" | "f32.reinterpret/i32" => F32ReinterpretI32,
+        "f64.reinterpret_i64" | "f64.reinterpret/i64" => F64ReinterpretI64,
+        "i32.extend8_s" => I32Extend8S,
+        "i32.extend16_s" => I32Extend16S,
+        "i64.extend8_s" => I64Extend8S,
+        "i64.extend16_s" => I64Extend16S,
+        "i64.extend32_s" => I64Extend32S,
+        "ref.is_null" => RefIsNull,
+        "ref.eq" => RefEq,
+        "ref.as_non_null" => RefAsNonNull,
+        "array.len" => ArrayLen,
+        "any.convert_extern" => AnyConvertExtern,
+        "extern.convert_any" => ExternConvertAny,
+        "ref.i31" => RefI31,
+        "i31.get_s" => I31GetS,
+        "i31.get_u" => I31GetU,
+        "i32.trunc_sat_f32_s" | "i32.trunc_s:sat/f32" => I32TruncSatSF32,
+        "i32.trunc_sat_f32_u" | "i32.trunc_u:sat/f32" => I32TruncSatUF32,
+        "i32.trunc_sat_f64_s" | "i32.trunc_s:sat/f64" => I32TruncSatSF64,
+        "i32.trunc_sat_f64_u" | "i32.trunc_u:sat/f64" => I32TruncSatUF64,
+        "i64.trunc_sat_f32_s" | "i64.trunc_s:sat/f32" => I64TruncSatSF32,
+        "i64.trunc_sat_f32_u" | "i64.trunc_u:sat/f32" => I64TruncSatUF32,
+        "i64.trunc_sat_f64_s" | "i64.trunc_s:sat/f64" => I64TruncSatSF64,
+        "i64.trunc_sat_f64_u" | "i64.trunc_u:sat/f64" => I64TruncSatUF64,
+        "i8x16.swizzle" => I8x16Swizzle,
+        "i8x16.splat" => I8x16Splat,
+        "i16x8.splat" => I16x8Splat,
+        "i32x4.splat" => I32x4Splat,
+        "i64x2.splat" => I64x2Splat,
+        "f32x4.splat" => F32x4Splat,
+        "f64x2.splat" => F64x2Splat,
+        "i8x16.eq" => I8x16Eq,
+        "i8x16.ne" => I8x16Ne,
+        "i8x16.lt_s" => I8x16LtS,
+        "i8x16.lt_u" => I8x16LtU,
+        "i8x16.gt_s" => I8x16GtS,
+        "i8x16.gt_u" => I8x16GtU,
+        "i8x16.le_s" => I8x16LeS,
+        "i8x16.le_u" => I8x16LeU,
+        "i8x16.ge_s" => I8x16GeS,
+        "i8x16.ge_u" => I8x16GeU,
+        "i16x8.eq" => I16x8Eq,
+        "i16x8.ne" => I16x8Ne,
+        "i16x8.lt_s" => I16x8LtS,
+        "i16x8.lt_u" => I16x8LtU,
+        "i16x8.gt_s" => I16x8GtS,
+        "i16x8.gt_u" => I16x8GtU,
+        "i16x8.le_s" => I16x8LeS,
+        "i16x8.le_u" => I16x8LeU,
+        "i16x8.ge_s" => I16x8GeS,
+        "i16x8.ge_u" => I16x8GeU,
+        "i32x4.eq" => I32x4Eq,
+        "i32x4.ne" => I32x4Ne,
+        "i32x4.lt_s" => I32x4LtS,
+        "i32x4.lt_u" => I32x4LtU,
+        "i32x4.gt_s" => I32x4GtS,
+        "i32x4.gt_u" => I32x4GtU,
+        "i32x4.le_s" => I32x4LeS,
+        "i32x4.le_u" => I32x4LeU,
+        "i32x4.ge_s" => I32x4GeS,
+        "i32x4.ge_u" => I32x4GeU,
+        "f32x4.eq" => F32x4Eq,
+        "f32x4.ne" => F32x4Ne,
+        "f32x4.lt" => F32x4Lt,
+        "f32x4.gt" => F32x4Gt,
+        "f32x4.le" => F32x4Le,
+        "f32x4.ge" => F32x4Ge,
+        "f64x2.eq" => F64x2Eq,
+        "f64x2.ne" => F64x2Ne,
+        "f64x2.lt" => F64x2Lt,
+        "f64x2.gt" => F64x2Gt,
+        "f64x2.le" => F64x2Le,
+        "f64x2.ge" => F64x2Ge,
+        "v128.not" => V128Not,
+        "v128.and" => V128And,
+        "v128.andnot" => V128Andnot,
+        "v128.or" => V128Or,
+        "v128.xor" => V128Xor,
+        "v128.bitselect" => V128Bitselect,
+        "v128.any_true" => V128AnyTrue,
+        "f32x4.demote_f64x2_zero" => F32x4DemoteF64x2Zero,
+        "f64x2.promote_low_f32x4" => F64x2PromoteLowF32x4,
+        "i8x16.abs" => I8x16Abs,
+        "i8x16.neg" => I8x16Neg,
+        "i8x16.popcnt" => I8x16Popcnt,
+        "i8x16.all_true" => I8x16AllTrue,
+        "i8x16.bitmask" => I8x16Bitmask,
+        "i8x16.narrow_i16x8_s" => I8x16NarrowI16x8S,
+        "i8x16.narrow_i16x8_u" => I8x16NarrowI16x8U,
+        "f32x4.ceil" => F32x4Ceil,
+        "f32x4.floor" => F32x4Floor,
+        "f32x4.trunc" => F32x4Trunc,
+        "f32x4.nearest" => F32x4Nearest,
+        "i8x16.shl" => I8x16Shl,
+        "i8x16.shr_s" => I8x16ShrS,
+        "i8x16.shr_u" => I8x16ShrU,
+        "i8x16.add" => I8x16Add,
+        "i8x16.add_sat_s" => I8x16AddSatS,
+        "i8x16.add_sat_u" => I8x16AddSatU,
+        "i8x16.sub" => I8x16Sub,
+        "i8x16.sub_sat_s" => I8x16SubSatS,
+        "i8x16.sub_sat_u" => I8x16SubSatU,
+        "f64x2.ceil" => F64x2Ceil,
+        "f64x2.floor" => F64x2Floor,
+        "i8x16.min_s" => I8x16MinS,
+        "i8x16.min_u" => I8x16MinU,
+        "i8x16.max_s" => I8x16MaxS,
+        "i8x16.max_u" => I8x16MaxU,
+        "f64x2.trunc" => F64x2Trunc,
+        "i8x16.avgr_u" => I8x16AvgrU,
+        "i16x8.extadd_pairwise_i8x16_s" => I16x8ExtaddPairwiseI8x16S,
+        "i16x8.extadd_pairwise_i8x16_u" => I16x8ExtaddPairwiseI8x16U,
+        "i32x4.extadd_pairwise_i16x8_s" => I32x4ExtaddPairwiseI16x8S,
+        "i32x4.extadd_pairwise_i16x8_u" => I32x4ExtaddPairwiseI16x8U,
+        "i16x8.abs" => I16x8Abs,
+        "i16x8.neg" => I16x8Neg,
+        "i16x8.q15mulr_sat_s" => I16x8Q15mulrSatS,
+        "i16x8.all_true" => I16x8AllTrue,
+        "i16x8.bitmask" => I16x8Bitmask,
+        "i16x8.narrow_i32x4_s" => I16x8NarrowI32x4S,
+        "i16x8.narrow_i32x4_u" => I16x8NarrowI32x4U,
+        "i16x8.extend_low_i8x16_s" => I16x8ExtendLowI8x16S,
+        "i16x8.extend_high_i8x16_s" => I16x8ExtendHighI8x16S,
+        "i16x8.extend_low_i8x16_u" => I16x8ExtendLowI8x16U,
+        "i16x8.extend_high_i8x16_u" => I16x8ExtendHighI8x16U,
+        "i16x8.shl" => I16x8Shl,
+        "i16x8.shr_s" => I16x8ShrS,
+        "i16x8.shr_u" => I16x8ShrU,
+        "i16x8.add" => I16x8Add,
+        "i16x8.add_sat_s" => I16x8AddSatS,
+        "i16x8.add_sat_u" => I16x8AddSatU,
+        "i16x8.sub" => I16x8Sub,
+        "i16x8.sub_sat_s" => I16x8SubSatS,
+        "i16x8.sub_sat_u" => I16x8SubSatU,
+        "f64x2.nearest" => F64x2Nearest,
+        "i16x8.mul" => I16x8Mul,
+        "i16x8.min_s" => I16x8MinS,
+        "i16x8.min_u" => I16x8MinU,
+        "i16x8.max_s" => I16x8MaxS,
+        "i16x8.max_u" => I16x8MaxU,
+        "i16x8.avgr_u" => I16x8AvgrU,
+        "i16x8.extmul_low_i8x16_s" => I16x8ExtmulLowI8x16S,
+        "i16x8.extmul_high_i8x16_s" => I16x8ExtmulHighI8x16S,
+        "i16x8.extmul_low_i8x16_u" => I16x8ExtmulLowI8x16U,
+        "i16x8.extmul_high_i8x16_u" => I16x8ExtmulHighI8x16U,
+        "i32x4.abs" => I32x4Abs,
+        "i32x4.neg" => I32x4Neg,
+        "i32x4.all_true" => I32x4AllTrue,
+        "i32x4.bitmask" => I32x4Bitmask,
+        "i32x4.extend_low_i16x8_s" => I32x4ExtendLowI16x8S,
+        "i32x4.extend_high_i16x8_s" => I32x4ExtendHighI16x8S,
+        "i32x4.extend_low_i16x8_u" => I32x4ExtendLowI16x8U,
+        "i32x4.extend_high_i16x8_u" => I32x4ExtendHighI16x8U,
+        "i32x4.shl" => I32x4Shl,
+        "i32x4.shr_s" => I32x4ShrS,
+        "i32x4.shr_u" => I32x4ShrU,
+        "i32x4.add" => I32x4Add,
+        "i32x4.sub" => I32x4Sub,
+        "i32x4.mul" => I32x4Mul,
+        "i32x4.min_s" => I32x4MinS,
+        "i32x4.min_u" => I32x4MinU,
+        "i32x4.max_s" => I32x4MaxS,
+        "i32x4.max_u" => I32x4MaxU,
+        "i32x4.dot_i16x8_s" => I32x4DotI16x8S,
+        "i32x4.extmul_low_i16x8_s" => I32x4ExtmulLowI16x8S,
+        "i32x4.extmul_high_i16x8_s" => I32x4ExtmulHighI16x8S,
+        "i32x4.extmul_low_i16x8_u" => I32x4ExtmulLowI16x8U,
+        "i32x4.extmul_high_i16x8_u" => I32x4ExtmulHighI16x8U,
+        "i64x2.abs" => I64x2Abs,
+        "i64x2.neg" => I64x2Neg,
+        "i64x2.all_true" => I64x2AllTrue,
+        "i64x2.bitmask" => I64x2Bitmask,
+        "i64x2.extend_low_i32x4_s" => I64x2ExtendLowI32x4S,
+        "i64x2.extend_high_i32x4_s" => I64x2ExtendHighI32x4S,
+        "i64x2.extend_low_i32x4_u" => I64x2ExtendLowI32x4U,
+        "i64x2.extend_high_i32x4_u" => I64x2ExtendHighI32x4U,
+        "i64x2.shl" => I64x2Shl,
+        "i64x2.shr_s" => I64x2ShrS,
+        "i64x2.shr_u" => I64x2ShrU,
+        "i64x2.add" => I64x2Add,
+        "i64x2.sub" => I64x2Sub,
+        "i64x2.mul" => I64x2Mul,
+        "i64x2.eq" => I64x2Eq,
+        "i64x2.ne" => I64x2Ne,
+        "i64x2.lt_s" => I64x2LtS,
+        "i64x2.gt_s" => I64x2GtS,
+        "i64x2.le_s" => I64x2LeS,
+        "i64x2.ge_s" => I64x2GeS,
+        "i64x2.extmul_low_i32x4_s" => I64x2ExtmulLowI32x4S,
+        "i64x2.extmul_high_i32x4_s" => I64x2ExtmulHighI32x4S,
+        "i64x2.extmul_low_i32x4_u" => I64x2ExtmulLowI32x4U,
+        "i64x2.extmul_high_i32x4_u" => I64x2ExtmulHighI32x4U,
+        "f32x4.abs" => F32x4Abs,
+        "f32x4.neg" => F32x4Neg,
+        "f32x4.sqrt" => F32x4Sqrt,
+        "f32x4.add" => F32x4Add,
+        "f32x4.sub" => F32x4Sub,
+        "f32x4.mul" => F32x4Mul,
+        "f32x4.div" => F32x4Div,
+        "f32x4.min" => F32x4Min,
+        "f32x4.max" => F32x4Max,
+        "f32x4.pmin" => F32x4Pmin,
+        "f32x4.pmax" => F32x4Pmax,
+        "f64x2.abs" => F64x2Abs,
+        "f64x2.neg" => F64x2Neg,
+        "f64x2.sqrt" => F64x2Sqrt,
+        "f64x2.add" => F64x2Add,
+        "f64x2.sub" => F64x2Sub,
+        "f64x2.mul" => F64x2Mul,
+        "f64x2.div" => F64x2Div,
+        "f64x2.min" => F64x2Min,
+        "f64x2.max" => F64x2Max,
+        "f64x2.pmin" => F64x2Pmin,
+        "f64x2.pmax" => F64x2Pmax,
+        "i32x4.trunc_sat_f32x4_s" => I32x4TruncSatF32x4S,
+        "i32x4.trunc_sat_f32x4_u" => I32x4TruncSatF32x4U,
+        "f32x4.convert_i32x4_s" => F32x4ConvertI32x4S,
+        "f32x4.convert_i32x4_u" => F32x4ConvertI32x4U,
+        "i32x4.trunc_sat_f64x2_s_zero" => I32x4TruncSatF64x2SZero,
+        "i32x4.trunc_sat_f64x2_u_zero" => I32x4TruncSatF64x2UZero,
+        "f64x2.convert_low_i32x4_s" => F64x2ConvertLowI32x4S,
+        "f64x2.convert_low_i32x4_u" => F64x2ConvertLowI32x4U,
+        _ => return None,
+    };
+    Some(op)
+}
+
+fn memory_instr(name: &str) -> Option<fn(MemoryImmediate) -> Op> {
+    use ops::Op::*;
+    let ctor: fn(MemoryImmediate) -> Op = match name {
+        "i32.load" => |imm| I32Load { imm: imm },
+        "i64.load" => |imm| I64Load { imm: imm },
+        "f32.load" => |imm| F32Load { imm: imm },
+        "f64.load" => |imm| F64Load { imm: imm },
+        "i32.load8_s" => |imm| I32Load8S { imm: imm },
+        "i32.load8_u" => |imm| I32Load8U { imm: imm },
+        "i32.load16_s" => |imm| I32Load16S { imm: imm },
+        "i32.load16_u" => |imm| I32Load16U { imm: imm },
+        "i64.load8_s" => |imm| I64Load8S { imm: imm },
+        "i64.load8_u" => |imm| I64Load8U { imm: imm },
+        "i64.load16_s" => |imm| I64Load16S { imm: imm },
+        "i64.load16_u" => |imm| I64Load16U { imm: imm },
+        "i64.load32_s" => |imm| I64load32S { imm: imm },
+        "i64.load32_u" => |imm| I64load32U { imm: imm },
+        "i32.store" => |imm| I32Store { imm: imm },
+        "i64.store" => |imm| I64Store { imm: imm },
+        "f32.store" => |imm| F32Store { imm: imm },
+        "f64.store" => |imm| F64Store { imm: imm },
+        "i32.store8" => |imm| I32Store8 { imm: imm },
+        "i32.store16" => |imm| I32Store16 { imm: imm },
+        "i64.store8" => |imm| I64Store8 { imm: imm },
+        "i64.store16" => |imm| I64Store16 { imm: imm },
+        "i64.store32" => |imm| I64Store32 { imm: imm },
+        "v128.load" => |imm| V128Load { imm: imm },
+        "v128.load8x8_s" => |imm| V128Load8x8S { imm: imm },
+        "v128.load8x8_u" => |imm| V128Load8x8U { imm: imm },
+        "v128.load16x4_s" => |imm| V128Load16x4S { imm: imm },
+        "v128.load16x4_u" => |imm| V128Load16x4U { imm: imm },
+        "v128.load32x2_s" => |imm| V128Load32x2S { imm: imm },
+        "v128.load32x2_u" => |imm| V128Load32x2U { imm: imm },
+        "v128.load8_splat" => |imm| V128Load8Splat { imm: imm },
+        "v128.load16_splat" => |imm| V128Load16Splat { imm: imm },
+        "v128.load32_splat" => |imm| V128Load32Splat { imm: imm },
+        "v128.load64_splat" => |imm| V128Load64Splat { imm: imm },
+        "v128.store" => |imm| V128Store { imm: imm },
+        "v128.load32_zero" => |imm| V128Load32Zero { imm: imm },
+        "v128.load64_zero" => |imm| V128Load64Zero { imm: imm },
+        "memory.atomic.notify" => |imm| MemoryAtomicNotify { imm: imm },
+        "memory.atomic.wait32" => |imm| MemoryAtomicWait32 { imm: imm },
+        "memory.atomic.wait64" => |imm| MemoryAtomicWait64 { imm: imm },
+        "i32.atomic.load" => |imm| I32AtomicLoad { imm: imm },
+        "i64.atomic.load" => |imm| I64AtomicLoad { imm: imm },
+        "i32.atomic.load8_u" => |imm| I32AtomicLoad8U { imm: imm },
+        "i32.atomic.load16_u" => |imm| I32AtomicLoad16U { imm: imm },
+        "i64.atomic.load8_u" => |imm| I64AtomicLoad8U { imm: imm },
+        "i64.atomic.load16_u" => |imm| I64AtomicLoad16U { imm: imm },
+        "i64.atomic.load32_u" => |imm| I64AtomicLoad32U { imm: imm },
+        "i32.atomic.store" => |imm| I32AtomicStore { imm: imm },
+        "i64.atomic.store" => |imm| I64AtomicStore { imm: imm },
+        "i32.atomic.store8" => |imm| I32AtomicStore8 { imm: imm },
+        "i32.atomic.store16" => |imm| I32AtomicStore16 { imm: imm },
+        "i64.atomic.store8" => |imm| I64AtomicStore8 { imm: imm },
+        "i64.atomic.store16" => |imm| I64AtomicStore16 { imm: imm },
+        "i64.atomic.store32" => |imm| I64AtomicStore32 { imm: imm },
+        "i32.atomic.rmw.add" => |imm| I32AtomicRmwAdd { imm: imm },
+        "i64.atomic.rmw.add" => |imm| I64AtomicRmwAdd { imm: imm },
+        "i32.atomic.rmw8.add_u" => |imm| I32AtomicRmw8AddU { imm: imm },
+        "i32.atomic.rmw16.add_u" => |imm| I32AtomicRmw16AddU { imm: imm },
+        "i64.atomic.rmw8.add_u" => |imm| I64AtomicRmw8AddU { imm: imm },
+        "i64.atomic.rmw16.add_u" => |imm| I64AtomicRmw16AddU { imm: imm },
+        "i64.atomic.rmw32.add_u" => |imm| I64AtomicRmw32AddU { imm: imm },
+        "i32.atomic.rmw.sub" => |imm| I32AtomicRmwSub { imm: imm },
+        "i64.atomic.rmw.sub" => |imm| I64AtomicRmwSub { imm: imm },
+        "i32.atomic.rmw8.sub_u" => |imm| I32AtomicRmw8SubU { imm: imm },
+        "i32.atomic.rmw16.sub_u" => |imm| I32AtomicRmw16SubU { imm: imm },
+        "i64.atomic.rmw8.sub_u" => |imm| I64AtomicRmw8SubU { imm: imm },
+        "i64.atomic.rmw16.sub_u" => |imm| I64AtomicRmw16SubU { imm: imm },
+        "i64.atomic.rmw32.sub_u" => |imm| I64AtomicRmw32SubU { imm: imm },
+        "i32.atomic.rmw.and" => |imm| I32AtomicRmwAnd { imm: imm },
+        "i64.atomic.rmw.and" => |imm| I64AtomicRmwAnd { imm: imm },
+        "i32.atomic.rmw8.and_u" => |imm| I32AtomicRmw8AndU { imm: imm },
+        "i32.atomic.rmw16.and_u" => |imm| I32AtomicRmw16AndU { imm: imm },
+        "i64.atomic.rmw8.and_u" => |imm| I64AtomicRmw8AndU { imm: imm },
+        "i64.atomic.rmw16.and_u" => |imm| I64AtomicRmw16AndU { imm: imm },
+        "i64.atomic.rmw32.and_u" => |imm| I64AtomicRmw32AndU { imm: imm },
+        "i32.atomic.rmw.or" => |imm| I32AtomicRmwOr { imm: imm },
+        "i64.atomic.rmw.or" => |imm| I64AtomicRmwOr { imm: imm },
+        "i32.atomic.rmw8.or_u" => |imm| I32AtomicRmw8OrU { imm: imm },
+        "i32.atomic.rmw16.or_u" => |imm| I32AtomicRmw16OrU { imm: imm },
+        "i64.atomic.rmw8.or_u" => |imm| I64AtomicRmw8OrU { imm: imm },
+        "i64.atomic.rmw16.or_u" => |imm| I64AtomicRmw16OrU { imm: imm },
+        "i64.atomic.rmw32.or_u" => |imm| I64AtomicRmw32OrU { imm: imm },
+        "i32.atomic.rmw.xor" => |imm| I32AtomicRmwXor { imm: imm },
+        "i64.atomic.rmw.xor" => |imm| I64AtomicRmwXor { imm: imm },
+        "i32.atomic.rmw8.xor_u" => |imm| I32AtomicRmw8XorU { imm: imm },
+        "i32.atomic.rmw16.xor_u" => |imm| I32AtomicRmw16XorU { imm: imm },
+        "i64.atomic.rmw8.xor_u" => |imm| I64AtomicRmw8XorU { imm: imm },
+        "i64.atomic.rmw16.xor_u" => |imm| I64AtomicRmw16XorU { imm: imm },
+        "i64.atomic.rmw32.xor_u" => |imm| I64AtomicRmw32XorU { imm: imm },
+        "i32.atomic.rmw.xchg" => |imm| I32AtomicRmwXchg { imm: imm },
+        "i64.atomic.rmw.xchg" => |imm| I64AtomicRmwXchg { imm: imm },
+        "i32.atomic.rmw8.xchg_u" => |imm| I32AtomicRmw8XchgU { imm: imm },
+        "i32.atomic.rmw16.xchg_u" => |imm| I32AtomicRmw16XchgU { imm: imm },
+        "i64.atomic.rmw8.xchg_u" => |imm| I64AtomicRmw8XchgU { imm: imm },
+        "i64.atomic.rmw16.xchg_u" => |imm| I64AtomicRmw16XchgU { imm: imm },
+        "i64.atomic.rmw32.xchg_u" => |imm| I64AtomicRmw32XchgU { imm: imm },
+        "i32.atomic.rmw.cmpxchg" => |imm| I32AtomicRmwCmpxchg { imm: imm },
+        "i64.atomic.rmw.cmpxchg" => |imm| I64AtomicRmwCmpxchg { imm: imm },
+        "i32.atomic.rmw8.cmpxchg_u" => |imm| I32AtomicRmw8CmpxchgU { imm: imm },
+        "i32.atomic.rmw16.cmpxchg_u" => |imm| I32AtomicRmw16CmpxchgU { imm: imm },
+        "i64.atomic.rmw8.cmpxchg_u" => |imm| I64AtomicRmw8CmpxchgU { imm: imm },
+        "i64.atomic.rmw16.cmpxchg_u" => |imm| I64AtomicRmw16CmpxchgU { imm: imm },
+        "i64.atomic.rmw32.cmpxchg_u" => |imm| I64AtomicRmw32CmpxchgU { imm: imm },
+        _ => return None,
+    };
+    Some(ctor)
+}
+
+fn lane_memory_instr(name: &str) -> Option<fn(MemoryImmediate, u8) -> Op> {
+    use ops::Op::*;
+    let ctor: fn(MemoryImmediate, u8) -> Op = match name {
+        "v128.load8_lane" => |imm, lane| V128Load8Lane { imm: imm, lane: lane },
+        "v128.load16_lane" => |imm, lane| V128Load16Lane { imm: imm, lane: lane },
+        "v128.load32_lane" => |imm, lane| V128Load32Lane { imm: imm, lane: lane },
+        "v128.load64_lane" => |imm, lane| V128Load64Lane { imm: imm, lane: lane },
+        "v128.store8_lane" => |imm, lane| V128Store8Lane { imm: imm, lane: lane },
+        "v128.store16_lane" => |imm, lane| V128Store16Lane { imm: imm, lane: lane },
+        "v128.store32_lane" => |imm, lane| V128Store32Lane { imm: imm, lane: lane },
+        "v128.store64_lane" => |imm, lane| V128Store64Lane { imm: imm, lane: lane },
+        _ => return None,
+    };
+    Some(ctor)
+}
+
+fn lane_instr(name: &str) -> Option<fn(u8) -> Op> {
+    use ops::Op::*;
+    let ctor: fn(u8) -> Op = match name {
+        "i8x16.extract_lane_s" => I8x16ExtractLaneS,
+        "i8x16.extract_lane_u" => I8x16ExtractLaneU,
+        "i8x16.replace_lane" => I8x16ReplaceLane,
+        "i16x8.extract_lane_s" => I16x8ExtractLaneS,
+        "i16x8.extract_lane_u" => I16x8ExtractLaneU,
+        "i16x8.replace_lane" => I16x8ReplaceLane,
+        "i32x4.extract_lane" => I32x4ExtractLane,
+        "i32x4.replace_lane" => I32x4ReplaceLane,
+        "i64x2.extract_lane" => I64x2ExtractLane,
+        "i64x2.replace_lane" => I64x2ReplaceLane,
+        "f32x4.extract_lane" => F32x4ExtractLane,
+        "f32x4.replace_lane" => F32x4ReplaceLane,
+        "f64x2.extract_lane" => F64x2ExtractLane,
+        "f64x2.replace_lane" => F64x2ReplaceLane,
+        _ => return None,
+    };
+    Some(ctor)
+}
+
+/// A cursor over the items of a list.
+struct Cursor<'a> {
+    items: &'a [Sexp],
+    pos: usize,
+    /// where the list ends, for errors about missing items
+    end: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn new(items: &'a [Sexp], end: usize) -> Self {
+        Cursor {
+            items: items,
+            pos: 0,
+            end: end,
+        }
+    }
+
+    fn list(sexp: &'a Sexp) -> Self {
+        match *sexp {
+            Sexp::List(ref items, offset) => {
+                let mut cursor = Cursor::new(items, offset);
+                // skip the head
+                cursor.pos = 1;
+                cursor
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    fn list_checked(sexp: &'a Sexp) -> Result<Self> {
+        match sexp.head() {
+            Some(_) => Ok(Cursor::list(sexp)),
+            None => Err(sexp.unexpected()),
+        }
+    }
+
+    fn peek(&self) -> Option<&'a Sexp> {
+        self.items.get(self.pos)
+    }
+
+    fn next(&mut self) -> Result<&'a Sexp> {
+        let item = self.items.get(self.pos).ok_or(Fail(self.end, WatErrorKind::UnexpectedEof))?;
+        self.pos += 1;
+        Ok(item)
+    }
+
+    fn is_done(&self) -> bool {
+        self.pos == self.items.len()
+    }
+
+    fn finish(&self) -> Result<()> {
+        match self.peek() {
+            None => Ok(()),
+            Some(item) => Err(item.unexpected()),
+        }
+    }
+
+    fn peek_head(&self) -> Option<&'a str> {
+        self.peek().and_then(|s| s.head())
+    }
+
+    fn peek_atom(&self) -> Option<&'a str> {
+        match self.peek() {
+            Some(&Sexp::Atom(ref a, _)) => Some(a),
+            _ => None,
+        }
+    }
+
+    /// Takes the next item if it is a list starting with `head`.
+    fn take_list(&mut self, head: &str) -> Option<&'a Sexp> {
+        if self.peek_head() == Some(head) {
+            self.pos += 1;
+            Some(&self.items[self.pos - 1])
+        } else {
+            None
+        }
+    }
+
+    fn id(&mut self) -> Option<(&'a str, usize)> {
+        match self.peek() {
+            Some(&Sexp::Id(ref id, offset)) => {
+                self.pos += 1;
+                Some((id, offset))
+            }
+            _ => None,
+        }
+    }
+
+    fn atom(&mut self) -> Result<(&'a str, usize)> {
+        match *self.next()? {
+            Sexp::Atom(ref a, offset) => Ok((a, offset)),
+            ref other => Err(other.unexpected()),
+        }
+    }
+
+    fn keyword(&mut self, kw: &str) -> Result<()> {
+        match *self.next()? {
+            Sexp::Atom(ref a, _) if a == kw => Ok(()),
+            ref other => Err(other.unexpected()),
+        }
+    }
+
+    fn bytes(&mut self) -> Result<&'a [u8]> {
+        match *self.next()? {
+            Sexp::Str(ref s, _) => Ok(s),
+            ref other => Err(other.unexpected()),
+        }
+    }
+
+    fn name(&mut self) -> Result<String> {
+        let offset = self.peek().map_or(self.end, |s| s.offset());
+        String::from_utf8(self.bytes()?.to_vec()).map_err(|_| Fail(offset, WatErrorKind::InvalidUtf8))
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        let (a, offset) = self.atom()?;
+        parse_nat(a)
+            .filter(|&n| n <= u32::MAX as u64)
+            .map(|n| n as u32)
+            .ok_or_else(|| Fail(offset, WatErrorKind::InvalidNumber(a.to_string())))
+    }
+
+    fn u64(&mut self) -> Result<u64> {
+        let (a, offset) = self.atom()?;
+        parse_nat(a).ok_or_else(|| Fail(offset, WatErrorKind::InvalidNumber(a.to_string())))
+    }
+
+    /// A lane index, which the validator checks against the vector shape.
+    fn lane(&mut self) -> Result<u8> {
+        let (a, offset) = self.atom()?;
+        parse_nat(a)
+            .filter(|&n| n <= u8::MAX as u64)
+            .map(|n| n as u8)
+            .ok_or_else(|| Fail(offset, WatErrorKind::InvalidNumber(a.to_string())))
+    }
+
+    /// A value type, resolving the `$names` of defined types in references.
+    fn value_type(&mut self, types: &Space) -> Result<ValueType> {
+        let ty = match self.peek_atom() {
+            Some("i32") => ValueType::I32,
+            Some("i64") => ValueType::I64,
+            Some("f32") => ValueType::F32,
+            Some("f64") => ValueType::F64,
+            Some("v128") => ValueType::V128,
+            _ => return Ok(ValueType::reference(self.ref_type(types)?)),
+        };
+        self.pos += 1;
+        Ok(ty)
+    }
+
+    /// `(ref null? ht)` or the shorthand of a nullable abstract reference like `anyref`.
+    fn ref_type(&mut self, types: &Space) -> Result<RefType> {
+        let item = self.next()?;
+        match *item {
+            Sexp::Atom(ref a, _) => {
+                HEAP_TYPES.iter()
+                    .find(|h| h.2 == a)
+                    .map(|h| RefType::nullable(h.0))
+                    .ok_or_else(|| item.unexpected())
+            }
+            Sexp::List(..) if item.head() == Some("ref") => {
+                let mut inner = Cursor::list(item);
+                let nullable = inner.peek_atom() == Some("null");
+                if nullable {
+                    inner.pos += 1;
+                }
+                let heap = inner.heap_type(types)?;
+                inner.finish()?;
+                Ok(RefType {
+                       nullable: nullable,
+                       heap: heap,
+                   })
+            }
+            _ => Err(item.unexpected()),
+        }
+    }
+
+    /// An abstract heap type like `func`, or a defined type.
+    fn heap_type(&mut self, types: &Space) -> Result<HeapType> {
+        if let Some(a) = self.peek_atom() {
+            if let Some(h) = HEAP_TYPES.iter().find(|h| h.1 == a) {
+                self.pos += 1;
+                return Ok(h.0);
+            }
+        }
+        Ok(HeapType::Type(TypeIndex::new(types.resolve(self)?)))
+    }
+}
+
+/// An index space with its `$names`.
+#[derive(Default)]
+struct Space {
+    names: HashMap<String, u32>,
+    count: u32,
+}
+
+impl Space {
+    fn push(&mut self, id: Option<(&str, usize)>) -> Result<u32> {
+        if let Some((id, offset)) = id {
+            if self.names.insert(id.to_string(), self.count).is_some() {
+                return Err(Fail(offset, WatErrorKind::DuplicateName(id.to_string())));
+            }
+        }
+        self.count += 1;
+        Ok(self.count - 1)
+    }
+
+    fn resolve(&self, cursor: &mut Cursor) -> Result<u32> {
+        match cursor.id() {
+            Some((id, offset)) => {
+                self.names
+                    .get(id)
+                    .cloned()
+                    .ok_or_else(|| Fail(offset, WatErrorKind::UnknownName(id.to_string())))
+            }
+            None => cursor.u32(),
+        }
+    }
+}
+
+/// Names and labels in scope while parsing instructions.
+#[derive(Default)]
+struct Scope {
+    locals: Space,
+    labels: Vec<Option<String>>,
+}
+
+impl Scope {
+    fn label(&self, cursor: &mut Cursor) -> Result<u32> {
+        match cursor.id() {
+            Some((id, offset)) => {
+                self.labels
+                    .iter()
+                    .rev()
+                    .position(|l| l.as_ref().map(|l| l.as_str()) == Some(id))
+                    .map(|depth| depth as u32)
+                    .ok_or_else(|| Fail(offset, WatErrorKind::UnknownName(id.to_string())))
+            }
+            None => cursor.u32(),
+        }
+    }
+
+    /// The optional label repeated after `else` or `end` has to name the block.
+    fn check_label(&self, cursor: &mut Cursor) -> Result<()> {
+        if let Some((id, offset)) = cursor.id() {
+            match self.labels.last() {
+                Some(&Some(ref label)) if label == id => {}
+                _ => return Err(Fail(offset, WatErrorKind::LabelMismatch(id.to_string()))),
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+struct Assembler {
+    types: Space,
+    /// the `$names` of the fields of each struct type
+    field_names: HashMap<u32, Space>,
+    funcs: Space,
+    tables: Space,
+    memories: Space,
+    tags: Space,
+    globals: Space,
+    elem_segments: Space,
+    data_segments: Space,
+
+    imports: Vec<ImportEntry>,
+    functions: Vec<Function>,
+    codes: Vec<FunctionBody>,
+    table_defs: Vec<TableType>,
+    memory_defs: Vec<MemoryType>,
+    tag_defs: Vec<TagType>,
+    global_defs: Vec<GlobalVariable>,
+    exports: Vec<ExportEntry>,
+    start: Option<FunctionIndex>,
+    elements: Vec<ElemSegment>,
+    data: Vec<DataSegment>,
+    customs: Vec<CustomSection>,
+    type_defs: Vec<RecGroup>,
+}
+
+fn assemble(sexps: &[Sexp], len: usize) -> Result<Module> {
+    let mut fields = Cursor::new(sexps, len);
+    if sexps.len() == 1 && sexps[0].head() == Some("module") {
+        fields = Cursor::list(&sexps[0]);
+        fields.id();
+    }
+    let fields = &fields.items[fields.pos..];
+
+    let mut asm = Assembler::default();
+    // the index every func, table, memory, tag and global field gets
+    let mut indices = Vec::new();
+    let mut defined = false;
+    for field in fields {
+        let head = field.head().ok_or_else(|| field.unexpected())?;
+        let mut cursor = Cursor::list(field);
+        let id = cursor.id();
+        let index = match head {
+            "type" => asm.types.push(id)?,
+            "rec" => {
+                if id.is_some() {
+                    return Err(field.unexpected());
+                }
+                while let Some(item) = cursor.peek() {
+                    if item.head() != Some("type") {
+                        return Err(item.unexpected());
+                    }
+                    asm.types.push(Cursor::list(item).id())?;
+                    cursor.pos += 1;
+                }
+                0
+            }
+            "import" => {
+                if defined {
+                    return Err(Fail(field.offset(), WatErrorKind::ImportAfterDefinition));
+                }
+                cursor.name()?;
+                cursor.name()?;
+                let desc = cursor.next()?;
+                let mut desc_cursor = Cursor::list_checked(desc)?;
+                let id = desc_cursor.id();
+                asm.space(desc.head().unwrap(), desc)?.push(id)?
+            }
+            "func" | "table" | "memory" | "tag" | "global" => {
+                while cursor.take_list("export").is_some() {}
+                if cursor.peek_head() == Some("import") {
+                    if defined {
+                        return Err(Fail(field.offset(), WatErrorKind::ImportAfterDefinition));
+                    }
+                } else {
+                    defined = true;
+                }
+                // an inline `(elem ..)` or `(data ..)` is a segment too
+                let inline = |kw| list_items(field).iter().any(|item| item.head() == Some(kw));
+                match head {
+                    "table" if inline("elem") => {
+                        asm.elem_segments.push(None)?;
+                    }
+                    "memory" if inline("data") => {
+                        asm.data_segments.push(None)?;
+                    }
+                    _ => {}
+                }
+                asm.space(head, field)?.push(id)?
+            }
+            "elem" => asm.elem_segments.push(id)?,
+            "data" => asm.data_segments.push(id)?,
+            "export" | "start" | "@custom" => 0,
+            _ => return Err(field.unexpected()),
+        };
+        indices.push(index);
+    }
+
+    // types refer to each other by name, so they are parsed once all of them are named
+    let mut ntypes = 0;
+    for field in fields {
+        let mut cursor = Cursor::list(field);
+        match field.head().unwrap() {
+            "type" => {
+                cursor.id();
+                let ty = asm.type_def(&mut cursor, ntypes)?;
+                cursor.finish()?;
+                asm.type_defs.push(ty.into());
+                ntypes += 1;
+            }
+            "rec" => {
+                let mut group = Vec::new();
+                while !cursor.is_done() {
+                    let mut inner = Cursor::list(cursor.next()?);
+                    inner.id();
+                    group.push(asm.type_def(&mut inner, ntypes)?);
+                    inner.finish()?;
+                    ntypes += 1;
+                }
+                asm.type_defs.push(RecGroup { types: group });
+            }
+            _ => {}
+        }
+    }
+
+    for (field, index) in fields.iter().zip(indices) {
+        let mut cursor = Cursor::list(field);
+        match field.head().unwrap() {
+            "type" | "rec" => continue,
+            "import" => asm.import(&mut cursor)?,
+            "func" => asm.func(&mut cursor, index)?,
+            "table" => asm.table(&mut cursor, index)?,
+            "memory" => asm.memory(&mut cursor, index)?,
+            "tag" => asm.tag(&mut cursor, index)?,
+            "global" => asm.global(&mut cursor, index)?,
+            "export" => asm.export(&mut cursor)?,
+            "start" => {
+                let index = asm.funcs.resolve(&mut cursor)?;
+                if asm.start.is_some() {
+                    return Err(field.unexpected());
+                }
+                asm.start = Some(FunctionIndex(index));
+            }
+            "elem" => asm.elem(&mut cursor)?,
+            "data" => asm.data(&mut cursor)?,
+            "@custom" => asm.custom(&mut cursor)?,
+            _ => unreachable!(),
+        }
+        cursor.finish()?;
+    }
+    Ok(asm.finish())
+}
+
+fn some_if_any<T>(v: Vec<T>) -> Option<Vec<T>> {
+    if v.is_empty() { None } else { Some(v) }
+}
+
+impl Assembler {
+    fn space(&mut self, kind: &str, at: &Sexp) -> Result<&mut Space> {
+        match kind {
+            "func" => Ok(&mut self.funcs),
+            "table" => Ok(&mut self.tables),
+            "memory" => Ok(&mut self.memories),
+            "tag" => Ok(&mut self.tags),
+            "global" => Ok(&mut self.globals),
+            _ => Err(at.unexpected()),
+        }
+    }
+
+    fn finish(self) -> Module {
+        let nimports = self.funcs.count - self.functions.len() as u32;
+        let mut codes = self.codes;
+        for code in codes.iter_mut() {
+            code.classify_functions(nimports);
+        }
+        let mut globals = self.global_defs;
+        for g in globals.iter_mut() {
+            g.init.classify_functions(nimports);
+        }
+        let mut elements = self.elements;
+        for e in elements.iter_mut() {
+            e.elems.classify_functions(nimports);
+        }
+        let mut module = Module {
+            customs: some_if_any(self.customs),
+            types: some_if_any(self.type_defs),
+            imports: some_if_any(self.imports),
+            functions: some_if_any(self.functions),
+            tables: some_if_any(self.table_defs),
+            memories: some_if_any(self.memory_defs),
+            tags: some_if_any(self.tag_defs),
+            globals: some_if_any(globals),
+            exports: some_if_any(self.exports),
+            start: self.start,
+            elements: some_if_any(elements),
+            data_count: None,
+            codes: some_if_any(codes),
+            data: some_if_any(self.data),
+            names: None,
+        };
+        if module.needs_data_count() {
+            module.data_count = Some(self.data_segments.count);
+        }
+        module
+    }
+
+    /// `(sub final? x* comptype)`, or a composite type that is final without supertypes.
+    fn type_def(&mut self, cursor: &mut Cursor, index: u32) -> Result<SubType> {
+        let item = cursor.next()?;
+        if item.head() != Some("sub") {
+            return Ok(SubType::from_composite(self.composite_type(item, index)?));
+        }
+        let mut inner = Cursor::list(item);
+        let is_final = inner.peek_atom() == Some("final");
+        if is_final {
+            inner.pos += 1;
+        }
+        let mut supertypes = Vec::new();
+        while inner.peek().is_some_and(is_index) {
+            supertypes.push(TypeIndex::new(self.types.resolve(&mut inner)?));
+        }
+        let composite = self.composite_type(inner.next()?, index)?;
+        inner.finish()?;
+        Ok(SubType {
+               is_final: is_final,
+               supertypes: supertypes,
+               composite: composite,
+           })
+    }
+
+    /// `(func (param ..)* (result ..)*)`, `(struct (field ..)*)` or `(array ft)`
+    fn composite_type(&mut self, item: &Sexp, index: u32) -> Result<CompositeType> {
+        let mut inner = Cursor::list_checked(item)?;
+        let composite = match item.head() {
+            Some("func") => CompositeType::Func(signature(&mut inner, &self.types)?.1),
+            Some("struct") => {
+                let mut fields = Vec::new();
+                let mut names = Space::default();
+                while let Some(field) = inner.take_list("field") {
+                    let mut field = Cursor::list(field);
+                    match field.id() {
+                        Some(id) => {
+                            names.push(Some(id))?;
+                            fields.push(field_type(&mut field, &self.types)?);
+                        }
+                        None => {
+                            while !field.is_done() {
+                                names.push(None)?;
+                                fields.push(field_type(&mut field, &self.types)?);
+                            }
+                        }
+                    }
+                    field.finish()?;
+                }
+                self.field_names.insert(index, names);
+                CompositeType::Struct(fields)
+            }
+            Some("array") => CompositeType::Array(field_type(&mut inner, &self.types)?),
+            _ => return Err(item.unexpected()),
+        };
+        inner.finish()?;
+        Ok(composite)
+    }
+
+    /// The type at `index` if it is a function type.
+    fn func_type(&self, index: u32) -> Option<&FuncType> {
+        self.type_defs
+            .iter()
+            .flat_map(|g| g.types.iter())
+            .nth(index as usize)
+            .and_then(|t| t.func_type())
+    }
+
+    /// `(type x)? (param ..)* (result ..)*`. Returns the type index and the parameter names.
+    /// Signatures without `(type x)` reuse an equal type or append one.
+    fn type_use(&mut self, cursor: &mut Cursor) -> Result<(u32, Vec<Name>)> {
+        let explicit = match cursor.take_list("type") {
+            Some(ty) => {
+                let mut inner = Cursor::list(ty);
+                let index = self.types.resolve(&mut inner)?;
+                inner.finish()?;
+                Some((index, ty.offset()))
+            }
+            None => None,
+        };
+        let start = cursor.pos;
+        let (names, ty) = signature(cursor, &self.types)?;
+        let inline = cursor.pos != start;
+        match explicit {
+            Some((index, offset)) => {
+                let declared = self.func_type(index);
+                if inline && declared != Some(&ty) {
+                    return Err(Fail(offset, WatErrorKind::TypeMismatch));
+                }
+                let nparams = declared.map_or(0, |t| t.params.len());
+                let names = if inline { names } else { vec![None; nparams] };
+                Ok((index, names))
+            }
+            None => Ok((self.intern_type(ty), names)),
+        }
+    }
+
+    /// The index of an inline signature, appending a type if no group of just that type matches.
+    fn intern_type(&mut self, ty: FuncType) -> u32 {
+        let group = RecGroup::from(ty);
+        let mut index = 0;
+        for g in self.type_defs.iter() {
+            if *g == group {
+                return index;
+            }
+            index += g.types.len() as u32;
+        }
+        self.type_defs.push(group);
+        index
+    }
+
+    /// `(export "name")*` of a definition.
+    fn inline_exports(&mut self, cursor: &mut Cursor, kind: &str, index: u32) -> Result<()> {
+        while let Some(export) = cursor.take_list("export") {
+            let mut inner = Cursor::list(export);
+            let field = inner.name()?;
+            inner.finish()?;
+            self.exports.push(ExportEntry {
+                                  field: field,
+                                  kind: export_kind(kind, index),
+                              });
+        }
+        Ok(())
+    }
+
+    /// `(import "module" "field")` of a definition.
+    fn inline_import(&mut self, cursor: &mut Cursor) -> Result<Option<(String, String)>> {
+        match cursor.take_list("import") {
+            Some(import) => {
+                let mut inner = Cursor::list(import);
+                let names = (inner.name()?, inner.name()?);
+                inner.finish()?;
+                Ok(Some(names))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn import(&mut self, cursor: &mut Cursor) -> Result<()> {
+        let module = cursor.name()?;
+        let field = cursor.name()?;
+        let desc = cursor.next()?;
+        let mut inner = Cursor::list(desc);
+        inner.id();
+        let kind = self.import_kind(desc.head().unwrap(), &mut inner)?;
+        inner.finish()?;
+        self.imports.push(ImportEntry {
+                              module: module,
+                              field: field,
+                              kind: kind,
+                          });
+        Ok(())
+    }
+
+    fn import_kind(&mut self, kind: &str, cursor: &mut Cursor) -> Result<ImportKind> {
+        Ok(match kind {
+               "func" => ImportKind::Function(TypeIndex::new(self.type_use(cursor)?.0)),
+               "table" => ImportKind::Table(table_type(cursor, &self.types)?),
+               "memory" => ImportKind::Memory(memory_type(cursor)?),
+               "global" => ImportKind::Global(global_type(cursor, &self.types)?),
+               "tag" => ImportKind::Tag(TagType { ty: TypeIndex::new(self.type_use(cursor)?.0) }),
+               _ => unreachable!(),
+           })
+    }
+
+    /// Imports written as a definition with an `(import ..)`.
+    fn as_import(&mut self, cursor: &mut Cursor, kind: &str, index: u32) -> Result<bool> {
+        self.inline_exports(cursor, kind, index)?;
+        match self.inline_import(cursor)? {
+            Some((module, field)) => {
+                let kind = self.import_kind(kind, cursor)?;
+                self.imports.push(ImportEntry {
+                                      module: module,
+                                      field: field,
+                                      kind: kind,
+                                  });
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    fn func(&mut self, cursor: &mut Cursor, index: u32) -> Result<()> {
+        cursor.id();
+        if self.as_import(cursor, "func", index)? {
+            return Ok(());
+        }
+        let (ty, params) = self.type_use(cursor)?;
+        let mut scope = Scope::default();
+        for name in params {
+            scope.locals.push(name.as_ref().map(|&(ref n, o)| (n.as_str(), o)))?;
+        }
+        let mut locals = Vec::new();
+        while let Some(local) = cursor.take_list("local") {
+            let mut inner = Cursor::list(local);
+            match inner.id() {
+                Some(id) => {
+                    scope.locals.push(Some(id))?;
+                    locals.push(inner.value_type(&self.types)?);
+                }
+                None => {
+                    while !inner.is_done() {
+                        scope.locals.push(None)?;
+                        locals.push(inner.value_type(&self.types)?);
+                    }
+                }
+            }
+            inner.finish()?;
+        }
+        let mut code = Vec::new();
+        self.instrs(cursor, &mut scope, &mut code)?;
+        self.functions.push(Function(TypeIndex::new(ty)));
+        self.codes.push(FunctionBody {
+                            locals: locals.into_iter().map(|ty| LocalEntry { count: 1, ty: ty }).collect(),
+                            code: Code(code),
+                        });
+        Ok(())
+    }
+
+    fn table(&mut self, cursor: &mut Cursor, index: u32) -> Result<()> {
+        cursor.id();
+        if self.as_import(cursor, "table", index)? {
+            return Ok(());
+        }
+        let table = if cursor.peek_atom().is_some_and(|a| elem_type(a).is_some()) {
+            // `funcref (elem x*)` or `reftype (elem item*)` sizes the table to the segment
+            let element = elem_type(cursor.atom()?.0).unwrap();
+            let elem = cursor.take_list("elem").ok_or_else(|| match cursor.peek() {
+                    Some(item) => item.unexpected(),
+                    None => Fail(cursor.end, WatErrorKind::UnexpectedEof),
+                })?;
+            let mut inner = Cursor::list(elem);
+            let elems = if inner.peek_head().is_some() {
+                Elements::Expressions(element, self.elem_exprs(&mut inner)?)
+            } else {
+                Elements::Functions(self.func_indices(&mut inner)?)
+            };
+            let n = elems.len() as u32;
+            self.elements.push(ElemSegment {
+                                   mode: ElemMode::Active {
+                                       index: TableIndex::new(index),
+                                       offset: InitExpr(Code(vec![Op::I32Const(0), Op::End])),
+                                   },
+                                   elems: elems,
+                               });
+            TableType {
+                element: element.into(),
+                limits: ResizableLimits::new(n.into()).max(n.into()),
+            }
+        } else {
+            table_type(cursor, &self.types)?
+        };
+        self.table_defs.push(table);
+        Ok(())
+    }
+
+    fn memory(&mut self, cursor: &mut Cursor, index: u32) -> Result<()> {
+        cursor.id();
+        if self.as_import(cursor, "memory", index)? {
+            return Ok(());
+        }
+        let memory64 = index_type(cursor)?;
+        let memory = match cursor.take_list("data") {
+            // `(data "..")` sizes the memory to the segment
+            Some(data) => {
+                let mut inner = Cursor::list(data);
+                let mut bytes = Vec::new();
+                while !inner.is_done() {
+                    bytes.extend_from_slice(inner.bytes()?);
+                }
+                let pages = bytes.len().div_ceil(0x10000) as u64;
+                let offset = if memory64 { Op::I64Const(0) } else { Op::I32Const(0) };
+                self.data.push(DataSegment {
+                                   mode: DataMode::Active {
+                                       index: MemoryIndex::new(index),
+                                       offset: InitExpr(Code(vec![offset, Op::End])),
+                                   },
+                                   data: bytes,
+                               });
+                MemoryType {
+                    limits: ResizableLimits::new(pages).max(pages),
+                    shared: false,
+                    memory64: memory64,
+                }
+            }
+            None => memory_limits(cursor, memory64)?,
+        };
+        self.memory_defs.push(memory);
+        Ok(())
+    }
+
+    fn tag(&mut self, cursor: &mut Cursor, index: u32) -> Result<()> {
+        cursor.id();
+        if self.as_import(cursor, "tag", index)? {
+            return Ok(());
+        }
+        let (ty, _) = self.type_use(cursor)?;
+        self.tag_defs.push(TagType { ty: TypeIndex::new(ty) });
+        Ok(())
+    }
+
+    fn global(&mut self, cursor: &mut Cursor, index: u32) -> Result<()> {
+        cursor.id();
+        if self.as_import(cursor, "global", index)? {
+            return Ok(());
+        }
+        let ty = global_type(cursor, &self.types)?;
+        let init = self.expr(cursor)?;
+        self.global_defs.push(GlobalVariable {
+                                  ty: ty,
+                                  init: init,
+                              });
+        Ok(())
+    }
+
+    fn export(&mut self, cursor: &mut Cursor) -> Result<()> {
+        let field = cursor.name()?;
+        let desc = cursor.next()?;
+        let kind = desc.head().ok_or_else(|| desc.unexpected())?;
+        let mut inner = Cursor::list(desc);
+        let index = self.space(kind, desc)?.resolve(&mut inner)?;
+        inner.finish()?;
+        self.exports.push(ExportEntry {
+                              field: field,
+                              kind: export_kind(kind, index),
+                          });
+        Ok(())
+    }
+
+    /// `(elem (table x)? offset elemlist)`, where the table may also be a bare index,
+    /// or `(elem declare? elemlist)` for a declarative or passive segment.
+    fn elem(&mut self, cursor: &mut Cursor) -> Result<()> {
+        cursor.id();
+        let declare = cursor.peek_atom() == Some("declare");
+        if declare {
+            cursor.pos += 1;
+        }
+        let kind = cursor.peek_atom();
+        if declare || kind == Some("func") || kind.and_then(elem_type).is_some() {
+            let elems = self.elem_list(cursor)?;
+            self.elements.push(ElemSegment {
+                                   mode: if declare { ElemMode::Declarative } else { ElemMode::Passive },
+                                   elems: elems,
+                               });
+            return Ok(());
+        }
+        let table = match cursor.take_list("table") {
+            Some(table) => {
+                let mut inner = Cursor::list(table);
+                let index = self.tables.resolve(&mut inner)?;
+                inner.finish()?;
+                index
+            }
+            None if cursor.peek_head().is_none() => self.tables.resolve(cursor)?,
+            None => 0,
+        };
+        let offset = self.offset(cursor)?;
+        let elems = self.elem_list(cursor)?;
+        self.elements.push(ElemSegment {
+                               mode: ElemMode::Active {
+                                   index: TableIndex::new(table),
+                                   offset: offset,
+                               },
+                               elems: elems,
+                           });
+        Ok(())
+    }
+
+    /// `(data (memory x)? offset "bytes"*)`, where the memory may also be a bare index,
+    /// or `(data "bytes"*)` for a passive segment.
+    fn data(&mut self, cursor: &mut Cursor) -> Result<()> {
+        cursor.id();
+        if let None | Some(&Sexp::Str(..)) = cursor.peek() {
+            let mut bytes = Vec::new();
+            while !cursor.is_done() {
+                bytes.extend_from_slice(cursor.bytes()?);
+            }
+            self.data.push(DataSegment {
+                               mode: DataMode::Passive,
+                               data: bytes,
+                           });
+            return Ok(());
+        }
+        let memory = match cursor.take_list("memory") {
+            Some(memory) => {
+                let mut inner = Cursor::list(memory);
+                let index = self.memories.resolve(&mut inner)?;
+                inner.finish()?;
+                index
+            }
+            None if cursor.peek_head().is_none() => self.memories.resolve(cursor)?,
+            None => 0,
+        };
+        let offset = self.offset(cursor)?;
+        let mut bytes = Vec::new();
+        while !cursor.is_done() {
+            bytes.extend_from_slice(cursor.bytes()?);
+        }
+        self.data.push(DataSegment {
+                           mode: DataMode::Active {
+                               index: MemoryIndex::new(memory),
+                               offset: offset,
+                           },
+                           data: bytes,
+                       });
+        Ok(())
+    }
+
+    /// `(@custom "name" (before|after section)? "payload"*)`, placed after
+    /// the last section when no placement is given.
+    fn custom(&mut self, cursor: &mut Cursor) -> Result<()> {
+        let name = cursor.name()?;
+        let mut placement = Placement::After(SectionId::Data);
+        if let Some(head) = cursor.peek_head() {
+            let mut inner = Cursor::list(cursor.next()?);
+            let (section, offset) = inner.atom()?;
+            let id = match section {
+                "first" if head == "before" => None,
+                "last" if head == "after" => Some(SectionId::Data),
+                _ => {
+                    let id = (1..14).filter_map(SectionId::from_id).find(|&id| section_name(id) == section);
+                    Some(id.ok_or_else(|| Fail(offset, WatErrorKind::UnexpectedToken(section.to_string())))?)
+                }
+            };
+            placement = match (head, id) {
+                ("after", Some(id)) => Placement::After(id),
+                ("before", None) => Placement::First,
+                ("before", Some(id)) => id.previous().map_or(Placement::First, Placement::After),
+                _ => return Err(Fail(offset, WatErrorKind::UnexpectedToken(section.to_string()))),
+            };
+            inner.finish()?;
+        }
+        let mut payload = Vec::new();
+        while !cursor.is_done() {
+            payload.extend_from_slice(cursor.bytes()?);
+        }
+        self.customs.push(CustomSection {
+                              name: name,
+                              placement: placement,
+                              payload: payload,
+                          });
+        Ok(())
+    }
+
+    /// `func x*`, `reftype item*`, or just `x*` in an active segment.
+    fn elem_list(&mut self, cursor: &mut Cursor) -> Result<Elements> {
+        if let Some(ty) = cursor.peek_atom().and_then(elem_type) {
+            cursor.pos += 1;
+            return Ok(Elements::Expressions(ty, self.elem_exprs(cursor)?));
+        }
+        if cursor.peek_atom() == Some("func") {
+            cursor.pos += 1;
+        }
+        Ok(Elements::Functions(self.func_indices(cursor)?))
+    }
+
+    /// `(item instr*)` or single folded instructions up to the end of the list.
+    fn elem_exprs(&mut self, cursor: &mut Cursor) -> Result<Vec<InitExpr>> {
+        let mut exprs = Vec::new();
+        while !cursor.is_done() {
+            exprs.push(self.const_expr(cursor, "item")?);
+        }
+        Ok(exprs)
+    }
+
+    fn func_indices(&self, cursor: &mut Cursor) -> Result<Vec<FunctionSpaceIndex>> {
+        let mut elems = Vec::new();
+        while !cursor.is_done() {
+            elems.push(FunctionIndex(self.funcs.resolve(cursor)?).into());
+        }
+        Ok(elems)
+    }
+
+    /// `(offset instr*)` or a single folded instruction.
+    fn offset(&mut self, cursor: &mut Cursor) -> Result<InitExpr> {
+        self.const_expr(cursor, "offset")
+    }
+
+    /// `(keyword instr*)` or a single folded instruction.
+    fn const_expr(&mut self, cursor: &mut Cursor, keyword: &str) -> Result<InitExpr> {
+        match cursor.take_list(keyword) {
+            Some(list) => {
+                let mut inner = Cursor::list(list);
+                self.expr(&mut inner)
+            }
+            None => {
+                let item = cursor.next()?;
+                if item.head().is_none() {
+                    return Err(item.unexpected());
+                }
+                let mut code = Vec::new();
+                self.folded(item, &mut Scope::default(), &mut code)?;
+                code.push(Op::End);
+                Ok(InitExpr(Code(code)))
+            }
+        }
+    }
+
+    /// The rest of the list as a constant expression.
+    fn expr(&mut self, cursor: &mut Cursor) -> Result<InitExpr> {
+        let mut code = Vec::new();
+        self.instrs(cursor, &mut Scope::default(), &mut code)?;
+        code.push(Op::End);
+        Ok(InitExpr(Code(code)))
+    }
+
+    /// Instructions up to the end of the list or a flat `else`, `end` or
+    /// clause of a `try`.
+    fn instrs(&mut self, cursor: &mut Cursor, scope: &mut Scope, out: &mut Vec<Op>) -> Result<()> {
+        while let Some(item) = cursor.peek() {
+            match *item {
+                Sexp::List(..) => {
+                    cursor.pos += 1;
+                    self.folded(item, scope, out)?;
+                }
+                Sexp::Atom(ref kw, _) => {
+                    if let "end" | "else" | "catch" | "catch_all" | "delegate" = kw.as_str() {
+                        break;
+                    }
+                    cursor.pos += 1;
+                    self.flat(kw, item, cursor, scope, out)?;
+                }
+                _ => return Err(item.unexpected()),
+            }
+        }
+        Ok(())
+    }
+
+    fn flat(&mut self,
+            kw: &str,
+            item: &Sexp,
+            cursor: &mut Cursor,
+            scope: &mut Scope,
+            out: &mut Vec<Op>)
+            -> Result<()> {
+        match kw {
+            "block" | "loop" | "if" => {
+                let label = cursor.id().map(|(id, _)| id.to_string());
+                let sig = self.block_type(cursor)?;
+                out.push(block_op(kw, sig));
+                scope.labels.push(label);
+                self.instrs(cursor, scope, out)?;
+                if kw == "if" && cursor.peek_atom() == Some("else") {
+                    cursor.pos += 1;
+                    scope.check_label(cursor)?;
+                    out.push(Op::Else);
+                    self.instrs(cursor, scope, out)?;
+                }
+                cursor.keyword("end")?;
+                scope.check_label(cursor)?;
+                scope.labels.pop();
+                out.push(Op::End);
+            }
+            "try" => {
+                let label = cursor.id().map(|(id, _)| id.to_string());
+                let sig = self.block_type(cursor)?;
+                out.push(Op::Try { sig: sig });
+                scope.labels.push(label);
+                self.instrs(cursor, scope, out)?;
+                if cursor.peek_atom() == Some("delegate") {
+                    cursor.pos += 1;
+                    // the label is looked up outside of the `try`
+                    scope.labels.pop();
+                    out.push(Op::Delegate { depth: scope.label(cursor)? });
+                    return Ok(());
+                }
+                while cursor.peek_atom() == Some("catch") {
+                    cursor.pos += 1;
+                    out.push(Op::Catch(TagIndex::new(self.tags.resolve(cursor)?)));
+                    self.instrs(cursor, scope, out)?;
+                }
+                if cursor.peek_atom() == Some("catch_all") {
+                    cursor.pos += 1;
+                    out.push(Op::CatchAll);
+                    self.instrs(cursor, scope, out)?;
+                }
+                cursor.keyword("end")?;
+                scope.check_label(cursor)?;
+                scope.labels.pop();
+                out.push(Op::End);
+            }
+            _ => out.push(self.plain(kw, item, cursor, scope)?),
+        }
+        Ok(())
+    }
+
+    fn folded(&mut self, list: &Sexp, scope: &mut Scope, out: &mut Vec<Op>) -> Result<()> {
+        let kw = list.head().ok_or_else(|| list.unexpected())?;
+        let mut cursor = Cursor::list(list);
+        match kw {
+            "block" | "loop" => {
+                let label = cursor.id().map(|(id, _)| id.to_string());
+                let sig = self.block_type(&mut cursor)?;
+                out.push(block_op(kw, sig));
+                scope.labels.push(label);
+                self.instrs(&mut cursor, scope, out)?;
+                scope.labels.pop();
+                out.push(Op::End);
+            }
+            "if" => {
+                let label = cursor.id().map(|(id, _)| id.to_string());
+                let sig = self.block_type(&mut cursor)?;
+                // the condition is evaluated outside of the block
+                while cursor.peek().is_some() && cursor.peek_head() != Some("then") {
+                    let cond = cursor.next()?;
+                    if cond.head().is_none() {
+                        return Err(cond.unexpected());
+                    }
+                    self.folded(cond, scope, out)?;
+                }
+                out.push(Op::If { sig: sig });
+                scope.labels.push(label);
+                let then = cursor.take_list("then").ok_or(Fail(cursor.end, WatErrorKind::UnexpectedEof))?;
+                let mut inner = Cursor::list(then);
+                self.instrs(&mut inner, scope, out)?;
+                inner.finish()?;
+                if let Some(else_) = cursor.take_list("else") {
+                    out.push(Op::Else);
+                    let mut inner = Cursor::list(else_);
+                    self.instrs(&mut inner, scope, out)?;
+                    inner.finish()?;
+                }
+                scope.labels.pop();
+                out.push(Op::End);
+            }
+            // `(try (do ..) (catch x ..)* (catch_all ..)?)` or `(try (do ..) (delegate l))`
+            "try" => {
+                let label = cursor.id().map(|(id, _)| id.to_string());
+                let sig = self.block_type(&mut cursor)?;
+                out.push(Op::Try { sig: sig });
+                scope.labels.push(label);
+                let do_ = cursor.take_list("do").ok_or(Fail(cursor.end, WatErrorKind::UnexpectedEof))?;
+                let mut inner = Cursor::list(do_);
+                self.instrs(&mut inner, scope, out)?;
+                inner.finish()?;
+                if let Some(delegate) = cursor.take_list("delegate") {
+                    scope.labels.pop();
+                    let mut inner = Cursor::list(delegate);
+                    out.push(Op::Delegate { depth: scope.label(&mut inner)? });
+                    inner.finish()?;
+                    return cursor.finish();
+                }
+                while let Some(catch) = cursor.take_list("catch") {
+                    let mut inner = Cursor::list(catch);
+                    out.push(Op::Catch(TagIndex::new(self.tags.resolve(&mut inner)?)));
+                    self.instrs(&mut inner, scope, out)?;
+                    inner.finish()?;
+                }
+                if let Some(catch_all) = cursor.take_list("catch_all") {
+                    out.push(Op::CatchAll);
+                    let mut inner = Cursor::list(catch_all);
+                    self.instrs(&mut inner, scope, out)?;
+                    inner.finish()?;
+                }
+                scope.labels.pop();
+                out.push(Op::End);
+            }
+            _ => {
+                let op = self.plain(kw, &list_items(list)[0], &mut cursor, scope)?;
+                // the operands
+                while let Some(operand) = cursor.peek() {
+                    if operand.head().is_none() {
+                        return Err(operand.unexpected());
+                    }
+                    cursor.pos += 1;
+                    self.folded(operand, scope, out)?;
+                }
+                out.push(op);
+            }
+        }
+        cursor.finish()
+    }
+
+    /// `(type x)? (param t*)* (result t*)*`. Without parameters a single
+    /// result or none is encoded without a type.
+    fn block_type(&mut self, cursor: &mut Cursor) -> Result<BlockType> {
+        let (index, params) = if cursor.peek_head() == Some("type") {
+            self.type_use(cursor)?
+        } else {
+            let (names, ty) = signature(cursor, &self.types)?;
+            if ty.params.is_empty() && ty.results.len() <= 1 {
+                return Ok(match ty.results.first() {
+                              Some(&ty) => BlockType::Value(ty),
+                              None => BlockType::Empty,
+                          });
+            }
+            (self.intern_type(ty), names)
+        };
+        if let Some(&Some((ref name, offset))) = params.iter().find(|p| p.is_some()) {
+            return Err(Fail(offset, WatErrorKind::UnexpectedToken(format!("${}", name))));
+        }
+        Ok(BlockType::Type(TypeIndex::new(index)))
+    }
+
+    /// An instruction other than a block, with its immediates.
+    fn plain(&mut self, kw: &str, item: &Sexp, cursor: &mut Cursor, scope: &Scope) -> Result<Op> {
+        use ops::Op::*;
+        let number = |cursor: &mut Cursor, bits: u32, float: Option<u32>| -> Result<u64> {
+            let (a, offset) = cursor.atom()?;
+            let n = match float {
+                Some(mant_bits) => parse_float(a, mant_bits, bits - 1 - mant_bits),
+                None => parse_int(a, bits),
+            };
+            n.ok_or_else(|| Fail(offset, WatErrorKind::InvalidNumber(a.to_string())))
+        };
+        let op = match kw {
+            "br" => Br { depth: scope.label(cursor)? },
+            "throw" => Throw(TagIndex::new(self.tags.resolve(cursor)?)),
+            "rethrow" => Rethrow { depth: scope.label(cursor)? },
+            "br_if" => BrIf { depth: scope.label(cursor)? },
+            "br_table" => {
+                let mut table = Vec::new();
+                while cursor.peek().is_some_and(is_index) {
+                    table.push(scope.label(cursor)?);
+                }
+                let default_target = table.pop().ok_or_else(|| match cursor.peek() {
+                        Some(item) => item.unexpected(),
+                        None => Fail(cursor.end, WatErrorKind::UnexpectedEof),
+                    })?;
+                BrTable(BrTarget {
+                            table: table,
+                            default_target: default_target,
+                        })
+            }
+            "br_on_null" => BrOnNull { depth: scope.label(cursor)? },
+            "br_on_non_null" => BrOnNonNull { depth: scope.label(cursor)? },
+            "br_on_cast" | "br_on_cast_fail" => {
+                let depth = scope.label(cursor)?;
+                let from = cursor.ref_type(&self.types)?;
+                let to = cursor.ref_type(&self.types)?;
+                if kw == "br_on_cast" {
+                    BrOnCast {
+                        depth: depth,
+                        from: from,
+                        to: to,
+                    }
+                } else {
+                    BrOnCastFail {
+                        depth: depth,
+                        from: from,
+                        to: to,
+                    }
+                }
+            }
+            "call" => Call { index: FunctionIndex(self.funcs.resolve(cursor)?).into() },
+            "return_call" => ReturnCall { index: FunctionIndex(self.funcs.resolve(cursor)?).into() },
+            "call_indirect" | "return_call_indirect" => {
+                let table = self.table_index(cursor)?;
+                let (index, params) = self.type_use(cursor)?;
+                if let Some(&Some((ref name, offset))) = params.iter().find(|p| p.is_some()) {
+                    return Err(Fail(offset, WatErrorKind::UnexpectedToken(format!("${}", name))));
+                }
+                let (index, table) = (TypeIndex::new(index), TableIndex::new(table));
+                if kw == "call_indirect" {
+                    CallIndirect {
+                        index: index,
+                        table: table,
+                    }
+                } else {
+                    ReturnCallIndirect {
+                        index: index,
+                        table: table,
+                    }
+                }
+            }
+            "call_ref" => CallRef { index: TypeIndex::new(self.types.resolve(cursor)?) },
+            "return_call_ref" => ReturnCallRef { index: TypeIndex::new(self.types.resolve(cursor)?) },
+            "select" => {
+                match cursor.take_list("result") {
+                    Some(result) => {
+                        let mut inner = Cursor::list(result);
+                        let ty = inner.value_type(&self.types)?;
+                        inner.finish()?;
+                        TypedSelect(ty)
+                    }
+                    None => Select,
+                }
+            }
+            "local.get" | "get_local" => GetLocal(LocalIndex::new(scope.locals.resolve(cursor)?)),
+            "local.set" | "set_local" => SetLocal(LocalIndex::new(scope.locals.resolve(cursor)?)),
+            "local.tee" | "tee_local" => TeeLocal(LocalIndex::new(scope.locals.resolve(cursor)?)),
+            "global.get" | "get_global" => GetGlobal(GlobalIndex::new(self.globals.resolve(cursor)?)),
+            "global.set" | "set_global" => SetGlobal(GlobalIndex::new(self.globals.resolve(cursor)?)),
+            "table.get" => TableGet(TableIndex::new(self.table_index(cursor)?)),
+            "table.set" => TableSet(TableIndex::new(self.table_index(cursor)?)),
+            "table.size" => TableSize(TableIndex::new(self.table_index(cursor)?)),
+            "table.grow" => TableGrow(TableIndex::new(self.table_index(cursor)?)),
+            "table.fill" => TableFill(TableIndex::new(self.table_index(cursor)?)),
+            "ref.null" => {
+                // the names of reference types were accepted before heap types
+                match cursor.peek_atom().and_then(elem_type) {
+                    Some(ty) => {
+                        cursor.pos += 1;
+                        RefNull(ty.into())
+                    }
+                    None => RefNull(cursor.heap_type(&self.types)?),
+                }
+            }
+            "ref.func" => RefFunc(FunctionIndex(self.funcs.resolve(cursor)?).into()),
+            "ref.test" => RefTest(cursor.ref_type(&self.types)?),
+            "ref.cast" => RefCast(cursor.ref_type(&self.types)?),
+            "struct.new" => StructNew { index: TypeIndex::new(self.types.resolve(cursor)?) },
+            "struct.new_default" => StructNewDefault { index: TypeIndex::new(self.types.resolve(cursor)?) },
+            "struct.get" | "struct.get_s" | "struct.get_u" | "struct.set" => {
+                let index = self.types.resolve(cursor)?;
+                // fields are named per struct type
+                let field = match self.field_names.get(&index) {
+                    Some(names) => names.resolve(cursor)?,
+                    None => cursor.u32()?,
+                };
+                let index = TypeIndex::new(index);
+                match kw {
+                    "struct.get" => StructGet { index: index, field: field },
+                    "struct.get_s" => StructGetS { index: index, field: field },
+                    "struct.get_u" => StructGetU { index: index, field: field },
+                    _ => StructSet { index: index, field: field },
+                }
+            }
+            "array.new" | "array.new_default" | "array.get" | "array.get_s" | "array.get_u" | "array.set" |
+            "array.fill" => {
+                let index = TypeIndex::new(self.types.resolve(cursor)?);
+                match kw {
+                    "array.new" => ArrayNew { index: index },
+                    "array.new_default" => ArrayNewDefault { index: index },
+                    "array.get" => ArrayGet { index: index },
+                    "array.get_s" => ArrayGetS { index: index },
+                    "array.get_u" => ArrayGetU { index: index },
+                    "array.set" => ArraySet { index: index },
+                    _ => ArrayFill { index: index },
+                }
+            }
+            "array.new_fixed" => {
+                ArrayNewFixed {
+                    index: TypeIndex::new(self.types.resolve(cursor)?),
+                    len: cursor.u32()?,
+                }
+            }
+            "array.new_data" | "array.init_data" => {
+                let index = TypeIndex::new(self.types.resolve(cursor)?);
+                let segment = DataIndex::new(self.data_segments.resolve(cursor)?);
+                if kw == "array.new_data" {
+                    ArrayNewData {
+                        index: index,
+                        segment: segment,
+                    }
+                } else {
+                    ArrayInitData {
+                        index: index,
+                        segment: segment,
+                    }
+                }
+            }
+            "array.new_elem" | "array.init_elem" => {
+                let index = TypeIndex::new(self.types.resolve(cursor)?);
+                let segment = ElementIndex::new(self.elem_segments.resolve(cursor)?);
+                if kw == "array.new_elem" {
+                    ArrayNewElem {
+                        index: index,
+                        segment: segment,
+                    }
+                } else {
+                    ArrayInitElem {
+                        index: index,
+                        segment: segment,
+                    }
+                }
+            }
+            "array.copy" => {
+                ArrayCopy {
+                    dst: TypeIndex::new(self.types.resolve(cursor)?),
+                    src: TypeIndex::new(self.types.resolve(cursor)?),
+                }
+            }
+            "memory.size" | "current_memory" => CurrentMemory { memory: MemoryIndex::new(self.memory_index(cursor)?) },
+            "memory.grow" | "grow_memory" => GrowMemory { memory: MemoryIndex::new(self.memory_index(cursor)?) },
+            "memory.init" => {
+                // `memory.init memory? segment`
+                let memory = match cursor.items.get(cursor.pos + 1) {
+                    Some(item) if is_index(item) => self.memories.resolve(cursor)?,
+                    _ => 0,
+                };
+                MemoryInit {
+                    segment: DataIndex::new(self.data_segments.resolve(cursor)?),
+                    memory: MemoryIndex::new(memory),
+                }
+            }
+            "data.drop" => DataDrop(DataIndex::new(self.data_segments.resolve(cursor)?)),
+            "memory.copy" => {
+                let (dst, src) = if cursor.peek().is_some_and(is_index) {
+                    (self.memories.resolve(cursor)?, self.memories.resolve(cursor)?)
+                } else {
+                    (0, 0)
+                };
+                MemoryCopy {
+                    dst: MemoryIndex::new(dst),
+                    src: MemoryIndex::new(src),
+                }
+            }
+            "memory.fill" => MemoryFill { memory: MemoryIndex::new(self.memory_index(cursor)?) },
+            "atomic.fence" => AtomicFence { reserved: false },
+            "table.init" => {
+                // `table.init table? segment`
+                let table = match cursor.items.get(cursor.pos + 1) {
+                    Some(item) if is_index(item) => self.tables.resolve(cursor)?,
+                    _ => 0,
+                };
+                TableInit {
+                    segment: ElementIndex::new(self.elem_segments.resolve(cursor)?),
+                    table: TableIndex::new(table),
+                }
+            }
+            "elem.drop" => ElemDrop(ElementIndex::new(self.elem_segments.resolve(cursor)?)),
+            "table.copy" => {
+                let (dst, src) = if cursor.peek().is_some_and(is_index) {
+                    (self.tables.resolve(cursor)?, self.tables.resolve(cursor)?)
+                } else {
+                    (0, 0)
+                };
+                TableCopy {
+                    dst: TableIndex::new(dst),
+                    src: TableIndex::new(src),
+                }
+            }
+            "i32.const" => I32Const(number(cursor, 32, None)? as u32 as i32),
+            "i64.const" => I64Const(number(cursor, 64, None)? as i64),
+            "f32.const" => F32Const(f32::from_bits(number(cursor, 32, Some(23))? as u32)),
+            "f64.const" => F64Const(f64::from_bits(number(cursor, 64, Some(52))?)),
+            "v128.const" => {
+                let (shape, offset) = cursor.atom()?;
+                let (bits, float) = match shape {
+                    "i8x16" => (8, None),
+                    "i16x8" => (16, None),
+                    "i32x4" => (32, None),
+                    "i64x2" => (64, None),
+                    "f32x4" => (32, Some(23)),
+                    "f64x2" => (64, Some(52)),
+                    _ => return Err(Fail(offset, WatErrorKind::UnexpectedToken(shape.to_string()))),
+                };
+                let mut v = 0u128;
+                for i in 0..128 / bits {
+                    v |= (number(cursor, bits, float)? as u128) << (i * bits);
+                }
+                V128Const(v)
+            }
+            "i8x16.shuffle" => {
+                let mut lanes = [0; 16];
+                for lane in lanes.iter_mut() {
+                    *lane = cursor.lane()?;
+                }
+                I8x16Shuffle(lanes)
+            }
+            _ => {
+                let placeholder = MemoryImmediate {
+                    flags: 0,
+                    offset: 0,
+                    memory: MemoryIndex::new(0),
+                };
+                if let Some(ctor) = memory_instr(kw) {
+                    let op = ctor(placeholder);
+                    let natural = memory_access(&op).map(|a| a.1).or_else(|| atomic_access(&op).map(|a| a.0));
+                    let memory = self.memory_index(cursor)?;
+                    ctor(memory_immediate(cursor, memory, natural.unwrap())?)
+                } else if let Some(ctor) = lane_memory_instr(kw) {
+                    let natural = lane_access(&ctor(placeholder, 0)).unwrap().0;
+                    // a lone index is the lane, a memory is followed by the memarg or the lane
+                    let memory = match cursor.items.get(cursor.pos + 1) {
+                        Some(item) if cursor.peek().is_some_and(is_index) && (is_index(item) || is_memarg(item)) => {
+                            self.memories.resolve(cursor)?
+                        }
+                        _ => 0,
+                    };
+                    let imm = memory_immediate(cursor, memory, natural)?;
+                    ctor(imm, cursor.lane()?)
+                } else if let Some(ctor) = lane_instr(kw) {
+                    ctor(cursor.lane()?)
+                } else {
+                    plain_instr(kw)
+                        .ok_or_else(|| Fail(item.offset(), WatErrorKind::UnknownInstruction(kw.to_string())))?
+                }
+            }
+        };
+        Ok(op)
+    }
+
+    /// An optional table index, 0 if absent.
+    fn table_index(&self, cursor: &mut Cursor) -> Result<u32> {
+        if cursor.peek().is_some_and(is_index) {
+            self.tables.resolve(cursor)
+        } else {
+            Ok(0)
+        }
+    }
+
+    /// An optional memory index, 0 if absent.
+    fn memory_index(&self, cursor: &mut Cursor) -> Result<u32> {
+        if cursor.peek().is_some_and(is_index) {
+            self.memories.resolve(cursor)
+        } else {
+            Ok(0)
+        }
+    }
+}
+
+/// A `$name` or a number, as opposed to the next instruction.
+fn is_index(item: &Sexp) -> bool {
+    match *item {
+        Sexp::Id(..) => true,
+        Sexp::Atom(ref a, _) => a.starts_with(|c: char| c.is_ascii_digit()),
+        _ => false,
+    }
+}
+
+/// An `offset=` or `align=` of a memory instruction.
+fn is_memarg(item: &Sexp) -> bool {
+    match *item {
+        Sexp::Atom(ref a, _) => a.starts_with("offset=") || a.starts_with("align="),
+        _ => false,
+    }
+}
+
+fn list_items(list: &Sexp) -> &[Sexp] {
+    match *list {
+        Sexp::List(ref items, _) => items,
+        _ => unreachable!(),
+    }
+}
+
+fn block_op(kw: &str, sig: BlockType) -> Op {
+    match kw {
+        "block" => Op::Block { sig: sig },
+        "loop" => Op::Loop { sig: sig },
+        _ => Op::If { sig: sig },
+    }
+}
+
+/// `offset=n? align=n?`, following the index of `memory`
+fn memory_immediate(cursor: &mut Cursor, memory: u32, natural: u32) -> Result<MemoryImmediate> {
+    let mut imm = MemoryImmediate {
+        flags: natural,
+        offset: 0,
+        memory: MemoryIndex::new(memory),
+    };
+    for key in &["offset=", "align="] {
+        let value = match cursor.peek_atom().and_then(|a| a.strip_prefix(key)) {
+            Some(value) => value,
+            None => continue,
+        };
+        let offset = cursor.peek().unwrap().offset();
+        cursor.pos += 1;
+        // offsets into 64-bit memories may exceed a u32
+        let bound = if *key == "offset=" { u64::MAX } else { u32::MAX as u64 };
+        let n = parse_nat(value)
+            .filter(|&n| n <= bound)
+            .ok_or_else(|| Fail(offset, WatErrorKind::InvalidNumber(value.to_string())))?;
+        if *key == "offset=" {
+            imm.offset = n;
+        } else if n.is_power_of_two() {
+            imm.flags = n.trailing_zeros();
+        } else {
+            return Err(Fail(offset, WatErrorKind::InvalidAlignment(n as u32)));
+        }
+    }
+    Ok(imm)
+}
+
+/// `(param $x t)`, `(param t*)` and `(result t*)` lists.
+/// Returns the parameter names along with the type.
+fn signature(cursor: &mut Cursor, types: &Space) -> Result<(Vec<Name>, FuncType)> {
+    let mut params = Vec::new();
+    while let Some(param) = cursor.take_list("param") {
+        let mut inner = Cursor::list(param);
+        match inner.id() {
+            Some((id, offset)) => {
+                if params.iter().any(|&(ref name, _)| match *name {
+                                             Some((ref name, _)) => name == id,
+                                             None => false,
+                                         }) {
+                    return Err(Fail(offset, WatErrorKind::DuplicateName(id.to_string())));
+                }
+                params.push((Some((id.to_string(), offset)), inner.value_type(types)?));
+            }
+            None => {
+                while !inner.is_done() {
+                    params.push((None, inner.value_type(types)?));
+                }
+            }
+        }
+        inner.finish()?;
+    }
+    let mut results = Vec::new();
+    while let Some(result) = cursor.take_list("result") {
+        let mut inner = Cursor::list(result);
+        while !inner.is_done() {
+            results.push(inner.value_type(types)?);
+        }
+    }
+    let (names, params) = params.into_iter().unzip();
+    Ok((names,
+        FuncType {
+            params: params,
+            results: results,
+        }))
+}
+
+/// `st` or `(mut st)`
+fn field_type(cursor: &mut Cursor, types: &Space) -> Result<FieldType> {
+    match cursor.take_list("mut") {
+        Some(mutable) => {
+            let mut inner = Cursor::list(mutable);
+            let storage = storage_type(&mut inner, types)?;
+            inner.finish()?;
+            Ok(FieldType {
+                   storage: storage,
+                   mutable: true,
+               })
+        }
+        None => {
+            Ok(FieldType {
+                   storage: storage_type(cursor, types)?,
+                   mutable: false,
+               })
+        }
+    }
+}
+
+/// A value type, or one of the packed `i8` and `i16`.
+fn storage_type(cursor: &mut Cursor, types: &Space) -> Result<StorageType> {
+    let packed = match cursor.peek_atom() {
+        Some("i8") => StorageType::I8,
+        Some("i16") => StorageType::I16,
+        _ => return Ok(StorageType::Val(cursor.value_type(types)?)),
+    };
+    cursor.pos += 1;
+    Ok(packed)
+}
+
+fn elem_type(name: &str) -> Option<ElemType> {
+    match name {
+        "funcref" | "anyfunc" => Some(ElemType::AnyFunc),
+        "externref" => Some(ElemType::ExternRef),
+        _ => None,
+    }
+}
+
+/// The limits of a table or 32-bit memory, or with `wide` those of a 64-bit memory.
+fn limits(cursor: &mut Cursor, wide: bool) -> Result<ResizableLimits> {
+    let bound = |cursor: &mut Cursor| if wide { cursor.u64() } else { cursor.u32().map(u64::from) };
+    let limits = ResizableLimits::new(bound(cursor)?);
+    match cursor.peek_atom() {
+        Some(a) if a.starts_with(|c: char| c.is_ascii_digit()) => Ok(limits.max(bound(cursor)?)),
+        _ => Ok(limits),
+    }
+}
+
+/// The optional `i32` or `i64` address type of a memory, whether it is 64-bit.
+fn index_type(cursor: &mut Cursor) -> Result<bool> {
+    let memory64 = cursor.peek_atom() == Some("i64");
+    if memory64 || cursor.peek_atom() == Some("i32") {
+        cursor.next()?;
+    }
+    Ok(memory64)
+}
+
+/// `i64? limits shared?`
+fn memory_type(cursor: &mut Cursor) -> Result<MemoryType> {
+    let memory64 = index_type(cursor)?;
+    memory_limits(cursor, memory64)
+}
+
+/// The rest of a memory type after its address type.
+fn memory_limits(cursor: &mut Cursor, memory64: bool) -> Result<MemoryType> {
+    let limits = limits(cursor, memory64)?;
+    let shared = cursor.peek_atom() == Some("shared");
+    if shared {
+        cursor.next()?;
+    }
+    Ok(MemoryType {
+           limits: limits,
+           shared: shared,
+           memory64: memory64,
+       })
+}
+
+fn table_type(cursor: &mut Cursor, types: &Space) -> Result<TableType> {
+    let limits = limits(cursor, false)?;
+    let element = cursor.ref_type(types)?;
+    Ok(TableType {
+           element: element,
+           limits: limits,
+       })
+}
+
+/// `t` or `(mut t)`
+fn global_type(cursor: &mut Cursor, types: &Space) -> Result<GlobalType> {
+    match cursor.take_list("mut") {
+        Some(mutable) => {
+            let mut inner = Cursor::list(mutable);
+            let content = inner.value_type(types)?;
+            inner.finish()?;
+            Ok(GlobalType {
+                   content: content,
+                   mutable: true,
+               })
+        }
+        None => {
+            Ok(GlobalType {
+                   content: cursor.value_type(types)?,
+                   mutable: false,
+               })
+        }
+    }
+}
+
+fn export_kind(kind: &str, index: u32) -> ExportKind {
+    match kind {
+        "func" => ExportKind::Function(FunctionIndex(index)),
+        "table" => ExportKind::Table(TableIndex::new(index)),
+        "memory" => ExportKind::Memory(MemoryIndex::new(index)),
+        "tag" => ExportKind::Tag(TagIndex::new(index)),
+        _ => ExportKind::Global(GlobalIndex::new(index)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use Dump;
+
+    fn dump(text: &str) -> Vec<u8> {
+        let module = Module::from_wat(text).unwrap_or_else(|e| panic!("{}", e));
+        let mut buf = Vec::new();
+        module.dump(&mut buf);
+        buf
+    }
+
+    fn error(text: &str) -> (usize, usize, WatErrorKind) {
+        let e = Module::from_wat(text).unwrap_err();
+        (e.line, e.column, e.kind)
+    }
+
+    #[test]
+    fn names() {
+        assert_eq!(dump(r#"
+            (module
+              (import "env" "log" (func $log (param i32)))
+              (global $g (mut i32) (i32.const 0))
+              (global $h i32 (i32.const 1))
+              (func $f (param $x i32) (result i32)
+                (local $y i64) (local $z i32)
+                (block $outer
+                  (loop $inner
+                    (br_if $outer (local.get $x))
+                    (br $inner)))
+                (local.set $z (global.get $h))
+                (global.set $g (local.get $z))
+                (call $log (local.get $x))
+                (call $f (local.get $z)))
+              (export "f" (func $f)))"#),
+                   dump(r#"
+            (module
+              (import "env" "log" (func (param i32)))
+              (global (mut i32) (i32.const 0))
+              (global i32 (i32.const 1))
+              (func (param i32) (result i32)
+                (local i64) (local i32)
+                (block
+                  (loop
+                    (br_if 1 (local.get 0))
+                    (br 0)))
+                (local.set 2 (global.get 1))
+                (global.set 0 (local.get 2))
+                (call 0 (local.get 0))
+                (call 1 (local.get 2)))
+              (export "f" (func 1)))"#));
+        // a label may be repeated after `end` and shadows outer labels of the same name
+        assert_eq!(dump("(module (func block $l block $l br $l end $l br $l end $l))"),
+                   dump("(module (func block block br 0 end br 0 end))"));
+    }
+
+    #[test]
+    fn flat_and_folded() {
+        assert_eq!(dump(r#"
+            (module (func (param i32) (result i32)
+              local.get 0
+              if (result i32)
+                i32.const 1
+              else
+                local.get 0
+                i32.const 1
+                i32.sub
+              end))"#),
+                   dump(r#"
+            (module (func (param i32) (result i32)
+              (if (result i32) (local.get 0)
+                (then (i32.const 1))
+                (else (i32.sub (local.get 0) (i32.const 1))))))"#));
+    }
+
+    #[test]
+    fn custom_sections() {
+        let module = Module::from_wat(r#"
+            (module
+              (@custom "a" (before func) "x" "yz")
+              (@custom "b" (before type))
+              (@custom "c" (after last))
+              (@custom "d")
+              (func))"#)
+            .unwrap();
+        let customs: Vec<_> = module.customs
+            .unwrap()
+            .into_iter()
+            .map(|c| (c.name, c.placement, c.payload))
+            .collect();
+        assert_eq!(customs,
+                   [("a".to_string(), Placement::After(SectionId::Import), b"xyz".to_vec()),
+                    ("b".to_string(), Placement::First, Vec::new()),
+                    ("c".to_string(), Placement::After(SectionId::Data), Vec::new()),
+                    ("d".to_string(), Placement::After(SectionId::Data), Vec::new())]);
+        assert_eq!(error("(module (@custom \"a\" (after funcs)))"),
+                   (1, 29, WatErrorKind::UnexpectedToken("funcs".into())));
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(error("(module\n  (func (call $missing)))"),
+                   (2, 15, WatErrorKind::UnknownName("missing".into())));
+        assert_eq!(error("(module (func $f) (func $f))"),
+                   (1, 25, WatErrorKind::DuplicateName("f".into())));
+        assert_eq!(error("(module (func"), (1, 14, WatErrorKind::UnexpectedEof));
+        assert_eq!(error("(module (func\n    i32.frob))"),
+                   (2, 5, WatErrorKind::UnknownInstruction("i32.frob".into())));
+        assert_eq!(error("(module (func block $a end $b))"),
+                   (1, 28, WatErrorKind::LabelMismatch("b".into())));
+        assert_eq!(error("(module (type (func)) (func (type 0) (param i32)))"),
+                   (1, 29, WatErrorKind::TypeMismatch));
+        assert_eq!(error("(module (memory 1) (import \"a\" \"b\" (func)))"),
+                   (1, 20, WatErrorKind::ImportAfterDefinition));
+        assert_eq!(error("(module (func i32.const 0x))"),
+                   (1, 25, WatErrorKind::InvalidNumber("0x".into())));
+        assert_eq!(error("(module (data \"\\q\"))"), (1, 16, WatErrorKind::InvalidEscape));
+        assert_eq!(error("(module (; unclosed"), (1, 9, WatErrorKind::UnterminatedComment));
+        assert_eq!(error("(module (data \"abc"), (1, 15, WatErrorKind::UnterminatedString));
+        assert_eq!(error("(module (func i32.load align=3))"),
+                   (1, 24, WatErrorKind::InvalidAlignment(3)));
+        assert_eq!(error("(module) )"), (1, 10, WatErrorKind::UnexpectedToken(")".into())));
+    }
+}
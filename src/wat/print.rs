@@ -0,0 +1,1088 @@
+use std::fmt;
+use std::fmt::Write;
+
+use module::*;
+use ops::{MemoryImmediate, Op};
+use types::*;
+use validate::{atomic_access, lane_access, lane_immediate, memory_access, simple_signature, Context};
+use super::{elem_type, heap_type, mnemonic, ref_type, section_name, value_type};
+
+/// How instructions in function bodies are laid out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatStyle {
+    /// one instruction per line, blocks closed by `end`
+    Flat,
+    /// S-expressions nesting operands into the instructions that consume them
+    Folded,
+}
+
+impl Module {
+    /// Renders the module in the WebAssembly text format.
+    pub fn to_wat(&self, style: WatStyle) -> String {
+        let mut printer = Printer {
+            module: self,
+            ctx: Context::new(self).ok(),
+            style: style,
+            out: String::new(),
+        };
+        printer.print_module();
+        printer.out
+    }
+}
+
+/// A folded instruction.
+enum Node {
+    Plain { text: String, operands: Vec<Node> },
+    Block { head: String, body: Vec<Node> },
+    If {
+        head: String,
+        cond: Vec<Node>,
+        then: Vec<Node>,
+        else_: Option<Vec<Node>>,
+    },
+    /// the handlers are `catch` or `catch_all` with their bodies
+    Try {
+        head: String,
+        body: Vec<Node>,
+        handlers: Vec<(String, Vec<Node>)>,
+        delegate: Option<String>,
+    },
+}
+
+struct Printer<'a> {
+    module: &'a Module,
+    /// `None` if the module refers to unknown types. Calls are not folded then.
+    ctx: Option<Context<'a>>,
+    style: WatStyle,
+    out: String,
+}
+
+impl<'a> Printer<'a> {
+    fn print_module(&mut self) {
+        let module = self.module;
+        self.out.push_str("(module");
+
+        // a group of a single type is printed without its `rec`
+        let mut ntypes = 0;
+        for group in module.types.iter().flat_map(|t| t.iter()) {
+            if group.types.len() == 1 {
+                let _ = write!(self.out, "\n  (type (;{};) {})", ntypes, sub_type(&group.types[0]));
+                ntypes += 1;
+                continue;
+            }
+            self.out.push_str("\n  (rec");
+            for ty in group.types.iter() {
+                let _ = write!(self.out, "\n    (type (;{};) {})", ntypes, sub_type(ty));
+                ntypes += 1;
+            }
+            self.out.push(')');
+        }
+
+        let (mut nfuncs, mut ntables, mut nmems, mut ntags, mut nglobals) = (0, 0, 0, 0, 0);
+        for import in module.imports.iter().flat_map(|i| i.iter()) {
+            let desc = match import.kind {
+                ImportKind::Function(ref t) => {
+                    nfuncs += 1;
+                    format!("(func (;{};) {})", nfuncs - 1, self.type_use(**t))
+                }
+                ImportKind::Table(ref t) => {
+                    ntables += 1;
+                    format!("(table (;{};) {})", ntables - 1, table_type(t))
+                }
+                ImportKind::Memory(ref m) => {
+                    nmems += 1;
+                    format!("(memory (;{};) {})", nmems - 1, memory_type(m))
+                }
+                ImportKind::Global(ref g) => {
+                    nglobals += 1;
+                    format!("(global (;{};) {})", nglobals - 1, global_type(g))
+                }
+                ImportKind::Tag(ref t) => {
+                    ntags += 1;
+                    format!("(tag (;{};) {})", ntags - 1, self.type_use(*t.ty))
+                }
+            };
+            let _ = write!(self.out,
+                           "\n  (import {} {} {})",
+                           string(import.module.as_bytes()),
+                           string(import.field.as_bytes()),
+                           desc);
+        }
+
+        let functions = module.functions.iter().flat_map(|f| f.iter());
+        let codes = module.codes.iter().flat_map(|c| c.iter());
+        for (i, (f, body)) in functions.zip(codes).enumerate() {
+            self.print_function(nfuncs + i, *f.0, body);
+        }
+
+        for (i, t) in module.tables.iter().flat_map(|t| t.iter()).enumerate() {
+            let _ = write!(self.out, "\n  (table (;{};) {})", ntables + i, table_type(t));
+        }
+        for (i, m) in module.memories.iter().flat_map(|m| m.iter()).enumerate() {
+            let _ = write!(self.out, "\n  (memory (;{};) {})", nmems + i, memory_type(m));
+        }
+        for (i, t) in module.tags.iter().flat_map(|t| t.iter()).enumerate() {
+            let type_use = self.type_use(*t.ty);
+            let _ = write!(self.out, "\n  (tag (;{};) {})", ntags + i, type_use);
+        }
+        for (i, g) in module.globals.iter().flat_map(|g| g.iter()).enumerate() {
+            let init = self.init_expr(&g.init);
+            let _ = write!(self.out,
+                           "\n  (global (;{};) {} {})",
+                           nglobals + i,
+                           global_type(&g.ty),
+                           init);
+        }
+
+        for e in module.exports.iter().flat_map(|e| e.iter()) {
+            let desc = match e.kind {
+                ExportKind::Function(ref i) => format!("func {}", **i),
+                ExportKind::Table(ref i) => format!("table {}", **i),
+                ExportKind::Memory(ref i) => format!("memory {}", **i),
+                ExportKind::Global(ref i) => format!("global {}", **i),
+                ExportKind::Tag(ref i) => format!("tag {}", **i),
+            };
+            let _ = write!(self.out, "\n  (export {} ({}))", string(e.field.as_bytes()), desc);
+        }
+
+        if let Some(ref start) = module.start {
+            let _ = write!(self.out, "\n  (start {})", **start);
+        }
+
+        for (i, e) in module.elements.iter().flat_map(|e| e.iter()).enumerate() {
+            let _ = write!(self.out, "\n  (elem (;{};)", i);
+            // functions are listed after `func`, expressions after their type
+            let kind = match e.elems {
+                Elements::Functions(_) => "func",
+                Elements::Expressions(ref ty, _) => elem_type(ty),
+            };
+            match e.mode {
+                // the MVP form only abbreviates a list of functions for table 0
+                ElemMode::Active { ref index, ref offset } if **index == 0 && kind == "func" => {
+                    let _ = write!(self.out, " {}", self.offset(offset));
+                }
+                ElemMode::Active { ref index, ref offset } => {
+                    let _ = write!(self.out, " (table {}) {} {}", **index, self.offset(offset), kind);
+                }
+                ElemMode::Passive => {
+                    let _ = write!(self.out, " {}", kind);
+                }
+                ElemMode::Declarative => {
+                    let _ = write!(self.out, " declare {}", kind);
+                }
+            }
+            match e.elems {
+                Elements::Functions(ref fs) => {
+                    for f in fs.iter() {
+                        let _ = write!(self.out, " {}", **f);
+                    }
+                }
+                Elements::Expressions(_, ref es) => {
+                    for expr in es.iter() {
+                        let item = self.wrapped(expr, "item");
+                        let _ = write!(self.out, " {}", item);
+                    }
+                }
+            }
+            self.out.push(')');
+        }
+
+        for (i, d) in module.data.iter().flat_map(|d| d.iter()).enumerate() {
+            let _ = write!(self.out, "\n  (data (;{};)", i);
+            if let DataMode::Active { ref index, ref offset } = d.mode {
+                if **index != 0 {
+                    let _ = write!(self.out, " (memory {})", **index);
+                }
+                let _ = write!(self.out, " {}", self.offset(offset));
+            }
+            let _ = write!(self.out, " {})", string(&d.data));
+        }
+
+        for c in module.customs.iter().flat_map(|c| c.iter()) {
+            let placement = match c.placement {
+                Placement::First => "before first".to_string(),
+                Placement::After(id) => format!("after {}", section_name(id)),
+            };
+            let _ = write!(self.out,
+                           "\n  (@custom {} ({}) {})",
+                           string(c.name.as_bytes()),
+                           placement,
+                           string(&c.payload));
+        }
+
+        self.out.push_str(")\n");
+    }
+
+    fn type_use(&self, index: u32) -> String {
+        match self.module.func_type(index) {
+            Some(ty) => format!("(type {}){}", index, signature(ty)),
+            None => format!("(type {})", index),
+        }
+    }
+
+    fn print_function(&mut self, index: usize, ty: u32, body: &FunctionBody) {
+        let type_use = self.type_use(ty);
+        let _ = write!(self.out, "\n  (func (;{};) {}", index, type_use);
+        if !body.locals.is_empty() {
+            self.out.push_str("\n    (local");
+            for l in body.locals.iter() {
+                for _ in 0..l.count {
+                    let _ = write!(self.out, " {}", value_type(&l.ty));
+                }
+            }
+            self.out.push(')');
+        }
+
+        let code = &body.code.0;
+        match self.style {
+            WatStyle::Flat => {
+                let mut depth = 2;
+                for op in code.iter() {
+                    if let Op::Else | Op::End | Op::Catch(_) | Op::CatchAll | Op::Delegate { .. } = *op {
+                        depth = (depth - 1).max(2);
+                    }
+                    let _ = write!(self.out, "\n{:width$}{}", "", instr(op), width = depth * 2);
+                    if let Op::Block { .. } | Op::Loop { .. } | Op::If { .. } | Op::Else | Op::Try { .. } |
+                           Op::Catch(_) | Op::CatchAll = *op {
+                        depth += 1;
+                    }
+                }
+            }
+            WatStyle::Folded => {
+                let results = self.module.func_type(ty).map(|ty| ty.results.len());
+                let mut labels = vec![results];
+                let nodes = self.fold(code, &mut 0, &mut labels);
+                for node in nodes.iter() {
+                    render(&mut self.out, node, 4);
+                }
+            }
+        }
+        self.out.push(')');
+    }
+
+    /// Renders a constant expression as folded instructions on a single line.
+    fn init_expr(&self, expr: &InitExpr) -> String {
+        let mut labels = vec![Some(1)];
+        let nodes = self.fold(&(expr.0).0, &mut 0, &mut labels);
+        let mut out = String::new();
+        for node in nodes.iter() {
+            render(&mut out, node, 0);
+        }
+        out.split('\n')
+            .map(|line| line.trim())
+            .filter(|line| !line.is_empty())
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    /// A segment offset needs `(offset ...)` unless it folds into a single instruction.
+    fn offset(&self, expr: &InitExpr) -> String {
+        self.wrapped(expr, "offset")
+    }
+
+    /// Wraps `expr` into `(keyword ...)` unless it folds into a single instruction.
+    fn wrapped(&self, expr: &InitExpr, keyword: &str) -> String {
+        let folded = self.init_expr(expr);
+        let mut labels = vec![Some(1)];
+        if self.fold(&(expr.0).0, &mut 0, &mut labels).len() == 1 {
+            folded
+        } else {
+            format!("({} {})", keyword, folded)
+        }
+    }
+
+    /// Folds the instructions from `*pos` up to the next `else`, `end` or
+    /// clause of a `try` at the current nesting, leaving `*pos` after it.
+    fn fold(&self, code: &[Op], pos: &mut usize, labels: &mut Vec<Option<usize>>) -> Vec<Node> {
+        // the folded instructions, and whether each pushes exactly one value
+        let mut out: Vec<(Node, bool)> = Vec::new();
+        while *pos < code.len() {
+            let op = &code[*pos];
+            *pos += 1;
+            let (node, results) = match *op {
+                Op::Else | Op::End | Op::Catch(_) | Op::CatchAll | Op::Delegate { .. } => break,
+                Op::Block { ref sig } | Op::Loop { ref sig } => {
+                    let (params, results) = self.block_arity(sig);
+                    let is_loop = matches!(*op, Op::Loop { .. });
+                    labels.push(if is_loop { params } else { results });
+                    let body = self.fold(code, pos, labels);
+                    labels.pop();
+                    let head = format!("{}{}", mnemonic(op), block_type(sig));
+                    (Node::Block {
+                         head: head,
+                         body: body,
+                     },
+                     results)
+                }
+                Op::If { ref sig } => {
+                    let (_, results) = self.block_arity(sig);
+                    let cond = take_operands(&mut out, 1);
+                    labels.push(results);
+                    let then = self.fold(code, pos, labels);
+                    let else_ = if let Some(&Op::Else) = code.get(*pos - 1) {
+                        Some(self.fold(code, pos, labels))
+                    } else {
+                        None
+                    };
+                    labels.pop();
+                    let head = format!("{}{}", mnemonic(op), block_type(sig));
+                    (Node::If {
+                         head: head,
+                         cond: cond,
+                         then: then,
+                         else_: else_,
+                     },
+                     results)
+                }
+                Op::Try { ref sig } => {
+                    let (_, results) = self.block_arity(sig);
+                    labels.push(results);
+                    let body = self.fold(code, pos, labels);
+                    let mut handlers = Vec::new();
+                    let mut delegate = None;
+                    loop {
+                        match code.get(*pos - 1) {
+                            Some(handler @ &Op::Catch(_)) | Some(handler @ &Op::CatchAll) => {
+                                handlers.push((instr(handler), self.fold(code, pos, labels)));
+                            }
+                            Some(d @ &Op::Delegate { .. }) => {
+                                delegate = Some(instr(d));
+                                break;
+                            }
+                            _ => break,
+                        }
+                    }
+                    labels.pop();
+                    let head = format!("{}{}", mnemonic(op), block_type(sig));
+                    (Node::Try {
+                         head: head,
+                         body: body,
+                         handlers: handlers,
+                         delegate: delegate,
+                     },
+                     results)
+                }
+                _ => {
+                    let (operands, results) = match self.arity(op, labels) {
+                        Some((params, results)) => (take_operands(&mut out, params), Some(results)),
+                        None => (Vec::new(), None),
+                    };
+                    (Node::Plain {
+                         text: instr(op),
+                         operands: operands,
+                     },
+                     results)
+                }
+            };
+            out.push((node, results == Some(1)));
+        }
+        out.into_iter().map(|(node, _)| node).collect()
+    }
+
+    /// The number of values a block takes and leaves, unknown for a missing type.
+    fn block_arity(&self, sig: &BlockType) -> (Option<usize>, Option<usize>) {
+        match *sig {
+            BlockType::Empty => (Some(0), Some(0)),
+            BlockType::Value(_) => (Some(0), Some(1)),
+            BlockType::Type(ref index) => {
+                match self.module.func_type(**index) {
+                    Some(ty) => (Some(ty.params.len()), Some(ty.results.len())),
+                    None => (None, None),
+                }
+            }
+        }
+    }
+
+    /// The number of values an instruction pops and pushes.
+    fn arity(&self, op: &Op, labels: &[Option<usize>]) -> Option<(usize, usize)> {
+        use ops::Op::*;
+        if let Some((params, results)) = simple_signature(op) {
+            return Some((params.len(), results.len()));
+        }
+        if let Some((_, _, store)) = memory_access(op) {
+            return Some(if store { (2, 0) } else { (1, 1) });
+        }
+        if let Some((_, store)) = lane_access(op) {
+            return Some(if store { (2, 0) } else { (2, 1) });
+        }
+        if let Some((_, params, results)) = atomic_access(op) {
+            return Some((params.len(), results.len()));
+        }
+        let label = |depth: u32| labels.len().checked_sub(depth as usize + 1).and_then(|i| labels[i]);
+        let arity = match *op {
+            Unreachable => (0, 0),
+            Br { depth } => (label(depth)?, 0),
+            BrIf { depth } => {
+                let n = label(depth)?;
+                (n + 1, n)
+            }
+            BrTable(ref target) => (label(target.default_target)? + 1, 0),
+            Return => (labels[0]?, 0),
+            Call { ref index } => {
+                let ty = self.ctx.as_ref()?.function(**index).ok()?;
+                (ty.params.len(), ty.results.len())
+            }
+            CallIndirect { ref index, .. } => {
+                let ty = self.ctx.as_ref()?.func_type(**index).ok()?;
+                (ty.params.len() + 1, ty.results.len())
+            }
+            ReturnCall { ref index } => (self.ctx.as_ref()?.function(**index).ok()?.params.len(), 0),
+            ReturnCallIndirect { ref index, .. } => (self.ctx.as_ref()?.func_type(**index).ok()?.params.len() + 1, 0),
+            CallRef { ref index } => {
+                let ty = self.ctx.as_ref()?.func_type(**index).ok()?;
+                (ty.params.len() + 1, ty.results.len())
+            }
+            ReturnCallRef { ref index } => (self.ctx.as_ref()?.func_type(**index).ok()?.params.len() + 1, 0),
+            Throw(ref tag) => (self.ctx.as_ref()?.tag(**tag).ok()?.params.len(), 0),
+            Rethrow { .. } => (0, 0),
+            Drop => (1, 0),
+            Select | TypedSelect(_) => (3, 1),
+            GetLocal(_) | GetGlobal(_) => (0, 1),
+            SetLocal(_) | SetGlobal(_) => (1, 0),
+            TableGet(_) => (1, 1),
+            TableSet(_) => (2, 0),
+            TableSize(_) => (0, 1),
+            TableGrow(_) => (2, 1),
+            TableFill(_) => (3, 0),
+            RefNull(_) | RefFunc(_) => (0, 1),
+            RefIsNull => (1, 1),
+            TeeLocal(_) => (1, 1),
+            CurrentMemory { .. } => (0, 1),
+            GrowMemory { .. } => (1, 1),
+            MemoryInit { .. } | MemoryCopy { .. } | MemoryFill { .. } => (3, 0),
+            TableInit { .. } | TableCopy { .. } => (3, 0),
+            DataDrop(_) | ElemDrop(_) => (0, 0),
+            RefEq => (2, 1),
+            RefAsNonNull => (1, 1),
+            BrOnNull { depth } => {
+                let n = label(depth)?;
+                (n + 1, n + 1)
+            }
+            // the label takes the reference as its last value
+            BrOnNonNull { depth } => {
+                let n = label(depth)?;
+                (n, n.checked_sub(1)?)
+            }
+            BrOnCast { depth, .. } | BrOnCastFail { depth, .. } => {
+                let n = label(depth)?;
+                (n, n)
+            }
+            StructNew { ref index } => (self.ctx.as_ref()?.struct_type(**index).ok()?.len(), 1),
+            StructNewDefault { .. } => (0, 1),
+            StructGet { .. } | StructGetS { .. } | StructGetU { .. } => (1, 1),
+            StructSet { .. } => (2, 0),
+            ArrayNew { .. } => (2, 1),
+            ArrayNewDefault { .. } => (1, 1),
+            ArrayNewFixed { len, .. } => (len as usize, 1),
+            ArrayNewData { .. } | ArrayNewElem { .. } => (2, 1),
+            ArrayGet { .. } | ArrayGetS { .. } | ArrayGetU { .. } => (2, 1),
+            ArraySet { .. } => (3, 0),
+            ArrayLen => (1, 1),
+            ArrayFill { .. } => (4, 0),
+            ArrayCopy { .. } => (5, 0),
+            ArrayInitData { .. } | ArrayInitElem { .. } => (4, 0),
+            RefTest(_) | RefCast(_) => (1, 1),
+            AnyConvertExtern | ExternConvertAny | RefI31 | I31GetS | I31GetU => (1, 1),
+            _ => return None,
+        };
+        Some(arity)
+    }
+}
+
+/// Takes the last `n` folded instructions as operands if each of them pushes exactly one value.
+fn take_operands(out: &mut Vec<(Node, bool)>, n: usize) -> Vec<Node> {
+    if n == 0 || out.len() < n || !out[out.len() - n..].iter().all(|&(_, single)| single) {
+        return Vec::new();
+    }
+    let at = out.len() - n;
+    out.drain(at..).map(|(node, _)| node).collect()
+}
+
+fn render(out: &mut String, node: &Node, indent: usize) {
+    let _ = write!(out, "\n{:width$}", "", width = indent);
+    match *node {
+        Node::Plain {
+            ref text,
+            ref operands,
+        } => {
+            let _ = write!(out, "({}", text);
+            for operand in operands.iter() {
+                render(out, operand, indent + 2);
+            }
+        }
+        Node::Block { ref head, ref body } => {
+            let _ = write!(out, "({}", head);
+            for n in body.iter() {
+                render(out, n, indent + 2);
+            }
+        }
+        Node::If {
+            ref head,
+            ref cond,
+            ref then,
+            ref else_,
+        } => {
+            let _ = write!(out, "({}", head);
+            for n in cond.iter() {
+                render(out, n, indent + 2);
+            }
+            let _ = write!(out, "\n{:width$}(then", "", width = indent + 2);
+            for n in then.iter() {
+                render(out, n, indent + 4);
+            }
+            out.push(')');
+            if let Some(ref else_) = *else_ {
+                let _ = write!(out, "\n{:width$}(else", "", width = indent + 2);
+                for n in else_.iter() {
+                    render(out, n, indent + 4);
+                }
+                out.push(')');
+            }
+        }
+        Node::Try {
+            ref head,
+            ref body,
+            ref handlers,
+            ref delegate,
+        } => {
+            let _ = write!(out, "({}", head);
+            let _ = write!(out, "\n{:width$}(do", "", width = indent + 2);
+            for n in body.iter() {
+                render(out, n, indent + 4);
+            }
+            out.push(')');
+            for &(ref handler, ref body) in handlers.iter() {
+                let _ = write!(out, "\n{:width$}({}", "", handler, width = indent + 2);
+                for n in body.iter() {
+                    render(out, n, indent + 4);
+                }
+                out.push(')');
+            }
+            if let Some(ref delegate) = *delegate {
+                let _ = write!(out, "\n{:width$}({})", "", delegate, width = indent + 2);
+            }
+        }
+    }
+    out.push(')');
+}
+
+/// An instruction with its immediates.
+fn instr(op: &Op) -> String {
+    use ops::Op::*;
+    let mut s = mnemonic(op).to_string();
+    if let Some(imm) = op.memory_immediate() {
+        let natural = memory_access(op)
+            .map(|a| a.1)
+            .or_else(|| lane_access(op).map(|a| a.0))
+            .or_else(|| atomic_access(op).map(|a| a.0));
+        memory_immediate(&mut s, imm, natural.expect("memory access"));
+        if let Some((lane, _)) = lane_immediate(op) {
+            let _ = write!(s, " {}", lane);
+        }
+        return s;
+    }
+    let _ = match *op {
+        Block { ref sig } | Loop { ref sig } | If { ref sig } | Try { ref sig } => write!(s, "{}", block_type(sig)),
+        Br { depth } | BrIf { depth } | Delegate { depth } | Rethrow { depth } => write!(s, " {}", depth),
+        Catch(ref tag) | Throw(ref tag) => write!(s, " {}", **tag),
+        BrTable(ref target) => {
+            for t in target.table.iter() {
+                let _ = write!(s, " {}", t);
+            }
+            write!(s, " {}", target.default_target)
+        }
+        Call { ref index } | ReturnCall { ref index } => write!(s, " {}", **index),
+        CallRef { ref index } | ReturnCallRef { ref index } => write!(s, " {}", **index),
+        BrOnNull { depth } | BrOnNonNull { depth } => write!(s, " {}", depth),
+        BrOnCast { depth, ref from, ref to } | BrOnCastFail { depth, ref from, ref to } => {
+            write!(s, " {} {} {}", depth, ref_type(from), ref_type(to))
+        }
+        StructNew { ref index } |
+        StructNewDefault { ref index } |
+        ArrayNew { ref index } |
+        ArrayNewDefault { ref index } |
+        ArrayGet { ref index } |
+        ArrayGetS { ref index } |
+        ArrayGetU { ref index } |
+        ArraySet { ref index } |
+        ArrayFill { ref index } => write!(s, " {}", **index),
+        StructGet { ref index, field } |
+        StructGetS { ref index, field } |
+        StructGetU { ref index, field } |
+        StructSet { ref index, field } => write!(s, " {} {}", **index, field),
+        ArrayNewFixed { ref index, len } => write!(s, " {} {}", **index, len),
+        ArrayNewData { ref index, ref segment } | ArrayInitData { ref index, ref segment } => {
+            write!(s, " {} {}", **index, **segment)
+        }
+        ArrayNewElem { ref index, ref segment } | ArrayInitElem { ref index, ref segment } => {
+            write!(s, " {} {}", **index, **segment)
+        }
+        ArrayCopy { ref dst, ref src } => write!(s, " {} {}", **dst, **src),
+        RefTest(ref ty) | RefCast(ref ty) => write!(s, " {}", ref_type(ty)),
+        CallIndirect { ref index, ref table } | ReturnCallIndirect { ref index, ref table } => {
+            if **table != 0 {
+                let _ = write!(s, " {}", **table);
+            }
+            write!(s, " (type {})", **index)
+        }
+        TypedSelect(ref ty) => write!(s, " (result {})", value_type(ty)),
+        GetLocal(ref i) | SetLocal(ref i) | TeeLocal(ref i) => write!(s, " {}", **i),
+        GetGlobal(ref i) | SetGlobal(ref i) => write!(s, " {}", **i),
+        TableGet(ref i) | TableSet(ref i) | TableGrow(ref i) | TableSize(ref i) | TableFill(ref i) => {
+            write!(s, " {}", **i)
+        }
+        RefNull(ref ty) => write!(s, " {}", heap_type(ty)),
+        RefFunc(ref index) => write!(s, " {}", **index),
+        MemoryInit { ref segment, ref memory } => {
+            if **memory != 0 {
+                let _ = write!(s, " {}", **memory);
+            }
+            write!(s, " {}", **segment)
+        }
+        DataDrop(ref segment) => write!(s, " {}", **segment),
+        CurrentMemory { ref memory } | GrowMemory { ref memory } | MemoryFill { ref memory } if **memory != 0 => {
+            write!(s, " {}", **memory)
+        }
+        MemoryCopy { ref dst, ref src } if **dst != 0 || **src != 0 => write!(s, " {} {}", **dst, **src),
+        TableInit { ref segment, ref table } => {
+            if **table != 0 {
+                let _ = write!(s, " {}", **table);
+            }
+            write!(s, " {}", **segment)
+        }
+        ElemDrop(ref segment) => write!(s, " {}", **segment),
+        TableCopy { ref dst, ref src } if **dst != 0 || **src != 0 => write!(s, " {} {}", **dst, **src),
+        I32Const(i) => write!(s, " {}", i),
+        I64Const(i) => write!(s, " {}", i),
+        F32Const(f) => {
+            let payload = f.to_bits() as u64 & 0x7f_ffff;
+            let plain = f == 0.0 || (1e-5..1e21).contains(&f.abs());
+            write!(s, " {}", float(f.is_nan(), f.is_sign_negative(), payload, 1 << 22, f.abs(), plain))
+        }
+        F64Const(f) => {
+            let payload = f.to_bits() & 0xf_ffff_ffff_ffff;
+            let plain = f == 0.0 || (1e-5..1e21).contains(&f.abs());
+            write!(s, " {}", float(f.is_nan(), f.is_sign_negative(), payload, 1 << 51, f.abs(), plain))
+        }
+        V128Const(v) => {
+            let _ = write!(s, " i32x4");
+            for i in 0..4 {
+                let _ = write!(s, " 0x{:08x}", (v >> (32 * i)) as u32);
+            }
+            Ok(())
+        }
+        I8x16Shuffle(ref lanes) => {
+            for lane in lanes.iter() {
+                let _ = write!(s, " {}", lane);
+            }
+            Ok(())
+        }
+        _ => match lane_immediate(op) {
+            Some((lane, _)) => write!(s, " {}", lane),
+            None => Ok(()),
+        },
+    };
+    s
+}
+
+fn memory_immediate(s: &mut String, imm: &MemoryImmediate, natural: u32) {
+    if *imm.memory != 0 {
+        let _ = write!(s, " {}", *imm.memory);
+    }
+    if imm.offset != 0 {
+        let _ = write!(s, " offset={}", imm.offset);
+    }
+    if imm.flags != natural {
+        let _ = write!(s, " align={}", 1u64 << imm.flags.min(63));
+    }
+}
+
+/// Formats a float given its NaN payload. Rust prints the shortest digits that round-trip,
+/// very small and large values are written with an exponent.
+fn float<F>(nan: bool, negative: bool, payload: u64, canonical: u64, abs: F, plain: bool) -> String
+    where F: fmt::Display + fmt::LowerExp
+{
+    let sign = if negative { "-" } else { "" };
+    if nan && payload == canonical {
+        format!("{}nan", sign)
+    } else if nan {
+        format!("{}nan:0x{:x}", sign, payload)
+    } else if plain {
+        format!("{}{}", sign, abs)
+    } else {
+        format!("{}{:e}", sign, abs)
+    }
+}
+
+fn block_type(sig: &BlockType) -> String {
+    match *sig {
+        BlockType::Empty => String::new(),
+        BlockType::Value(ref t) => format!(" (result {})", value_type(t)),
+        BlockType::Type(ref index) => format!(" (type {})", **index),
+    }
+}
+
+/// A type of the type section, as `sub` unless it is final without supertypes.
+fn sub_type(ty: &SubType) -> String {
+    let composite = match ty.composite {
+        CompositeType::Func(ref f) => format!("(func{})", signature(f)),
+        CompositeType::Struct(ref fields) => {
+            let mut s = String::from("(struct");
+            for f in fields.iter() {
+                let _ = write!(s, " (field {})", field_type(f));
+            }
+            s.push(')');
+            s
+        }
+        CompositeType::Array(ref f) => format!("(array {})", field_type(f)),
+    };
+    if ty.is_final && ty.supertypes.is_empty() {
+        return composite;
+    }
+    let mut s = String::from("(sub");
+    if ty.is_final {
+        s.push_str(" final");
+    }
+    for sup in ty.supertypes.iter() {
+        let _ = write!(s, " {}", **sup);
+    }
+    let _ = write!(s, " {})", composite);
+    s
+}
+
+fn field_type(field: &FieldType) -> String {
+    let storage = match field.storage {
+        StorageType::I8 => "i8".to_string(),
+        StorageType::I16 => "i16".to_string(),
+        StorageType::Val(ref t) => value_type(t),
+    };
+    if field.mutable {
+        format!("(mut {})", storage)
+    } else {
+        storage
+    }
+}
+
+fn signature(ty: &FuncType) -> String {
+    let mut s = String::new();
+    if !ty.params.is_empty() {
+        s.push_str(" (param");
+        for p in ty.params.iter() {
+            let _ = write!(s, " {}", value_type(p));
+        }
+        s.push(')');
+    }
+    if !ty.results.is_empty() {
+        s.push_str(" (result");
+        for r in ty.results.iter() {
+            let _ = write!(s, " {}", value_type(r));
+        }
+        s.push(')');
+    }
+    s
+}
+
+fn limits(limits: &ResizableLimits) -> String {
+    match limits.maximum {
+        Some(max) => format!("{} {}", limits.initial, max),
+        None => format!("{}", limits.initial),
+    }
+}
+
+fn memory_type(memory: &MemoryType) -> String {
+    let mut s = limits(&memory.limits);
+    if memory.memory64 {
+        s.insert_str(0, "i64 ");
+    }
+    if memory.shared {
+        s.push_str(" shared");
+    }
+    s
+}
+
+fn table_type(table: &TableType) -> String {
+    format!("{} {}", limits(&table.limits), ref_type(&table.element))
+}
+
+fn global_type(global: &GlobalType) -> String {
+    if global.mutable {
+        format!("(mut {})", value_type(&global.content))
+    } else {
+        value_type(&global.content)
+    }
+}
+
+/// A string literal, escaping everything but printable ASCII.
+fn string(bytes: &[u8]) -> String {
+    let mut s = String::from("\"");
+    for &b in bytes {
+        match b {
+            b'"' => s.push_str("\\\""),
+            b'\\' => s.push_str("\\\\"),
+            0x20..=0x7e => s.push(b as char),
+            _ => {
+                let _ = write!(s, "\\{:02x}", b);
+            }
+        }
+    }
+    s.push('"');
+    s
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use builder::*;
+    use ops::Op::*;
+    use Dump;
+    use {funtype, ty, ty_vec};
+
+    fn small() -> Module {
+        let mut mb = ModuleBuilder::new();
+        let log_ty = mb.add_type(funtype!((i32)));
+        let log = mb.import("env", "log", log_ty);
+        let log = mb.function_index_of(log).unwrap();
+        mb.new_memory(1..);
+        mb.new_global(GlobalType {
+                          content: ValueType::I32,
+                          mutable: true,
+                      },
+                      Code(vec![I32Const(0), End]));
+        let x = LocalIndex::new(0);
+        let y = LocalIndex::new(1);
+        let code = vec![Block { sig: BlockType::Empty },
+                        GetLocal(x),
+                        I32Eqz,
+                        BrIf { depth: 0 },
+                        GetLocal(x),
+                        Call { index: log },
+                        End,
+                        GetLocal(x),
+                        GetGlobal(GlobalIndex::new(0)),
+                        I32Add,
+                        SetLocal(y),
+                        GetLocal(y),
+                        If { sig: BlockType::Value(ValueType::I32) },
+                        GetLocal(y),
+                        I32Load {
+                            imm: MemoryImmediate {
+                                flags: 2,
+                                offset: 4,
+                                memory: MemoryIndex::new(0),
+                            },
+                        },
+                        Else,
+                        I32Const(-1),
+                        End];
+        let body = FunctionBody {
+            locals: vec![LocalEntry {
+                             count: 1,
+                             ty: ValueType::I32,
+                         }],
+            code: Code(code),
+        };
+        mb.new_function((funtype!((i32) -> i32), body));
+        // exports count the imported function
+        mb.export("f", FunctionIndex::new(1));
+        mb.build()
+    }
+
+    fn dump(module: &Module) -> Vec<u8> {
+        let mut buf = Vec::new();
+        module.dump(&mut buf);
+        buf
+    }
+
+    /// Printing in either style and parsing the text again gives the same module.
+    fn assert_round_trip(module: &Module) {
+        for &style in [WatStyle::Flat, WatStyle::Folded].iter() {
+            let text = module.to_wat(style);
+            let parsed = Module::from_wat(&text).unwrap_or_else(|e| panic!("{}\n{}", e, text));
+            assert_eq!(dump(&parsed), dump(module), "{}", text);
+        }
+    }
+
+    #[test]
+    fn flat() {
+        assert_eq!(small().to_wat(WatStyle::Flat),
+                   r#"(module
+  (type (;0;) (func (param i32)))
+  (type (;1;) (func (param i32) (result i32)))
+  (import "env" "log" (func (;0;) (type 0) (param i32)))
+  (func (;1;) (type 1) (param i32) (result i32)
+    (local i32)
+    block
+      local.get 0
+      i32.eqz
+      br_if 0
+      local.get 0
+      call 0
+    end
+    local.get 0
+    global.get 0
+    i32.add
+    local.set 1
+    local.get 1
+    if (result i32)
+      local.get 1
+      i32.load offset=4
+    else
+      i32.const -1
+    end)
+  (memory (;0;) 1)
+  (global (;0;) (mut i32) (i32.const 0))
+  (export "f" (func 1)))
+"#);
+    }
+
+    #[test]
+    fn folded() {
+        assert_eq!(small().to_wat(WatStyle::Folded),
+                   r#"(module
+  (type (;0;) (func (param i32)))
+  (type (;1;) (func (param i32) (result i32)))
+  (import "env" "log" (func (;0;) (type 0) (param i32)))
+  (func (;1;) (type 1) (param i32) (result i32)
+    (local i32)
+    (block
+      (br_if 0
+        (i32.eqz
+          (local.get 0)))
+      (call 0
+        (local.get 0)))
+    (local.set 1
+      (i32.add
+        (local.get 0)
+        (global.get 0)))
+    (if (result i32)
+      (local.get 1)
+      (then
+        (i32.load offset=4
+          (local.get 1)))
+      (else
+        (i32.const -1))))
+  (memory (;0;) 1)
+  (global (;0;) (mut i32) (i32.const 0))
+  (export "f" (func 1)))
+"#);
+    }
+
+    #[test]
+    fn round_trip() {
+        assert_round_trip(&small());
+        assert_round_trip(&Module::from_wat(r#"
+            (module
+              (type $unary (func (param i64) (result i64)))
+              (import "env" "t" (table 1 funcref))
+              (import "env" "g" (global $imported i32))
+              (memory $m 1 2)
+              (global $c i64 (i64.const -9223372036854775808))
+              (func $id (type $unary) (local.get 0))
+              (func $branches (param i32) (result i32)
+                (block $out (result i32)
+                  (loop $again
+                    (br_table $out $again $out (i32.const 7) (local.get 0)))
+                  (i32.const 0))
+                (drop)
+                (select (i32.const 1) (i32.const 2) (local.get 0))
+                (call_indirect (type 1) (i32.const 0))
+                (return))
+              (func $pair (param i32) (result i32 i64)
+                (local.get 0)
+                (block $b (param i32) (result i32 i64)
+                  (loop $l (param i32) (result i32)
+                    (br_if $l (local.get 0)))
+                  (i64.const 1)))
+              (func $mem (param i64)
+                (i64.store offset=8 align=4 (i32.const 0) (local.get 0))
+                (drop (memory.grow (i32.const 1)))
+                (drop (f64.promote_f32 (f32.load (global.get $imported)))))
+              (elem (i32.const 0) $id $branches)
+              (data (i32.const 16) "\00\01bytes\ff")
+              (export "branches" (func $branches))
+              (start $start)
+              (func $start)
+              (@custom "first" (before first))
+              (@custom "meta" (after func) "\01" "\02"))"#)
+            .unwrap());
+    }
+
+    #[test]
+    fn gc_round_trip() {
+        assert_round_trip(&Module::from_wat(r#"
+            (module
+              (rec
+                (type $tree (sub (struct (field $left (ref null $tree)) (field $right (ref null $tree)))))
+                (type $leaf (sub final $tree (struct (field $left (ref null $tree)) (field $right (ref null $tree)) (field (mut i8))))))
+              (type $table (array (mut (ref null $tree))))
+              (type $sum (func (param (ref null $tree)) (result i32)))
+              (global $none (ref null $tree) (ref.null $tree))
+              (global $root (ref $tree) (struct.new $tree (global.get $none) (global.get $none)))
+              (global $one i31ref (ref.i31 (i32.const 1)))
+              (table $trees 1 (ref null $tree))
+              (elem declare func $sum)
+              (func $sum (type $sum)
+                (block $leaf (result (ref $leaf))
+                  (br_on_cast $leaf anyref (ref $leaf) (local.get 0))
+                  (br_on_null 1 (i32.const 0))
+                  (drop)
+                  (return (i32.add
+                    (call_ref $sum (struct.get $tree $left (ref.cast (ref $tree) (local.get 0))) (ref.func $sum))
+                    (call_ref $sum (struct.get $tree $right (ref.as_non_null (local.get 0))) (ref.func $sum)))))
+                (struct.get_s $leaf 2))
+              (func (result i32)
+                (local $t (ref null $table))
+                (local.set $t (array.new $table (global.get $root) (i32.const 2)))
+                (array.set $table (local.get $t) (i32.const 1) (struct.new_default $leaf))
+                (table.set $trees (i32.const 0) (array.get $table (local.get $t) (i32.const 0)))
+                (drop (i31.get_u (global.get $one)))
+                (drop (any.convert_extern (extern.convert_any (global.get $one))))
+                (if (result i32) (ref.test (ref $leaf) (array.get $table (local.get $t) (i32.const 1)))
+                  (then (call $sum (array.get $table (local.get $t) (i32.const 1))))
+                  (else (array.len (local.get $t))))))"#)
+            .unwrap());
+    }
+
+    #[test]
+    fn floats() {
+        let values = ["0", "-0", "1.5", "-1e-10", "3.4028235e38", "1e-45", "inf", "-inf", "nan", "-nan", "nan:0x1",
+                      "0x1.fffffep127"];
+        for v in values.iter() {
+            let src = format!("(module (func (drop (f32.const {v})) (drop (f64.const {v}))))", v = v);
+            assert_round_trip(&Module::from_wat(&src).unwrap());
+        }
+
+        let cases = [(F32Const(-0.0), "f32.const -0"),
+                     (F32Const(1.5), "f32.const 1.5"),
+                     (F32Const(f32::INFINITY), "f32.const inf"),
+                     (F32Const(f32::from_bits(0xffa00000)), "f32.const -nan:0x200000"),
+                     (F64Const(1e300), "f64.const 1e300"),
+                     (F64Const(f64::NEG_INFINITY), "f64.const -inf"),
+                     (F64Const(f64::from_bits(0x7ff8000000000000)), "f64.const nan")];
+        for &(ref op, expected) in cases.iter() {
+            let mut mb = ModuleBuilder::new();
+            mb.new_function((funtype!(()),
+                             FunctionBody {
+                                 locals: Vec::new(),
+                                 code: Code(vec![op.clone(), Drop]),
+                             }));
+            let text = mb.build().to_wat(WatStyle::Flat);
+            assert!(text.contains(&format!("    {}\n", expected)), "{}", text);
+        }
+    }
+
+    #[test]
+    fn unknown_types() {
+        // a module referring to a missing type is still printed, without folding calls
+        let mut mb = ModuleBuilder::new();
+        mb.new_function((funtype!(()),
+                         FunctionBody {
+                             locals: Vec::new(),
+                             code: Code(vec![Call { index: FunctionIndex::new(0).into() }]),
+                         }));
+        let mut module = mb.build();
+        module.types = None;
+        let text = module.to_wat(WatStyle::Folded);
+        assert!(text.contains("(func (;0;) (type 0)\n    (call 0))"), "{}", text);
+    }
+}